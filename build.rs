@@ -0,0 +1,10 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Compile the gRPC service with protox, so that protoc doesn't need to be installed
+    #[cfg(feature = "grpc")]
+    {
+        println!("cargo:rerun-if-changed=proto/payments_engine.proto");
+        let file_descriptors = protox::compile(["proto/payments_engine.proto"], ["proto"])?;
+        tonic_prost_build::configure().compile_fds(file_descriptors)?;
+    }
+    Ok(())
+}
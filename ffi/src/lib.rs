@@ -0,0 +1,6 @@
+//! The `cdylib` of the Python, C and WebAssembly builds of the engine. Their bindings are
+//! the `python`, `ffi` and `wasm` modules of the `payments-engine` library, with the features
+//! of the same names, and this only links them into a dynamic library, which the library
+//! can't be itself and still build without `std`.
+
+pub use payments_engine::*;
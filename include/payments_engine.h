@@ -0,0 +1,45 @@
+/*
+ * C API of the payments engine, built into libpayments_engine_ffi with
+ * `cargo build --release -p payments-engine-ffi --features ffi`.
+ * See src/ffi.rs for the details.
+ */
+#ifndef PAYMENTS_ENGINE_H
+#define PAYMENTS_ENGINE_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Return codes of engine_apply_csv_line */
+#define ENGINE_OK 0
+#define ENGINE_INTERNAL_ERROR 1
+#define ENGINE_INVALID_ARGUMENT 2
+#define ENGINE_IO_ERROR 3
+#define ENGINE_PARSE_ERROR 4
+#define ENGINE_SEMANTIC_ERROR 5
+
+typedef struct PaymentsEngine PaymentsEngine;
+
+/* Creates an engine that keeps all of its state in memory. */
+PaymentsEngine *engine_new(void);
+
+/* Applies a single CSV record, without a header, e.g. "deposit, 1, 1, 1.0".
+ * Invalid transactions (insufficient funds, unknown transactions, locked
+ * accounts) are ignored and return ENGINE_OK. */
+int engine_apply_csv_line(PaymentsEngine *engine, const char *line);
+
+/* The account balances as a JSON array, sorted by client id, or NULL on error.
+ * The string must be released with engine_string_free. */
+char *engine_report_json(const PaymentsEngine *engine);
+
+/* Releases a string returned by the engine. */
+void engine_string_free(char *s);
+
+/* Releases an engine. */
+void engine_free(PaymentsEngine *engine);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PAYMENTS_ENGINE_H */
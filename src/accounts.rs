@@ -1,29 +1,490 @@
-use core::fmt;
+use std::io::{self, Write};
+use std::iter;
 
-#[derive(Debug, Clone)]
-pub struct AccountBalance {
-    pub client: u16,
-    pub available: f32,
-    pub held: f32,
-    pub locked: bool,
+use serde::{Deserialize, Serialize};
+
+use crate::amount::AmountValue;
+pub use crate::balance::{AccountBalance, AccountStatus, AccountStatusFromStrError};
+use crate::clients::{ClientDirectory, CLIENT_COLUMNS};
+use crate::transactions::{Amount, ClientId, TxId};
+
+/// What is known of the account of a client, see [`PaymentsEngine::account_view`](crate::PaymentsEngine::account_view).
+/// It serializes as the balance, with its `open_disputes` and `last_activity`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountView {
+    #[serde(flatten)]
+    pub balance: AccountBalance,
+    /// The disputes of the deposits of the client still open, charged back or not, by deposit id.
+    pub open_disputes: Vec<OpenDispute>,
+    /// The latest `timestamp` of the transactions of the client applied, if any had one.
+    pub last_activity: Option<u64>,
+}
+
+/// A dispute still open, with the funds it holds, see [`PaymentsEngine::open_disputes`](crate::PaymentsEngine::open_disputes).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OpenDispute {
+    /// The client of the deposit, unless it was restored from a snapshot without the clients.
+    pub client: Option<ClientId>,
+    /// The id of the deposit disputed.
+    pub tx: TxId,
+    pub held: Amount,
+    pub charged_back: bool,
+    /// The `timestamp` of the dispute, if it had one and wasn't restored from a snapshot.
+    pub opened_at: Option<u64>,
+}
+
+/// The header of the account balances report.
+pub const REPORT_HEADER: &str = "client, available, held, pending, total, locked";
+
+/// Formats the account balances report.
+pub fn format_report(balances: &[AccountBalance]) -> String {
+    let mut output = vec![String::from(REPORT_HEADER)];
+    for account_balance in balances {
+        output.push(format!("{}", account_balance));
+    }
+    output.join("\n")
+}
+
+/// Formats the account balances report, with the metadata of the clients.
+pub fn format_report_with_clients(balances: &[AccountBalance], clients: &ClientDirectory) -> String {
+    let mut output = vec![format!("{}, {}", REPORT_HEADER, CLIENT_COLUMNS)];
+    for account_balance in balances {
+        output.push(format!(
+            "{}, {}",
+            account_balance,
+            clients.columns(account_balance.client)
+        ));
+    }
+    output.join("\n")
+}
+
+/// What to sort the account balances report by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortKey {
+    #[default]
+    Client,
+    Total,
+    Held,
+}
+
+/// Sorts the account balances by `key`, the clients with the same value by client id.
+pub fn sort_balances(balances: &mut [AccountBalance], key: SortKey, descending: bool) {
+    let value = |account_balance: &AccountBalance| match key {
+        SortKey::Client => account_balance.client as f64,
+        SortKey::Total => account_balance.total().to_f64(),
+        SortKey::Held => account_balance.held.to_f64(),
+    };
+    balances.sort_by_key(|account_balance| account_balance.client);
+    balances.sort_by(|a, b| {
+        let ordering = value(a).total_cmp(&value(b));
+        if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+/// A column of the account balances report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportColumn {
+    Client,
+    Available,
+    Held,
+    Pending,
+    Total,
+    Locked,
+}
+
+impl ReportColumn {
+    /// The name of the column in the header.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReportColumn::Client => "client",
+            ReportColumn::Available => "available",
+            ReportColumn::Held => "held",
+            ReportColumn::Pending => "pending",
+            ReportColumn::Total => "total",
+            ReportColumn::Locked => "locked",
+        }
+    }
+
+    /// The amount of the column for an account, if it is an amount column.
+    fn amount(&self, account_balance: &AccountBalance) -> Option<Amount> {
+        match self {
+            ReportColumn::Available => Some(account_balance.available),
+            ReportColumn::Held => Some(account_balance.held),
+            ReportColumn::Pending => Some(account_balance.pending),
+            ReportColumn::Total => Some(account_balance.total()),
+            ReportColumn::Client | ReportColumn::Locked => None,
+        }
+    }
+
+    /// The value of the column for an account, with its amounts formatted as `amounts`.
+    pub(crate) fn value(&self, account_balance: &AccountBalance, amounts: &AmountFormat) -> String {
+        match (self, self.amount(account_balance)) {
+            (_, Some(amount)) => amounts.format(amount),
+            (ReportColumn::Locked, None) => account_balance.is_locked().to_string(),
+            _ => account_balance.client.to_string(),
+        }
+    }
 }
 
-impl AccountBalance {
-    fn get_total(&self) -> f32 {
-        self.available + self.held
+/// All the columns of the report, in their usual order.
+pub const REPORT_COLUMNS: [ReportColumn; 6] = [
+    ReportColumn::Client,
+    ReportColumn::Available,
+    ReportColumn::Held,
+    ReportColumn::Pending,
+    ReportColumn::Total,
+    ReportColumn::Locked,
+];
+
+/// How the amounts are rounded to the precision of the report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rounding {
+    /// To the nearest, the ties to an even last digit.
+    #[default]
+    HalfEven,
+    /// Toward zero, dropping the digits beyond the precision.
+    Truncate,
+}
+
+/// Whether the amounts are written as decimals or as integers of minor units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AmountStyle {
+    /// E.g. `1.5000`.
+    #[default]
+    Decimal,
+    /// The integer number of the smallest units of the precision, e.g. `15000` basis points
+    /// for `1.5000` with a precision of 4. With the `fixed-amounts` feature these are exact, and
+    /// the units of the amounts themselves at a precision of 4.
+    MinorUnits,
+}
+
+/// How the amounts of the report are written.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmountFormat {
+    /// The number of decimal places.
+    pub precision: usize,
+    pub rounding: Rounding,
+    pub style: AmountStyle,
+    /// Whether the JSON report has the amounts as strings, as written in the CSV one,
+    /// instead of numbers.
+    pub json_strings: bool,
+}
+
+impl Default for AmountFormat {
+    fn default() -> Self {
+        AmountFormat {
+            precision: 4,
+            rounding: Rounding::default(),
+            style: AmountStyle::default(),
+            json_strings: false,
+        }
     }
 }
 
-impl fmt::Display for AccountBalance {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}, {:.4}, {:.4}, {:.4}, {}",
-            self.client,
-            self.available,
-            self.held,
-            self.get_total(),
-            self.locked
-        )
+impl AmountFormat {
+    /// The amount rounded to `precision` decimal places, in the `style`.
+    pub fn format(&self, amount: Amount) -> String {
+        let decimal = self.decimal(amount);
+        if self.style == AmountStyle::Decimal {
+            return decimal;
+        }
+        #[cfg(feature = "fixed-amounts")]
+        if self.precision == crate::amount::MinorUnits::DECIMALS {
+            return amount.0.to_string();
+        }
+        // The digits of the rounded decimal, without going through a float again
+        let (sign, digits) = match decimal.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", decimal.as_str()),
+        };
+        match digits.replace('.', "").trim_start_matches('0') {
+            "" => String::from("0"),
+            digits => format!("{}{}", sign, digits),
+        }
     }
-}
\ No newline at end of file
+
+    /// The amount with `precision` decimal places.
+    fn decimal(&self, amount: Amount) -> String {
+        match self.rounding {
+            Rounding::HalfEven => format!("{:.*}", self.precision, amount),
+            Rounding::Truncate => {
+                // Of the shortest decimal that reads back as the amount, e.g. 2.3 and not 2.2999999523
+                let shortest = amount.to_string();
+                let (integer, fraction) = shortest.split_once('.').unwrap_or((&shortest, ""));
+                if self.precision == 0 {
+                    return integer.to_string();
+                }
+                let fraction: String = fraction.chars().chain(iter::repeat('0')).take(self.precision).collect();
+                format!("{}.{}", integer, fraction)
+            }
+        }
+    }
+}
+
+/// The rows of the report written between two flushes by [`write_report_columns`].
+pub const FLUSH_ROWS: usize = 1024;
+
+/// Formats the account balances report with only these columns, in this order,
+/// followed by the metadata of the clients if there is any.
+pub fn format_report_columns(
+    balances: &[AccountBalance],
+    columns: &[ReportColumn],
+    clients: Option<&ClientDirectory>,
+    amounts: &AmountFormat,
+) -> String {
+    let mut output = vec![];
+    write_report_columns(balances, columns, clients, amounts, &mut output).expect("writing to a Vec can't fail");
+    output.pop();
+    String::from_utf8(output).expect("the report is valid UTF-8")
+}
+
+/// Formats the row of a balance of the report of [`format_report_columns`].
+pub fn format_report_row(
+    account_balance: &AccountBalance,
+    columns: &[ReportColumn],
+    clients: Option<&ClientDirectory>,
+    amounts: &AmountFormat,
+) -> String {
+    let mut row: Vec<String> = columns
+        .iter()
+        .map(|column| column.value(account_balance, amounts))
+        .collect();
+    row.extend(clients.map(|clients| clients.columns(account_balance.client)));
+    row.join(", ")
+}
+
+/// Writes the same report as [`format_report_columns`] a line at a time, each one ending with a
+/// newline, flushing the writer every [`FLUSH_ROWS`] rows and at the end so that a reader at the
+/// other end of a pipe gets the rows as they are written.
+pub fn write_report_columns<W: Write>(
+    balances: &[AccountBalance],
+    columns: &[ReportColumn],
+    clients: Option<&ClientDirectory>,
+    amounts: &AmountFormat,
+    mut writer: W,
+) -> io::Result<()> {
+    let mut header: Vec<&str> = columns.iter().map(ReportColumn::name).collect();
+    header.extend(clients.map(|_| CLIENT_COLUMNS));
+    writeln!(writer, "{}", header.join(", "))?;
+    for (index, account_balance) in balances.iter().enumerate() {
+        writeln!(
+            writer,
+            "{}",
+            format_report_row(account_balance, columns, clients, amounts)
+        )?;
+        if (index + 1) % FLUSH_ROWS == 0 {
+            writer.flush()?;
+        }
+    }
+    writer.flush()
+}
+
+/// Formats the account balances as a JSON array.
+pub fn format_report_json(balances: &[AccountBalance]) -> String {
+    serde_json::to_value(balances)
+        .expect("balances are always valid JSON")
+        .to_string()
+}
+
+/// Formats the account balances as a JSON array, with the amounts formatted as `amounts`.
+pub fn format_report_json_with(balances: &[AccountBalance], amounts: &AmountFormat) -> String {
+    let rows: Vec<serde_json::Value> = balances
+        .iter()
+        .map(|account_balance| {
+            let mut row = serde_json::to_value(account_balance).expect("balances are always valid JSON");
+            for column in REPORT_COLUMNS {
+                if let Some(amount) = column.amount(account_balance) {
+                    let amount = amounts.format(amount);
+                    row[column.name()] = match (amount.parse::<i64>(), amount.parse::<f64>()) {
+                        _ if amounts.json_strings => serde_json::Value::String(amount),
+                        (Ok(units), _) => serde_json::json!(units),
+                        (_, Ok(number)) => serde_json::json!(number),
+                        _ => serde_json::Value::String(amount),
+                    };
+                }
+            }
+            row
+        })
+        .collect();
+    serde_json::Value::Array(rows).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use super::{
+        format_report_columns, format_report_json_with, sort_balances, write_report_columns, AccountBalance,
+        AccountStatus, AmountFormat, AmountStyle, ReportColumn, Rounding, SortKey, FLUSH_ROWS, REPORT_COLUMNS,
+    };
+    use crate::amount::amount;
+    use crate::transactions::ClientId;
+
+    #[test]
+    fn test_serde() {
+        let account_balance = AccountBalance {
+            available: amount(1.5),
+            held: amount(2.0),
+            status: AccountStatus::Frozen,
+            ..AccountBalance::new(1)
+        };
+        let json = serde_json::to_string(&account_balance).unwrap();
+        assert_eq!(
+            json,
+            r#"{"client":1,"available":1.5,"held":2.0,"pending":0.0,"total":3.5,"locked":false,"status":"frozen"}"#
+        );
+        let restored: AccountBalance = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.status, AccountStatus::Frozen);
+        assert_eq!(restored.total(), amount(3.5));
+
+        // The report format, without a status
+        let mut rdr = crate::engine::csv_reader_builder()
+            .from_reader("client, available, held, total, locked\n2, 1.0, 0.5, 1.5, true".as_bytes());
+        let restored: AccountBalance = rdr.deserialize().next().unwrap().unwrap();
+        assert_eq!(
+            (restored.client, restored.held, restored.status),
+            (2, amount(0.5), AccountStatus::Locked)
+        );
+    }
+
+    #[test]
+    fn test_sort_and_columns() {
+        let mut balances: Vec<AccountBalance> = [(1, 2.0, 0.5), (2, 1.0, 3.0), (3, 0.0, 0.5)]
+            .iter()
+            .map(|&(client, available, held)| AccountBalance {
+                available: amount(available),
+                held: amount(held),
+                ..AccountBalance::new(client)
+            })
+            .collect();
+
+        // The largest held first, the ties by client id
+        sort_balances(&mut balances, SortKey::Held, true);
+        assert_eq!(
+            format_report_columns(
+                &balances,
+                &[ReportColumn::Client, ReportColumn::Held, ReportColumn::Total],
+                None,
+                &AmountFormat::default()
+            ),
+            "client, held, total\n2, 3.0000, 4.0000\n1, 0.5000, 2.5000\n3, 0.5000, 0.5000"
+        );
+        sort_balances(&mut balances, SortKey::Total, false);
+        let clients: Vec<_> = balances.iter().map(|account_balance| account_balance.client).collect();
+        assert_eq!(clients, [3, 1, 2]);
+    }
+
+    #[test]
+    fn test_amount_format() {
+        let truncate = AmountFormat {
+            precision: 2,
+            rounding: Rounding::Truncate,
+            ..AmountFormat::default()
+        };
+        let half_even = AmountFormat {
+            precision: 2,
+            ..AmountFormat::default()
+        };
+        for (value, truncated, rounded) in [
+            (2.3, "2.30", "2.30"),
+            (0.125, "0.12", "0.12"),
+            (0.375, "0.37", "0.38"),
+            (-1.999, "-1.99", "-2.00"),
+        ] {
+            let value = amount(value);
+            assert_eq!(
+                (truncate.format(value).as_str(), half_even.format(value).as_str()),
+                (truncated, rounded)
+            );
+        }
+        assert_eq!(
+            AmountFormat {
+                precision: 0,
+                ..truncate
+            }
+            .format(amount(7.9)),
+            "7"
+        );
+
+        let balances = [AccountBalance {
+            available: amount(1.23456),
+            ..AccountBalance::new(1)
+        }];
+        assert_eq!(
+            format_report_json_with(
+                &balances,
+                &AmountFormat {
+                    json_strings: true,
+                    ..AmountFormat::default()
+                }
+            ),
+            r#"[{"available":"1.2346","client":1,"held":"0.0000","locked":false,"pending":"0.0000","status":"active","total":"1.2346"}]"#
+        );
+        assert!(format_report_json_with(&balances, &half_even).contains(r#""available":1.23,"#));
+
+        // In basis points
+        let minor_units = AmountFormat {
+            style: AmountStyle::MinorUnits,
+            ..AmountFormat::default()
+        };
+        for (value, units) in [
+            (1.5, "15000"),
+            (0.0001, "1"),
+            (-0.25, "-2500"),
+            (0.0, "0"),
+            (-0.00001, "0"),
+        ] {
+            assert_eq!(minor_units.format(amount(value)), units);
+        }
+        assert!(format_report_json_with(&balances, &minor_units).contains(r#""available":12346,"#));
+
+        // The exact units, where an f32 would have 12345678750
+        #[cfg(feature = "fixed-amounts")]
+        {
+            let amount = "1234567.89".parse().unwrap();
+            assert_eq!(minor_units.format(amount), "12345678900");
+            assert_eq!(
+                AmountFormat {
+                    precision: 1,
+                    ..minor_units
+                }
+                .format(amount),
+                "12345679"
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_report_columns() {
+        #[derive(Default)]
+        struct Flushes {
+            output: Vec<u8>,
+            flushed: Vec<usize>,
+        }
+        impl Write for Flushes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.output.write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                self.flushed
+                    .push(self.output.iter().filter(|&&byte| byte == b'\n').count());
+                Ok(())
+            }
+        }
+
+        let balances: Vec<AccountBalance> = (0..2 * FLUSH_ROWS as ClientId + 1).map(AccountBalance::new).collect();
+        let mut writer = Flushes::default();
+        write_report_columns(&balances, &REPORT_COLUMNS, None, &AmountFormat::default(), &mut writer).unwrap();
+        // The same report, a line at a time, flushed after blocks of rows and at the end
+        let report = format_report_columns(&balances, &REPORT_COLUMNS, None, &AmountFormat::default());
+        assert_eq!(String::from_utf8(writer.output).unwrap(), report + "\n");
+        assert_eq!(writer.flushed, [FLUSH_ROWS + 1, 2 * FLUSH_ROWS + 1, 2 * FLUSH_ROWS + 2]);
+    }
+}
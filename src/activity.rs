@@ -0,0 +1,233 @@
+//! The activity of all the clients by day, week or month, going by the `timestamp` of the
+//! transactions: the deposits, withdrawals, disputes and chargebacks applied in each period,
+//! and their amounts.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::accounts::AmountFormat;
+use crate::amount::AmountValue;
+use crate::dates::{civil_from_days, SECS_PER_DAY};
+use crate::ledger::JournalEntry;
+use crate::transactions::{Amount, Transaction, TransactionType};
+
+/// How long the periods of the activity are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Bucket {
+    #[default]
+    Daily,
+    /// From Monday to Sunday.
+    Weekly,
+    Monthly,
+}
+
+#[derive(Debug)]
+pub struct BucketFromStrError(String);
+
+impl Error for BucketFromStrError {}
+
+impl fmt::Display for BucketFromStrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid bucket `{}`, expected `daily`, `weekly` or `monthly`",
+            self.0
+        )
+    }
+}
+
+impl FromStr for Bucket {
+    type Err = BucketFromStrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "daily" => Ok(Bucket::Daily),
+            "weekly" => Ok(Bucket::Weekly),
+            "monthly" => Ok(Bucket::Monthly),
+            _ => Err(BucketFromStrError(s.to_string())),
+        }
+    }
+}
+
+impl Bucket {
+    /// The `(year, month, day)` of the first day of the period of a timestamp.
+    fn start(self, timestamp: u64) -> (i64, u32, u32) {
+        let day = (timestamp / SECS_PER_DAY) as i64;
+        match self {
+            Bucket::Daily => civil_from_days(day),
+            // The Unix epoch was a Thursday
+            Bucket::Weekly => civil_from_days(day - (day + 3).rem_euclid(7)),
+            Bucket::Monthly => {
+                let (year, month, _) = civil_from_days(day);
+                (year, month, 1)
+            }
+        }
+    }
+}
+
+/// What was applied in a period.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActivityTotals {
+    pub deposits: u64,
+    pub deposited: Amount,
+    pub withdrawals: u64,
+    pub withdrawn: Amount,
+    pub disputes: u64,
+    /// The funds held by the disputes.
+    pub disputed: Amount,
+    pub chargebacks: u64,
+    /// The funds lost to chargebacks.
+    pub charged_back: Amount,
+}
+
+/// The activity so far, by the first day of each period, see [`PaymentsEngine::with_activity`](crate::engine::PaymentsEngine::with_activity).
+/// The transactions without a timestamp are in a period of their own, without a first day.
+#[derive(Debug, Default)]
+pub(crate) struct Activity {
+    bucket: Bucket,
+    periods: BTreeMap<Option<(i64, u32, u32)>, ActivityTotals>,
+}
+
+impl Activity {
+    pub(crate) fn new(bucket: Bucket) -> Self {
+        Activity {
+            bucket,
+            periods: BTreeMap::new(),
+        }
+    }
+
+    /// Adds an applied transaction, with the entry it posted, to its period.
+    pub(crate) fn record(&mut self, transaction: &Transaction, entry: &JournalEntry) {
+        let start = transaction.timestamp.map(|timestamp| self.bucket.start(timestamp));
+        let totals = self.periods.entry(start).or_default();
+        let amount = entry.postings.first().map_or(Amount::ZERO, |posting| posting.amount);
+        match transaction.tx_type {
+            TransactionType::Deposit => {
+                totals.deposits += 1;
+                totals.deposited += amount;
+            }
+            TransactionType::Withdrawal => {
+                totals.withdrawals += 1;
+                totals.withdrawn += amount;
+            }
+            TransactionType::Dispute => {
+                totals.disputes += 1;
+                totals.disputed += amount;
+            }
+            TransactionType::Chargeback => {
+                totals.chargebacks += 1;
+                totals.charged_back += amount;
+            }
+            _ => {}
+        }
+    }
+
+    /// The periods with any activity, in order, by the `YYYY-MM-DD` of their first day.
+    pub(crate) fn periods(&self) -> Vec<(Option<String>, ActivityTotals)> {
+        self.periods
+            .iter()
+            .map(|(start, totals)| {
+                let start = start.map(|(year, month, day)| format!("{:04}-{:02}-{:02}", year, month, day));
+                (start, totals.clone())
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+struct ActivityRow<'a> {
+    period: &'a str,
+    deposits: u64,
+    deposited: String,
+    withdrawals: u64,
+    withdrawn: String,
+    disputes: u64,
+    disputed: String,
+    chargebacks: u64,
+    charged_back: String,
+}
+
+/// Writes the activity of each period as CSV, with the amounts formatted as `amounts`.
+/// The period of the transactions without a timestamp is empty.
+pub fn write_report<W: Write>(
+    periods: &[(Option<String>, ActivityTotals)],
+    amounts: &AmountFormat,
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for (period, totals) in periods {
+        wtr.serialize(ActivityRow {
+            period: period.as_deref().unwrap_or_default(),
+            deposits: totals.deposits,
+            deposited: amounts.format(totals.deposited),
+            withdrawals: totals.withdrawals,
+            withdrawn: amounts.format(totals.withdrawn),
+            disputes: totals.disputes,
+            disputed: amounts.format(totals.disputed),
+            chargebacks: totals.chargebacks,
+            charged_back: amounts.format(totals.charged_back),
+        })?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_report, Bucket};
+    use crate::accounts::AmountFormat;
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_activity() {
+        // 2024-06-01 was a Saturday
+        let day = 86_400;
+        let start = 19_875 * day;
+        let input = format!(
+            "type, client, tx, amount, timestamp\n\
+             deposit, 1, 1, 10.0, {}\n\
+             deposit, 1, 2, 5.0, {}\n\
+             withdrawal, 1, 3, 2.0, {}\n\
+             withdrawal, 1, 4, 100.0, {}\n\
+             dispute, 1, 2, , {}\n\
+             chargeback, 1, 2, , {}\n\
+             deposit, 2, 5, 1.0,\n",
+            start,
+            start + 100,
+            start + day,
+            start + day,
+            start + 2 * day,
+            start + 2 * day + 1,
+        );
+        let report = |bucket| {
+            let mut engine = PaymentsEngine::new().with_activity(bucket);
+            engine.process_csv_reader(input.as_bytes()).unwrap();
+            let mut report = vec![];
+            write_report(&engine.activity(), &AmountFormat::default(), &mut report).unwrap();
+            String::from_utf8(report).unwrap()
+        };
+        assert_eq!(
+            report(Bucket::Daily),
+            "period,deposits,deposited,withdrawals,withdrawn,disputes,disputed,chargebacks,charged_back\n\
+             ,1,1.0000,0,0.0000,0,0.0000,0,0.0000\n\
+             2024-06-01,2,15.0000,0,0.0000,0,0.0000,0,0.0000\n\
+             2024-06-02,0,0.0000,1,2.0000,0,0.0000,0,0.0000\n\
+             2024-06-03,0,0.0000,0,0.0000,1,5.0000,1,5.0000\n"
+        );
+        assert_eq!(
+            report(Bucket::Weekly).lines().skip(2).collect::<Vec<_>>(),
+            [
+                "2024-05-27,2,15.0000,1,2.0000,0,0.0000,0,0.0000",
+                "2024-06-03,0,0.0000,0,0.0000,1,5.0000,1,5.0000"
+            ]
+        );
+        assert_eq!(
+            report(Bucket::Monthly).lines().nth(2),
+            Some("2024-06-01,2,15.0000,1,2.0000,1,5.0000,1,5.0000")
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! Flags suspicious activity of the clients, without changing how transactions are applied.
+//!
+//! The analyzer looks for:
+//! - structuring: several deposits just under a reporting threshold,
+//! - rapid cycles: most of a deposit withdrawn shortly after it, using the `timestamp` column,
+//! - a high dispute rate: a large share of the deposits of a client disputed.
+//!
+//! The thresholds are set in the `[aml]` section of the config file.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::amount::AmountValue;
+use crate::transactions::{Amount, ClientId, Transaction, TransactionType, TxId};
+
+/// The thresholds of the analyzer.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmlRules {
+    /// The amount deposits are being kept under.
+    pub structuring_threshold: Amount,
+    /// How far under the threshold, in percent of it, a deposit is "just under" it.
+    pub structuring_margin_percent: Amount,
+    /// How many deposits just under the threshold a client can make before being flagged.
+    pub structuring_count: u32,
+    /// How soon after a deposit a withdrawal is a rapid cycle, in seconds.
+    pub cycle_window_secs: u64,
+    /// How much of the deposit must be withdrawn for a rapid cycle, in percent of it.
+    pub cycle_percent: Amount,
+    /// The largest share of the deposits of a client that can be disputed, in percent.
+    pub max_dispute_rate_percent: Amount,
+    /// How many deposits a client must have for its dispute rate to count.
+    pub min_deposits_for_dispute_rate: u32,
+}
+
+impl Default for AmlRules {
+    fn default() -> Self {
+        AmlRules {
+            structuring_threshold: Amount::from_f64(10_000.0),
+            structuring_margin_percent: Amount::from_f64(10.0),
+            structuring_count: 3,
+            cycle_window_secs: 3_600,
+            cycle_percent: Amount::from_f64(90.0),
+            max_dispute_rate_percent: Amount::from_f64(20.0),
+            min_deposits_for_dispute_rate: 5,
+        }
+    }
+}
+
+/// A kind of suspicious activity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FindingKind {
+    Structuring,
+    RapidCycle,
+    HighDisputeRate,
+}
+
+impl fmt::Display for FindingKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FindingKind::Structuring => write!(f, "structuring"),
+            FindingKind::RapidCycle => write!(f, "rapid_cycle"),
+            FindingKind::HighDisputeRate => write!(f, "high_dispute_rate"),
+        }
+    }
+}
+
+/// Suspicious activity of a client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub client: ClientId,
+    pub kind: FindingKind,
+    /// The transaction that raised the finding, if it was raised by a single one.
+    pub tx_id: Option<TxId>,
+    pub detail: String,
+}
+
+/// What the analyzer knows about a client.
+#[derive(Debug, Default)]
+struct ClientActivity {
+    deposits: u32,
+    disputes: u32,
+    just_under_threshold: u32,
+    // The timestamp, amount and id of the last deposit
+    last_deposit: Option<(u64, Amount, TxId)>,
+}
+
+/// Looks at the transactions as they are applied, see [`PaymentsEngine::with_aml`](crate::PaymentsEngine::with_aml).
+#[derive(Debug, Default)]
+pub(crate) struct AmlAnalyzer {
+    clients: HashMap<ClientId, ClientActivity>,
+    findings: Vec<Finding>,
+}
+
+impl AmlAnalyzer {
+    /// Looks at a transaction, and whether the engine applied it.
+    pub(crate) fn observe(&mut self, rules: &AmlRules, transaction: &Transaction, applied: bool) {
+        let client = transaction.client_id;
+        let activity = self.clients.entry(client).or_default();
+        let amount = transaction.amount.unwrap_or_default();
+
+        match transaction.tx_type {
+            TransactionType::Deposit if applied => {
+                activity.deposits += 1;
+                let floor =
+                    rules.structuring_threshold - rules.structuring_threshold.percent(rules.structuring_margin_percent);
+                if amount >= floor && amount < rules.structuring_threshold {
+                    activity.just_under_threshold += 1;
+                    if activity.just_under_threshold == rules.structuring_count {
+                        self.findings.push(Finding {
+                            client,
+                            kind: FindingKind::Structuring,
+                            tx_id: Some(transaction.tx_id),
+                            detail: format!(
+                                "{} deposits between {:.4} and {:.4}",
+                                activity.just_under_threshold, floor, rules.structuring_threshold
+                            ),
+                        });
+                    }
+                }
+                if let Some(timestamp) = transaction.timestamp {
+                    activity.last_deposit = Some((timestamp, amount, transaction.tx_id));
+                }
+            }
+            TransactionType::Withdrawal if applied => {
+                let Some((timestamp, (deposited_at, deposit, deposit_tx))) =
+                    transaction.timestamp.zip(activity.last_deposit)
+                else {
+                    return;
+                };
+                let elapsed = timestamp.saturating_sub(deposited_at);
+                if elapsed <= rules.cycle_window_secs && amount >= deposit.percent(rules.cycle_percent) {
+                    self.findings.push(Finding {
+                        client,
+                        kind: FindingKind::RapidCycle,
+                        tx_id: Some(transaction.tx_id),
+                        detail: format!(
+                            "withdrew {:.4} {}s after depositing {:.4} in tx {}",
+                            amount, elapsed, deposit, deposit_tx
+                        ),
+                    });
+                    // Only flag a deposit once
+                    activity.last_deposit = None;
+                }
+            }
+            TransactionType::Dispute if applied => activity.disputes += 1,
+            _ => {}
+        }
+    }
+
+    /// The findings so far, the dispute rates being checked as of now.
+    pub(crate) fn findings(&self, rules: &AmlRules) -> Vec<Finding> {
+        let mut findings = self.findings.clone();
+        let mut dispute_rates: Vec<_> = self
+            .clients
+            .iter()
+            .filter(|(_, activity)| activity.deposits > 0 && activity.deposits >= rules.min_deposits_for_dispute_rate)
+            .map(|(&client, activity)| {
+                (
+                    client,
+                    activity,
+                    Amount::from_f64(activity.disputes as f64 * 100.0 / activity.deposits as f64),
+                )
+            })
+            .filter(|(_, _, rate)| *rate > rules.max_dispute_rate_percent)
+            .collect();
+        dispute_rates.sort_by_key(|(client, _, _)| *client);
+        for (client, activity, rate) in dispute_rates {
+            findings.push(Finding {
+                client,
+                kind: FindingKind::HighDisputeRate,
+                tx_id: None,
+                detail: format!(
+                    "{} of {} deposits disputed ({:.1}%)",
+                    activity.disputes, activity.deposits, rate
+                ),
+            });
+        }
+        findings
+    }
+}
+
+/// A row of the findings report.
+#[derive(Debug, Serialize)]
+struct FindingRow<'a> {
+    client: ClientId,
+    finding: String,
+    tx: Option<TxId>,
+    detail: &'a str,
+}
+
+/// Writes the findings as CSV.
+pub fn write_findings<W: Write>(findings: &[Finding], writer: W) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(writer);
+    wtr.write_record(["client", "finding", "tx", "detail"])?;
+    for finding in findings {
+        wtr.serialize(FindingRow {
+            client: finding.client,
+            finding: finding.kind.to_string(),
+            tx: finding.tx_id,
+            detail: &finding.detail,
+        })?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
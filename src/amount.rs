@@ -0,0 +1,513 @@
+//! What the engine needs of an [`Amount`](crate::transactions::Amount), see [`AmountValue`]:
+//! an `f32` by default, an `f64` with the `wide-amounts` feature, or an exact [`MinorUnits`]
+//! with the `fixed-amounts` one, the two features being exclusive. Like the rest of the state machine of the engine, it only
+//! needs `core` and `alloc`.
+//!
+//! The floats are exact to about seven and sixteen significant digits, so a deposit of
+//! 1234567.89 is kept as 1234567.875 in an `f32`, while the minor units of four decimal places
+//! are exact up to 922 trillion, at the cost of twice the memory of an `f32` for each amount.
+
+use alloc::string::String;
+use core::cmp::Ordering;
+use core::fmt;
+use core::iter::Sum;
+use core::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+use core::str::FromStr;
+
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+
+/// The arithmetic the engine does on amounts, so that it does the same on any of them.
+pub trait AmountValue:
+    Copy
+    + Default
+    + PartialOrd
+    + fmt::Debug
+    + fmt::Display
+    + FromStr
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Neg<Output = Self>
+    + AddAssign
+    + SubAssign
+    + Sum
+{
+    /// No funds.
+    const ZERO: Self;
+
+    /// The amount nearest to `value`, e.g. of a rate applied to another amount.
+    fn from_f64(value: f64) -> Self;
+
+    /// The amount as an `f64`, exactly for the amounts of the input.
+    fn to_f64(self) -> f64;
+
+    /// `percent` percent of the amount, e.g. the fee of a withdrawal.
+    fn percent(self, percent: Self) -> Self;
+
+    /// The sum, or `None` if it is out of the range of the amounts, infinite for the floats.
+    fn checked_add(self, other: Self) -> Option<Self>;
+
+    /// The difference, or `None` if it is out of the range of the amounts.
+    fn checked_sub(self, other: Self) -> Option<Self>;
+}
+
+impl AmountValue for f32 {
+    const ZERO: Self = 0.0;
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn percent(self, percent: Self) -> Self {
+        self * percent / 100.0
+    }
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        Some(self + other).filter(|sum| sum.is_finite())
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        Some(self - other).filter(|difference| difference.is_finite())
+    }
+}
+
+impl AmountValue for f64 {
+    const ZERO: Self = 0.0;
+
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn percent(self, percent: Self) -> Self {
+        self * percent / 100.0
+    }
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        Some(self + other).filter(|sum| sum.is_finite())
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        Some(self - other).filter(|difference| difference.is_finite())
+    }
+}
+
+/// An exact amount, as an integer number of ten-thousandths, the precision of the report.
+///
+/// It parses from the decimals of the input without going through a float, and formats
+/// with as many decimal places as asked, rounding half to even below four.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct MinorUnits(pub i64);
+
+impl MinorUnits {
+    /// The number of decimal places.
+    pub const DECIMALS: usize = 4;
+    /// The minor units in one unit of funds.
+    pub const SCALE: i64 = 10_000;
+
+    pub fn abs(self) -> Self {
+        MinorUnits(self.0.saturating_abs())
+    }
+
+    pub fn min(self, other: Self) -> Self {
+        Ord::min(self, other)
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        Ord::max(self, other)
+    }
+
+    /// Always, as there is no infinite or NaN minor units, for the checks of the floats.
+    pub fn is_finite(self) -> bool {
+        true
+    }
+
+    /// Never, see [`is_finite`](MinorUnits::is_finite).
+    pub fn is_nan(self) -> bool {
+        false
+    }
+
+    pub fn total_cmp(&self, other: &Self) -> Ordering {
+        self.cmp(other)
+    }
+
+    pub fn to_le_bytes(self) -> [u8; 8] {
+        self.0.to_le_bytes()
+    }
+
+    pub fn from_le_bytes(bytes: [u8; 8]) -> Self {
+        MinorUnits(i64::from_le_bytes(bytes))
+    }
+}
+
+impl AmountValue for MinorUnits {
+    const ZERO: Self = MinorUnits(0);
+
+    /// Rounded half to even to four decimal places, and saturated to the range of an `i64`.
+    fn from_f64(value: f64) -> Self {
+        let scaled = value * MinorUnits::SCALE as f64;
+        // `as` rounds toward zero and saturates, NaN to 0
+        let units = scaled as i64;
+        let fraction = scaled - units as f64;
+        let away = match fraction.abs() {
+            0.5 => units % 2 != 0,
+            fraction => fraction > 0.5,
+        };
+        MinorUnits(match (away, fraction < 0.0) {
+            (false, _) => units,
+            (true, false) => units.saturating_add(1),
+            (true, true) => units.saturating_sub(1),
+        })
+    }
+
+    fn to_f64(self) -> f64 {
+        self.0 as f64 / MinorUnits::SCALE as f64
+    }
+
+    /// Rounded half to even to four decimal places, without going through a float.
+    fn percent(self, percent: Self) -> Self {
+        let product = i128::from(self.0) * i128::from(percent.0);
+        let divisor = 100 * i128::from(MinorUnits::SCALE);
+        let (quotient, remainder) = (product / divisor, product % divisor);
+        let away = match (remainder.abs() * 2).cmp(&divisor) {
+            Ordering::Greater => true,
+            Ordering::Equal => quotient % 2 != 0,
+            Ordering::Less => false,
+        };
+        let units = match (away, product < 0) {
+            (false, _) => quotient,
+            (true, false) => quotient + 1,
+            (true, true) => quotient - 1,
+        };
+        MinorUnits(units.clamp(i128::from(i64::MIN), i128::from(i64::MAX)) as i64)
+    }
+
+    fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(MinorUnits)
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(MinorUnits)
+    }
+}
+
+// The engine checks the sums that can overflow, see `AmountValue::checked_add`, so that the
+// others overflowing is a bug: they panic then, in release builds too, instead of wrapping
+// around to a balance of the other sign
+const OVERFLOW: &str = "the amount is out of the range of the minor units";
+
+impl Add for MinorUnits {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        AmountValue::checked_add(self, other).expect(OVERFLOW)
+    }
+}
+
+impl Sub for MinorUnits {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        AmountValue::checked_sub(self, other).expect(OVERFLOW)
+    }
+}
+
+impl Neg for MinorUnits {
+    type Output = Self;
+    fn neg(self) -> Self {
+        MinorUnits(self.0.checked_neg().expect(OVERFLOW))
+    }
+}
+
+impl AddAssign for MinorUnits {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for MinorUnits {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
+
+impl Sum for MinorUnits {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(MinorUnits(0), Add::add)
+    }
+}
+
+impl fmt::Display for MinorUnits {
+    /// With the precision of the formatter, e.g. `{:.2}`, or else with the decimal places it
+    /// needs, as a float would be, e.g. `1.5` or `2`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scale = MinorUnits::SCALE.unsigned_abs();
+        let (integer, fraction) = (self.0.unsigned_abs() / scale, self.0.unsigned_abs() % scale);
+        let digits = match f.precision() {
+            Some(precision) if precision < MinorUnits::DECIMALS => {
+                // Rounded half to even, which can carry over to the integer part
+                let dropped = 10u64.pow((MinorUnits::DECIMALS - precision) as u32);
+                let (kept, rest) = (self.0.unsigned_abs() / dropped, self.0.unsigned_abs() % dropped);
+                let kept = match rest * 2 {
+                    twice if twice > dropped || (twice == dropped && kept % 2 == 1) => kept + 1,
+                    _ => kept,
+                };
+                let unit = 10u64.pow(precision as u32);
+                match precision {
+                    0 => alloc::format!("{}", kept),
+                    _ => alloc::format!("{}.{:0width$}", kept / unit, kept % unit, width = precision),
+                }
+            }
+            Some(precision) => alloc::format!(
+                "{}.{:04}{:0<width$}",
+                integer,
+                fraction,
+                "",
+                width = precision - MinorUnits::DECIMALS
+            ),
+            None => {
+                let fraction = alloc::format!("{:04}", fraction);
+                match fraction.trim_end_matches('0') {
+                    "" => alloc::format!("{}", integer),
+                    fraction => alloc::format!("{}.{}", integer, fraction),
+                }
+            }
+        };
+        let negative = self.0 < 0 && digits.bytes().any(|digit| digit.is_ascii_digit() && digit != b'0');
+        f.pad_integral(!negative, "", &digits)
+    }
+}
+
+/// An amount of the tests, written as a float whatever the type of the amounts.
+#[cfg(test)]
+pub(crate) fn amount(value: f64) -> crate::transactions::Amount {
+    AmountValue::from_f64(value)
+}
+
+/// Why a string isn't a [`MinorUnits`] amount.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseMinorUnitsError(String);
+
+impl core::error::Error for ParseMinorUnitsError {}
+
+impl fmt::Display for ParseMinorUnitsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid amount `{}`, expected a decimal number with at most 4 decimal places",
+            self.0
+        )
+    }
+}
+
+impl FromStr for MinorUnits {
+    type Err = ParseMinorUnitsError;
+
+    /// A decimal number, e.g. `-12.5` or `2.5e1`, with digits beyond the fourth decimal place
+    /// only if they are zeros.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseMinorUnitsError(String::from(s));
+        let (negative, digits) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let (digits, exponent) = match digits.split_once(['e', 'E']) {
+            Some((digits, exponent)) => (digits, exponent.parse::<i8>().map_err(|_| error())?),
+            None => (digits, 0),
+        };
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let all_digits = |part: &str| part.bytes().all(|digit| digit.is_ascii_digit());
+        if (integer.is_empty() && fraction.is_empty()) || !all_digits(integer) || !all_digits(fraction) {
+            return Err(error());
+        }
+        // The exponent moves the decimal point, padding either part with zeros as it goes
+        let shifted;
+        let (integer, fraction) = if exponent == 0 {
+            (integer, fraction)
+        } else {
+            let point = integer.len() as isize + isize::from(exponent);
+            let length = (integer.len() + fraction.len()) as isize;
+            let zeros = |count: isize| "0".repeat(count.max(0) as usize);
+            shifted = alloc::format!("{}{}{}{}", zeros(-point), integer, fraction, zeros(point - length));
+            shifted.split_at(point.max(0) as usize)
+        };
+        let (fraction, beyond) = fraction.split_at(fraction.len().min(MinorUnits::DECIMALS));
+        if beyond.bytes().any(|digit| digit != b'0') {
+            return Err(error());
+        }
+        let mut units: i64 = 0;
+        let padded = fraction
+            .bytes()
+            .chain(core::iter::repeat(b'0'))
+            .take(MinorUnits::DECIMALS);
+        for digit in integer.bytes().chain(padded) {
+            units = units
+                .checked_mul(10)
+                .and_then(|units| units.checked_add(i64::from(digit - b'0')))
+                .ok_or_else(error)?;
+        }
+        Ok(MinorUnits(if negative { -units } else { units }))
+    }
+}
+
+impl Serialize for MinorUnits {
+    /// As a number, the nearest `f64` to it.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.to_f64())
+    }
+}
+
+/// The largest numbers read as the shortest decimal of their `f64`, the ones of up to four
+/// decimal places within the 15 significant digits an `f64` keeps.
+const MAX_EXACT_F64: f64 = 1e11;
+
+struct MinorUnitsVisitor;
+
+impl Visitor<'_> for MinorUnitsVisitor {
+    type Value = MinorUnits;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a decimal amount")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<MinorUnits, E> {
+        value.trim().parse().map_err(E::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<MinorUnits, E> {
+        value
+            .checked_mul(MinorUnits::SCALE)
+            .map(MinorUnits)
+            .ok_or_else(|| E::custom("the amount is too large"))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<MinorUnits, E> {
+        let value = i64::try_from(value).map_err(|_| E::custom("the amount is too large"))?;
+        self.visit_i64(value)
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<MinorUnits, E> {
+        // Beyond, the `f64` may already have lost digits of the number, e.g. of 922337203685477.5807
+        if value.abs() >= MAX_EXACT_F64 {
+            return Err(E::custom(alloc::format!(
+                "The amount {} is too large to be read exactly from a number, it must be a string",
+                value
+            )));
+        }
+        self.visit_str(&alloc::format!("{}", value))
+    }
+}
+
+impl<'de> Deserialize<'de> for MinorUnits {
+    /// From a string or a number exactly, a number by the shortest decimal of its `f64`, so the same
+    /// amounts are rejected whether they are quoted or not. The numbers from 100 billion can't be
+    /// read exactly, so they have to be strings.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(MinorUnitsVisitor)
+    }
+}
+
+/// An optional amount read from the text of a CSV field, which the `csv` crate would otherwise
+/// read as an `f64` if it looks like one, losing the digits beyond its precision.
+#[cfg(feature = "fixed-amounts")]
+pub(crate) fn deserialize_text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<MinorUnits>, D::Error> {
+    struct Text(MinorUnits);
+
+    impl<'de> Deserialize<'de> for Text {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_str(MinorUnitsVisitor).map(Text)
+        }
+    }
+
+    Ok(Option::<Text>::deserialize(deserializer)?.map(|Text(amount)| amount))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::string::ToString;
+
+    use super::{AmountValue, MinorUnits};
+
+    #[test]
+    fn test_minor_units() {
+        let amount: MinorUnits = "1234567.89".parse().unwrap();
+        assert_eq!(amount, MinorUnits(12_345_678_900));
+        assert_eq!(format!("{:.4}", amount), "1234567.8900");
+        assert_eq!(
+            (amount.to_string(), format!("{:.6}", amount)),
+            ("1234567.89".into(), "1234567.890000".into())
+        );
+        for (input, units) in [
+            ("-0.5", -5_000),
+            ("+2", 20_000),
+            (".25", 2_500),
+            ("3.", 30_000),
+            ("1.000100", 10_001),
+            ("1e3", 10_000_000),
+            ("2.5E-3", 25),
+            ("-0.0012e2", -1_200),
+        ] {
+            assert_eq!(input.parse(), Ok(MinorUnits(units)));
+        }
+        for input in [
+            "",
+            "-",
+            ".",
+            "1.00001",
+            "1e-5",
+            "1e",
+            "NaN",
+            "1.2.3",
+            "922337203685477.5808",
+            "1e15",
+        ] {
+            assert!(input.parse::<MinorUnits>().is_err(), "{}", input);
+        }
+
+        // Rounded half to even below four decimal places, without a negative zero
+        let rounded = [
+            (1_250, "0.12"),
+            (1_350, "0.14"),
+            (-19_990, "-2.00"),
+            (-49, "0.00"),
+            (99_950, "10.00"),
+        ];
+        for (units, rounded) in rounded {
+            assert_eq!(format!("{:.2}", MinorUnits(units)), rounded);
+        }
+        assert_eq!(format!("{:.0}", MinorUnits(25_000)), "2");
+        assert_eq!(MinorUnits(-20_000).to_string(), "-2");
+
+        assert_eq!(MinorUnits::from_f64(0.00004), MinorUnits(0));
+        assert_eq!(MinorUnits::from_f64(-0.00016), MinorUnits(-2));
+        assert_eq!(MinorUnits::from_f64(f64::NAN), MinorUnits(0));
+        assert_eq!(MinorUnits::from_f64(1234567.89), amount);
+        assert_eq!(MinorUnits(-15_000).to_f64(), -1.5);
+        assert_eq!(MinorUnits(20_500).percent(MinorUnits(10_000)), MinorUnits(205));
+        assert_eq!(MinorUnits(12_345).percent(MinorUnits(5_000)), MinorUnits(62));
+        assert_eq!(MinorUnits(-12_355).percent(MinorUnits(5_000)), MinorUnits(-62));
+        let one_percent = |units| MinorUnits(units).percent(MinorUnits(10_000));
+        assert_eq!((one_percent(50), one_percent(150)), (MinorUnits(0), MinorUnits(2)));
+        let total: MinorUnits = [MinorUnits(1), MinorUnits(2)].into_iter().sum();
+
+        // Numbers are read as exactly as strings
+        for input in ["1234567.89", "\"1234567.89\"", "1234567.8900"] {
+            assert_eq!(serde_json::from_str::<MinorUnits>(input).unwrap(), amount, "{}", input);
+        }
+        assert_eq!(serde_json::from_str::<MinorUnits>("-3").unwrap(), MinorUnits(-30_000));
+        assert!(serde_json::from_str::<MinorUnits>("0.00001").is_err());
+        assert_eq!((total - MinorUnits(4)).abs(), MinorUnits(1));
+
+        let max = MinorUnits(i64::MAX);
+        assert_eq!(max.checked_add(MinorUnits(1)), None);
+        assert_eq!(MinorUnits(i64::MIN).checked_sub(MinorUnits(1)), None);
+        assert_eq!(max.checked_sub(MinorUnits(1)), Some(MinorUnits(i64::MAX - 1)));
+    }
+}
@@ -0,0 +1,127 @@
+//! Applies transactions straight from Arrow record batches.
+//!
+//! The batch must have `type`, `client`, `tx` and `amount` columns. Any
+//! string, integer or floating point column types that can be cast to the
+//! fields of a [`Transaction`] are accepted.
+
+use std::error::Error;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::ArrowPrimitiveType;
+#[cfg(not(any(feature = "wide-amounts", feature = "fixed-amounts")))]
+use arrow_array::types::Float32Type as AmountType;
+#[cfg(any(feature = "wide-amounts", feature = "fixed-amounts"))]
+use arrow_array::types::Float64Type as AmountType;
+#[cfg(not(feature = "wide-ids"))]
+use arrow_array::types::{UInt16Type as ClientIdType, UInt32Type as TxIdType};
+#[cfg(feature = "wide-ids")]
+use arrow_array::types::{UInt32Type as ClientIdType, UInt64Type as TxIdType};
+use arrow_array::{Array, ArrayRef, RecordBatch};
+use arrow_cast::cast;
+use arrow_schema::DataType;
+
+use crate::amount::AmountValue;
+use crate::custom_errors::{InputSchemaError, SchemaErrorType};
+use crate::engine::PaymentsEngine;
+use crate::transactions::{Amount, Transaction, TransactionType};
+
+impl PaymentsEngine {
+    /// Applies all the transactions in a record batch, in row order.
+    /// The columns are cast once per batch, so there is no per-row deserialization.
+    pub fn apply_record_batch(&mut self, batch: &RecordBatch) -> Result<(), Box<dyn Error>> {
+        let tx_types = column(batch, "type", &DataType::Utf8)?;
+        let tx_types = tx_types.as_string::<i32>();
+        let client_ids = column(batch, "client", &ClientIdType::DATA_TYPE)?;
+        let client_ids = client_ids.as_primitive::<ClientIdType>();
+        let tx_ids = column(batch, "tx", &TxIdType::DATA_TYPE)?;
+        let tx_ids = tx_ids.as_primitive::<TxIdType>();
+        let amounts = column(batch, "amount", &AmountType::DATA_TYPE)?;
+        let amounts = amounts.as_primitive::<AmountType>();
+
+        for row in 0..batch.num_rows() {
+            // Out of range values are cast to nulls, so they are caught here too
+            if tx_types.is_null(row) {
+                return Err(Box::new(missing_value("type")));
+            }
+            if client_ids.is_null(row) {
+                return Err(Box::new(missing_value("client")));
+            }
+            if tx_ids.is_null(row) {
+                return Err(Box::new(missing_value("tx")));
+            }
+
+            // Rejected transactions are counted in the stats
+            let _ = self.apply(&Transaction {
+                tx_type: tx_types.value(row).trim().parse::<TransactionType>()?,
+                reason: None,
+                timestamp: None,
+                settles_at: None,
+                client_id: client_ids.value(row),
+                tx_id: tx_ids.value(row),
+                amount: if amounts.is_null(row) {
+                    None
+                } else {
+                    Some(Amount::from_f64(amounts.value(row).to_f64()))
+                },
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Get a column by name, cast to the type we need.
+fn column(batch: &RecordBatch, name: &'static str, data_type: &DataType) -> Result<ArrayRef, InputSchemaError> {
+    let column = batch.column_by_name(name).ok_or(InputSchemaError {
+        error_type: SchemaErrorType::MissingColumn(name),
+    })?;
+    cast(column, data_type).map_err(|_| InputSchemaError {
+        error_type: SchemaErrorType::InvalidColumnType(name),
+    })
+}
+
+fn missing_value(column: &'static str) -> InputSchemaError {
+    InputSchemaError {
+        error_type: SchemaErrorType::MissingValue(column),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_array::{Float32Array, RecordBatch, StringArray, UInt16Array, UInt32Array};
+
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_missing_column() {
+        let batch = RecordBatch::try_from_iter(vec![
+            ("type", Arc::new(StringArray::from(vec!["deposit"])) as _),
+            ("client", Arc::new(UInt16Array::from(vec![1])) as _),
+            ("amount", Arc::new(Float32Array::from(vec![1.0])) as _),
+        ])
+        .unwrap();
+
+        let err = PaymentsEngine::new().apply_record_batch(&batch).unwrap_err();
+        assert_eq!(err.to_string(), "The input is missing the `tx` column");
+    }
+
+    #[test]
+    fn test_record_batch_dispute() {
+        let batch = RecordBatch::try_from_iter(vec![
+            ("type", Arc::new(StringArray::from(vec!["deposit", "dispute"])) as _),
+            ("client", Arc::new(UInt16Array::from(vec![1, 1])) as _),
+            ("tx", Arc::new(UInt32Array::from(vec![1, 1])) as _),
+            ("amount", Arc::new(Float32Array::from(vec![Some(1.0), None])) as _),
+        ])
+        .unwrap();
+
+        let mut engine = PaymentsEngine::new();
+        engine.apply_record_batch(&batch).unwrap();
+        assert_eq!(
+            engine.report().unwrap(),
+            "client, available, held, pending, total, locked\n1, 0.0000, 1.0000, 0.0000, 1.0000, false"
+        );
+    }
+}
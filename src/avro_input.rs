@@ -0,0 +1,160 @@
+//! Reads transactions from Avro container files.
+//!
+//! The schema the files were written with must be resolvable to
+//! [`TRANSACTION_SCHEMA`], following the Avro schema resolution rules
+//! (e.g. an `int` tx id or a `float` amount are promoted).
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use apache_avro::schema_compatibility::SchemaCompatibility;
+use apache_avro::{from_value, Reader, Schema};
+use serde::Deserialize;
+
+use crate::amount::AmountValue;
+use crate::custom_errors::{InputSchemaError, SchemaErrorType};
+use crate::engine::PaymentsEngine;
+use crate::transactions::{Amount, ClientId, Transaction, TransactionType, TxId};
+
+/// The Avro schema agreed with the partners for transaction records.
+pub const TRANSACTION_SCHEMA: &str = r#"
+{
+    "type": "record",
+    "name": "Transaction",
+    "fields": [
+        {"name": "type", "type": "string"},
+        {"name": "client", "type": "int"},
+        {"name": "tx", "type": "long"},
+        {"name": "amount", "type": ["null", "double"], "default": null}
+    ]
+}
+"#;
+
+/// A record as read with the agreed schema, before checking the ranges of the ids.
+#[derive(Debug, Deserialize)]
+struct AvroTransaction {
+    #[serde(rename = "type")]
+    tx_type: String,
+    client: i32,
+    tx: i64,
+    amount: Option<f64>,
+}
+
+impl TryFrom<AvroTransaction> for Transaction {
+    type Error = Box<dyn Error>;
+    fn try_from(record: AvroTransaction) -> Result<Self, Self::Error> {
+        let out_of_range = |column| InputSchemaError {
+            error_type: SchemaErrorType::OutOfRange(column),
+        };
+        Ok(Transaction {
+            tx_type: record.tx_type.trim().parse::<TransactionType>()?,
+            reason: None,
+            timestamp: None,
+            settles_at: None,
+            client_id: ClientId::try_from(record.client).map_err(|_| out_of_range("client"))?,
+            tx_id: TxId::try_from(record.tx).map_err(|_| out_of_range("tx"))?,
+            amount: record.amount.map(Amount::from_f64),
+        })
+    }
+}
+
+impl PaymentsEngine {
+    /// Applies all the transactions in an Avro container file.
+    pub fn process_avro(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        self.process_avro_reader(File::open(path)?)
+    }
+
+    /// Applies all the transactions read as an Avro container from any source.
+    pub fn process_avro_reader<R: Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        let schema = Schema::parse_str(TRANSACTION_SCHEMA)?;
+        let reader = Reader::builder(BufReader::new(reader)).reader_schema(&schema).build()?;
+
+        // Check the whole schema upfront, instead of failing on the first record
+        if let Err(err) = SchemaCompatibility::can_read(reader.writer_schema(), &schema) {
+            return Err(Box::new(InputSchemaError {
+                error_type: SchemaErrorType::IncompatibleSchema(err.to_string()),
+            }));
+        }
+
+        for value in reader {
+            let record: AvroTransaction = from_value(&value?)?;
+            // Rejected transactions are counted in the stats
+            let _ = self.apply(&Transaction::try_from(record)?)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::path::{Path, PathBuf};
+
+    use apache_avro::types::Record;
+    use apache_avro::{Schema, Writer};
+
+    use crate::engine::PaymentsEngine;
+
+    fn write_avro(name: &str, schema: &str, records: &[(&str, i32, i32, Option<f32>)]) -> PathBuf {
+        let schema = Schema::parse_str(schema).unwrap();
+        let path = std::env::temp_dir().join(name);
+        let mut writer = Writer::new(&schema, File::create(&path).unwrap()).unwrap();
+        for &(tx_type, client, tx, amount) in records {
+            let mut record = Record::new(&schema).unwrap();
+            record.put("type", tx_type);
+            record.put("client", client);
+            record.put("tx", tx);
+            record.put("amount", amount);
+            writer.append_value(record).unwrap();
+        }
+        writer.flush().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_avro_matches_csv() {
+        // Partners may use narrower types than the agreed schema
+        let schema = r#"{"type": "record", "name": "Transaction", "fields": [
+            {"name": "type", "type": "string"},
+            {"name": "client", "type": "int"},
+            {"name": "tx", "type": "int"},
+            {"name": "amount", "type": ["null", "float"]}
+        ]}"#;
+        let path = write_avro(
+            "payments_engine_dispute_resolve.avro",
+            schema,
+            &[
+                ("deposit", 1, 1, Some(1.0)),
+                ("dispute", 1, 1, None),
+                ("resolve", 1, 1, None),
+            ],
+        );
+
+        let mut engine = PaymentsEngine::new();
+        engine.process_avro(&path).unwrap();
+        assert_eq!(
+            engine.report().unwrap(),
+            crate::process_csv(Path::new("sample_files/dispute_resolve.csv"))
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_avro_incompatible_schema() {
+        let schema = r#"{"type": "record", "name": "Transaction", "fields": [
+            {"name": "type", "type": "string"},
+            {"name": "client", "type": "string"},
+            {"name": "tx", "type": "int"},
+            {"name": "amount", "type": ["null", "float"]}
+        ]}"#;
+        let path = write_avro("payments_engine_incompatible.avro", schema, &[]);
+
+        let err = PaymentsEngine::new().process_avro(&path).unwrap_err();
+        assert!(err.to_string().starts_with("The input schema is incompatible"));
+    }
+}
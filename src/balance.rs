@@ -0,0 +1,158 @@
+//! The balance of an account, and what it can still be used for. Like the rest of the state
+//! machine of the engine, it only needs `core` and `alloc`, see the `std` feature.
+
+use alloc::string::{String, ToString};
+use core::fmt;
+use core::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::amount::AmountValue;
+use crate::transactions::{Amount, ClientId};
+
+/// A balance serializes with the columns of the report, `total` and `locked` included, and its
+/// `status`. When deserializing, `total` is ignored since it is computed, `pending` is optional,
+/// and without a `status` the account is locked or active depending on `locked`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(into = "AccountBalanceFields", from = "AccountBalanceFields")]
+pub struct AccountBalance {
+    pub client: ClientId,
+    pub available: Amount,
+    pub held: Amount,
+    /// Deposited funds that haven't settled yet, see [`Transaction::settles_at`](crate::Transaction::settles_at).
+    pub pending: Amount,
+    pub status: AccountStatus,
+}
+
+/// The fields of a serialized [`AccountBalance`].
+#[derive(Serialize, Deserialize)]
+struct AccountBalanceFields {
+    client: ClientId,
+    available: Amount,
+    held: Amount,
+    #[serde(default)]
+    pending: Amount,
+    #[serde(default, skip_deserializing)]
+    total: Amount,
+    locked: bool,
+    #[serde(default)]
+    status: Option<AccountStatus>,
+}
+
+impl From<AccountBalance> for AccountBalanceFields {
+    fn from(account_balance: AccountBalance) -> Self {
+        AccountBalanceFields {
+            client: account_balance.client,
+            available: account_balance.available,
+            held: account_balance.held,
+            pending: account_balance.pending,
+            total: account_balance.total(),
+            locked: account_balance.is_locked(),
+            status: Some(account_balance.status),
+        }
+    }
+}
+
+impl From<AccountBalanceFields> for AccountBalance {
+    fn from(fields: AccountBalanceFields) -> Self {
+        AccountBalance {
+            client: fields.client,
+            available: fields.available,
+            held: fields.held,
+            pending: fields.pending,
+            status: fields.status.unwrap_or(match fields.locked {
+                true => AccountStatus::Locked,
+                false => AccountStatus::Active,
+            }),
+        }
+    }
+}
+
+/// What an account can still be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountStatus {
+    #[default]
+    Active,
+    /// Frozen by a `freeze` transaction, until an `unfreeze` one.
+    /// Withdrawals are ignored, but deposits and disputes are still applied.
+    Frozen,
+    /// Locked by a chargeback. All the later transactions of the client are ignored.
+    Locked,
+    /// Closed by a `close` transaction, its available funds paid out.
+    /// All the later transactions of the client are ignored.
+    Closed,
+}
+
+impl AccountStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Frozen => "frozen",
+            AccountStatus::Locked => "locked",
+            AccountStatus::Closed => "closed",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AccountStatusFromStrError(String);
+
+impl core::error::Error for AccountStatusFromStrError {}
+
+impl fmt::Display for AccountStatusFromStrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid account status `{}`", self.0)
+    }
+}
+
+impl FromStr for AccountStatus {
+    type Err = AccountStatusFromStrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(AccountStatus::Active),
+            "frozen" => Ok(AccountStatus::Frozen),
+            "locked" => Ok(AccountStatus::Locked),
+            "closed" => Ok(AccountStatus::Closed),
+            _ => Err(AccountStatusFromStrError(s.to_string())),
+        }
+    }
+}
+
+impl AccountBalance {
+    /// A new, empty and active account.
+    pub fn new(client: ClientId) -> Self {
+        AccountBalance {
+            client,
+            available: Amount::ZERO,
+            held: Amount::ZERO,
+            pending: Amount::ZERO,
+            status: AccountStatus::Active,
+        }
+    }
+
+    /// Whether the account was locked by a chargeback, as the report shows it.
+    pub fn is_locked(&self) -> bool {
+        self.status == AccountStatus::Locked
+    }
+
+    /// All the funds of the account: available, held and pending.
+    pub fn total(&self) -> Amount {
+        self.available + self.held + self.pending
+    }
+}
+
+impl fmt::Display for AccountBalance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, {:.4}, {:.4}, {:.4}, {:.4}, {}",
+            self.client,
+            self.available,
+            self.held,
+            self.pending,
+            self.total(),
+            self.is_locked()
+        )
+    }
+}
@@ -0,0 +1,202 @@
+//! Processes a directory of input files against the same state, e.g. one file per day.
+
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::custom_errors::FileError;
+use crate::engine::PaymentsEngine;
+use crate::input::InputFormat;
+
+/// What happened when processing one of the files of a directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManifestEntry {
+    pub file: String,
+    /// `applied`, or `rolled_back` if it failed halfway through and was skipped.
+    pub status: &'static str,
+    pub rows: u64,
+    pub rejected: u64,
+    /// The state hash after processing this file and all the ones before it, or after rolling
+    /// it back.
+    pub state_hash: String,
+}
+
+/// The files of a directory in the given format, in lexical order.
+/// Hidden files and subdirectories are skipped.
+pub fn discover_files(dir: &Path, format: InputFormat) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        let matches_format = path.extension().is_some_and(|extension| {
+            format
+                .extensions()
+                .iter()
+                .any(|&format_extension| extension == format_extension)
+        });
+        if entry.file_type()?.is_file() && !hidden && matches_format {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+impl PaymentsEngine {
+    /// Applies all the files of a directory, in lexical order, and writes a CSV manifest
+    /// with one entry per file. Each entry is flushed as soon as its file is processed,
+    /// so the manifest of an aborted run still records the files that were completed.
+    ///
+    /// With `skip_failed`, a file that fails halfway through is rolled back to a savepoint set
+    /// before it, see [`PaymentsEngine::savepoint`], and the next files are still applied. The
+    /// skipped files are returned with their errors, and are in the manifest as `rolled_back`.
+    pub fn process_dir<W: Write>(
+        &mut self,
+        dir: &Path,
+        format: InputFormat,
+        manifest: W,
+        skip_failed: bool,
+    ) -> Result<Vec<FileError>, Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_writer(manifest);
+        let mut skipped = vec![];
+
+        for path in discover_files(dir, format)? {
+            let before = self.stats().clone();
+            let file = path.to_string_lossy().into_owned();
+            if skip_failed {
+                self.savepoint(&file);
+            }
+            let processed = self.process_input(&file, format);
+            if skip_failed {
+                if processed.is_err() {
+                    self.rollback_to(&file)?;
+                }
+                self.release_savepoint(&file)?;
+            }
+            let status = match processed {
+                Ok(()) => "applied",
+                Err(error) if skip_failed => {
+                    skipped.push(FileError { file, error });
+                    "rolled_back"
+                }
+                Err(error) => return Err(Box::new(FileError { file, error })),
+            };
+
+            wtr.serialize(ManifestEntry {
+                file: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                status,
+                rows: self.stats().processed - before.processed,
+                rejected: self.stats().rejected - before.rejected,
+                state_hash: self.state_hash()?,
+            })?;
+            wtr.flush()?;
+        }
+
+        Ok(skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::amount::amount;
+    use crate::engine::PaymentsEngine;
+    use crate::input::InputFormat;
+    use crate::store::MemoryDedupStore;
+
+    #[test]
+    fn test_process_dir() {
+        let dir = std::env::temp_dir().join("payments_engine_process_dir");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("day-02.csv"),
+            "type,client,tx,amount\ndispute, 1, 1\nwithdrawal, 1, 2, 5.0\n",
+        )
+        .unwrap();
+        fs::write(dir.join("day-01.csv"), "type,client,tx,amount\ndeposit, 1, 1, 1.0\n").unwrap();
+        fs::write(dir.join("notes.txt"), "not a transactions file").unwrap();
+
+        let mut manifest = vec![];
+        let mut engine = PaymentsEngine::new();
+        assert!(engine
+            .process_dir(&dir, InputFormat::Csv, &mut manifest, false)
+            .unwrap()
+            .is_empty());
+
+        let mut single_file_engine = PaymentsEngine::new();
+        single_file_engine
+            .process_csv(Path::new("sample_files/dispute.csv"))
+            .unwrap();
+
+        let manifest = String::from_utf8(manifest).unwrap();
+        let lines: Vec<&str> = manifest.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "file,status,rows,rejected,state_hash");
+        assert!(lines[1].starts_with("day-01.csv,applied,1,0,"));
+        assert_eq!(
+            lines[2],
+            format!("day-02.csv,applied,2,1,{}", single_file_engine.state_hash().unwrap())
+        );
+
+        // A corrupt file is backed out, and the next ones still applied
+        fs::write(
+            dir.join("day-03.csv"),
+            "type,client,tx,amount
+deposit, 2, 3, 4.0
+deposit, 2, 4
+",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("day-04.csv"),
+            "type,client,tx,amount
+deposit, 3, 5, 1.0
+",
+        )
+        .unwrap();
+        let mut engine = PaymentsEngine::new();
+        assert!(engine.process_dir(&dir, InputFormat::Csv, vec![], false).is_err());
+        let mut manifest = vec![];
+        let mut engine = PaymentsEngine::new();
+        let skipped = engine.process_dir(&dir, InputFormat::Csv, &mut manifest, true).unwrap();
+        assert_eq!(skipped.len(), 1);
+        assert!(skipped[0].file.ends_with("day-03.csv"));
+        assert!(engine.account(2).unwrap().is_none());
+        assert_eq!(engine.account(3).unwrap().unwrap().available, amount(1.0));
+        let manifest = String::from_utf8(manifest).unwrap();
+        assert_eq!(manifest.lines().count(), 5);
+        assert!(manifest
+            .lines()
+            .nth(3)
+            .unwrap()
+            .starts_with("day-03.csv,rolled_back,1,0,"));
+        assert!(engine.rollback_to("day-05.csv").is_err());
+    }
+
+    #[test]
+    fn test_process_dir_dedup() {
+        let dir = std::env::temp_dir().join("payments_engine_process_dir_dedup");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.csv"), "type,client,tx,amount\ndeposit, 1, 1, 5.0\n").unwrap();
+        fs::write(
+            dir.join("b.csv"),
+            "type,client,tx,amount\ndeposit, 1, 2, 3.0\ndeposit, 1, 3\n",
+        )
+        .unwrap();
+        fs::write(dir.join("c.csv"), "type,client,tx,amount\ndeposit, 1, 2, 3.0\n").unwrap();
+
+        // The deposit of the file rolled back isn't a duplicate when resubmitted
+        let mut engine = PaymentsEngine::new().with_dedup(Box::new(MemoryDedupStore::default()));
+        let skipped = engine.process_dir(&dir, InputFormat::Csv, vec![], true).unwrap();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(engine.account(1).unwrap().unwrap().available, amount(8.0));
+    }
+}
@@ -0,0 +1,152 @@
+//! Synthetic transactions to measure the engine with, see `payments-engine bench`.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::transactions::{ClientId, TxId};
+
+/// A small, fast and deterministic pseudo random number generator (SplitMix64).
+#[derive(Debug)]
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..bound`.
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+/// A CSV with `rows` transactions of up to `clients` clients.
+///
+/// Most of them are deposits and withdrawals, and about one in fifty disputes an earlier
+/// deposit, which is then resolved or charged back. The same `seed` gives the same data.
+pub fn generate_csv(rows: u64, clients: ClientId, seed: u64) -> Vec<u8> {
+    let mut rng = SplitMix64(seed);
+    let clients = u64::from(clients).max(1);
+    let mut csv = Vec::with_capacity(rows as usize * 32);
+    csv.extend_from_slice(b"type, client, tx, amount\n");
+    // The deposits that can still be disputed, and the ones being disputed
+    let mut deposits: Vec<(u64, TxId)> = vec![];
+    let mut disputes: Vec<(u64, TxId)> = vec![];
+
+    for row in 0..rows {
+        let tx = (row + 1) as TxId;
+        let roll = rng.below(100);
+        let line = if roll < 2 && !deposits.is_empty() {
+            let (client, deposit) = deposits.swap_remove(rng.below(deposits.len() as u64) as usize);
+            disputes.push((client, deposit));
+            format!("dispute, {}, {},\n", client, deposit)
+        } else if roll < 4 && !disputes.is_empty() {
+            let (client, deposit) = disputes.swap_remove(rng.below(disputes.len() as u64) as usize);
+            let tx_type = if roll == 2 { "resolve" } else { "chargeback" };
+            format!("{}, {}, {},\n", tx_type, client, deposit)
+        } else {
+            let client = 1 + rng.below(clients);
+            let amount = rng.below(1_000_000) as f64 / 100.0;
+            if roll < 60 {
+                // Only a sample of the deposits is kept around to dispute
+                if deposits.len() < 10_000 {
+                    deposits.push((client, tx));
+                }
+                format!("deposit, {}, {}, {:.2}\n", client, tx, amount)
+            } else {
+                format!("withdrawal, {}, {}, {:.2}\n", client, tx, amount / 2.0)
+            }
+        };
+        csv.extend_from_slice(line.as_bytes());
+    }
+    csv
+}
+
+/// A size in the `/proc/self/status` of this process, in bytes. Only Linux has it.
+fn status_bytes(field: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with(field))?;
+    let kilobytes: u64 = line
+        .trim_start_matches(field)
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// The resident set size of this process, where the platform tells it.
+pub fn rss_bytes() -> Option<u64> {
+    status_bytes("VmRSS:")
+}
+
+/// The peak resident set size of this process, since it started or since
+/// [`reset_peak_rss`], where the platform tells it.
+pub fn peak_rss_bytes() -> Option<u64> {
+    status_bytes("VmHWM:")
+}
+
+/// Starts the peak resident set size over from the current one, returning whether the
+/// platform allows it, e.g. to leave out what was allocated before measuring.
+pub fn reset_peak_rss() -> bool {
+    std::fs::write("/proc/self/clear_refs", "5").is_ok()
+}
+
+/// What a benchmark run measured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchReport {
+    pub rows: u64,
+    pub elapsed: Duration,
+    /// How much the resident set size grew at its peak while processing, over the one before,
+    /// so that the generated input isn't counted, if the platform tells it.
+    pub peak_memory_bytes: Option<u64>,
+    /// The allocations while processing, if they were counted.
+    pub allocations: Option<u64>,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "rows: {}", self.rows)?;
+        writeln!(f, "elapsed: {:.3}s", self.elapsed.as_secs_f64())?;
+        writeln!(
+            f,
+            "rows/sec: {:.0}",
+            self.rows as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+        )?;
+        match self.peak_memory_bytes {
+            Some(bytes) => writeln!(f, "peak memory: {:.1} MiB", bytes as f64 / (1024.0 * 1024.0))?,
+            None => writeln!(f, "peak memory: unknown")?,
+        }
+        match self.allocations {
+            Some(allocations) => write!(f, "allocations: {}", allocations),
+            None => write!(f, "allocations: unknown"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate_csv;
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_generate_csv() {
+        let csv = generate_csv(10_000, 100, 7);
+        assert_eq!(csv, generate_csv(10_000, 100, 7));
+
+        let mut engine = PaymentsEngine::new();
+        engine.process_csv_reader(csv.as_slice()).unwrap();
+        assert_eq!(engine.stats().processed, 10_000);
+        assert!(engine.balances().unwrap().len() <= 100);
+        assert!(engine
+            .balances()
+            .unwrap()
+            .iter()
+            .any(|account_balance| account_balance.is_locked()));
+    }
+}
@@ -0,0 +1,180 @@
+//! Builds an engine with all of its options at once, instead of chaining the `with_*` methods.
+
+use crate::clients::ClientDirectory;
+use crate::config::{EngineConfig, OverAvailable};
+use crate::engine::{Observer, PaymentsEngine};
+use crate::store::{AccountStore, DedupStore, MemoryAccountStore, MemoryTxStore, TxStore};
+
+/// The options of an engine, see [`PaymentsEngine::builder`].
+pub struct EngineBuilder {
+    config: EngineConfig,
+    // Applied over the config, whenever it is set
+    admin_ops: Option<bool>,
+    strict: Option<bool>,
+    disputes_over_available: Option<OverAvailable>,
+    accounts: Box<dyn AccountStore>,
+    transactions: Box<dyn TxStore>,
+    dedup: Option<Box<dyn DedupStore>>,
+    observers: Vec<Box<dyn Observer>>,
+    clients: Option<ClientDirectory>,
+    aml: bool,
+    journal: bool,
+    rejections: bool,
+}
+
+impl PaymentsEngine {
+    /// The options of a new engine, by default the same as [`PaymentsEngine::new`].
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder {
+            config: EngineConfig::default(),
+            admin_ops: None,
+            strict: None,
+            disputes_over_available: None,
+            accounts: Box::new(MemoryAccountStore::default()),
+            transactions: Box::new(MemoryTxStore::default()),
+            dedup: None,
+            observers: vec![],
+            clients: None,
+            aml: false,
+            journal: false,
+            rejections: false,
+        }
+    }
+}
+
+impl EngineBuilder {
+    /// Uses the given settings, e.g. read from a config file, instead of the default ones.
+    /// The other settings of the builder change them, whether they are set before or after.
+    pub fn config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Whether admin operations, i.e. `adjustment`, `freeze` and `unfreeze` transactions, are allowed.
+    pub fn admin_ops(mut self, admin_ops: bool) -> Self {
+        self.admin_ops = Some(admin_ops);
+        self
+    }
+
+    /// Whether a malformed CSV record fails the processing, which it does by default,
+    /// or is skipped, see [`CsvOptions::lenient`](crate::csv_input::CsvOptions::lenient).
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = Some(strict);
+        self
+    }
+
+    /// What a dispute of more than the available funds does, see
+    /// [`DisputeRules::over_available`](crate::config::DisputeRules::over_available).
+    pub fn disputes_over_available(mut self, policy: OverAvailable) -> Self {
+        self.disputes_over_available = Some(policy);
+        self
+    }
+
+    /// Keeps the account balances in the given store.
+    pub fn account_store(mut self, accounts: Box<dyn AccountStore>) -> Self {
+        self.accounts = accounts;
+        self
+    }
+
+    /// Keeps the transactions that can be disputed, reversed or settled in the given store.
+    pub fn tx_store(mut self, transactions: Box<dyn TxStore>) -> Self {
+        self.transactions = transactions;
+        self
+    }
+
+    /// See [`PaymentsEngine::with_dedup`].
+    pub fn dedup_store(mut self, dedup: Box<dyn DedupStore>) -> Self {
+        self.dedup = Some(dedup);
+        self
+    }
+
+    /// Adds an observer, see [`PaymentsEngine::with_observer`].
+    pub fn observer(mut self, observer: Box<dyn Observer>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// See [`PaymentsEngine::with_clients`].
+    pub fn clients(mut self, clients: ClientDirectory) -> Self {
+        self.clients = Some(clients);
+        self
+    }
+
+    /// See [`PaymentsEngine::with_aml`].
+    pub fn aml(mut self, aml: bool) -> Self {
+        self.aml = aml;
+        self
+    }
+
+    /// See [`PaymentsEngine::with_journal`].
+    pub fn journal(mut self, journal: bool) -> Self {
+        self.journal = journal;
+        self
+    }
+
+    /// See [`PaymentsEngine::with_rejections`].
+    pub fn rejections(mut self, rejections: bool) -> Self {
+        self.rejections = rejections;
+        self
+    }
+
+    /// The engine with all the options.
+    pub fn build(mut self) -> PaymentsEngine {
+        if let Some(admin_ops) = self.admin_ops {
+            self.config.admin_ops = admin_ops;
+        }
+        if let Some(strict) = self.strict {
+            self.config.csv.lenient = !strict;
+        }
+        if let Some(policy) = self.disputes_over_available {
+            self.config.disputes.over_available = policy;
+        }
+        let mut engine = PaymentsEngine::with_stores(self.accounts, self.transactions).with_config(self.config);
+        for observer in self.observers {
+            engine = engine.with_observer(observer);
+        }
+        if let Some(dedup) = self.dedup {
+            engine = engine.with_dedup(dedup);
+        }
+        if let Some(clients) = self.clients {
+            engine = engine.with_clients(clients);
+        }
+        if self.aml {
+            engine = engine.with_aml();
+        }
+        if self.journal {
+            engine = engine.with_journal();
+        }
+        if self.rejections {
+            engine = engine.with_rejections();
+        }
+        engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::amount::amount;
+    use crate::config::EngineConfig;
+    use crate::engine::PaymentsEngine;
+    use crate::store::MemoryTxStore;
+
+    #[test]
+    fn test_builder() {
+        let config = EngineConfig::from_toml("[risk]\nmax_withdrawal = 10.0").unwrap();
+        let mut engine = PaymentsEngine::builder()
+            .admin_ops(true)
+            .config(config)
+            .tx_store(Box::new(MemoryTxStore::default()))
+            .journal(true)
+            .rejections(true)
+            .build();
+        assert!(engine.config().admin_ops);
+        assert_eq!(engine.config().risk.max_withdrawal, Some(amount(10.0)));
+
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 20.0\nwithdrawal, 1, 2, 15.0";
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        assert_eq!(engine.journal().len(), 1);
+        assert_eq!(engine.rejections().len(), 1);
+    }
+}
@@ -0,0 +1,80 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use crate::transactions::ClientId;
+
+/// A set of client ids, parsed from a list like `1,2,100-200`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientFilter {
+    ranges: Vec<RangeInclusive<ClientId>>,
+}
+
+impl ClientFilter {
+    /// Whether the client is in the set.
+    pub fn contains(&self, client: ClientId) -> bool {
+        self.ranges.iter().any(|range| range.contains(&client))
+    }
+}
+
+#[derive(Debug)]
+pub struct ClientFilterFromStrError(String);
+
+impl Error for ClientFilterFromStrError {}
+
+impl fmt::Display for ClientFilterFromStrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid client id or range `{}`, expected e.g. `1,2,100-200`",
+            self.0
+        )
+    }
+}
+
+impl FromStr for ClientFilter {
+    type Err = ClientFilterFromStrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ranges = vec![];
+        for part in s.split(',').map(str::trim) {
+            let invalid = || ClientFilterFromStrError(part.to_string());
+            let range = match part.split_once('-') {
+                Some((start, end)) => {
+                    let start: ClientId = start.trim().parse().map_err(|_| invalid())?;
+                    let end: ClientId = end.trim().parse().map_err(|_| invalid())?;
+                    if start > end {
+                        return Err(invalid());
+                    }
+                    start..=end
+                }
+                None => {
+                    let client: ClientId = part.parse().map_err(|_| invalid())?;
+                    client..=client
+                }
+            };
+            ranges.push(range);
+        }
+        Ok(ClientFilter { ranges })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClientFilter;
+    use crate::transactions::ClientId;
+
+    #[test]
+    fn test_client_filter() {
+        let filter: ClientFilter = "1, 2,100-200".parse().unwrap();
+        assert!(filter.contains(1));
+        assert!(filter.contains(150));
+        assert!(filter.contains(200));
+        assert!(!filter.contains(3));
+        assert!(!filter.contains(201));
+
+        assert!("1,".parse::<ClientFilter>().is_err());
+        assert!("200-100".parse::<ClientFilter>().is_err());
+        assert!((ClientId::MAX as u64 + 1).to_string().parse::<ClientFilter>().is_err());
+    }
+}
@@ -0,0 +1,72 @@
+//! Metadata about the clients, joined to the report from a secondary CSV file like:
+//!
+//! ```text
+//! client, name, segment, region
+//! 1, Alice, retail, eu-west
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Read;
+
+use serde::Deserialize;
+
+use crate::engine::csv_reader_builder;
+use crate::transactions::ClientId;
+
+/// The extra columns of the report with the metadata of the clients.
+pub const CLIENT_COLUMNS: &str = "name, segment, region";
+
+/// What we know about a client besides its balance.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientInfo {
+    pub name: String,
+    pub segment: String,
+    pub region: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientRow {
+    client: ClientId,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    segment: String,
+    #[serde(default)]
+    region: String,
+}
+
+/// The metadata of the known clients.
+#[derive(Debug, Clone, Default)]
+pub struct ClientDirectory {
+    clients: HashMap<ClientId, ClientInfo>,
+}
+
+impl ClientDirectory {
+    /// Reads a CSV with a `client` column and optional `name`, `segment` and `region` ones.
+    pub fn read<R: Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut rdr = csv_reader_builder().from_reader(reader);
+        let mut clients = HashMap::new();
+        for row in rdr.deserialize() {
+            let row: ClientRow = row?;
+            let info = ClientInfo {
+                name: row.name,
+                segment: row.segment,
+                region: row.region,
+            };
+            clients.insert(row.client, info);
+        }
+        Ok(ClientDirectory { clients })
+    }
+
+    /// The metadata of a client, if it is known.
+    pub fn get(&self, client: ClientId) -> Option<&ClientInfo> {
+        self.clients.get(&client)
+    }
+
+    /// Formats the metadata columns of a client, empty if it isn't known.
+    pub fn columns(&self, client: ClientId) -> String {
+        let info = self.get(client).cloned().unwrap_or_default();
+        format!("{}, {}, {}", info.name, info.segment, info.region)
+    }
+}
@@ -0,0 +1,421 @@
+//! A [`TxStore`] that keeps the past transactions in memory in about half the space of a
+//! [`MemoryTxStore`](crate::store::MemoryTxStore), for inputs with billions of deposits.
+//!
+//! The amounts are kept as integer minor units of four decimal places, the precision of the
+//! report, so that an amount with more decimal places is disputed or reversed rounded to them.
+//! The ids are split like a roaring bitmap: their high bits pick a chunk, and their low 16
+//! bits a slot of it, which has the amount, the client and the state of the transaction packed
+//! together instead of an entry of their own in a map each. A chunk is an open-addressing table
+//! of the low bits while it is sparse, and an array indexed by them once it is dense, which
+//! is when the ids are sequential: then a slot is all it takes, without a key.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::amount::AmountValue;
+use crate::store::{PendingSettlement, RecordedTx, StoreResult, TxStatus, TxStore};
+use crate::transactions::{Amount, ClientId, TransactionType, TxId};
+
+const CHUNK_BITS: u32 = 16;
+const CHUNK_SIZE: usize = 1 << CHUNK_BITS;
+// How many slots a sparse chunk starts with
+const SPARSE_SIZE: usize = 16;
+const MINOR_UNITS: f64 = 10_000.0;
+
+// The state of a slot, in its flags, a withdrawal being a recorded slot that isn't a deposit
+const OCCUPIED: u8 = 1;
+const RECORDED: u8 = 1 << 1;
+const DEPOSIT: u8 = 1 << 2;
+const OWNED: u8 = 1 << 3;
+const PENDING: u8 = 1 << 4;
+const DISPUTED: u8 = 1 << 5;
+const CHARGED_BACK: u8 = 1 << 6;
+const REVERSED: u8 = 1 << 7;
+
+fn to_minor(amount: Amount) -> i64 {
+    (amount.to_f64() * MINOR_UNITS).round() as i64
+}
+
+fn from_minor(units: i64) -> Amount {
+    Amount::from_f64(units as f64 / MINOR_UNITS)
+}
+
+/// The chunk of an id and its slot in it.
+fn split(tx_id: TxId) -> (TxId, u16) {
+    (tx_id >> CHUNK_BITS, (tx_id & 0xFFFF) as u16)
+}
+
+/// The slots of a chunk, one array per field so that none of them is padded.
+#[derive(Debug)]
+struct Slots {
+    flags: Vec<u8>,
+    amounts: Vec<i64>,
+    clients: Vec<ClientId>,
+}
+
+impl Slots {
+    fn with_len(len: usize) -> Self {
+        Slots {
+            flags: vec![0; len],
+            amounts: vec![0; len],
+            clients: vec![0; len],
+        }
+    }
+
+    fn copy(&mut self, to: usize, from: &Slots, index: usize) {
+        self.flags[to] = from.flags[index];
+        self.amounts[to] = from.amounts[index];
+        self.clients[to] = from.clients[index];
+    }
+}
+
+#[derive(Debug)]
+enum Chunk {
+    // With linear probing from the hash of the low bits, and grown at 7/8 full
+    Sparse { keys: Vec<u16>, slots: Slots, len: usize },
+    // Indexed by the low bits
+    Dense(Slots),
+}
+
+impl Chunk {
+    fn new() -> Self {
+        Chunk::Sparse {
+            keys: vec![0; SPARSE_SIZE],
+            slots: Slots::with_len(SPARSE_SIZE),
+            len: 0,
+        }
+    }
+
+    fn slots(&self) -> &Slots {
+        match self {
+            Chunk::Sparse { slots, .. } | Chunk::Dense(slots) => slots,
+        }
+    }
+
+    fn slots_mut(&mut self) -> &mut Slots {
+        match self {
+            Chunk::Sparse { slots, .. } | Chunk::Dense(slots) => slots,
+        }
+    }
+
+    /// Where the probing for the low bits starts in a table of `size` slots.
+    fn start(low: u16, size: usize) -> usize {
+        // Fibonacci hashing, so that ids with a common stride don't all land together
+        let hash = (u32::from(low) * 40_503) & 0xFFFF;
+        (hash >> (CHUNK_BITS - size.trailing_zeros())) as usize
+    }
+
+    /// The slot of the low bits of an id, or of where they would go in a sparse chunk.
+    fn probe(keys: &[u16], slots: &Slots, low: u16) -> usize {
+        let mask = keys.len() - 1;
+        let mut index = Self::start(low, keys.len());
+        while slots.flags[index] & OCCUPIED != 0 && keys[index] != low {
+            index = (index + 1) & mask;
+        }
+        index
+    }
+
+    /// The slot of the low bits of an id, if it is occupied.
+    fn find(&self, low: u16) -> Option<usize> {
+        let index = match self {
+            Chunk::Sparse { keys, slots, .. } => Self::probe(keys, slots, low),
+            Chunk::Dense(_) => usize::from(low),
+        };
+        (self.slots().flags[index] & OCCUPIED != 0).then_some(index)
+    }
+
+    /// The slot of the low bits of an id, occupied for them if it wasn't already.
+    fn occupy(&mut self, low: u16) -> usize {
+        if let Some(index) = self.find(low) {
+            return index;
+        }
+        if let Chunk::Sparse { keys, len, .. } = self {
+            if (*len + 1) * 8 > keys.len() * 7 {
+                self.grow();
+            }
+        }
+        let index = match self {
+            Chunk::Sparse { keys, slots, len } => {
+                let index = Self::probe(keys, slots, low);
+                keys[index] = low;
+                *len += 1;
+                index
+            }
+            Chunk::Dense(_) => usize::from(low),
+        };
+        self.slots_mut().flags[index] = OCCUPIED;
+        index
+    }
+
+    /// Doubles the size of a sparse chunk, or makes it dense once that would take as much memory.
+    fn grow(&mut self) {
+        let Chunk::Sparse { keys, slots, len } = self else {
+            return;
+        };
+        let len = *len;
+        let size = keys.len() * 2;
+        let occupied = (0..keys.len()).filter(|index| slots.flags[*index] & OCCUPIED != 0);
+        if size >= CHUNK_SIZE {
+            let mut dense = Slots::with_len(CHUNK_SIZE);
+            for index in occupied {
+                dense.copy(usize::from(keys[index]), slots, index);
+            }
+            *self = Chunk::Dense(dense);
+            return;
+        }
+        let mut grown_keys = vec![0; size];
+        let mut grown = Slots::with_len(size);
+        for index in occupied {
+            let to = Self::probe(&grown_keys, &grown, keys[index]);
+            grown_keys[to] = keys[index];
+            grown.copy(to, slots, index);
+        }
+        *self = Chunk::Sparse {
+            keys: grown_keys,
+            slots: grown,
+            len,
+        };
+    }
+
+    /// The low bits and slots of the occupied slots, sorted by the low bits.
+    fn occupied(&self) -> Vec<(u16, usize)> {
+        let slots = self.slots();
+        let mut occupied: Vec<_> = match self {
+            Chunk::Sparse { keys, .. } => (0..keys.len()).map(|index| (keys[index], index)).collect(),
+            Chunk::Dense(_) => (0..CHUNK_SIZE).map(|index| (index as u16, index)).collect(),
+        };
+        occupied.retain(|(_, index)| slots.flags[*index] & OCCUPIED != 0);
+        occupied.sort_unstable();
+        occupied
+    }
+}
+
+/// Keeps the same state as a [`MemoryTxStore`](crate::store::MemoryTxStore) in compact chunks
+/// of the ids, see the [module](self). The amounts held by the partial disputes and the
+/// settlements of the pending deposits, of which there are few at a time, are kept aside.
+#[derive(Debug, Default)]
+pub struct CompactTxStore {
+    // By the high bits of the ids
+    chunks: HashMap<TxId, Chunk>,
+    // The amounts held by the disputes of less than their deposit
+    held: HashMap<TxId, i64>,
+    // The pending deposits by when they settle, and when each of them does
+    pending: BTreeMap<(u64, TxId), PendingSettlement>,
+    pending_txs: HashMap<TxId, u64>,
+}
+
+impl CompactTxStore {
+    /// The slots of the chunk of an id, and its slot in it, occupied if it wasn't already.
+    fn occupy(&mut self, tx_id: TxId) -> (&mut Slots, usize) {
+        let (high, low) = split(tx_id);
+        let chunk = self.chunks.entry(high).or_insert_with(Chunk::new);
+        let index = chunk.occupy(low);
+        (chunk.slots_mut(), index)
+    }
+
+    /// The deposit or withdrawal of a slot, if one is recorded in it.
+    fn unpack(&self, tx_id: TxId, flags: u8, amount: i64, client_id: ClientId) -> Option<RecordedTx> {
+        if flags & RECORDED == 0 {
+            return None;
+        }
+        let held = || from_minor(self.held.get(&tx_id).copied().unwrap_or(amount));
+        let status = if flags & REVERSED != 0 {
+            TxStatus::Reversed
+        } else if flags & PENDING != 0 {
+            TxStatus::Pending
+        } else if flags & CHARGED_BACK != 0 {
+            TxStatus::ChargedBack { held: held() }
+        } else if flags & DISPUTED != 0 {
+            TxStatus::Disputed { held: held() }
+        } else {
+            TxStatus::Settled
+        };
+        Some(RecordedTx {
+            tx_id,
+            client_id: (flags & OWNED != 0).then_some(client_id),
+            tx_type: if flags & DEPOSIT != 0 {
+                TransactionType::Deposit
+            } else {
+                TransactionType::Withdrawal
+            },
+            amount: from_minor(amount),
+            status,
+        })
+    }
+}
+
+impl TxStore for CompactTxStore {
+    fn recorded(&self, tx_id: TxId) -> StoreResult<Option<RecordedTx>> {
+        let (high, low) = split(tx_id);
+        let Some((chunk, index)) = self.chunks.get(&high).and_then(|chunk| Some((chunk, chunk.find(low)?))) else {
+            return Ok(None);
+        };
+        let slots = chunk.slots();
+        Ok(self.unpack(tx_id, slots.flags[index], slots.amounts[index], slots.clients[index]))
+    }
+
+    fn record(&mut self, recorded: &RecordedTx) -> StoreResult<()> {
+        let amount = to_minor(recorded.amount);
+        let mut flags = OCCUPIED | RECORDED;
+        if recorded.tx_type == TransactionType::Deposit {
+            flags |= DEPOSIT;
+        }
+        flags |= match recorded.status {
+            TxStatus::Settled => 0,
+            TxStatus::Pending => PENDING,
+            TxStatus::Disputed { .. } => DISPUTED,
+            TxStatus::ChargedBack { .. } => CHARGED_BACK,
+            TxStatus::Reversed => REVERSED,
+        };
+        // Most disputes are of the whole amount, the others hold theirs aside
+        match recorded.status.held().map(to_minor) {
+            Some(held) if held != amount => self.held.insert(recorded.tx_id, held),
+            _ => self.held.remove(&recorded.tx_id),
+        };
+        let (slots, index) = self.occupy(recorded.tx_id);
+        if let Some(client_id) = recorded.client_id {
+            flags |= OWNED;
+            slots.clients[index] = client_id;
+        }
+        slots.flags[index] = flags;
+        slots.amounts[index] = amount;
+        Ok(())
+    }
+
+    fn all(&self) -> StoreResult<Vec<RecordedTx>> {
+        let mut chunks: Vec<_> = self.chunks.iter().collect();
+        chunks.sort_unstable_by_key(|(high, _)| **high);
+        let mut recorded = vec![];
+        for (high, chunk) in chunks {
+            let slots = chunk.slots();
+            for (low, index) in chunk.occupied() {
+                let tx_id = (*high << CHUNK_BITS) | TxId::from(low);
+                recorded.extend(self.unpack(tx_id, slots.flags[index], slots.amounts[index], slots.clients[index]));
+            }
+        }
+        Ok(recorded)
+    }
+
+    fn record_settlement(&mut self, settlement: &PendingSettlement) -> StoreResult<()> {
+        if let Some(settles_at) = self.pending_txs.insert(settlement.tx_id, settlement.settles_at) {
+            self.pending.remove(&(settles_at, settlement.tx_id));
+        }
+        self.pending
+            .insert((settlement.settles_at, settlement.tx_id), settlement.clone());
+        Ok(())
+    }
+
+    fn take_settled(&mut self, until: u64) -> StoreResult<Vec<PendingSettlement>> {
+        let later = match until.checked_add(1) {
+            Some(after) => self.pending.split_off(&(after, 0)),
+            None => BTreeMap::new(),
+        };
+        let settled = std::mem::replace(&mut self.pending, later);
+        for settlement in settled.values() {
+            self.pending_txs.remove(&settlement.tx_id);
+        }
+        Ok(settled.into_values().collect())
+    }
+
+    fn settlements(&self) -> StoreResult<Vec<PendingSettlement>> {
+        let mut settlements: Vec<_> = self.pending.values().cloned().collect();
+        settlements.sort_by_key(|settlement| settlement.tx_id);
+        Ok(settlements)
+    }
+
+    fn settlement(&self, tx_id: TxId) -> StoreResult<Option<PendingSettlement>> {
+        Ok(self
+            .pending_txs
+            .get(&tx_id)
+            .and_then(|settles_at| self.pending.get(&(*settles_at, tx_id)))
+            .cloned())
+    }
+
+    fn take_settlement(&mut self, tx_id: TxId) -> StoreResult<Option<PendingSettlement>> {
+        Ok(self
+            .pending_txs
+            .remove(&tx_id)
+            .and_then(|settles_at| self.pending.remove(&(settles_at, tx_id))))
+    }
+
+    fn forget(&mut self, tx_id: TxId) -> StoreResult<()> {
+        if self.recorded(tx_id)?.is_some() {
+            // The slot stays occupied, so that the ones probed after it are still found
+            let (slots, index) = self.occupy(tx_id);
+            slots.flags[index] = OCCUPIED;
+            slots.amounts[index] = 0;
+        }
+        self.held.remove(&tx_id);
+        if let Some(settles_at) = self.pending_txs.remove(&tx_id) {
+            self.pending.remove(&(settles_at, tx_id));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CompactTxStore;
+    use crate::amount::amount;
+    use crate::bench::generate_csv;
+    use crate::engine::PaymentsEngine;
+    use crate::store::{MemoryAccountStore, MemoryTxStore, RecordedTx, TxStatus, TxStore};
+    use crate::transactions::{ClientId, TransactionType, TxId};
+
+    #[test]
+    fn test_compact_tx_store() {
+        // The same balances as with the default store, over enough ids for dense chunks
+        let csv = generate_csv(200_000, 50, 3);
+        let mut compact = PaymentsEngine::with_stores(
+            Box::new(MemoryAccountStore::default()),
+            Box::new(CompactTxStore::default()),
+        );
+        compact.process_csv_reader(csv.as_slice()).unwrap();
+        let mut memory = PaymentsEngine::new();
+        memory.process_csv_reader(csv.as_slice()).unwrap();
+        assert_eq!(compact.report().unwrap(), memory.report().unwrap());
+        assert_eq!(compact.stats().rejected, memory.stats().rejected);
+
+        // Sparse ids, and every status
+        let mut compact = CompactTxStore::default();
+        let mut memory = MemoryTxStore::default();
+        let ids: Vec<TxId> = (0..5_000).map(|n| 1 + n * 977).collect();
+        let recorded = |tx_id, status| RecordedTx {
+            tx_id,
+            client_id: Some(1 + (tx_id % 7) as ClientId),
+            tx_type: TransactionType::Deposit,
+            amount: amount(1.5),
+            status,
+        };
+        for store in [&mut compact as &mut dyn TxStore, &mut memory] {
+            for tx_id in &ids {
+                store.record(&recorded(*tx_id, TxStatus::Settled)).unwrap();
+            }
+            let withdrawal = RecordedTx {
+                tx_type: TransactionType::Withdrawal,
+                amount: amount(0.75),
+                ..recorded(2, TxStatus::Settled)
+            };
+            store.record(&withdrawal).unwrap();
+            store.record(&recorded(ids[3], TxStatus::Pending)).unwrap();
+            store
+                .record(&recorded(ids[4], TxStatus::Disputed { held: amount(1.5) }))
+                .unwrap();
+            store
+                .record(&recorded(ids[5], TxStatus::ChargedBack { held: amount(1.0) }))
+                .unwrap();
+            store.record(&recorded(ids[8], TxStatus::Reversed)).unwrap();
+            store.forget(ids[9]).unwrap();
+        }
+        assert_eq!(compact.all().unwrap(), memory.all().unwrap());
+        assert_eq!(
+            compact.recorded(ids[5]).unwrap().unwrap().status,
+            TxStatus::ChargedBack { held: amount(1.0) }
+        );
+        assert_eq!(
+            compact.recorded(2).unwrap().unwrap().tx_type,
+            TransactionType::Withdrawal
+        );
+        assert_eq!(compact.recorded(ids[9]).unwrap(), None);
+        assert_eq!(compact.recorded(4).unwrap(), None);
+    }
+}
@@ -0,0 +1,584 @@
+//! How the engine behaves, beyond the transactions it is given.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::accounts::AmountFormat;
+use crate::aml::AmlRules;
+use crate::amount::AmountValue;
+use crate::csv_input::CsvOptions;
+use crate::interest::InterestConfig;
+use crate::machine::Rules;
+pub use crate::machine::{OverAvailable, OverAvailableFromStrError};
+use crate::queue::QueueConfig;
+use crate::rate_limit::RateLimits;
+use crate::rejections::RejectionReason;
+use crate::risk::RiskRules;
+use crate::transactions::{Amount, Transaction, TransactionType};
+use crate::webhooks::WebhookConfig;
+
+/// The settings of an engine, which can also be read from a TOML file, see [`DEFAULT_CONFIG`].
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    /// Whether admin operations, i.e. `adjustment`, `freeze` and `unfreeze` transactions, are allowed.
+    pub admin_ops: bool,
+    /// Fees posted automatically when a qualifying transaction is applied.
+    pub fee_rules: Vec<FeeRule>,
+    /// Interest accrued on the available funds, if any.
+    pub interest: Option<InterestConfig>,
+    /// How disputes and chargebacks are handled.
+    pub disputes: DisputeRules,
+    /// How deposits are handled.
+    pub deposits: DepositRules,
+    /// The range of the amounts of single deposits and withdrawals.
+    pub limits: AmountLimits,
+    /// Limits on the transactions of each client.
+    pub risk: RiskRules,
+    /// Limits on how fast each client can submit transactions, in follow and serve modes.
+    pub rate_limit: RateLimits,
+    /// The sources of input, by name, see [`PaymentsEngine::with_source`](crate::PaymentsEngine::with_source).
+    pub sources: BTreeMap<String, SourceRules>,
+    /// The thresholds of the suspicious activity analyzer, see [`PaymentsEngine::with_aml`](crate::PaymentsEngine::with_aml).
+    pub aml: AmlRules,
+    /// How the CSV input is read.
+    pub csv: CsvOptions,
+    /// The queue between reading the transactions and applying them, in follow and serve modes.
+    pub queue: QueueConfig,
+    /// How the amounts of the report are written.
+    pub output: AmountFormat,
+    /// Where to notify the accounts locked, the chargebacks and the negative balances, in
+    /// follow and serve modes, see [`Webhooks`](crate::webhooks::Webhooks).
+    pub webhooks: WebhookConfig,
+}
+
+/// A commented config file with all the settings, set to their defaults or commented out.
+pub const DEFAULT_CONFIG: &str = r#"# Settings of the payments engine, given with `--config engine.toml`.
+# The command line options override them.
+
+# Allow admin operations, i.e. `adjustment`, `freeze` and `unfreeze` transactions.
+admin_ops = false
+
+# Fees charged automatically on deposits or withdrawals: a `percent` of the amount
+# and/or a `fixed` amount, on the amounts `over` a threshold.
+fee_rules = []
+# fee_rules = ["withdrawal:percent=1.5,fixed=0.25,over=1000"]
+
+# Daily interest on the available funds, posted `daily` or `monthly`.
+# [interest]
+# annual_rate_percent = 3.5
+# period = "monthly"
+
+# How disputes and chargebacks are handled.
+[disputes]
+# Whether reversing a chargeback, after it was won on appeal, unlocks the account.
+unlock_on_chargeback_reversal = true
+# What a dispute of more than the available funds does, e.g. of a deposit that was
+# already withdrawn: "allow" it to drive them negative, "cap" the hold at what is
+# available, or "reject" it.
+over_available = "allow"
+# After how many days an open dispute is resolved automatically, releasing the funds
+# it holds, going by the `timestamp` of the transactions. Never by default.
+# expire_after_days = 90
+# The fee the card network charges for each chargeback, counted in the losses but not
+# posted to the accounts.
+chargeback_fee = 0.0
+
+# How deposits are handled.
+[deposits]
+# For how many days the funds of a deposit are pending before they are available,
+# going by its `timestamp`, unless it has a `settles_at` or a `settle` transaction
+# settles it earlier. Available at once by default.
+# hold_days = 3
+
+# The smallest and largest amounts of a single deposit or withdrawal, rejecting the
+# ones outside them as AMOUNT_BELOW_MIN or AMOUNT_ABOVE_MAX.
+[limits]
+# deposit = { min = 0.01 }
+# withdrawal = { min = 0.01, max = 10000.0 }
+
+# Limits on the transactions of each client, rejecting the ones beyond them.
+[risk]
+# max_withdrawal = 1000.0
+# max_daily_withdrawals = 2500.0
+# velocity = { max_transactions = 10, window_secs = 60 }
+# Lock the account of a client after `max_disputes` disputes within `window_days`, or
+# once its disputes hold more than `max_held_percent` of its total funds.
+# auto_lock = { disputes = { max_disputes = 3, window_days = 30 }, max_held_percent = 50.0 }
+
+# Limits on how many transactions each client can submit with `--follow` and `serve`,
+# rejecting the ones beyond them as RATE_LIMITED, so they can be submitted again later.
+[rate_limit]
+# per_second = 10
+# per_minute = 300
+
+# The transaction types each source of input may have, given with `--source NAME`,
+# rejecting the others as TYPE_NOT_ALLOWED, e.g. when chargebacks only come from the
+# card network feed. All the types are allowed without `--source`.
+# [sources.partner]
+# allowed_types = ["deposit", "withdrawal", "dispute", "resolve"]
+# [sources.card-network]
+# allowed_types = ["chargeback", "chargeback_reversal"]
+
+# Thresholds of the suspicious activity report, with `--aml-report`.
+[aml]
+structuring_threshold = 10000.0
+structuring_margin_percent = 10.0
+structuring_count = 3
+cycle_window_secs = 3600
+cycle_percent = 90.0
+max_dispute_rate_percent = 20.0
+min_deposits_for_dispute_rate = 5
+
+# How the CSV input is read.
+[csv]
+# Whether the first row is a header. Otherwise the columns are `type`, `client`, `tx`,
+# `amount`, etc. in order.
+headers = true
+# What separates the fields, a single character or "tab". Commas by default, or tabs
+# for `.tsv` files.
+# delimiter = ";"
+# How the records are parsed, "serde" or the "fast" parser.
+parser = "serde"
+# Whether malformed records are skipped, and listed in the `--rejection-report`,
+# instead of failing the whole run.
+lenient = false
+# How the amounts are written: "strict", e.g. 1234.5, or with thousands separators in
+# "us", e.g. "1,234.5", or in "eu", with a decimal comma, e.g. "1.234,5".
+amount_locale = "strict"
+# The columns named differently than `type`, `client`, `tx`, `amount`, etc.
+column_map = {}
+# column_map = { txn_type = "type", customer = "client", reference = "tx", value = "amount" }
+
+# The queue between reading the transactions and applying them, with `--follow` and `serve`.
+[queue]
+# How many transactions (or batches, when serving) can wait to be applied before the input
+# waits for the engine.
+capacity = 1024
+
+# How the amounts of the report are written.
+[output]
+# The number of decimal places.
+precision = 4
+# How the amounts are rounded to them: "half-even", to the nearest and the ties to an even
+# digit, or "truncate" toward zero.
+rounding = "half-even"
+# Whether they are written as "decimal" numbers, e.g. 1.5000, or as integer "minor-units"
+# of the precision, e.g. 15000 basis points, exact in a build with `fixed-amounts`.
+style = "decimal"
+# Whether the JSON report, with `--output-format json`, has the amounts as strings instead
+# of numbers.
+json_strings = false
+
+# The URLs notified of the events that need attention, with `--follow` and `serve`: a
+# JSON POST for each account locked, chargeback and balance going negative.
+[webhooks]
+urls = []
+# urls = ["https://risk.example.com/hooks/payments"]
+events = ["account_locked", "chargeback", "negative_balance"]
+# How many more times a notification is sent when it fails, waiting `backoff_ms` before
+# the first retry and twice as long before each one after it.
+retries = 3
+backoff_ms = 500
+# How many notifications can wait to be sent, e.g. while an endpoint is down. The ones
+# beyond it are dropped.
+capacity = 1024
+"#;
+
+/// The prefix of the environment variables with settings of the engine.
+pub const ENV_PREFIX: &str = "PAYMENTS_ENGINE_";
+
+/// The top level keys of the config file, which the environment variables can set.
+const CONFIG_KEYS: [&str; 14] = [
+    "admin_ops",
+    "fee_rules",
+    "interest",
+    "disputes",
+    "deposits",
+    "limits",
+    "risk",
+    "rate_limit",
+    "sources",
+    "aml",
+    "csv",
+    "queue",
+    "output",
+    "webhooks",
+];
+
+impl EngineConfig {
+    /// The settings of a TOML config file, with the default ones for anything it doesn't set.
+    pub fn from_toml(toml: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(toml)
+    }
+
+    /// The settings of the `PAYMENTS_ENGINE_*` environment variables, overridden by the ones
+    /// of a TOML config file if there is one.
+    ///
+    /// The variables are named after the keys of the config file, with `__` between the keys
+    /// of nested sections, e.g. `PAYMENTS_ENGINE_RISK__MAX_WITHDRAWAL=1000` for `max_withdrawal`
+    /// in `[risk]`. Their values are TOML values, or strings if they aren't valid TOML.
+    /// Variables for other keys are ignored, since they are the command line options.
+    pub fn from_env_and_toml<I>(vars: I, toml: Option<&str>) -> Result<Self, toml::de::Error>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut settings = toml::Table::new();
+        for (name, value) in vars {
+            let Some(name) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let name = name.to_lowercase();
+            if !name.split("__").next().is_some_and(|key| CONFIG_KEYS.contains(&key)) {
+                continue;
+            }
+            let value = toml::from_str::<toml::Table>(&format!("value = {}", value))
+                .ok()
+                .and_then(|mut table| table.remove("value"))
+                .unwrap_or(toml::Value::String(value));
+            let (sections, key) = name.rsplit_once("__").unwrap_or(("", &name));
+            let mut setting = toml::Table::from_iter([(key.to_string(), value)]);
+            for section in sections.rsplit("__").filter(|section| !section.is_empty()) {
+                setting = toml::Table::from_iter([(section.to_string(), setting.into())]);
+            }
+            merge(&mut settings, setting);
+        }
+        if let Some(toml) = toml {
+            merge(&mut settings, toml.parse()?);
+        }
+        settings.try_into()
+    }
+
+    /// The settings that change what a transaction does to the balance of its client.
+    pub fn rules(&self) -> Rules {
+        Rules {
+            admin_ops: self.admin_ops,
+            over_available: self.disputes.over_available,
+            unlock_on_chargeback_reversal: self.disputes.unlock_on_chargeback_reversal,
+            deposit_hold_days: self.deposits.hold_days,
+        }
+    }
+
+    /// The total fee charged for a transaction by all the rules it qualifies for.
+    pub fn fee_for(&self, transaction: &Transaction) -> Amount {
+        let amount = transaction.amount.unwrap_or_default();
+        self.fee_rules
+            .iter()
+            .filter(|rule| rule.tx_type == transaction.tx_type && amount > rule.over)
+            .map(|rule| amount.percent(rule.percent) + rule.fixed)
+            .sum()
+    }
+}
+
+/// How disputes and chargebacks are handled.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisputeRules {
+    /// Whether a `chargeback_reversal` unlocks the account locked by the chargeback.
+    pub unlock_on_chargeback_reversal: bool,
+    /// What a dispute of more than the available funds of the client does.
+    pub over_available: OverAvailable,
+    /// After how many days an open dispute is resolved automatically, releasing its hold,
+    /// see [`dispute_expiry`](crate::dispute_expiry).
+    pub expire_after_days: Option<u32>,
+    /// The fee the card network charges for each chargeback, only counted in the losses,
+    /// see [`losses`](crate::losses).
+    pub chargeback_fee: Amount,
+}
+
+impl Default for DisputeRules {
+    fn default() -> Self {
+        DisputeRules {
+            unlock_on_chargeback_reversal: true,
+            over_available: OverAvailable::default(),
+            expire_after_days: None,
+            chargeback_fee: Amount::ZERO,
+        }
+    }
+}
+
+/// How deposits are handled.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DepositRules {
+    /// For how many days the funds of a deposit with a `timestamp` are pending, unless it has
+    /// a `settles_at` of its own or is settled earlier by a `settle` transaction.
+    pub hold_days: Option<u32>,
+}
+
+/// What a source of input may have.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourceRules {
+    /// The transaction types allowed, or all of them if not set.
+    pub allowed_types: Option<Vec<TransactionType>>,
+}
+
+impl SourceRules {
+    /// Whether transactions of this type are allowed.
+    pub fn allows(&self, tx_type: TransactionType) -> bool {
+        self.allowed_types
+            .as_ref()
+            .is_none_or(|allowed_types| allowed_types.contains(&tx_type))
+    }
+}
+
+/// The range of the amounts of single deposits and withdrawals.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmountLimits {
+    pub deposit: AmountRange,
+    pub withdrawal: AmountRange,
+}
+
+/// The smallest and largest amounts allowed, both included.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmountRange {
+    pub min: Option<Amount>,
+    pub max: Option<Amount>,
+}
+
+impl AmountLimits {
+    /// Why the amount of the transaction is out of its range, if it is.
+    pub fn check(&self, transaction: &Transaction) -> Option<RejectionReason> {
+        let range = match transaction.tx_type {
+            TransactionType::Deposit => &self.deposit,
+            TransactionType::Withdrawal => &self.withdrawal,
+            _ => return None,
+        };
+        let amount = transaction.amount?;
+        if !amount.is_finite() {
+            // NaN is neither under nor over any limit
+            Some(RejectionReason::InvalidAmount)
+        } else if range.min.is_some_and(|min| amount < min) {
+            Some(RejectionReason::BelowMinAmount)
+        } else if range.max.is_some_and(|max| amount > max) {
+            Some(RejectionReason::AboveMaxAmount)
+        } else {
+            None
+        }
+    }
+}
+
+/// Sets the values of `overrides` in `settings`, merging the sections they both have.
+fn merge(settings: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (settings.get_mut(&key), value) {
+            (Some(toml::Value::Table(section)), toml::Value::Table(overrides)) => merge(section, overrides),
+            (_, value) => {
+                settings.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Charges `percent` of the amount plus `fixed` on the transactions of a type
+/// (deposits or withdrawals) with an amount over a threshold.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct FeeRule {
+    pub tx_type: TransactionType,
+    /// Only amounts strictly greater than this are charged.
+    pub over: Amount,
+    pub percent: Amount,
+    pub fixed: Amount,
+}
+
+#[derive(Debug)]
+pub struct FeeRuleFromStrError(String);
+
+impl Error for FeeRuleFromStrError {}
+
+impl fmt::Display for FeeRuleFromStrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid fee rule `{}`, expected e.g. `withdrawal:percent=1.5,fixed=0.25,over=1000`",
+            self.0
+        )
+    }
+}
+
+impl TryFrom<String> for FeeRule {
+    type Error = FeeRuleFromStrError;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for FeeRule {
+    type Err = FeeRuleFromStrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || FeeRuleFromStrError(s.to_string());
+        let (tx_type, settings) = s.split_once(':').ok_or_else(invalid)?;
+        let tx_type: TransactionType = tx_type.trim().parse().map_err(|_| invalid())?;
+        if !matches!(tx_type, TransactionType::Deposit | TransactionType::Withdrawal) {
+            return Err(invalid());
+        }
+
+        let mut rule = FeeRule {
+            tx_type,
+            over: Amount::ZERO,
+            percent: Amount::ZERO,
+            fixed: Amount::ZERO,
+        };
+        for setting in settings.split(',') {
+            let (key, value) = setting.split_once('=').ok_or_else(invalid)?;
+            let value: Amount = value.trim().parse().map_err(|_| invalid())?;
+            if value.is_nan() || value < Amount::ZERO {
+                return Err(invalid());
+            }
+            match key.trim() {
+                "over" => rule.over = value,
+                "percent" => rule.percent = value,
+                "fixed" => rule.fixed = value,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(rule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EngineConfig, FeeRule, DEFAULT_CONFIG};
+    use crate::amount::amount;
+    use crate::engine::PaymentsEngine;
+    use crate::interest::InterestPeriod;
+    use crate::rejections::RejectionReason;
+    use crate::transactions::{Transaction, TransactionType};
+
+    #[test]
+    fn test_fee_rules() {
+        let config = EngineConfig {
+            fee_rules: vec![
+                "withdrawal:percent=1,over=100".parse().unwrap(),
+                "withdrawal: fixed=0.5".parse().unwrap(),
+            ],
+            ..EngineConfig::default()
+        };
+        let withdrawal =
+            |amount| Transaction::parse_csv_record(format!("withdrawal, 1, 1, {}", amount).as_bytes()).unwrap();
+        assert_eq!(config.fee_for(&withdrawal(100.0)), amount(0.5));
+        assert_eq!(config.fee_for(&withdrawal(200.0)), amount(2.5));
+        assert_eq!(
+            config.fee_for(&Transaction::parse_csv_record(b"deposit, 1, 1, 200").unwrap()),
+            amount(0.0)
+        );
+
+        assert_eq!(
+            "deposit:over=10".parse::<FeeRule>().unwrap(),
+            FeeRule {
+                tx_type: TransactionType::Deposit,
+                over: amount(10.0),
+                percent: amount(0.0),
+                fixed: amount(0.0)
+            }
+        );
+        assert!("dispute:fixed=1".parse::<FeeRule>().is_err());
+        assert!("withdrawal:fixed=-1".parse::<FeeRule>().is_err());
+        assert!("withdrawal:percentage=1".parse::<FeeRule>().is_err());
+    }
+
+    #[test]
+    fn test_amount_limits() {
+        let config = EngineConfig::from_toml("[limits]\nwithdrawal = { min = 1.0, max = 10000.0 }").unwrap();
+        let mut engine = PaymentsEngine::new().with_config(config);
+        let record = |record: &str| Transaction::parse_csv_record(record.as_bytes()).unwrap();
+        assert!(engine.apply(&record("deposit, 1, 1, 20000.0")).unwrap().is_ok());
+        for (row, reason) in [
+            ("withdrawal, 1, 2, 10000.5", Some(RejectionReason::AboveMaxAmount)),
+            ("withdrawal, 1, 3, 0.5", Some(RejectionReason::BelowMinAmount)),
+            ("withdrawal, 1, 4, 10000.0", None),
+            ("withdrawal, 1, 5, 1.0", None),
+        ] {
+            let outcome = engine.apply(&record(row)).unwrap();
+            assert_eq!(outcome.err().map(|rejected| rejected.reason), reason);
+        }
+        assert_eq!(engine.account(1).unwrap().unwrap().available, amount(9999.0));
+        // The minor units have neither
+        #[cfg(not(feature = "fixed-amounts"))]
+        for row in ["withdrawal, 1, 6, NaN", "withdrawal, 1, 7, inf"] {
+            assert_eq!(
+                engine.config().limits.check(&record(row)),
+                Some(RejectionReason::InvalidAmount)
+            );
+        }
+    }
+
+    #[test]
+    fn test_source_allowed_types() {
+        let config = EngineConfig::from_toml(
+            "[sources.partner]\nallowed_types = [\"deposit\", \"withdrawal\"]\n[sources.card-network]",
+        )
+        .unwrap();
+        assert!(EngineConfig::from_toml("[sources.partner]\nallowed_types = [\"transfer\"]").is_err());
+        let record = |record: &str| Transaction::parse_csv_record(record.as_bytes()).unwrap();
+        let mut engine = PaymentsEngine::new().with_config(config.clone()).with_source("partner");
+        assert!(engine.apply(&record("deposit, 1, 1, 10.0")).unwrap().is_ok());
+        let rejected = engine.apply(&record("dispute, 1, 1,")).unwrap().unwrap_err();
+        assert_eq!(rejected.reason, RejectionReason::TypeNotAllowed);
+        assert_eq!(engine.account(1).unwrap().unwrap().held, amount(0.0));
+
+        // A source without allowed types has all of them
+        let mut engine = PaymentsEngine::new().with_config(config).with_source("card-network");
+        assert!(engine.apply(&record("deposit, 1, 1, 10.0")).unwrap().is_ok());
+        assert!(engine.apply(&record("dispute, 1, 1,")).unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_config_file() {
+        assert_eq!(
+            EngineConfig::from_toml(DEFAULT_CONFIG).unwrap(),
+            EngineConfig::default()
+        );
+
+        let config = EngineConfig::from_toml(
+            r#"fee_rules = ["deposit:fixed=1"]
+               interest = { annual_rate_percent = 2.0 }
+               [risk]
+               max_withdrawal = 10.0"#,
+        )
+        .unwrap();
+        assert_eq!(config.fee_rules, vec!["deposit:fixed=1".parse::<FeeRule>().unwrap()]);
+        assert_eq!(config.interest.unwrap().period, InterestPeriod::Monthly);
+        assert_eq!(config.risk.max_withdrawal, Some(amount(10.0)));
+
+        assert!(EngineConfig::from_toml("fee_rules = [\"dispute:fixed=1\"]").is_err());
+        assert!(EngineConfig::from_toml("admin = true").is_err());
+    }
+
+    #[test]
+    fn test_env_config() {
+        let vars = || {
+            [
+                ("PAYMENTS_ENGINE_ADMIN_OPS", "true"),
+                ("PAYMENTS_ENGINE_FEE_RULES", r#"["deposit:fixed=1"]"#),
+                ("PAYMENTS_ENGINE_RISK__MAX_WITHDRAWAL", "10"),
+                ("PAYMENTS_ENGINE_RISK__MAX_DAILY_WITHDRAWALS", "20"),
+                ("PAYMENTS_ENGINE_INTEREST__ANNUAL_RATE_PERCENT", "2.5"),
+                ("PAYMENTS_ENGINE_INTEREST__PERIOD", "daily"),
+                ("PAYMENTS_ENGINE_INPUT", "transactions.csv"),
+                ("HOME", "/root"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+        };
+        let config = EngineConfig::from_env_and_toml(vars(), None).unwrap();
+        assert!(config.admin_ops);
+        assert_eq!(config.fee_rules.len(), 1);
+        assert_eq!(config.risk.max_withdrawal, Some(amount(10.0)));
+        assert_eq!(config.interest.unwrap().period, InterestPeriod::Daily);
+
+        let config = EngineConfig::from_env_and_toml(vars(), Some("[risk]\nmax_withdrawal = 5.0")).unwrap();
+        assert_eq!(config.risk.max_withdrawal, Some(amount(5.0)));
+        assert_eq!(config.risk.max_daily_withdrawals, Some(amount(20.0)));
+
+        let vars = [("PAYMENTS_ENGINE_RISK__MAX_WITHDRAWAL".to_string(), "lots".to_string())];
+        assert!(EngineConfig::from_env_and_toml(vars, None).is_err());
+    }
+}
@@ -0,0 +1,536 @@
+//! How the CSV input with the transactions is read, for partner files that don't quite
+//! use our format. The options are set in the `[csv]` section of the config file:
+//!
+//! ```toml
+//! [csv]
+//! column_map = { txn_type = "type", customer = "client", reference = "tx", value = "amount" }
+//! ```
+//!
+//! Files without a header row have `headers = false`, and their columns in the order of `CSV_COLUMNS`.
+//! The fields are separated by commas, or by tabs in `.tsv` files, unless a `delimiter` is set.
+//!
+//! With `parser = "fast"`, the records are parsed straight from their bytes instead of with serde.
+//! Records the fast parser can't make sense of are handed to serde, so the transactions and
+//! errors are the same either way.
+//!
+//! With `lenient = true`, the records that can't be read as transactions are skipped instead
+//! of failing, and kept with their line and error in the stats.
+//!
+//! With `amount_locale = "eu"` or `"us"`, the amounts can have thousands separators, e.g.
+//! `"1.234,5"` or `"1,234.5"`, and a comma as the decimal separator in `eu`. Amounts whose
+//! separators don't fit the locale, e.g. `"1,5"` in `us`, are malformed rather than read as
+//! another number. The default `strict` only reads amounts like `1234.5`. All of them read
+//! scientific notation, e.g. `1.2345e3`.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use csv::{ByteRecord, StringRecord};
+use serde::Deserialize;
+
+use crate::engine::csv_reader_builder;
+use crate::transactions::{Transaction, CSV_COLUMNS};
+
+/// The options of the CSV readers of transactions.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CsvOptions {
+    /// Whether the first row is a header, otherwise the columns are in the usual order.
+    pub headers: bool,
+    /// What separates the fields, if not a comma (or a tab for `.tsv` files).
+    pub delimiter: Option<Delimiter>,
+    /// The columns of the input named differently than ours.
+    pub column_map: ColumnMap,
+    /// How the records are parsed.
+    pub parser: CsvParser,
+    /// Whether malformed records are skipped, and kept in
+    /// [`ProcessingStats::malformed`](crate::engine::ProcessingStats::malformed), instead of failing.
+    pub lenient: bool,
+    /// How the amounts are written.
+    pub amount_locale: AmountLocale,
+}
+
+/// How the CSV records are parsed into transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CsvParser {
+    /// With serde.
+    #[default]
+    Serde,
+    /// Straight from the bytes of the records, falling back to serde for the ones it can't parse.
+    Fast,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            headers: true,
+            delimiter: None,
+            column_map: ColumnMap::default(),
+            parser: CsvParser::default(),
+            lenient: false,
+            amount_locale: AmountLocale::default(),
+        }
+    }
+}
+
+/// The separators of the amounts of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AmountLocale {
+    /// A point as the decimal separator, and no thousands separators.
+    #[default]
+    Strict,
+    /// A point as the decimal separator, and commas or spaces as the thousands separators.
+    Us,
+    /// A comma as the decimal separator, and points or spaces as the thousands separators.
+    Eu,
+}
+
+impl AmountLocale {
+    /// The amount in the strict format, if it is written in another locale and its separators
+    /// fit it. The rest is left to the usual parsing, and fails there if it isn't a number.
+    pub fn normalize(&self, amount: &str) -> Option<String> {
+        let (decimal, thousands) = match self {
+            AmountLocale::Strict => return None,
+            AmountLocale::Us => ('.', ','),
+            AmountLocale::Eu => (',', '.'),
+        };
+        let (mantissa, exponent) = amount.split_at(amount.find(['e', 'E']).unwrap_or(amount.len()));
+        let (sign, mantissa) = match mantissa.strip_prefix(['-', '+']) {
+            Some(unsigned) => (&mantissa[..1], unsigned),
+            None => ("", mantissa),
+        };
+        let (integer, fraction) = mantissa.split_once(decimal).unwrap_or((mantissa, ""));
+        if !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        // The groups of the thousands, of one to three digits and then of three
+        let groups: Vec<&str> = integer.split([thousands, ' ', '\u{a0}', '\u{202f}']).collect();
+        let grouped = groups.iter().enumerate().all(|(index, group)| {
+            let digits = group.bytes().all(|byte| byte.is_ascii_digit());
+            match index {
+                0 if groups.len() > 1 => digits && (1..=3).contains(&group.len()),
+                0 => digits,
+                _ => digits && group.len() == 3,
+            }
+        });
+        grouped.then(|| format!("{}{}.{}{}", sign, groups.concat(), fraction, exponent))
+    }
+}
+
+/// Deserializes the transaction of a record, with its amount read in `locale`.
+fn deserialize(record: &StringRecord, headers: &StringRecord, locale: AmountLocale) -> Result<Transaction, csv::Error> {
+    if locale != AmountLocale::Strict {
+        let index = headers.iter().position(|header| header == "amount");
+        if let Some((index, amount)) = index.and_then(|index| Some((index, locale.normalize(record.get(index)?)?))) {
+            let mut normalized: StringRecord = record
+                .iter()
+                .enumerate()
+                .map(|(field, value)| if field == index { amount.as_str() } else { value })
+                .collect();
+            normalized.set_position(record.position().cloned());
+            return normalized.deserialize(Some(headers));
+        }
+    }
+    record.deserialize(Some(headers))
+}
+
+impl CsvOptions {
+    /// The options for the input at `location`, which is tab separated if it is a `.tsv` file
+    /// and no other delimiter was set.
+    pub fn for_input(&self, location: &str) -> CsvOptions {
+        let tsv = Path::new(location)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("tsv"));
+        CsvOptions {
+            delimiter: self.delimiter.or(tsv.then_some(Delimiter(b'\t'))),
+            ..self.clone()
+        }
+    }
+
+    /// The CSV settings of the readers of transactions.
+    pub(crate) fn reader_builder(&self) -> csv::ReaderBuilder {
+        let mut builder = csv_reader_builder();
+        builder
+            .has_headers(self.headers)
+            .delimiter(self.delimiter.map_or(b',', |delimiter| delimiter.0));
+        builder
+    }
+
+    /// A reader of the CSV transactions in `reader`, and the headers to deserialize its records with.
+    pub(crate) fn reader<R: Read>(&self, reader: R) -> Result<(csv::Reader<R>, StringRecord), csv::Error> {
+        self.open(reader, csv::Trim::All)
+    }
+
+    /// A reader of the CSV transactions in `reader` for the fast parser, which trims the fields itself.
+    pub(crate) fn fast_reader<R: Read>(&self, reader: R) -> Result<(csv::Reader<R>, FastParser), csv::Error> {
+        let (rdr, headers) = self.open(reader, csv::Trim::Headers)?;
+        Ok((rdr, FastParser::new(headers, self.amount_locale)))
+    }
+
+    fn open<R: Read>(&self, reader: R, trim: csv::Trim) -> Result<(csv::Reader<R>, StringRecord), csv::Error> {
+        let mut rdr = self.reader_builder().trim(trim).from_reader(reader);
+        let headers = match self.positional_headers() {
+            Some(headers) => headers,
+            None => self.column_map.rename(rdr.headers()?),
+        };
+        Ok((rdr, headers))
+    }
+
+    /// The transaction of a record read with [`reader`](CsvOptions::reader), with the given headers.
+    pub(crate) fn deserialize(&self, record: &StringRecord, headers: &StringRecord) -> Result<Transaction, csv::Error> {
+        deserialize(record, headers, self.amount_locale)
+    }
+
+    /// The headers to deserialize the records with when the input has none.
+    pub(crate) fn positional_headers(&self) -> Option<StringRecord> {
+        (!self.headers).then(|| StringRecord::from(CSV_COLUMNS.to_vec()))
+    }
+}
+
+/// Parses untrimmed byte records into transactions without serde, which is several times
+/// faster since the records aren't copied to be trimmed nor checked to be UTF-8 as a whole,
+/// and the fields aren't deserialized one by one. Applying them takes most of a run then,
+/// which is only about 1.6 times faster, see `bench`.
+///
+/// Only the fields of the columns in `CSV_COLUMNS` are parsed, in the same way serde does,
+/// and any record it can't parse, e.g. with hexadecimal ids or an unknown type, is deserialized
+/// with serde instead, which returns the same transaction or error it would have without this.
+/// The only difference is that the columns that aren't in `CSV_COLUMNS` aren't checked to be UTF-8.
+pub(crate) struct FastParser {
+    // The index of each of the `CSV_COLUMNS` in the records, if they have it,
+    // or none if the headers have a column twice and so every record goes to serde
+    columns: Option<[Option<usize>; CSV_COLUMNS.len()]>,
+    headers: StringRecord,
+    amount_locale: AmountLocale,
+}
+
+impl FastParser {
+    /// A parser for the records with the given headers.
+    pub(crate) fn new(headers: StringRecord, amount_locale: AmountLocale) -> Self {
+        let mut columns = [None; CSV_COLUMNS.len()];
+        let mut duplicates = false;
+        for (column, name) in columns.iter_mut().zip(CSV_COLUMNS) {
+            let mut indexes = headers.iter().enumerate().filter(|(_, header)| *header == name);
+            *column = indexes.next().map(|(index, _)| index);
+            duplicates |= indexes.next().is_some();
+        }
+        FastParser {
+            columns: (!duplicates).then_some(columns),
+            headers,
+            amount_locale,
+        }
+    }
+
+    /// The headers the records are parsed with.
+    pub(crate) fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    /// The transaction of a record.
+    pub(crate) fn parse(&self, record: &ByteRecord) -> Result<Transaction, csv::Error> {
+        if let Some(transaction) = self.parse_fields(record) {
+            return Ok(transaction);
+        }
+
+        // What the usual reader does, trimming the record and checking that it is UTF-8.
+        // If it isn't, the transaction can still be deserialized if its own fields are.
+        let mut record = record.clone();
+        record.trim();
+        match StringRecord::from_byte_record(record) {
+            Ok(mut record) => {
+                record.trim();
+                deserialize(&record, &self.headers, self.amount_locale)
+            }
+            Err(error) => error
+                .into_byte_record()
+                .deserialize(Some(self.headers.as_byte_record())),
+        }
+    }
+
+    fn parse_fields(&self, record: &ByteRecord) -> Option<Transaction> {
+        let columns = self.columns.as_ref()?;
+        // The value of a column, `Some(None)` if the record doesn't have it or it's empty,
+        // and `None` if it isn't UTF-8
+        let value = |column: usize| match columns[column]
+            .and_then(|index| record.get(index))
+            .map(<[u8]>::trim_ascii)
+        {
+            None | Some(b"") => Some(None),
+            Some(bytes) => std::str::from_utf8(bytes).ok().map(Some),
+        };
+        Some(Transaction {
+            tx_type: value(0)??.parse().ok()?,
+            client_id: value(1)??.parse().ok()?,
+            tx_id: value(2)??.parse().ok()?,
+            amount: value(3)?
+                .map(|amount| match self.amount_locale.normalize(amount) {
+                    Some(normalized) => normalized.parse(),
+                    None => amount.parse(),
+                })
+                .transpose()
+                .ok()?,
+            reason: value(4)?.map(str::to_string),
+            timestamp: value(5)?.map(str::parse).transpose().ok()?,
+            settles_at: value(6)?.map(str::parse).transpose().ok()?,
+        })
+    }
+}
+
+/// A single ASCII character separating the fields, e.g. `;`, or `tab`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Delimiter(pub u8);
+
+#[derive(Debug)]
+pub struct DelimiterFromStrError(String);
+
+impl Error for DelimiterFromStrError {}
+
+impl fmt::Display for DelimiterFromStrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid delimiter `{}`, expected a single ASCII character or `tab`",
+            self.0
+        )
+    }
+}
+
+impl TryFrom<String> for Delimiter {
+    type Error = DelimiterFromStrError;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FromStr for Delimiter {
+    type Err = DelimiterFromStrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            b"tab" | b"\\t" => Ok(Delimiter(b'\t')),
+            &[byte] if byte.is_ascii() && byte != b'"' && byte != b'\n' => Ok(Delimiter(byte)),
+            _ => Err(DelimiterFromStrError(s.to_string())),
+        }
+    }
+}
+
+/// Maps the names of the columns of the input to the ones of a [`Transaction`](crate::Transaction),
+/// from `CSV_COLUMNS`. Columns that aren't mapped keep their name.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "BTreeMap<String, String>")]
+pub struct ColumnMap(BTreeMap<String, String>);
+
+impl ColumnMap {
+    /// The headers of the input, with the mapped columns renamed.
+    pub fn rename(&self, headers: &StringRecord) -> StringRecord {
+        headers
+            .iter()
+            .map(|header| self.0.get(header).map_or(header, String::as_str))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct ColumnMapFromStrError(String);
+
+impl Error for ColumnMapFromStrError {}
+
+impl fmt::Display for ColumnMapFromStrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid column map `{}`, expected e.g. `customer=client,value=amount` with the columns {}",
+            self.0,
+            CSV_COLUMNS.join(", ")
+        )
+    }
+}
+
+impl TryFrom<BTreeMap<String, String>> for ColumnMap {
+    type Error = ColumnMapFromStrError;
+    fn try_from(columns: BTreeMap<String, String>) -> Result<Self, Self::Error> {
+        match columns
+            .iter()
+            .find(|(_, column)| !CSV_COLUMNS.contains(&column.as_str()))
+        {
+            Some((name, column)) => Err(ColumnMapFromStrError(format!("{}={}", name, column))),
+            None => Ok(ColumnMap(columns)),
+        }
+    }
+}
+
+impl FromStr for ColumnMap {
+    type Err = ColumnMapFromStrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut columns = BTreeMap::new();
+        for mapping in s.split(',') {
+            let (name, column) = mapping
+                .split_once('=')
+                .ok_or_else(|| ColumnMapFromStrError(s.to_string()))?;
+            columns.insert(name.trim().to_string(), column.trim().to_string());
+        }
+        columns.try_into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AmountLocale, ColumnMap, CsvOptions, Delimiter};
+    use crate::amount::amount;
+    use crate::transactions::{Amount, Transaction, TransactionType};
+
+    #[test]
+    fn test_column_map() {
+        let options = CsvOptions {
+            column_map: "txn_type=type, customer=client, reference=tx, value=amount"
+                .parse()
+                .unwrap(),
+            ..CsvOptions::default()
+        };
+        let input = "txn_type, customer, reference, value, channel\ndeposit, 1, 7, 2.5, web";
+        let (mut rdr, headers) = options.reader(input.as_bytes()).unwrap();
+        let transaction: Transaction = rdr
+            .records()
+            .next()
+            .unwrap()
+            .unwrap()
+            .deserialize(Some(&headers))
+            .unwrap();
+        assert_eq!(transaction.tx_type, TransactionType::Deposit);
+        assert_eq!(
+            (transaction.client_id, transaction.tx_id, transaction.amount),
+            (1, 7, Some(amount(2.5)))
+        );
+
+        assert!("customer=customer_id".parse::<ColumnMap>().is_err());
+        assert!("customer".parse::<ColumnMap>().is_err());
+    }
+
+    #[test]
+    fn test_no_headers() {
+        let options = CsvOptions {
+            headers: false,
+            ..CsvOptions::default()
+        };
+        let (mut rdr, headers) = options
+            .reader("deposit, 1, 1, 2.5\nwithdrawal, 1, 2, 1.0".as_bytes())
+            .unwrap();
+        let transactions: Vec<Transaction> = rdr
+            .records()
+            .map(|record| record.unwrap().deserialize(Some(&headers)).unwrap())
+            .collect();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].amount, Some(amount(2.5)));
+        assert_eq!(transactions[1].tx_type, TransactionType::Withdrawal);
+    }
+
+    #[test]
+    fn test_delimiter() {
+        let options = CsvOptions::default();
+        assert_eq!(options.for_input("feed.TSV").delimiter, Some(Delimiter(b'\t')));
+        assert_eq!(options.for_input("feed.csv").delimiter, None);
+
+        let options = CsvOptions {
+            delimiter: Some(";".parse().unwrap()),
+            ..CsvOptions::default()
+        };
+        assert_eq!(options.for_input("feed.tsv").delimiter, Some(Delimiter(b';')));
+        let (mut rdr, headers) = options
+            .reader("type; client; tx; amount\ndeposit; 1; 1; 2.5".as_bytes())
+            .unwrap();
+        let transaction: Transaction = rdr
+            .records()
+            .next()
+            .unwrap()
+            .unwrap()
+            .deserialize(Some(&headers))
+            .unwrap();
+        assert_eq!(transaction.amount, Some(amount(2.5)));
+
+        assert_eq!("tab".parse::<Delimiter>().unwrap(), Delimiter(b'\t'));
+        assert!(";;".parse::<Delimiter>().is_err());
+        assert!("é".parse::<Delimiter>().is_err());
+    }
+
+    #[test]
+    fn test_amount_locale() {
+        let input = "type, client, tx, amount
+            deposit, 1, 1,\"1.234,5\"
+            deposit, 1, 2, 1 000
+            deposit, 1, 3,\"2,5e1\"
+            deposit, 1, 4,\"1,5\"";
+        let amounts = |options: &CsvOptions| -> Vec<Option<Amount>> {
+            let (mut rdr, headers) = options.reader(input.as_bytes()).unwrap();
+            let serde: Vec<_> = rdr
+                .records()
+                .map(|record| {
+                    options
+                        .deserialize(&record.unwrap(), &headers)
+                        .ok()
+                        .and_then(|transaction| transaction.amount)
+                })
+                .collect();
+            let (mut rdr, parser) = options.fast_reader(input.as_bytes()).unwrap();
+            let fast: Vec<_> = rdr
+                .byte_records()
+                .map(|record| {
+                    parser
+                        .parse(&record.unwrap())
+                        .ok()
+                        .and_then(|transaction| transaction.amount)
+                })
+                .collect();
+            assert_eq!(serde, fast);
+            serde
+        };
+        let eu = CsvOptions {
+            amount_locale: AmountLocale::Eu,
+            ..CsvOptions::default()
+        };
+        assert_eq!(
+            amounts(&eu),
+            [Some(1234.5), Some(1000.0), Some(25.0), Some(1.5)].map(|value| value.map(amount))
+        );
+        let us = CsvOptions {
+            amount_locale: AmountLocale::Us,
+            ..CsvOptions::default()
+        };
+        assert_eq!(amounts(&us), [None, Some(amount(1000.0)), None, None]);
+        assert_eq!(amounts(&CsvOptions::default()), [None, None, None, None]);
+
+        assert_eq!(AmountLocale::Us.normalize("-12,345,678.9").unwrap(), "-12345678.9");
+        assert_eq!(AmountLocale::Us.normalize("1.5E-3").unwrap(), "1.5E-3");
+        assert!(AmountLocale::Eu.normalize("1.23,4").is_none());
+        assert!(AmountLocale::Strict.normalize("1234.5").is_none());
+    }
+
+    #[test]
+    fn test_fast_parser() {
+        let input = "type, client, tx, amount, reason, timestamp, settles_at
+            deposit, 1, 1, 2.5, , 100, 200
+            adjustment, 1, 2, -1, typo, ,
+            dispute, 0x10, 1
+            deposit, 1, 3, lots
+            deposit, 1, 5, \u{a0}3\u{a0}
+            withdrawal, 1, 6,  \t
+            withdrawl, 1, 4, 1";
+        let (mut rdr, headers) = CsvOptions::default().reader(input.as_bytes()).unwrap();
+        let (mut fast_rdr, parser) = CsvOptions::default().fast_reader(input.as_bytes()).unwrap();
+        for (record, fast_record) in rdr.records().zip(fast_rdr.byte_records()) {
+            let serde: Result<Transaction, _> = record.unwrap().deserialize(Some(&headers));
+            let fast = parser.parse(&fast_record.unwrap());
+            assert_eq!(format!("{:?}", fast), format!("{:?}", serde));
+        }
+
+        let (_, parser) = CsvOptions::default()
+            .fast_reader("type, client, tx, client".as_bytes())
+            .unwrap();
+        assert!(parser.columns.is_none());
+    }
+}
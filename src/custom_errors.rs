@@ -1,24 +1,305 @@
 use std::{error::Error, fmt};
 
+pub use crate::reasons::{TransactionErrorType, TransactionRecordError};
+
+#[derive(Debug)]
+pub enum SchemaErrorType {
+    MissingColumn(&'static str),
+    InvalidColumnType(&'static str),
+    MissingValue(&'static str),
+    OutOfRange(&'static str),
+    IncompatibleSchema(String),
+}
+
+/// The input file doesn't have the columns we expect for a transaction.
+#[derive(Debug)]
+pub struct InputSchemaError {
+    pub error_type: SchemaErrorType,
+}
+
+impl Error for InputSchemaError {}
+
+impl fmt::Display for InputSchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.error_type {
+            SchemaErrorType::MissingColumn(column) => write!(f, "The input is missing the `{}` column", column),
+            SchemaErrorType::InvalidColumnType(column) => write!(f, "The `{}` column has an unsupported type", column),
+            SchemaErrorType::MissingValue(column) => {
+                write!(f, "A record is missing a value for the `{}` column", column)
+            }
+            SchemaErrorType::OutOfRange(column) => {
+                write!(f, "A record has an out of range value for the `{}` column", column)
+            }
+            SchemaErrorType::IncompatibleSchema(reason) => write!(f, "The input schema is incompatible: {}", reason),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InputSourceErrorType {
+    UnsupportedScheme(String),
+    InvalidUrl(String),
+    MissingCredentials(&'static str),
+    NotSeekable,
+    NotFollowable,
+    Truncated,
+    NotDecryptable,
+    NotTenanted,
+}
+
+/// The input location can't be opened.
+#[derive(Debug)]
+pub struct InputSourceError {
+    pub error_type: InputSourceErrorType,
+}
+
+impl Error for InputSourceError {}
+
+impl fmt::Display for InputSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.error_type {
+            InputSourceErrorType::UnsupportedScheme(scheme) => {
+                write!(f, "Reading the input from `{}` URLs is not supported", scheme)
+            }
+            InputSourceErrorType::InvalidUrl(url) => write!(f, "Invalid input URL `{}`", url),
+            InputSourceErrorType::MissingCredentials(variable) => {
+                write!(f, "The `{}` environment variable is required to read from S3", variable)
+            }
+            InputSourceErrorType::NotSeekable => write!(f, "This input format can only be read from a local file"),
+            InputSourceErrorType::NotFollowable => write!(f, "Only CSV files that aren't encrypted can be followed"),
+            InputSourceErrorType::Truncated => write!(f, "The input file was truncated while following it"),
+            InputSourceErrorType::NotDecryptable => write!(
+                f,
+                "Only CSV and Avro input can be decrypted, and PGP only from local files"
+            ),
+            InputSourceErrorType::NotTenanted => write!(f, "Only CSV input can have a tenant column"),
+        }
+    }
+}
+
 #[derive(Debug)]
-pub enum TransactionErrorType {
-    NoDepositAmount,
-    NoWithdrawalAmount
+pub enum SnapshotErrorType {
+    NotASnapshot,
+    Truncated,
+    UnsupportedVersion(u16),
+    OtherIdWidth,
 }
 
+/// A state snapshot can't be restored.
 #[derive(Debug)]
-pub struct TransactionRecordError {
-    pub error_type: TransactionErrorType
+pub struct SnapshotError {
+    pub error_type: SnapshotErrorType,
 }
 
-impl Error for TransactionRecordError {}
+impl Error for SnapshotError {}
 
-impl fmt::Display for TransactionRecordError {
+impl fmt::Display for SnapshotError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.error_type {
-            TransactionErrorType::NoDepositAmount => write!(f, "A deposit must have an amount"),
-            TransactionErrorType::NoWithdrawalAmount => write!(f, "An withdrawal must have an amount")
+            SnapshotErrorType::NotASnapshot => write!(f, "The file is not a payments engine snapshot"),
+            SnapshotErrorType::Truncated => write!(f, "The snapshot is truncated"),
+            SnapshotErrorType::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "The snapshot is of version {}, newer than this engine supports",
+                    version
+                )
+            }
+            SnapshotErrorType::OtherIdWidth => {
+                write!(f, "The snapshot was written by a build with the other width of ids or amounts, see `wide-ids`, `wide-amounts` and `fixed-amounts`")
+            }
         }
-        
     }
-}
\ No newline at end of file
+}
+
+/// An error while processing the record at a given line of the input.
+#[derive(Debug)]
+pub struct LineError {
+    pub line: u64,
+    pub error: Box<dyn Error>,
+}
+
+impl Error for LineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.error)
+    }
+}
+
+#[derive(Debug)]
+pub enum SavepointErrorType {
+    Unknown,
+    TooOld,
+}
+
+/// The engine can't roll back to a savepoint, see
+/// [`PaymentsEngine::rollback_to`](crate::engine::PaymentsEngine::rollback_to).
+#[derive(Debug)]
+pub struct SavepointError {
+    pub name: String,
+    pub error_type: SavepointErrorType,
+}
+
+impl Error for SavepointError {}
+
+impl fmt::Display for SavepointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.error_type {
+            SavepointErrorType::Unknown => write!(f, "There is no savepoint `{}`", self.name),
+            SavepointErrorType::TooOld => {
+                write!(
+                    f,
+                    "The savepoint `{}` is older than the transactions that can be undone",
+                    self.name
+                )
+            }
+        }
+    }
+}
+
+/// An error while processing one of several input files.
+#[derive(Debug)]
+pub struct FileError {
+    pub file: String,
+    pub error: Box<dyn Error>,
+}
+
+impl Error for FileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.error.as_ref())
+    }
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.file, self.error)
+    }
+}
+
+/// The kinds of failures that abort a run, so callers can tell them apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The arguments or options are invalid.
+    BadArguments,
+    /// Reading the input or writing the output failed.
+    Io,
+    /// The input is malformed.
+    Parse,
+    /// A record is well formed but invalid, e.g. a deposit without an amount.
+    Semantic,
+    /// Anything else.
+    Internal,
+}
+
+impl FailureKind {
+    /// A stable identifier of the kind of failure.
+    pub fn code(&self) -> &'static str {
+        match self {
+            FailureKind::BadArguments => "bad_arguments",
+            FailureKind::Io => "io_error",
+            FailureKind::Parse => "parse_error",
+            FailureKind::Semantic => "semantic_error",
+            FailureKind::Internal => "internal_error",
+        }
+    }
+
+    /// Classifies an error returned by the engine.
+    pub fn of(err: &(dyn Error + 'static)) -> FailureKind {
+        if let Some(err) = err.downcast_ref::<LineError>() {
+            return FailureKind::of(err.error.as_ref());
+        }
+        if let Some(err) = err.downcast_ref::<FileError>() {
+            return FailureKind::of(err.error.as_ref());
+        }
+        if let Some(err) = err.downcast_ref::<InputSourceError>() {
+            return match err.error_type {
+                InputSourceErrorType::Truncated => FailureKind::Io,
+                _ => FailureKind::BadArguments,
+            };
+        }
+        if let Some(err) = err.downcast_ref::<csv::Error>() {
+            return match err.kind() {
+                csv::ErrorKind::Io(err) => FailureKind::of(err),
+                _ => FailureKind::Parse,
+            };
+        }
+        // Decryption errors surface while the input is read
+        #[cfg(feature = "decrypt")]
+        if let Some(err) = err
+            .downcast_ref::<std::io::Error>()
+            .and_then(std::io::Error::get_ref)
+            .filter(|err| err.is::<crate::decrypt::DecryptError>())
+        {
+            return FailureKind::of(err);
+        }
+        if err.is::<std::io::Error>() {
+            return FailureKind::Io;
+        }
+        #[cfg(feature = "decrypt")]
+        if let Some(err) = err.downcast_ref::<crate::decrypt::DecryptError>() {
+            return match err.error_type {
+                crate::decrypt::DecryptErrorType::InvalidIdentity
+                | crate::decrypt::DecryptErrorType::NoMatchingIdentity => FailureKind::BadArguments,
+                _ => FailureKind::Parse,
+            };
+        }
+        if err.is::<InputSchemaError>()
+            || err.is::<SnapshotError>()
+            || err.is::<crate::transactions::TransactionTypeFromStrError>()
+        {
+            return FailureKind::Parse;
+        }
+        if err.is::<TransactionRecordError>()
+            || err.is::<crate::merge::DuplicateClientError>()
+            || err.is::<crate::event_log::BrokenChainError>()
+        {
+            return FailureKind::Semantic;
+        }
+        if err.is::<toml::de::Error>() || err.is::<SavepointError>() {
+            return FailureKind::BadArguments;
+        }
+        #[cfg(feature = "signatures")]
+        if err.is::<crate::signatures::PublicKeyError>() {
+            return FailureKind::BadArguments;
+        }
+        #[cfg(feature = "http")]
+        if err.is::<ureq::Error>() {
+            return FailureKind::Io;
+        }
+        #[cfg(feature = "sqlite")]
+        if err.is::<rusqlite::Error>() {
+            return FailureKind::Io;
+        }
+        #[cfg(feature = "arrow")]
+        if err.is::<arrow_schema::ArrowError>() {
+            return FailureKind::Parse;
+        }
+        #[cfg(feature = "parquet")]
+        if err.is::<parquet::errors::ParquetError>() {
+            return FailureKind::Parse;
+        }
+        #[cfg(feature = "avro")]
+        if err.is::<apache_avro::Error>() {
+            return FailureKind::Parse;
+        }
+        FailureKind::Internal
+    }
+}
+
+/// Whether writing the output failed because the reader went away, e.g. `head` at the other
+/// end of a pipe exited, which stops the run without being a failure of its own.
+pub fn is_broken_pipe(err: &(dyn Error + 'static)) -> bool {
+    if let Some(err) = err.downcast_ref::<FileError>() {
+        return is_broken_pipe(err.error.as_ref());
+    }
+    if let Some(csv::ErrorKind::Io(err)) = err.downcast_ref::<csv::Error>().map(csv::Error::kind) {
+        return err.kind() == std::io::ErrorKind::BrokenPipe;
+    }
+    err.downcast_ref::<std::io::Error>()
+        .is_some_and(|err| err.kind() == std::io::ErrorKind::BrokenPipe)
+}
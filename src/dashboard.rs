@@ -0,0 +1,232 @@
+//! A terminal dashboard with live statistics about a running engine.
+
+use std::error::Error;
+use std::io;
+use std::time::{Duration, Instant};
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::Style;
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::accounts::{AccountBalance, AccountStatus};
+use crate::amount::AmountValue;
+use crate::engine::PaymentsEngine;
+use crate::queue::QueueMetrics;
+use crate::transactions::{Amount, Transaction};
+
+/// How many accounts to list by held funds.
+const TOP_ACCOUNTS: usize = 10;
+
+/// What the dashboard shows, taken from the engine at each refresh.
+#[derive(Debug, Default)]
+pub struct DashboardView {
+    pub processed: u64,
+    pub rejected: u64,
+    /// Transactions processed per second since the previous refresh.
+    pub throughput: f64,
+    pub accounts: usize,
+    pub locked_accounts: usize,
+    /// The accounts with the most held funds, first the highest.
+    pub top_held: Vec<AccountBalance>,
+    /// The last rejected transactions, first the most recent.
+    pub recent_rejections: Vec<Transaction>,
+    /// The queue of transactions waiting to be applied, in follow and serve modes.
+    pub queue: Option<QueueMetrics>,
+}
+
+impl DashboardView {
+    pub fn of(engine: &PaymentsEngine, throughput: f64) -> Result<Self, Box<dyn Error>> {
+        let mut balances = engine.balances()?;
+        let locked_accounts = balances
+            .iter()
+            .filter(|account_balance| account_balance.is_locked())
+            .count();
+        let accounts = balances.len();
+        balances.retain(|account_balance| account_balance.held > Amount::ZERO);
+        balances.sort_by(|a, b| b.held.total_cmp(&a.held));
+        balances.truncate(TOP_ACCOUNTS);
+
+        Ok(DashboardView {
+            processed: engine.stats().processed,
+            rejected: engine.stats().rejected,
+            throughput,
+            accounts,
+            locked_accounts,
+            top_held: balances,
+            recent_rejections: engine.recent_rejections().rev().cloned().collect(),
+            queue: engine.queue_metrics(),
+        })
+    }
+
+    pub fn render(&self, frame: &mut Frame) {
+        let [summary, tables] = Layout::vertical([Constraint::Length(5), Constraint::Fill(1)]).areas(frame.area());
+        let [top_held, rejections] = Layout::horizontal([Constraint::Percentage(50); 2]).areas(tables);
+
+        let mut summary_text = vec![
+            Line::from(format!(
+                "Processed: {}   Rejected: {}   Throughput: {:.0} tx/s",
+                self.processed, self.rejected, self.throughput
+            )),
+            Line::from(format!(
+                "Accounts: {}   Locked accounts: {}",
+                self.accounts, self.locked_accounts
+            )),
+        ];
+        if let Some(queue) = &self.queue {
+            summary_text.push(Line::from(format!(
+                "Queue: {}/{}   Max queued: {}   Waited for room: {} times",
+                queue.depth, queue.capacity, queue.max_depth, queue.full
+            )));
+        }
+        frame.render_widget(
+            Paragraph::new(summary_text).block(Block::bordered().title(" Payments engine (q to quit) ")),
+            summary,
+        );
+
+        let rows = self.top_held.iter().map(|account_balance| {
+            Row::new([
+                account_balance.client.to_string(),
+                format!("{:.4}", account_balance.held),
+                format!("{:.4}", account_balance.available),
+                match account_balance.status {
+                    AccountStatus::Active => "",
+                    status => status.as_str(),
+                }
+                .to_string(),
+            ])
+        });
+        let widths = [
+            Constraint::Length(6),
+            Constraint::Fill(1),
+            Constraint::Fill(1),
+            Constraint::Length(6),
+        ];
+        frame.render_widget(
+            Table::new(rows, widths)
+                .header(Row::new(["client", "held", "available", ""]).style(Style::new().bold()))
+                .block(Block::bordered().title(" Top accounts by held funds ")),
+            top_held,
+        );
+
+        let rows = self.recent_rejections.iter().map(|transaction| {
+            Row::new([
+                format!("{:?}", transaction.tx_type).to_lowercase(),
+                transaction.client_id.to_string(),
+                transaction.tx_id.to_string(),
+                transaction
+                    .amount
+                    .map(|amount| format!("{:.4}", amount))
+                    .unwrap_or_default(),
+            ])
+        });
+        let widths = [
+            Constraint::Length(10),
+            Constraint::Length(6),
+            Constraint::Length(10),
+            Constraint::Fill(1),
+        ];
+        frame.render_widget(
+            Table::new(rows, widths)
+                .header(Row::new(["type", "client", "tx", "amount"]).style(Style::new().bold()))
+                .block(Block::bordered().title(" Recent rejections ")),
+            rejections,
+        );
+    }
+}
+
+/// Takes over the terminal until it is dropped.
+pub struct Dashboard {
+    terminal: DefaultTerminal,
+    last_refresh: Instant,
+    last_processed: u64,
+}
+
+impl Dashboard {
+    /// How often to redraw the dashboard.
+    pub const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+    pub fn new() -> io::Result<Self> {
+        Ok(Dashboard {
+            terminal: ratatui::try_init()?,
+            last_refresh: Instant::now(),
+            last_processed: 0,
+        })
+    }
+
+    /// Redraws the dashboard with the current state of the engine.
+    pub fn draw(&mut self, engine: &PaymentsEngine) -> Result<(), Box<dyn Error>> {
+        let elapsed = self.last_refresh.elapsed().as_secs_f64();
+        let processed = engine.stats().processed;
+        let throughput = if elapsed > 0.0 {
+            (processed - self.last_processed) as f64 / elapsed
+        } else {
+            0.0
+        };
+        self.last_refresh = Instant::now();
+        self.last_processed = processed;
+
+        let view = DashboardView::of(engine, throughput)?;
+        self.terminal.draw(|frame| view.render(frame))?;
+        Ok(())
+    }
+
+    /// Whether `q`, `Esc` or `Ctrl-C` was pressed since the last call.
+    pub fn quit_requested(&self) -> io::Result<bool> {
+        while event::poll(Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || ctrl_c)
+                {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    use super::DashboardView;
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_dashboard_view() {
+        let mut engine = PaymentsEngine::new();
+        engine.process_csv(Path::new("sample_files/dispute.csv")).unwrap();
+        engine
+            .process_csv(Path::new("sample_files/withdrawal_insufficient_funds.csv"))
+            .unwrap();
+
+        let view = DashboardView::of(&engine, 12.0).unwrap();
+        assert_eq!(view.top_held.len(), 1);
+        assert_eq!(view.top_held[0].client, 1);
+        assert_eq!(view.recent_rejections.len(), engine.stats().rejected as usize);
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+        terminal.draw(|frame| view.render(frame)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("Throughput: 12 tx/s"));
+        assert!(screen.contains("withdrawal"));
+    }
+}
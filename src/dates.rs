@@ -0,0 +1,40 @@
+//! Calendar dates, from the days since the Unix epoch.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The number of seconds in a day.
+pub const SECS_PER_DAY: u64 = 86_400;
+
+/// The `(year, month, day)` of the days since the Unix epoch,
+/// see http://howardhinnant.github.io/date_algorithms.html
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month as u32, day as u32)
+}
+
+/// Formats a time as an RFC 3339 UTC timestamp with milliseconds, e.g. `2024-06-01T12:30:00.250Z`.
+pub fn rfc3339(time: SystemTime) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = elapsed.as_secs();
+    let (days, secs_of_day) = ((secs / SECS_PER_DAY) as i64, secs % SECS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3_600,
+        secs_of_day % 3_600 / 60,
+        secs_of_day % 60,
+        elapsed.subsec_millis()
+    )
+}
@@ -0,0 +1,238 @@
+//! Decrypts encrypted input in memory, so that the plaintext transactions are never
+//! written to disk. The input is either an [age](https://age-encryption.org/v1) file for
+//! the X25519 identities of an identity file, e.g. the one written by `age-keygen`,
+//! decrypted with the `age` crate, or an OpenPGP one, which is decrypted by `gpg` with the
+//! keys of its keyring.
+//!
+//! The whole input is decrypted and authenticated before any of it is read, so that none of
+//! the rows of a file that was tampered with or truncated are applied, at the cost of
+//! keeping its plaintext in memory.
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use crate::custom_errors::{InputSourceError, InputSourceErrorType};
+use crate::input::{self, InputIo};
+
+/// How the input is decrypted.
+#[derive(Debug, Clone)]
+pub enum Decryption {
+    /// With the first of the identities the file was encrypted for.
+    Age(Vec<AgeIdentity>),
+    /// With `gpg --decrypt`, only for local files.
+    Pgp,
+}
+
+impl Decryption {
+    /// Opens the encrypted input at `location` for reading its plaintext.
+    pub fn open(&self, location: &str, io: InputIo) -> Result<Box<dyn Read>, Box<dyn Error>> {
+        match self {
+            Decryption::Age(identities) => Ok(Box::new(Cursor::new(decrypt_age(
+                input::open_with(location, io)?,
+                identities,
+            )?))),
+            Decryption::Pgp => {
+                if input::is_url(location) {
+                    return Err(Box::new(InputSourceError {
+                        error_type: InputSourceErrorType::NotDecryptable,
+                    }));
+                }
+                Ok(Box::new(Cursor::new(decrypt_pgp(File::open(location)?)?)))
+            }
+        }
+    }
+}
+
+/// Why encrypted input can't be decrypted.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecryptErrorType {
+    InvalidIdentity,
+    InvalidHeader,
+    NoMatchingIdentity,
+    InvalidMac,
+    InvalidPayload,
+    GpgFailed,
+}
+
+/// The encrypted input can't be decrypted.
+#[derive(Debug)]
+pub struct DecryptError {
+    pub error_type: DecryptErrorType,
+}
+
+impl Error for DecryptError {}
+
+impl fmt::Display for DecryptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.error_type {
+            DecryptErrorType::InvalidIdentity => write!(f, "Invalid age identity, expected AGE-SECRET-KEY-1..."),
+            DecryptErrorType::InvalidHeader => write!(f, "The input isn't an age encrypted file"),
+            DecryptErrorType::NoMatchingIdentity => write!(f, "The input wasn't encrypted for any of the identities"),
+            DecryptErrorType::InvalidMac => write!(f, "The header of the encrypted input was tampered with"),
+            DecryptErrorType::InvalidPayload => write!(f, "The encrypted input is truncated or was tampered with"),
+            DecryptErrorType::GpgFailed => write!(f, "gpg couldn't decrypt the input"),
+        }
+    }
+}
+
+impl DecryptError {
+    fn new(error_type: DecryptErrorType) -> Self {
+        DecryptError { error_type }
+    }
+}
+
+impl From<age::DecryptError> for DecryptError {
+    fn from(error: age::DecryptError) -> Self {
+        DecryptError::new(match error {
+            age::DecryptError::NoMatchingKeys => DecryptErrorType::NoMatchingIdentity,
+            age::DecryptError::InvalidMac => DecryptErrorType::InvalidMac,
+            age::DecryptError::DecryptionFailed | age::DecryptError::Io(_) => DecryptErrorType::InvalidPayload,
+            _ => DecryptErrorType::InvalidHeader,
+        })
+    }
+}
+
+/// The X25519 key of an age recipient.
+#[derive(Clone)]
+pub struct AgeIdentity(age::x25519::Identity);
+
+impl fmt::Debug for AgeIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AgeIdentity").finish_non_exhaustive()
+    }
+}
+
+impl AgeIdentity {
+    /// The identities of an identity file, one `AGE-SECRET-KEY-1...` per line,
+    /// skipping the empty lines and the `#` comments.
+    pub fn read_file(text: &str) -> Result<Vec<AgeIdentity>, DecryptError> {
+        let identities: Vec<AgeIdentity> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(AgeIdentity::parse)
+            .collect::<Result<_, _>>()?;
+        if identities.is_empty() {
+            return Err(DecryptError::new(DecryptErrorType::InvalidIdentity));
+        }
+        Ok(identities)
+    }
+
+    /// An identity in its Bech32 encoding, `AGE-SECRET-KEY-1...`.
+    pub fn parse(s: &str) -> Result<AgeIdentity, DecryptError> {
+        age::x25519::Identity::from_str(s)
+            .map(AgeIdentity)
+            .map_err(|_| DecryptError::new(DecryptErrorType::InvalidIdentity))
+    }
+}
+
+/// The most bytes the header of an age file is read for, far more than the stanzas of
+/// a few recipients take, so that an input that never ends its header can't take all
+/// the memory.
+const MAX_AGE_HEADER_SIZE: u64 = 64 * 1024;
+
+/// Decrypts an age file for the first of `identities` it was encrypted for, returning its
+/// plaintext once the header and every chunk of the payload are authenticated.
+pub fn decrypt_age<R: Read>(mut input: R, identities: &[AgeIdentity]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut start = vec![];
+    input.by_ref().take(MAX_AGE_HEADER_SIZE).read_to_end(&mut start)?;
+    // The header ends with the line of its MAC, which no stanza has as its base64 can't
+    let footer = start.windows(5).position(|window| window == b"\n--- ");
+    if !footer.is_some_and(|footer| start[footer + 1..].contains(&b'\n')) {
+        return Err(Box::new(DecryptError::new(DecryptErrorType::InvalidHeader)));
+    }
+
+    let decryptor =
+        age::Decryptor::new_buffered(BufReader::new(start.as_slice().chain(input))).map_err(DecryptError::from)?;
+    let mut payload = decryptor
+        .decrypt(identities.iter().map(|identity| &identity.0 as &dyn age::Identity))
+        .map_err(DecryptError::from)?;
+    let mut plaintext = vec![];
+    payload
+        .read_to_end(&mut plaintext)
+        .map_err(|error| match error.kind() {
+            // A chunk that doesn't authenticate, or the input ending before the last chunk
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+                Box::new(DecryptError::new(DecryptErrorType::InvalidPayload)) as Box<dyn Error>
+            }
+            _ => Box::new(error),
+        })?;
+    Ok(plaintext)
+}
+
+/// Decrypts an OpenPGP file with `gpg`, returning its plaintext once gpg exited successfully,
+/// as it only checks the integrity of the file once it decrypted all of it.
+pub fn decrypt_pgp(file: File) -> Result<Vec<u8>, Box<dyn Error>> {
+    let output = Command::new("gpg")
+        .args(["--batch", "--quiet", "--decrypt"])
+        .stdin(file)
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(Box::new(DecryptError::new(DecryptErrorType::GpgFailed)));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::process::Command;
+
+    use super::{decrypt_age, decrypt_pgp, AgeIdentity, DecryptError, DecryptErrorType};
+
+    fn error_type(error: Box<dyn std::error::Error>) -> DecryptErrorType {
+        error.downcast::<DecryptError>().unwrap().error_type
+    }
+
+    #[test]
+    fn test_decrypt_age() {
+        let identities = AgeIdentity::read_file(&fs::read_to_string("sample_files/age_identity.txt").unwrap()).unwrap();
+        let encrypted = fs::read("sample_files/multiple_clients.csv.age").unwrap();
+        let plaintext = decrypt_age(encrypted.as_slice(), &identities).unwrap();
+        assert_eq!(plaintext, fs::read("sample_files/multiple_clients.csv").unwrap());
+        assert!(
+            AgeIdentity::parse("AGE-SECRET-KEY-1QYPQXPQ9QCRSSZG2PVXQ6RS0ZQG3YYC5Z5TPWXQERGD3C8G7RUSQGPQYEF").is_err()
+        );
+
+        let other =
+            AgeIdentity::parse("AGE-SECRET-KEY-1QGPQYQSZQGPQYQSZQGPQYQSZQGPQYQSZQGPQYQSZQGPQYQSZQGPQ0N3JDM").unwrap();
+        let err = decrypt_age(encrypted.as_slice(), &[other]).err().unwrap();
+        assert_eq!(error_type(err), DecryptErrorType::NoMatchingIdentity);
+
+        // Tampering with the header or the payload, or truncating it, fails before any plaintext
+        let mut tampered = encrypted.clone();
+        tampered[24] ^= 1;
+        assert!(decrypt_age(tampered.as_slice(), &identities).is_err());
+        let mut tampered = encrypted.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        let err = decrypt_age(tampered.as_slice(), &identities).err().unwrap();
+        assert_eq!(error_type(err), DecryptErrorType::InvalidPayload);
+        let err = decrypt_age(&encrypted[..encrypted.len() - 1], &identities)
+            .err()
+            .unwrap();
+        assert_eq!(error_type(err), DecryptErrorType::InvalidPayload);
+
+        // A header that never ends is only read so far
+        let endless = b"age-encryption.org/v1\n-> X25519 AAAA\n".repeat(10_000);
+        let err = decrypt_age(endless.as_slice(), &identities).err().unwrap();
+        assert_eq!(error_type(err), DecryptErrorType::InvalidHeader);
+    }
+
+    #[test]
+    fn test_decrypt_pgp() {
+        // Only where gpg is installed
+        if Command::new("gpg").arg("--version").output().is_err() {
+            return;
+        }
+        // A file that isn't encrypted makes gpg fail, whatever it output
+        let err = decrypt_pgp(File::open("sample_files/multiple_clients.csv").unwrap())
+            .err()
+            .unwrap();
+        assert_eq!(error_type(err), DecryptErrorType::GpgFailed);
+    }
+}
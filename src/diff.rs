@@ -0,0 +1,154 @@
+//! Compares two states of the engine, e.g. to review what a re-run with corrected input changed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io::{Read, Write};
+
+use serde::Serialize;
+
+use crate::accounts::AccountBalance;
+use crate::amount::AmountValue;
+use crate::engine::PaymentsEngine;
+use crate::reconcile::read_expected;
+use crate::snapshot::is_snapshot;
+use crate::transactions::{Amount, ClientId};
+
+/// Reads the balances of a state, either a snapshot or a report CSV.
+pub fn read_state<R: Read>(mut reader: R) -> Result<Vec<AccountBalance>, Box<dyn Error>> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    if !is_snapshot(&bytes) {
+        return read_expected(bytes.as_slice());
+    }
+    let mut engine = PaymentsEngine::new();
+    engine.restore_snapshot(bytes.as_slice())?;
+    engine.balances()
+}
+
+/// A client whose available or held funds, or whether it is locked, differ between two states.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub client: ClientId,
+    /// `None` if the client isn't in the first state.
+    pub before: Option<AccountBalance>,
+    /// `None` if the client isn't in the second state.
+    pub after: Option<AccountBalance>,
+}
+
+/// What the report shows of a balance, for the columns compared.
+fn compared(account_balance: Option<&AccountBalance>) -> Option<(String, String, bool)> {
+    account_balance.map(|account_balance| {
+        (
+            format!("{:.4}", account_balance.available),
+            format!("{:.4}", account_balance.held),
+            account_balance.is_locked(),
+        )
+    })
+}
+
+fn by_client(balances: &[AccountBalance]) -> BTreeMap<ClientId, &AccountBalance> {
+    balances
+        .iter()
+        .map(|account_balance| (account_balance.client, account_balance))
+        .collect()
+}
+
+/// The clients whose balances differ between the two states, sorted by client id.
+pub fn diff(before: &[AccountBalance], after: &[AccountBalance]) -> Vec<Change> {
+    let (before, after) = (by_client(before), by_client(after));
+    let clients: BTreeSet<ClientId> = before.keys().chain(after.keys()).copied().collect();
+    clients
+        .into_iter()
+        .filter(|client| compared(before.get(client).copied()) != compared(after.get(client).copied()))
+        .map(|client| Change {
+            client,
+            before: before.get(&client).map(|&account_balance| account_balance.clone()),
+            after: after.get(&client).map(|&account_balance| account_balance.clone()),
+        })
+        .collect()
+}
+
+/// A row of the changes report.
+#[derive(Debug, Serialize)]
+struct ChangeRow {
+    client: ClientId,
+    available_before: Option<String>,
+    available_after: Option<String>,
+    available_change: String,
+    held_before: Option<String>,
+    held_after: Option<String>,
+    held_change: String,
+    locked_before: Option<bool>,
+    locked_after: Option<bool>,
+}
+
+/// Writes the changes as CSV, with the balances before and after side by side, and how much
+/// the funds changed, counting a missing client as having none.
+pub fn write_changes<W: Write>(changes: &[Change], writer: W) -> Result<(), Box<dyn Error>> {
+    let amount = |amount: Amount| format!("{:.4}", amount);
+    let mut wtr = csv::Writer::from_writer(writer);
+    for change in changes {
+        let (before, after) = (change.before.as_ref(), change.after.as_ref());
+        let funds = |balance: Option<&AccountBalance>| {
+            balance.map_or((Amount::ZERO, Amount::ZERO), |balance| {
+                (balance.available, balance.held)
+            })
+        };
+        let ((available_before, held_before), (available_after, held_after)) = (funds(before), funds(after));
+        wtr.serialize(ChangeRow {
+            client: change.client,
+            available_before: before.map(|before| amount(before.available)),
+            available_after: after.map(|after| amount(after.available)),
+            available_change: format!("{:+.4}", available_after - available_before),
+            held_before: before.map(|before| amount(before.held)),
+            held_after: after.map(|after| amount(after.held)),
+            held_change: format!("{:+.4}", held_after - held_before),
+            locked_before: before.map(|before| before.is_locked()),
+            locked_after: after.map(|after| after.is_locked()),
+        })?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{diff, read_state, write_changes};
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_diff() {
+        let mut engine = PaymentsEngine::new();
+        engine
+            .process_csv(Path::new("sample_files/multiple_clients.csv"))
+            .unwrap();
+        let mut snapshot = vec![];
+        engine.write_snapshot(&mut snapshot).unwrap();
+
+        // Client 1 without the chargeback, client 2 unchanged apart from its pending funds,
+        // client 3 gone and a new client 4
+        let report = "client, available, held, pending, total, locked
+1, 2.0000, 1.0000, 0.0000, 3.0000, false
+2, 0.5000, 0.0000, 1.0000, 1.5000, false
+4, 1.0000, 0.0000, 0.0000, 1.0000, false";
+        let before = read_state(snapshot.as_slice()).unwrap();
+        let after = read_state(report.as_bytes()).unwrap();
+        let changes = diff(&before, &after);
+        let clients: Vec<_> = changes.iter().map(|change| change.client).collect();
+        assert_eq!(clients, vec![1, 3, 4]);
+
+        let mut output = vec![];
+        write_changes(&changes, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[0],
+            "client,available_before,available_after,available_change,held_before,held_after,held_change,locked_before,locked_after"
+        );
+        assert_eq!(lines[1], "1,2.0000,2.0000,+0.0000,0.0000,1.0000,+1.0000,true,false");
+        assert_eq!(lines[2], "3,0.0000,,+0.0000,5.5000,,-5.5000,false,");
+        assert_eq!(lines[3], "4,,1.0000,+1.0000,,0.0000,+0.0000,,false");
+    }
+}
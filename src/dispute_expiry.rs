@@ -0,0 +1,205 @@
+//! The disputes that are still open, by when they were opened, so that the stale ones can be
+//! resolved automatically after [`DisputeRules::expire_after_days`](crate::config::DisputeRules::expire_after_days),
+//! and listed with their age, see [`PaymentsEngine::open_disputes`](crate::PaymentsEngine::open_disputes).
+//!
+//! Like the interest, the engine only knows what time it is from the `timestamp` of the
+//! transactions, so only timestamped disputes expire, once a later transaction is past their
+//! expiry. When they were opened is kept in snapshots, so the disputes of a restored engine
+//! still expire.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::accounts::{AmountFormat, OpenDispute};
+use crate::dates::SECS_PER_DAY;
+use crate::transactions::{ClientId, TxId};
+
+#[derive(Debug, Default)]
+pub(crate) struct OpenDisputes {
+    // The client of each dispute, by when it was opened
+    by_time: BTreeMap<(u64, TxId), ClientId>,
+    // When the last dispute of each transaction was opened
+    opened_at: HashMap<TxId, u64>,
+}
+
+impl OpenDisputes {
+    /// Records a dispute of the transaction opened at `timestamp`, instead of any earlier one.
+    pub(crate) fn open(&mut self, tx_id: TxId, client_id: ClientId, timestamp: u64) {
+        if let Some(opened_at) = self.opened_at.insert(tx_id, timestamp) {
+            self.by_time.remove(&(opened_at, tx_id));
+        }
+        self.by_time.insert((timestamp, tx_id), client_id);
+    }
+
+    /// Forgets the dispute of the transaction, once it is resolved.
+    pub(crate) fn close(&mut self, tx_id: TxId) {
+        if let Some(opened_at) = self.opened_at.remove(&tx_id) {
+            self.by_time.remove(&(opened_at, tx_id));
+        }
+    }
+
+    /// When the dispute of the transaction was opened, if it is open and was timestamped.
+    pub(crate) fn opened_at(&self, tx_id: TxId) -> Option<u64> {
+        self.opened_at.get(&tx_id).copied()
+    }
+
+    /// When the dispute of the transaction was opened and the client of it, if it is open and
+    /// was timestamped, to [`restore`](OpenDisputes::restore) later.
+    pub(crate) fn get(&self, tx_id: TxId) -> Option<(u64, ClientId)> {
+        let opened_at = self.opened_at(tx_id)?;
+        Some((opened_at, self.by_time[&(opened_at, tx_id)]))
+    }
+
+    /// The transaction, client and opening time of each dispute, by when they were opened.
+    pub(crate) fn all(&self) -> impl Iterator<Item = (TxId, ClientId, u64)> + '_ {
+        self.by_time
+            .iter()
+            .map(|(&(opened_at, tx_id), &client_id)| (tx_id, client_id, opened_at))
+    }
+
+    /// Puts the dispute of the transaction back as [`get`](OpenDisputes::get) returned it.
+    pub(crate) fn restore(&mut self, tx_id: TxId, dispute: Option<(u64, ClientId)>) {
+        self.close(tx_id);
+        if let Some((opened_at, client_id)) = dispute {
+            self.open(tx_id, client_id, opened_at);
+        }
+    }
+
+    /// Forgets the disputes opened at or before `until`, returning them by when they were opened.
+    /// Some of them may have been resolved or charged back since.
+    pub(crate) fn take_expired(&mut self, until: u64) -> Vec<(TxId, ClientId)> {
+        let later = match until.checked_add(1) {
+            Some(after) => self.by_time.split_off(&(after, 0)),
+            None => BTreeMap::new(),
+        };
+        let expired = std::mem::replace(&mut self.by_time, later);
+        expired
+            .into_iter()
+            .map(|((_, tx_id), client_id)| {
+                self.opened_at.remove(&tx_id);
+                (tx_id, client_id)
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+struct OpenDisputeRow {
+    client: Option<ClientId>,
+    tx: TxId,
+    held: String,
+    charged_back: bool,
+    opened_at: Option<u64>,
+    age_days: Option<u64>,
+}
+
+/// Writes the open disputes as CSV, with the amounts formatted as `amounts` and their age in
+/// whole days at `now`, empty when either isn't known.
+pub fn write_report<W: Write>(
+    disputes: &[OpenDispute],
+    now: Option<u64>,
+    amounts: &AmountFormat,
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for dispute in disputes {
+        let age = now
+            .zip(dispute.opened_at)
+            .map(|(now, opened_at)| now.saturating_sub(opened_at) / SECS_PER_DAY);
+        wtr.serialize(OpenDisputeRow {
+            client: dispute.client,
+            tx: dispute.tx,
+            held: amounts.format(dispute.held),
+            charged_back: dispute.charged_back,
+            opened_at: dispute.opened_at,
+            age_days: age,
+        })?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_report;
+    use crate::accounts::AmountFormat;
+    use crate::amount::amount;
+    use crate::config::EngineConfig;
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_dispute_expiry() {
+        let config = EngineConfig::from_toml("[disputes]\nexpire_after_days = 30").unwrap();
+        let mut engine = PaymentsEngine::new().with_config(config).with_journal();
+        let day = 86_400;
+        let input = format!(
+            "type, client, tx, amount, timestamp\n\
+             deposit, 1, 1, 10.0, 0\n\
+             deposit, 1, 2, 5.0, 0\n\
+             dispute, 1, 1, , {}\n\
+             dispute, 1, 2, , {}\n\
+             resolve, 1, 2, , {}\n\
+             dispute, 1, 2, , {}\n\
+             deposit, 2, 3, 1.0, {}\n",
+            day,
+            day,
+            2 * day,
+            20 * day,
+            31 * day,
+        );
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+
+        // Only the first dispute is 30 days old, the other one was opened again later
+        let account = engine.account(1).unwrap().unwrap();
+        assert_eq!((account.available, account.held), (amount(10.0), amount(5.0)));
+        assert_eq!(engine.stats().disputes_expired, 1);
+        let expiry = engine
+            .journal()
+            .iter()
+            .find(|entry| entry.reason.as_deref() == Some("dispute_expired"));
+        assert_eq!(
+            expiry.map(|entry| (entry.tx_id, entry.postings[0].amount)),
+            Some((1, amount(10.0)))
+        );
+    }
+
+    #[test]
+    fn test_open_disputes() {
+        let mut engine = PaymentsEngine::new();
+        let day = 86_400;
+        let input = format!(
+            "type, client, tx, amount, timestamp\n\
+             deposit, 1, 1, 10.0, 0\n\
+             deposit, 1, 2, 5.0, 0\n\
+             deposit, 2, 3, 4.0, 0\n\
+             dispute, 1, 1, 2.0, {}\n\
+             dispute, 1, 2, , {}\n\
+             resolve, 1, 2, , {}\n\
+             dispute, 2, 3, ,\n\
+             chargeback, 2, 3, , {}\n",
+            day,
+            day,
+            2 * day,
+            10 * day + 1,
+        );
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+
+        let mut report = vec![];
+        write_report(
+            &engine.open_disputes().unwrap(),
+            engine.latest_timestamp(),
+            &AmountFormat::default(),
+            &mut report,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,tx,held,charged_back,opened_at,age_days\n\
+             1,1,2.0000,false,86400,9\n\
+             2,3,4.0000,true,,\n"
+        );
+    }
+}
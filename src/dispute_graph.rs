@@ -0,0 +1,171 @@
+//! A GraphViz DOT graph of the disputes, resolves, chargebacks and chargeback reversals, linking
+//! them to the transactions they refer to and to the clients, to follow dispute chains in
+//! investigations.
+//!
+//! Clients are boxes and the disputed transactions ellipses, linked to the client they belong
+//! to by a dotted edge. Each dispute, resolve, chargeback or chargeback reversal is an edge from
+//! the client who sent it to the transaction, numbered in the order they were applied. The
+//! rejected ones are dashed and labeled with their reason code, and the transactions they refer
+//! to that are unknown are dashed as well.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io::Write;
+
+use crate::amount::AmountValue;
+use crate::ledger::JournalEntry;
+use crate::rejections::Rejection;
+use crate::transactions::{Amount, ClientId, TransactionType, TxId};
+
+/// Whether the transaction refers to a disputed transaction.
+fn is_dispute_step(tx_type: TransactionType) -> bool {
+    matches!(
+        tx_type,
+        TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::ChargebackReversal
+    )
+}
+
+fn color(tx_type: TransactionType) -> &'static str {
+    match tx_type {
+        TransactionType::Dispute => "orange",
+        TransactionType::Resolve => "darkgreen",
+        TransactionType::ChargebackReversal => "blue",
+        _ => "red",
+    }
+}
+
+fn type_name(tx_type: TransactionType) -> String {
+    format!("{:?}", tx_type).to_lowercase()
+}
+
+/// Writes the graph of the applied transactions of the `journal` and of the `rejections`.
+pub fn write_dot<W: Write>(
+    journal: &[JournalEntry],
+    rejections: &[Rejection],
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let steps = journal.iter().filter(|entry| is_dispute_step(entry.tx_type));
+    let rejected = rejections
+        .iter()
+        .filter(|rejection| is_dispute_step(rejection.transaction.tx_type));
+    let referenced: BTreeSet<TxId> = steps
+        .clone()
+        .map(|entry| entry.tx_id)
+        .chain(rejected.clone().map(|rejection| rejection.transaction.tx_id))
+        .collect();
+
+    // The first entry of each referenced transaction is the one that created it
+    let mut transactions: BTreeMap<TxId, &JournalEntry> = BTreeMap::new();
+    for entry in journal
+        .iter()
+        .filter(|entry| referenced.contains(&entry.tx_id) && !is_dispute_step(entry.tx_type))
+    {
+        transactions.entry(entry.tx_id).or_insert(entry);
+    }
+    let clients: BTreeSet<ClientId> = transactions
+        .values()
+        .map(|entry| entry.client_id)
+        .chain(steps.clone().map(|entry| entry.client_id))
+        .chain(rejected.clone().map(|rejection| rejection.transaction.client_id))
+        .collect();
+
+    writeln!(writer, "digraph disputes {{")?;
+    for client in clients {
+        writeln!(writer, "    \"client {}\" [shape=box];", client)?;
+    }
+    for tx_id in &referenced {
+        match transactions.get(tx_id) {
+            Some(entry) => {
+                let amount = entry.postings.first().map_or(Amount::ZERO, |posting| posting.amount);
+                writeln!(
+                    writer,
+                    "    \"tx {}\" [label=\"{} {}\\n{:.4}\"];",
+                    tx_id,
+                    type_name(entry.tx_type),
+                    tx_id,
+                    amount
+                )?;
+                writeln!(
+                    writer,
+                    "    \"client {}\" -> \"tx {}\" [style=dotted];",
+                    entry.client_id, tx_id
+                )?;
+            }
+            None => writeln!(
+                writer,
+                "    \"tx {}\" [label=\"tx {}\\nunknown\", style=dashed];",
+                tx_id, tx_id
+            )?,
+        }
+    }
+    for (step, entry) in steps.enumerate() {
+        writeln!(
+            writer,
+            "    \"client {}\" -> \"tx {}\" [label=\"{}: {}\", color={}];",
+            entry.client_id,
+            entry.tx_id,
+            step + 1,
+            type_name(entry.tx_type),
+            color(entry.tx_type)
+        )?;
+    }
+    for rejection in rejected {
+        let transaction = &rejection.transaction;
+        writeln!(
+            writer,
+            "    \"client {}\" -> \"tx {}\" [label=\"{} {}\", color={}, style=dashed];",
+            transaction.client_id,
+            transaction.tx_id,
+            type_name(transaction.tx_type),
+            rejection.reason,
+            color(transaction.tx_type)
+        )?;
+    }
+    writeln!(writer, "}}")?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_dot;
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_dispute_graph() {
+        let input = "type, client, tx, amount
+            deposit, 1, 1, 2.0
+            deposit, 2, 2, 1.0
+            dispute, 1, 1
+            resolve, 1, 1
+            dispute, 2, 2
+            chargeback, 2, 2
+            dispute, 3, 9";
+        let mut engine = PaymentsEngine::new().with_journal().with_rejections();
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+
+        let mut dot = vec![];
+        write_dot(engine.journal(), engine.rejections(), &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        let expected = r#"digraph disputes {
+    "client 1" [shape=box];
+    "client 2" [shape=box];
+    "client 3" [shape=box];
+    "tx 1" [label="deposit 1\n2.0000"];
+    "client 1" -> "tx 1" [style=dotted];
+    "tx 2" [label="deposit 2\n1.0000"];
+    "client 2" -> "tx 2" [style=dotted];
+    "tx 9" [label="tx 9\nunknown", style=dashed];
+    "client 1" -> "tx 1" [label="1: dispute", color=orange];
+    "client 1" -> "tx 1" [label="2: resolve", color=darkgreen];
+    "client 2" -> "tx 2" [label="3: dispute", color=orange];
+    "client 2" -> "tx 2" [label="4: chargeback", color=red];
+    "client 3" -> "tx 9" [label="dispute UNKNOWN_TX", color=orange, style=dashed];
+}
+"#;
+        assert_eq!(dot, expected);
+    }
+}
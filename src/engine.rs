@@ -0,0 +1,1476 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use csv::{ByteRecord, Position, StringRecord};
+use sha2::{Digest, Sha256};
+
+use crate::accounts::{
+    format_report, format_report_columns, AccountBalance, AccountStatus, AccountView, OpenDispute, REPORT_COLUMNS,
+};
+use crate::activity::{Activity, ActivityTotals, Bucket};
+use crate::aml::{AmlAnalyzer, Finding};
+use crate::amount::AmountValue;
+use crate::clients::ClientDirectory;
+use crate::config::EngineConfig;
+use crate::csv_input::{CsvOptions, CsvParser};
+use crate::custom_errors::{LineError, SavepointError, SavepointErrorType};
+use crate::dates::SECS_PER_DAY;
+#[cfg(feature = "decrypt")]
+use crate::decrypt::Decryption;
+use crate::dispute_expiry::OpenDisputes;
+use crate::event_log::EventLog;
+use crate::input::InputIo;
+use crate::interest::InterestAccrual;
+use crate::ledger::{self, JournalEntry, LedgerAccount};
+use crate::losses::{LossTotals, Losses};
+use crate::machine::{self, Effect};
+use crate::queue::{QueueGauge, QueueMetrics};
+use crate::rate_limit::RateLimiter;
+use crate::reasons::{TransactionErrorType, TransactionRecordError};
+use crate::rejections::{MalformedRecord, Rejection, RejectionReason};
+use crate::risk::{AutoLock, AutoLockRule, RiskState, RiskViolation};
+use crate::shard::Shard;
+#[cfg(feature = "signatures")]
+use crate::signatures::SignatureVerifier;
+use crate::store::{
+    AccountStore, Database, DedupStore, MemoryAccountStore, MemoryTxStore, PendingSettlement, RecordedTx, TxStatus,
+    TxStore,
+};
+use crate::transactions::{Amount, ClientId, Transaction, TransactionType, TxId};
+use crate::undo::{self, PreImage, UndoLog};
+use crate::watermark::Watermark;
+
+/// The index of the `signature` column of the CSV records, if they have one.
+pub(crate) fn signature_column(headers: &StringRecord) -> Option<usize> {
+    headers.iter().position(|header| header == "signature")
+}
+
+/// The CSV settings shared by all the ways of reading CSV input.
+pub(crate) fn csv_reader_builder() -> csv::ReaderBuilder {
+    let mut builder = csv::ReaderBuilder::new();
+    builder.delimiter(b',').trim(csv::Trim::All).flexible(true);
+    builder
+}
+
+/// Applies transactions to the client accounts.
+pub struct PaymentsEngine {
+    // We keep the account balances throughout the whole execution of the program.
+    // This is because we can get an update to a given client balance all the way to the last
+    // transaction, and we only want to output the results once, at the end.
+    pub(crate) accounts: Box<dyn AccountStore>,
+    // We hold a record of the deposit transaction amounts, so that we can process disputes,
+    // and of the disputed transactions, since resolves and chargebacks are only valid for those.
+    pub(crate) transactions: Box<dyn TxStore>,
+    stats: ProcessingStats,
+    recent_rejections: VecDeque<Transaction>,
+    // The ledger entries of all the applied transactions, only when asked for.
+    journal: Option<Vec<JournalEntry>>,
+    config: EngineConfig,
+    pub(crate) interest: InterestAccrual,
+    pub(crate) risk: RiskState,
+    rate_limiter: RateLimiter,
+    // The timestamped ones, for when they expire and their age
+    pub(crate) open_disputes: OpenDisputes,
+    // Only when looking for suspicious activity
+    aml: Option<AmlAnalyzer>,
+    // Only when reporting the activity by period
+    activity: Option<Activity>,
+    // The losses to the chargebacks of each client
+    pub(crate) losses: Losses,
+    // The latest timestamp of the transactions applied of each client
+    pub(crate) last_activity: HashMap<ClientId, u64>,
+    // Only when reporting the accounts as they become final
+    watermark: Option<Watermark>,
+    clients: Option<ClientDirectory>,
+    observers: Vec<Box<dyn Observer>>,
+    // All the rejected transactions, only when asked for.
+    rejections: Option<Vec<Rejection>>,
+    // The line of the CSV record being applied, if any
+    line: Option<u64>,
+    // Set to stop reading the CSV input, e.g. on a signal
+    interrupt: Option<Arc<AtomicBool>>,
+    // Where the CSV input was interrupted, if it was
+    interrupted_at: Option<Position>,
+    // The lines of the CSV input applied before it was interrupted, and skipped now
+    resume_after: u64,
+    // The balances read during the batch being applied, if any
+    batch: Option<HashMap<ClientId, AccountBalance>>,
+    // The queue the transactions are applied from, in follow and serve modes
+    queue: Option<QueueGauge>,
+    // The transactions already processed, only when asked for
+    pub(crate) dedup: Option<Box<dyn DedupStore>>,
+    // The database the stores share, if they do
+    database: Option<Box<dyn Database>>,
+    // The transactions of the file being read since the last commit to the database, if any
+    chunk: Option<usize>,
+    // The pre-images of the last transactions, only when they can be undone
+    pub(crate) undo: Option<UndoLog>,
+    // The clients to process, in a distributed run
+    shard: Option<Shard>,
+    // The name of the source of the input, for the transaction types it may have
+    source: Option<String>,
+    event_log: Option<EventLog>,
+    // The public key of the partner signing the CSV records, if they are checked
+    #[cfg(feature = "signatures")]
+    signatures: Option<SignatureVerifier>,
+    // How the input is decrypted, if it is encrypted
+    #[cfg(feature = "decrypt")]
+    pub(crate) decryption: Option<Decryption>,
+    // How the local input files are read
+    pub(crate) input_io: InputIo,
+}
+
+/// Hooks called by the engine as it processes the input, e.g. to log or count what it drops.
+pub trait Observer: Send {
+    /// Called for each transaction that was ignored, with why.
+    fn on_rejected(&mut self, _rejection: &Rejection) {}
+
+    /// Called for each record skipped because it was malformed, only when reading
+    /// [`lenient`](crate::csv_input::CsvOptions::lenient)ly.
+    fn on_malformed(&mut self, _record: &MalformedRecord) {}
+
+    /// Called for each account locked by one of the auto-lock rules of
+    /// [`RiskRules::auto_lock`](crate::risk::RiskRules::auto_lock).
+    fn on_auto_locked(&mut self, _lock: &AutoLock) {}
+
+    /// Called for each transaction that was applied, with the balance of the client before it.
+    fn on_applied(&mut self, _before: &AccountBalance, _applied: &Applied) {}
+}
+
+/// How many of the last rejected transactions the engine remembers.
+pub const RECENT_REJECTIONS: usize = 10;
+
+/// How many transactions of a file the engine commits to its database at once.
+pub const DATABASE_CHUNK: usize = 1000;
+
+/// Counters of what the engine did so far.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessingStats {
+    /// Transactions processed, applied or not.
+    pub processed: u64,
+    /// Transactions ignored because they were invalid.
+    pub rejected: u64,
+    /// How many of the rejected transactions were rejected for each reason.
+    pub rejections: BTreeMap<RejectionReason, u64>,
+    /// How many of the rejected transactions broke each of the risk rules.
+    pub risk_rejections: BTreeMap<RiskViolation, u64>,
+    /// Transactions of the clients of other shards, skipped without processing them,
+    /// if the engine was created [`with_shard`](PaymentsEngine::with_shard).
+    pub other_shards: u64,
+    /// The disputes of more than the available funds that were applied, driving them negative
+    /// or holding less, see [`DisputeRules::over_available`](crate::config::DisputeRules::over_available).
+    pub disputes_over_available: u64,
+    /// The disputes resolved automatically because they expired, see
+    /// [`DisputeRules::expire_after_days`](crate::config::DisputeRules::expire_after_days).
+    pub disputes_expired: u64,
+    /// The accounts locked by the auto-lock rules, by rule, see
+    /// [`RiskRules::auto_lock`](crate::risk::RiskRules::auto_lock).
+    pub auto_locked: BTreeMap<AutoLockRule, u64>,
+    /// The clients with transactions that aren't in the client metadata,
+    /// if the engine was created [`with_clients`](PaymentsEngine::with_clients).
+    pub unknown_clients: BTreeSet<ClientId>,
+    /// The records skipped because they were malformed, when reading
+    /// [`lenient`](crate::csv_input::CsvOptions::lenient)ly.
+    pub malformed: Vec<MalformedRecord>,
+}
+
+/// What applying a transaction did, see [`PaymentsEngine::apply`].
+pub type Outcome = Result<Applied, Rejected>;
+
+/// A transaction that was applied.
+#[derive(Debug, Clone)]
+pub struct Applied {
+    /// The balance of the client after the transaction.
+    pub balance: AccountBalance,
+    /// The funds the transaction moved, its fees included.
+    pub entry: JournalEntry,
+    /// The funds put on hold, if it was a dispute.
+    pub hold: Option<Amount>,
+}
+
+/// A transaction that was ignored because it was invalid.
+#[derive(Debug, Clone)]
+pub struct Rejected {
+    pub reason: RejectionReason,
+    /// The balance of the client, which the transaction didn't change.
+    pub balance: AccountBalance,
+}
+
+impl Error for Rejected {}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The transaction was rejected: {}", self.reason)
+    }
+}
+
+/// The outcome of processing the input, see [`PaymentsEngine::processing_report`].
+#[derive(Debug, Clone)]
+pub struct ProcessingReport {
+    /// All the account balances, sorted by client id.
+    pub balances: Vec<AccountBalance>,
+    pub stats: ProcessingStats,
+    /// The rejected transactions, in order, if they were kept.
+    pub rejections: Vec<Rejection>,
+    /// See [`PaymentsEngine::state_hash`].
+    pub state_hash: String,
+}
+
+impl fmt::Display for ProcessingReport {
+    /// The account balances, as in [`PaymentsEngine::report`] without client metadata.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&format_report(&self.balances))
+    }
+}
+
+impl Default for PaymentsEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PaymentsEngine {
+    /// Creates an engine that keeps all of its state in memory.
+    pub fn new() -> Self {
+        Self::with_stores(
+            Box::new(MemoryAccountStore::default()),
+            Box::new(MemoryTxStore::default()),
+        )
+    }
+
+    /// Creates an engine backed by the given stores.
+    pub fn with_stores(accounts: Box<dyn AccountStore>, transactions: Box<dyn TxStore>) -> Self {
+        PaymentsEngine {
+            accounts,
+            transactions,
+            stats: ProcessingStats::default(),
+            recent_rejections: VecDeque::with_capacity(RECENT_REJECTIONS),
+            journal: None,
+            config: EngineConfig::default(),
+            interest: InterestAccrual::default(),
+            risk: RiskState::default(),
+            rate_limiter: RateLimiter::default(),
+            open_disputes: OpenDisputes::default(),
+            aml: None,
+            activity: None,
+            losses: Losses::default(),
+            last_activity: HashMap::new(),
+            watermark: None,
+            clients: None,
+            observers: vec![],
+            rejections: None,
+            line: None,
+            interrupt: None,
+            interrupted_at: None,
+            resume_after: 0,
+            batch: None,
+            queue: None,
+            dedup: None,
+            database: None,
+            chunk: None,
+            undo: None,
+            shard: None,
+            source: None,
+            event_log: None,
+            #[cfg(feature = "signatures")]
+            signatures: None,
+            #[cfg(feature = "decrypt")]
+            decryption: None,
+            input_io: InputIo::Buffered,
+        }
+    }
+
+    /// Uses the given settings instead of the default ones.
+    pub fn with_config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Allows admin operations, i.e. `adjustment` transactions.
+    pub fn with_admin_ops(mut self) -> Self {
+        self.config.admin_ops = true;
+        self
+    }
+
+    /// The settings of the engine.
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Looks for suspicious activity in the transactions, with the thresholds of
+    /// [`EngineConfig::aml`], see [`PaymentsEngine::aml_findings`].
+    pub fn with_aml(mut self) -> Self {
+        self.aml = Some(AmlAnalyzer::default());
+        self
+    }
+
+    /// Adds up the transactions applied by the period of their timestamp,
+    /// see [`PaymentsEngine::activity`].
+    pub fn with_activity(mut self, bucket: Bucket) -> Self {
+        self.activity = Some(Activity::new(bucket));
+        self
+    }
+
+    /// Hands each account to `sink` as soon as it is final, when the input is in time order and
+    /// no client has a transaction more than `close_after` seconds after its previous one, going
+    /// by the `timestamp` column. The accounts are dropped once handed over, so that a long
+    /// stream only keeps the ones of the clients still active, and [`balances`](PaymentsEngine::balances)
+    /// only has those. The transactions of a client that come later anyway are rejected as
+    /// [`RejectionReason::AccountFinalized`].
+    ///
+    /// The accounts with pending funds only become final once they settle, and the ones with
+    /// held funds once their dispute can't expire anymore. None does while interest accrues.
+    pub fn with_close_after(
+        mut self,
+        close_after: u64,
+        sink: impl FnMut(&AccountBalance) -> Result<(), Box<dyn Error>> + Send + 'static,
+    ) -> Self {
+        self.watermark = Some(Watermark::new(close_after, Box::new(sink)));
+        self
+    }
+
+    /// Adds the metadata of the clients to the report, and counts the clients
+    /// that aren't in it in [`ProcessingStats::unknown_clients`].
+    pub fn with_clients(mut self, clients: ClientDirectory) -> Self {
+        self.clients = Some(clients);
+        self
+    }
+
+    /// The metadata of the clients, if any.
+    pub fn clients(&self) -> Option<&ClientDirectory> {
+        self.clients.as_ref()
+    }
+
+    /// Keeps the ledger entries of all the applied transactions, see [`PaymentsEngine::journal`].
+    pub fn with_journal(mut self) -> Self {
+        self.journal = Some(vec![]);
+        self
+    }
+
+    /// Writes every effect on the accounts to `writer` as it happens, as NDJSON events,
+    /// see [`event_log`](crate::event_log). The events of each transaction are flushed once it is done.
+    pub fn with_event_log(mut self, writer: Box<dyn Write + Send>) -> Self {
+        self.event_log = Some(EventLog::new(writer));
+        self
+    }
+
+    /// Tells the observer about what the engine does, along with the ones added before.
+    pub fn with_observer(mut self, observer: Box<dyn Observer>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Only processes the transactions of the clients of `shard`, so that the machines of a
+    /// distributed run can each process the whole input, and report their own clients.
+    /// The others are skipped as [`RejectionReason::OtherShard`], and only counted in
+    /// [`ProcessingStats::other_shards`].
+    pub fn with_shard(mut self, shard: Shard) -> Self {
+        self.shard = Some(shard);
+        self
+    }
+
+    /// Takes the input to come from the source `name` of [`EngineConfig::sources`], rejecting
+    /// the transactions of the types it isn't allowed as [`RejectionReason::TypeNotAllowed`].
+    /// A source that isn't in the config is allowed all of them.
+    pub fn with_source(mut self, name: &str) -> Self {
+        self.source = Some(name.to_string());
+        self
+    }
+
+    /// Only applies a transaction once, by type and id, keeping the processed ones in `dedup`.
+    /// The ones seen again, e.g. redelivered by an at-least-once source after a crash,
+    /// are rejected as [`RejectionReason::Duplicate`]. They are recorded whether they were
+    /// applied or rejected, so a retry can't turn a rejected transaction into an applied one.
+    pub fn with_dedup(mut self, dedup: Box<dyn DedupStore>) -> Self {
+        self.dedup = Some(dedup);
+        self
+    }
+
+    /// Commits the writes of the stores to the `database` they share once for each transaction
+    /// applied, for each [batch](PaymentsEngine::apply_batch), or for each [`DATABASE_CHUNK`]
+    /// transactions of a file, so that a crash leaves all of them or none, instead of a statement
+    /// at a time. The writes of a transaction that fails are rolled back.
+    pub fn with_database(mut self, database: Box<dyn Database>) -> Self {
+        self.database = Some(database);
+        self
+    }
+
+    /// Checks the signature of each CSV record, in its `signature` column, before applying it.
+    /// The transactions of the records without a valid one are rejected as
+    /// [`RejectionReason::InvalidSignature`], see [`signatures`](crate::signatures).
+    #[cfg(feature = "signatures")]
+    pub fn with_signature_verifier(mut self, verifier: SignatureVerifier) -> Self {
+        self.signatures = Some(verifier);
+        self
+    }
+
+    /// Decrypts the inputs opened by [`process_input`](PaymentsEngine::process_input) before
+    /// any of their rows is applied, see [`decrypt`](crate::decrypt).
+    #[cfg(feature = "decrypt")]
+    pub fn with_decryption(mut self, decryption: Decryption) -> Self {
+        self.decryption = Some(decryption);
+        self
+    }
+
+    /// Reads the local files opened by [`process_input`](PaymentsEngine::process_input) with `io`,
+    /// e.g. mapped into memory.
+    pub fn with_input_io(mut self, io: InputIo) -> Self {
+        self.input_io = io;
+        self
+    }
+
+    /// Keeps what the last `depth` transactions changed, as it was before, so that they can be
+    /// undone, see [`PaymentsEngine::undo`].
+    pub fn with_undo(mut self, depth: usize) -> Self {
+        self.undo = Some(UndoLog::new(depth));
+        self
+    }
+
+    /// Keeps all the rejected transactions, see [`PaymentsEngine::rejections`].
+    pub fn with_rejections(mut self) -> Self {
+        self.rejections = Some(vec![]);
+        self
+    }
+
+    /// Stops reading the CSV input once `interrupt` is set, e.g. by a signal handler, between two
+    /// records, so that what was applied until then can still be reported, see
+    /// [`PaymentsEngine::interrupted_at`].
+    pub fn with_interrupt(mut self, interrupt: Arc<AtomicBool>) -> Self {
+        self.interrupt = Some(interrupt);
+        self
+    }
+
+    /// Where the CSV input was interrupted, or none if it wasn't: the line of the last record
+    /// read, 0 if it was before the first one, and how many bytes of the input were read by then.
+    pub fn interrupted_at(&self) -> Option<&Position> {
+        self.interrupted_at.as_ref()
+    }
+
+    /// Skips the CSV records up to `line`, e.g. the ones applied before the input was interrupted
+    /// and the state was snapshotted, see [`PaymentsEngine::restore_snapshot`].
+    pub fn with_resume_after(mut self, line: u64) -> Self {
+        self.resume_after = line;
+        self
+    }
+
+    /// Whether the input has to stop, after the record at `line`, `byte` bytes into it.
+    fn interrupted(&mut self, line: u64, byte: u64) -> bool {
+        let interrupted = self
+            .interrupt
+            .as_ref()
+            .is_some_and(|interrupt| interrupt.load(Ordering::Relaxed));
+        if interrupted {
+            // The records skipped when resuming were read before
+            let mut position = Position::new();
+            position.set_line(line.max(self.resume_after)).set_byte(byte);
+            self.interrupted_at = Some(position);
+        }
+        interrupted
+    }
+
+    /// Applies all the transactions in a CSV file, tab separated if it is a `.tsv` one.
+    pub fn process_csv(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let options = self.config.csv.for_input(&path.to_string_lossy());
+        self.process_csv_with(File::open(path)?, &options)
+    }
+
+    /// Applies all the transactions read as CSV from any source, e.g. a network stream.
+    pub fn process_csv_reader<R: Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        let options = self.config.csv.clone();
+        self.process_csv_with(reader, &options)
+    }
+
+    /// Applies all the transactions read as CSV with the given options.
+    pub(crate) fn process_csv_with<R: Read>(&mut self, reader: R, options: &CsvOptions) -> Result<(), Box<dyn Error>> {
+        let Some(database) = &mut self.database else {
+            return self.process_csv_chunks(reader, options);
+        };
+        database.begin()?;
+        self.chunk = Some(0);
+        let processed = self.process_csv_chunks(reader, options);
+        self.chunk = None;
+        // Even if it failed, for the transactions applied before
+        if let Some(database) = &mut self.database {
+            database.commit()?;
+        }
+        processed
+    }
+
+    fn process_csv_chunks<R: Read>(&mut self, reader: R, options: &CsvOptions) -> Result<(), Box<dyn Error>> {
+        if options.parser == CsvParser::Fast {
+            let (mut rdr, parser) = options.fast_reader(reader)?;
+            let signature_column = signature_column(parser.headers());
+            let mut record = ByteRecord::new();
+            let mut line = 0;
+            while !self.interrupted(line, rdr.position().byte()) && rdr.read_byte_record(&mut record)? {
+                line = record.position().map_or(0, |position| position.line());
+                if line <= self.resume_after {
+                    continue;
+                }
+                let transaction = parser.parse(&record);
+                if self.check_signature(line, record.iter(), signature_column, &transaction)? {
+                    self.apply_record(line, transaction, options.lenient)?;
+                }
+                self.next_in_chunk()?;
+            }
+            return Ok(());
+        }
+
+        // Setup a CSV reader on top of the given reader.
+        let (mut rdr, headers) = options.reader(reader)?;
+        let signature_column = signature_column(&headers);
+        let mut record = StringRecord::new();
+        let mut line = 0;
+
+        while !self.interrupted(line, rdr.position().byte()) {
+            // Records that aren't valid UTF-8 can be skipped as well
+            let transaction = match rdr.read_record(&mut record) {
+                Ok(false) => break,
+                Ok(true)
+                    if record
+                        .position()
+                        .is_some_and(|position| position.line() <= self.resume_after) =>
+                {
+                    continue
+                }
+                Ok(true) => options.deserialize(&record, &headers),
+                Err(error) => Err(error),
+            };
+            let position = match &transaction {
+                Err(error) => error.position(),
+                Ok(_) => None,
+            };
+            line = position.or(record.position()).map_or(0, |position| position.line());
+            if self.check_signature(line, record.as_byte_record().iter(), signature_column, &transaction)? {
+                self.apply_record(line, transaction, options.lenient)?;
+            }
+            self.next_in_chunk()?;
+        }
+
+        Ok(())
+    }
+
+    /// Counts a record of the file being read, and commits the chunk to the database once it
+    /// has [`DATABASE_CHUNK`] of them, so that a crash loses at most a chunk.
+    fn next_in_chunk(&mut self) -> Result<(), Box<dyn Error>> {
+        let (Some(chunk), Some(database)) = (&mut self.chunk, &mut self.database) else {
+            return Ok(());
+        };
+        *chunk += 1;
+        if *chunk >= DATABASE_CHUNK {
+            *chunk = 0;
+            database.commit()?;
+            database.begin()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the record at `line` can be applied, which it can't if the engine checks the
+    /// signatures and its own doesn't verify. Its transaction is rejected then, and malformed
+    /// records are left to [`apply_record`](PaymentsEngine::apply_record).
+    #[cfg_attr(not(feature = "signatures"), allow(unused_variables))]
+    pub(crate) fn check_signature<'a>(
+        &mut self,
+        line: u64,
+        fields: impl Iterator<Item = &'a [u8]>,
+        signature_column: Option<usize>,
+        transaction: &Result<Transaction, csv::Error>,
+    ) -> Result<bool, Box<dyn Error>> {
+        #[cfg(feature = "signatures")]
+        if let (Some(verifier), Ok(transaction)) = (&self.signatures, transaction) {
+            if !verifier.verify_record(fields, signature_column) {
+                self.line = Some(line);
+                self.stats.processed += 1;
+                let rejected = self.reject(transaction, RejectionReason::InvalidSignature);
+                self.line = None;
+                if let Some(event_log) = &mut self.event_log {
+                    event_log.flush()?;
+                }
+                rejected?;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Applies the transaction parsed from the record at `line`, returning it if the record
+    /// was well formed. Errors applying the transaction are reported at that line, and when
+    /// `lenient` the malformed records are skipped and kept in the stats instead.
+    pub(crate) fn apply_record(
+        &mut self,
+        line: u64,
+        transaction: Result<Transaction, csv::Error>,
+        lenient: bool,
+    ) -> Result<Option<Transaction>, Box<dyn Error>> {
+        let result = match transaction {
+            Ok(transaction) => {
+                self.line = Some(line);
+                let applied = self.apply(&transaction);
+                self.line = None;
+                match applied {
+                    Ok(_) => return Ok(Some(transaction)),
+                    Err(error) => Box::new(LineError { line, error }) as Box<dyn Error>,
+                }
+            }
+            // Parsing errors already know their line
+            Err(error) => Box::new(error),
+        };
+        match MalformedRecord::from_error(line, result.as_ref()).filter(|_| lenient) {
+            Some(malformed) => {
+                for observer in &mut self.observers {
+                    observer.on_malformed(&malformed);
+                }
+                self.stats.malformed.push(malformed);
+                Ok(None)
+            }
+            None => Err(result),
+        }
+    }
+
+    /// Applies a single transaction, returning what it did to the account of the client.
+    /// Invalid transactions (insufficient funds, unknown transactions, locked or closed accounts)
+    /// are ignored and returned as [`Rejected`], and only malformed records return an error.
+    pub fn apply(&mut self, transaction: &Transaction) -> Result<Outcome, Box<dyn Error>> {
+        // Nested in the one of a batch or of a chunk of a file, if any
+        let Some(database) = &mut self.database else {
+            return self.apply_uncommitted(transaction);
+        };
+        database.begin()?;
+        let outcome = self.apply_uncommitted(transaction);
+        let Some(database) = &mut self.database else {
+            return outcome;
+        };
+        match &outcome {
+            Ok(_) => database.commit()?,
+            // None of its writes are kept if it failed half way
+            Err(_) => {
+                database.rollback()?;
+                if let Some(batch) = &mut self.batch {
+                    batch.clear();
+                }
+            }
+        }
+        outcome
+    }
+
+    fn apply_uncommitted(&mut self, transaction: &Transaction) -> Result<Outcome, Box<dyn Error>> {
+        // Whoever produced it, an amount that isn't a number or of the wrong sign is rejected
+        let invalid_amount = match transaction.validate() {
+            Ok(()) => false,
+            Err(TransactionRecordError {
+                error_type: TransactionErrorType::InvalidAmount,
+            }) => true,
+            Err(error) => return Err(Box::new(error)),
+        };
+        // The interest of the days that ended before this transaction comes first
+        let mut interest_undo = None;
+        if let (Some(interest), Some(timestamp)) = (&self.config.interest, transaction.timestamp) {
+            let accrual = (self.undo.is_some() && self.interest.changes_at(timestamp)).then(|| self.interest.clone());
+            let entries = self.interest.advance(interest, timestamp, self.accounts.as_mut())?;
+            interest_undo = accrual.map(|accrual| (accrual, entries.clone()));
+            if !entries.is_empty() {
+                // The interest was posted straight to the store
+                if let Some(batch) = &mut self.batch {
+                    batch.clear();
+                }
+            }
+            if let Some(event_log) = &mut self.event_log {
+                for entry in &entries {
+                    event_log.posted(self.line, entry)?;
+                }
+            }
+            if let Some(journal) = &mut self.journal {
+                journal.extend(entries);
+            }
+        }
+        if let Some(timestamp) = transaction.timestamp {
+            self.settle(timestamp)?;
+            self.expire_disputes(timestamp)?;
+            self.finalize_idle_accounts(timestamp)?;
+        }
+        // The time still passes for the clients of this shard
+        if self.shard.is_some_and(|shard| !shard.contains(transaction.client_id)) {
+            self.stats.other_shards += 1;
+            self.push_pre_image(transaction, interest_undo)?;
+            // With the settlements and interest of the clients of this shard
+            if let Some(event_log) = &mut self.event_log {
+                event_log.flush()?;
+            }
+            return Ok(Err(Rejected {
+                reason: RejectionReason::OtherShard,
+                balance: AccountBalance::new(transaction.client_id),
+            }));
+        }
+
+        if self
+            .watermark
+            .as_ref()
+            .is_some_and(|watermark| watermark.is_finalized(transaction.client_id))
+        {
+            self.stats.processed += 1;
+            self.reject(transaction, RejectionReason::AccountFinalized)?;
+            self.push_pre_image(transaction, interest_undo)?;
+            if let Some(event_log) = &mut self.event_log {
+                event_log.flush()?;
+            }
+            // Its balance was already handed over
+            return Ok(Err(Rejected {
+                reason: RejectionReason::AccountFinalized,
+                balance: AccountBalance::new(transaction.client_id),
+            }));
+        }
+
+        // If the client doesn't exist yet, we start from a new balance
+        let loaded = self.load_account(transaction.client_id)?;
+        let pre_image = match self.undo.is_some() {
+            true => Some(self.pre_image(transaction, loaded.clone(), interest_undo)?),
+            false => None,
+        };
+        let mut account_balance = match loaded {
+            Some(account_balance) => account_balance,
+            None => {
+                if self
+                    .clients
+                    .as_ref()
+                    .is_some_and(|clients| clients.get(transaction.client_id).is_none())
+                {
+                    self.stats.unknown_clients.insert(transaction.client_id);
+                }
+                AccountBalance::new(transaction.client_id)
+            }
+        };
+
+        self.stats.processed += 1;
+        let duplicate = match &self.dedup {
+            Some(dedup) => dedup.contains(transaction.tx_type, transaction.tx_id)?,
+            None => false,
+        };
+        // Only the submissions of the live modes, applied from a queue as they arrive, are rate limited
+        let throttled = !duplicate
+            && self.queue.is_some()
+            && !self
+                .rate_limiter
+                .allow(&self.config.rate_limit, transaction.client_id, Instant::now());
+        let not_allowed = self
+            .source
+            .as_ref()
+            .and_then(|source| self.config.sources.get(source))
+            .is_some_and(|rules| !rules.allows(transaction.tx_type));
+        // Transactions that break the risk rules are rejected, whatever they would do
+        let entry = if duplicate {
+            Err(RejectionReason::Duplicate)
+        } else if invalid_amount {
+            Err(RejectionReason::InvalidAmount)
+        } else if throttled {
+            Err(RejectionReason::RateLimited)
+        } else if not_allowed {
+            Err(RejectionReason::TypeNotAllowed)
+        } else if let Some(reason) = self.config.limits.check(transaction) {
+            Err(reason)
+        } else if let Some(violation) = self.risk.check(&self.config.risk, transaction) {
+            Err(RejectionReason::Risk(violation))
+        } else {
+            self.journal_entry(&account_balance, transaction)?
+        };
+        if let Some(aml) = self.aml.as_mut().filter(|_| !duplicate && !throttled) {
+            aml.observe(&self.config.aml, transaction, entry.is_ok());
+        }
+        let outcome = match entry {
+            Ok(entry) => {
+                let before = account_balance.clone();
+                let status = account_balance.status;
+                machine::apply(&mut account_balance, &entry, &self.config.rules());
+                // The accounts already locked or closed stay as they are
+                let auto_lock = self
+                    .risk
+                    .auto_lock(&self.config.risk.auto_lock, transaction, &account_balance)
+                    .filter(|_| matches!(account_balance.status, AccountStatus::Active | AccountStatus::Frozen));
+                if let Some(rule) = auto_lock {
+                    account_balance.status = AccountStatus::Locked;
+                    *self.stats.auto_locked.entry(rule).or_default() += 1;
+                    let lock = AutoLock {
+                        client: transaction.client_id,
+                        tx_id: transaction.tx_id,
+                        rule,
+                    };
+                    for observer in &mut self.observers {
+                        observer.on_auto_locked(&lock);
+                    }
+                }
+                match transaction.tx_type {
+                    TransactionType::Withdrawal => self.risk.record_withdrawal(transaction),
+                    TransactionType::Dispute => {
+                        if let Some(timestamp) = transaction.timestamp {
+                            self.open_disputes
+                                .open(transaction.tx_id, transaction.client_id, timestamp);
+                        }
+                    }
+                    TransactionType::Resolve | TransactionType::ChargebackReversal => {
+                        self.open_disputes.close(transaction.tx_id)
+                    }
+                    _ => {}
+                }
+                if let Some(event_log) = &mut self.event_log {
+                    event_log.posted(self.line, &entry)?;
+                    if account_balance.status != status {
+                        let reason = auto_lock.map(|rule| rule.code());
+                        event_log.status_changed(self.line, &entry, account_balance.status, reason)?;
+                    }
+                }
+                if let Some(activity) = &mut self.activity {
+                    activity.record(transaction, &entry);
+                }
+                self.losses.record(&entry, self.config.disputes.chargeback_fee);
+                if let Some(timestamp) = transaction.timestamp {
+                    let last_activity = self.last_activity.entry(transaction.client_id).or_insert(timestamp);
+                    *last_activity = (*last_activity).max(timestamp);
+                }
+                if let Some(journal) = &mut self.journal {
+                    journal.push(entry.clone());
+                }
+                let hold = (transaction.tx_type == TransactionType::Dispute).then(|| entry.postings[0].amount);
+                let applied = Applied {
+                    balance: account_balance.clone(),
+                    entry,
+                    hold,
+                };
+                for observer in &mut self.observers {
+                    observer.on_applied(&before, &applied);
+                }
+                Ok(applied)
+            }
+            Err(reason) => {
+                self.reject(transaction, reason)?;
+                Err(Rejected {
+                    reason,
+                    balance: account_balance.clone(),
+                })
+            }
+        };
+        self.save_account(account_balance)?;
+        // Only once its effects are stored, and not when it can be retried
+        let dedup = self.dedup.as_mut().filter(|_| !duplicate && !throttled);
+        if let (Some(undo), Some(mut pre_image)) = (&mut self.undo, pre_image) {
+            pre_image.entry = outcome.as_ref().ok().map(|applied| applied.entry.clone());
+            pre_image.deduped = dedup.is_some();
+            undo.push(pre_image);
+        }
+        if let Some(dedup) = dedup {
+            dedup.insert(transaction.tx_type, transaction.tx_id)?;
+        }
+        if let Some(watermark) = &mut self.watermark {
+            watermark.seen(transaction.client_id);
+        }
+        if let Some(event_log) = &mut self.event_log {
+            event_log.flush()?;
+        }
+
+        Ok(outcome)
+    }
+
+    /// What the transaction is about to change, as it is, with the `balance` of its client.
+    fn pre_image(
+        &self,
+        transaction: &Transaction,
+        balance: Option<AccountBalance>,
+        interest: Option<(InterestAccrual, Vec<JournalEntry>)>,
+    ) -> Result<PreImage, Box<dyn Error>> {
+        let recorded = self.transactions.recorded(transaction.tx_id)?;
+        let pending = recorded
+            .as_ref()
+            .is_some_and(|recorded| recorded.status == TxStatus::Pending);
+        let settlement = match pending {
+            true => self.transactions.settlement(transaction.tx_id)?,
+            false => None,
+        };
+        Ok(PreImage {
+            transaction: transaction.clone(),
+            balance,
+            recorded,
+            settlement,
+            entry: None,
+            risk: self.risk.client(transaction.client_id),
+            losses: self.losses.get(transaction.client_id),
+            dispute: self.open_disputes.get(transaction.tx_id),
+            deduped: false,
+            interest,
+        })
+    }
+
+    /// Keeps the pre-image of a transaction that isn't applied, for the interest it posted.
+    fn push_pre_image(
+        &mut self,
+        transaction: &Transaction,
+        interest: Option<(InterestAccrual, Vec<JournalEntry>)>,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.undo.is_none() {
+            return Ok(());
+        }
+        let balance = self.load_account(transaction.client_id)?;
+        let pre_image = self.pre_image(transaction, balance, interest)?;
+        if let Some(undo) = &mut self.undo {
+            undo.push(pre_image);
+        }
+        Ok(())
+    }
+
+    /// Undoes the last `n` transactions that can still be undone, see
+    /// [`PaymentsEngine::with_undo`], putting back the balances and the deposits and withdrawals
+    /// they changed, and returns them, the latest first. Rejected transactions are undone too,
+    /// though the only thing they may have changed is the creation of an empty account.
+    /// See [`undo`](crate::undo) for what isn't undone.
+    pub fn undo(&mut self, n: usize) -> Result<Vec<Transaction>, Box<dyn Error>> {
+        let mut undone = vec![];
+        while undone.len() < n {
+            let Some(pre_image) = self.undo.as_mut().and_then(UndoLog::pop) else {
+                break;
+            };
+            let transaction = pre_image.transaction;
+            let status = self
+                .load_account(transaction.client_id)?
+                .map(|account_balance| account_balance.status);
+            match pre_image.balance {
+                Some(account_balance) => self.save_account(account_balance)?,
+                None => {
+                    self.accounts.remove(transaction.client_id)?;
+                    if let Some(batch) = &mut self.batch {
+                        batch.remove(&transaction.client_id);
+                    }
+                }
+            }
+            if let Some(entry) = pre_image.entry {
+                let records_or_refers = matches!(
+                    transaction.tx_type,
+                    TransactionType::Deposit
+                        | TransactionType::Withdrawal
+                        | TransactionType::Dispute
+                        | TransactionType::Resolve
+                        | TransactionType::Chargeback
+                        | TransactionType::ChargebackReversal
+                        | TransactionType::Reversal
+                        | TransactionType::Settle
+                );
+                if records_or_refers {
+                    undo::restore(
+                        self.transactions.as_mut(),
+                        transaction.tx_id,
+                        pre_image.recorded.as_ref(),
+                        pre_image.settlement.as_ref(),
+                    )?;
+                }
+                let entry = undo::reverse(&entry);
+                if let Some(event_log) = &mut self.event_log {
+                    event_log.posted(self.line, &entry)?;
+                    let restored = self
+                        .accounts
+                        .get(transaction.client_id)?
+                        .map(|account_balance| account_balance.status);
+                    if let Some(restored) = restored.filter(|restored| Some(*restored) != status) {
+                        event_log.status_changed(self.line, &entry, restored, None)?;
+                    }
+                }
+                if let Some(journal) = &mut self.journal {
+                    journal.push(entry);
+                }
+            }
+            self.risk.restore(transaction.client_id, pre_image.risk);
+            self.losses.restore(transaction.client_id, pre_image.losses);
+            self.open_disputes.restore(transaction.tx_id, pre_image.dispute);
+            if let Some(dedup) = self.dedup.as_mut().filter(|_| pre_image.deduped) {
+                dedup.remove(transaction.tx_type, transaction.tx_id)?;
+            }
+            if let Some((accrual, entries)) = pre_image.interest {
+                self.interest = accrual;
+                for entry in entries.iter().rev().map(undo::reverse) {
+                    if let Some(mut account_balance) = self.load_account(entry.client_id)? {
+                        ledger::post(&mut account_balance, &entry);
+                        self.save_account(account_balance)?;
+                    }
+                    if let Some(event_log) = &mut self.event_log {
+                        event_log.posted(self.line, &entry)?;
+                    }
+                    if let Some(journal) = &mut self.journal {
+                        journal.push(entry);
+                    }
+                }
+            }
+            undone.push(transaction);
+        }
+        if let Some(event_log) = &mut self.event_log {
+            event_log.flush()?;
+        }
+        Ok(undone)
+    }
+
+    /// Marks the current state as the savepoint `name`, instead of any earlier savepoint of the
+    /// same name, so that [`PaymentsEngine::rollback_to`] can get back to it, e.g. before each of
+    /// several files applied to the same state. Without [`PaymentsEngine::with_undo`], the
+    /// pre-images of all the transactions are kept from the oldest savepoint on, until it is
+    /// released with [`PaymentsEngine::release_savepoint`].
+    pub fn savepoint(&mut self, name: &str) {
+        self.undo.get_or_insert_with(UndoLog::for_savepoints).savepoint(name);
+    }
+
+    /// Forgets the savepoint `name` and the ones set after it, once the transactions since are
+    /// not to be rolled back. It is an error if there is no such savepoint.
+    pub fn release_savepoint(&mut self, name: &str) -> Result<(), SavepointError> {
+        let Some(undo) = &mut self.undo else {
+            return Err(SavepointError {
+                name: name.to_string(),
+                error_type: SavepointErrorType::Unknown,
+            });
+        };
+        undo.release(name)?;
+        if undo.unused() {
+            self.undo = None;
+        }
+        Ok(())
+    }
+
+    /// Undoes the transactions since the savepoint `name`, see [`PaymentsEngine::undo`], and
+    /// returns them, the latest first. The savepoint stays, and the ones set after it are gone.
+    /// It is an error if there is no such savepoint, or if it is older than the `depth` of
+    /// [`PaymentsEngine::with_undo`], and then nothing is undone.
+    pub fn rollback_to(&mut self, name: &str) -> Result<Vec<Transaction>, Box<dyn Error>> {
+        let since = match &self.undo {
+            Some(undo) => undo.since(name)?,
+            None => {
+                return Err(Box::new(SavepointError {
+                    name: name.to_string(),
+                    error_type: SavepointErrorType::Unknown,
+                }))
+            }
+        };
+        self.undo(since)
+    }
+
+    /// Applies several transactions, returning the outcome of each in order. A malformed
+    /// transaction only fails its own outcome, and the ones after it are still applied.
+    ///
+    /// The balance of each client is only read once from the account store for the whole batch,
+    /// which saves most of the lookups of a persistent store for micro-batches from a queue.
+    pub fn apply_batch(&mut self, transactions: &[Transaction]) -> Vec<Result<Outcome, Box<dyn Error>>> {
+        if let Some(Err(err)) = self.database.as_mut().map(|database| database.begin()) {
+            return transactions.iter().map(|_| Err(err.to_string().into())).collect();
+        }
+        self.batch = Some(HashMap::new());
+        let outcomes = transactions.iter().map(|transaction| self.apply(transaction)).collect();
+        self.batch = None;
+        // Nothing of the batch was stored if it can't be committed
+        match self.database.as_mut().map(|database| database.commit()) {
+            Some(Err(err)) => transactions.iter().map(|_| Err(err.to_string().into())).collect(),
+            _ => outcomes,
+        }
+    }
+
+    /// The balance of a client, from the ones read during a batch if it is one.
+    fn load_account(&mut self, client_id: ClientId) -> Result<Option<AccountBalance>, Box<dyn Error>> {
+        let Some(batch) = &mut self.batch else {
+            return self.accounts.get(client_id);
+        };
+        if let Some(account_balance) = batch.get(&client_id) {
+            return Ok(Some(account_balance.clone()));
+        }
+        let account_balance = self.accounts.get(client_id)?;
+        if let Some(account_balance) = &account_balance {
+            batch.insert(client_id, account_balance.clone());
+        }
+        Ok(account_balance)
+    }
+
+    /// Writes the balance of a client to the store, and keeps it for the rest of the batch.
+    fn save_account(&mut self, account_balance: AccountBalance) -> Result<(), Box<dyn Error>> {
+        self.accounts.put(&account_balance)?;
+        if let Some(batch) = &mut self.batch {
+            batch.insert(account_balance.client, account_balance);
+        }
+        Ok(())
+    }
+
+    /// Rejects a transaction for a `reason` found outside of the engine, e.g. by a
+    /// [`SharedEngine`](crate::shared::SharedEngine) across its shards, as [`apply`](PaymentsEngine::apply) would.
+    pub(crate) fn reject_transaction(
+        &mut self,
+        transaction: &Transaction,
+        reason: RejectionReason,
+    ) -> Result<Outcome, Box<dyn Error>> {
+        self.stats.processed += 1;
+        self.reject(transaction, reason)?;
+        if let Some(event_log) = &mut self.event_log {
+            event_log.flush()?;
+        }
+        // Like the transactions rejected by the engine, it creates the account of a new client
+        let balance = match self.load_account(transaction.client_id)? {
+            Some(balance) => balance,
+            None => AccountBalance::new(transaction.client_id),
+        };
+        self.save_account(balance.clone())?;
+        Ok(Err(Rejected { reason, balance }))
+    }
+
+    /// Counts a rejected transaction, and tells the observers and the event log about it.
+    fn reject(&mut self, transaction: &Transaction, reason: RejectionReason) -> Result<(), Box<dyn Error>> {
+        self.stats.rejected += 1;
+        *self.stats.rejections.entry(reason).or_default() += 1;
+        if let RejectionReason::Risk(violation) = reason {
+            *self.stats.risk_rejections.entry(violation).or_default() += 1;
+        }
+        if self.recent_rejections.len() == RECENT_REJECTIONS {
+            self.recent_rejections.pop_front();
+        }
+        self.recent_rejections.push_back(transaction.clone());
+
+        let rejection = Rejection {
+            line: self.line,
+            transaction: transaction.clone(),
+            reason,
+        };
+        for observer in &mut self.observers {
+            observer.on_rejected(&rejection);
+        }
+        if let Some(event_log) = &mut self.event_log {
+            event_log.rejected(&rejection)?;
+        }
+        if let Some(rejections) = &mut self.rejections {
+            rejections.push(rejection);
+        }
+        Ok(())
+    }
+
+    /// Returns the ledger entry of the transaction, or why it was ignored if it was invalid,
+    /// recording what the transaction store needs to know of it, see [`machine::decide`].
+    fn journal_entry(
+        &mut self,
+        account_balance: &AccountBalance,
+        transaction: &Transaction,
+    ) -> Result<Result<JournalEntry, RejectionReason>, Box<dyn Error>> {
+        // The deposit or withdrawal it refers to, or the one of the same id
+        let recorded = match transaction.tx_type {
+            TransactionType::Deposit
+            | TransactionType::Withdrawal
+            | TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::ChargebackReversal
+            | TransactionType::Reversal
+            | TransactionType::Settle => self.transactions.recorded(transaction.tx_id)?,
+            _ => None,
+        };
+        // The fees of the rules the transaction qualifies for, posted with it
+        let fee = self.config.fee_for(transaction);
+        let decision = match machine::decide(
+            account_balance,
+            transaction,
+            recorded.as_ref(),
+            fee,
+            &self.config.rules(),
+        )? {
+            Ok(decision) => decision,
+            Err(reason) => return Ok(Err(reason)),
+        };
+
+        if let Some(updated) = decision.effect.recorded(transaction, recorded.as_ref()) {
+            self.transactions.record(&updated)?;
+        }
+        match decision.effect {
+            Effect::RecordDeposit {
+                amount,
+                settles_at: Some(settles_at),
+            } => {
+                self.transactions.record_settlement(&PendingSettlement {
+                    tx_id: transaction.tx_id,
+                    client_id: transaction.client_id,
+                    amount,
+                    settles_at,
+                })?;
+            }
+            Effect::Dispute {
+                over_available: true, ..
+            } => self.stats.disputes_over_available += 1,
+            Effect::Settle => {
+                self.transactions.take_settlement(transaction.tx_id)?;
+            }
+            _ => {}
+        }
+        Ok(Ok(decision.entry))
+    }
+
+    /// Makes the funds of the deposits that settle at or before `timestamp` available.
+    fn settle(&mut self, timestamp: u64) -> Result<(), Box<dyn Error>> {
+        for settlement in self.transactions.take_settled(timestamp)? {
+            if let Some(recorded) = self.transactions.recorded(settlement.tx_id)? {
+                self.transactions.record(&RecordedTx {
+                    status: TxStatus::Settled,
+                    ..recorded
+                })?;
+            }
+            let client = settlement.client_id;
+            let mut account_balance = match self.load_account(client)? {
+                Some(account_balance) => account_balance,
+                None => AccountBalance::new(client),
+            };
+            let entry = JournalEntry {
+                reason: Some(String::from("settlement")),
+                ..JournalEntry::single(
+                    TransactionType::Deposit,
+                    client,
+                    settlement.tx_id,
+                    LedgerAccount::Pending(client),
+                    LedgerAccount::Available(client),
+                    settlement.amount,
+                )
+            };
+            ledger::post(&mut account_balance, &entry);
+            self.save_account(account_balance)?;
+            if let Some(event_log) = &mut self.event_log {
+                event_log.posted(self.line, &entry)?;
+            }
+            if let Some(journal) = &mut self.journal {
+                journal.push(entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves the disputes opened `expire_after_days` or more before `timestamp`, releasing
+    /// their holds, unless they were resolved or charged back already.
+    fn expire_disputes(&mut self, timestamp: u64) -> Result<(), Box<dyn Error>> {
+        let Some(days) = self.config.disputes.expire_after_days else {
+            return Ok(());
+        };
+        let Some(until) = timestamp.checked_sub(days as u64 * SECS_PER_DAY) else {
+            return Ok(());
+        };
+        for (tx_id, client) in self.open_disputes.take_expired(until) {
+            let recorded = self.transactions.recorded(tx_id)?;
+            if !matches!(
+                recorded.map(|recorded| recorded.status),
+                Some(TxStatus::Disputed { .. })
+            ) {
+                continue;
+            }
+            let Some(mut account_balance) = self.load_account(client)? else {
+                continue;
+            };
+            let resolve = Transaction {
+                tx_type: TransactionType::Resolve,
+                client_id: client,
+                tx_id,
+                amount: None,
+                reason: None,
+                timestamp: Some(timestamp),
+                settles_at: None,
+            };
+            // A locked or closed account keeps the hold
+            let Ok(entry) = self.journal_entry(&account_balance, &resolve)? else {
+                continue;
+            };
+            let entry = JournalEntry {
+                reason: Some(String::from("dispute_expired")),
+                ..entry
+            };
+            ledger::post(&mut account_balance, &entry);
+            self.save_account(account_balance)?;
+            self.stats.disputes_expired += 1;
+            if let Some(event_log) = &mut self.event_log {
+                event_log.posted(self.line, &entry)?;
+            }
+            if let Some(journal) = &mut self.journal {
+                journal.push(entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Hands over the accounts of the clients idle since `close_after` before `timestamp`, unless
+    /// they can still change without a transaction of their own.
+    fn finalize_idle_accounts(&mut self, timestamp: u64) -> Result<(), Box<dyn Error>> {
+        let Some(watermark) = &mut self.watermark else {
+            return Ok(());
+        };
+        let idle = watermark.advance(timestamp);
+        for client in idle {
+            let Some(account_balance) = self.load_account(client)? else {
+                continue;
+            };
+            let watermark = self.watermark.as_mut().expect("the watermark is set");
+            let expiring = account_balance.held != Amount::ZERO && self.config.disputes.expire_after_days.is_some();
+            if self.config.interest.is_some() || account_balance.pending != Amount::ZERO || expiring {
+                watermark.seen(client);
+                continue;
+            }
+            (watermark.sink)(&account_balance)?;
+            watermark.finalize(client);
+            self.last_activity.remove(&client);
+            self.accounts.remove(client)?;
+            if let Some(batch) = &mut self.batch {
+                batch.remove(&client);
+            }
+        }
+        Ok(())
+    }
+
+    /// All the account balances, sorted by client id.
+    pub fn balances(&self) -> Result<Vec<AccountBalance>, Box<dyn Error>> {
+        self.accounts.all()
+    }
+
+    /// The deposit or withdrawal with this id, its client and where it stands, if it was recorded.
+    pub fn transaction(&self, tx_id: TxId) -> Result<Option<RecordedTx>, Box<dyn Error>> {
+        self.transactions.recorded(tx_id)
+    }
+
+    /// The balance of a client, if we have seen it before.
+    pub fn account(&self, client_id: ClientId) -> Result<Option<AccountBalance>, Box<dyn Error>> {
+        self.accounts.get(client_id)
+    }
+
+    /// The balance of a client, with its open disputes and when it was last active, if we have
+    /// seen it before. The open disputes are looked up among all the recorded deposits.
+    pub fn account_view(&self, client_id: ClientId) -> Result<Option<AccountView>, Box<dyn Error>> {
+        let Some(balance) = self.accounts.get(client_id)? else {
+            return Ok(None);
+        };
+        let open_disputes = self
+            .open_disputes()?
+            .into_iter()
+            .filter(|dispute| dispute.client == Some(client_id))
+            .collect();
+        Ok(Some(AccountView {
+            balance,
+            open_disputes,
+            last_activity: self.last_activity.get(&client_id).copied(),
+        }))
+    }
+
+    /// The disputes still open, charged back or not, sorted by the id of the deposit, looked up
+    /// among all the recorded deposits.
+    pub fn open_disputes(&self) -> Result<Vec<OpenDispute>, Box<dyn Error>> {
+        Ok(self
+            .transactions
+            .all()?
+            .into_iter()
+            .filter_map(|deposit| {
+                Some(OpenDispute {
+                    client: deposit.client_id,
+                    tx: deposit.tx_id,
+                    held: deposit.status.held()?,
+                    charged_back: matches!(deposit.status, TxStatus::ChargedBack { .. }),
+                    opened_at: self.open_disputes.opened_at(deposit.tx_id),
+                })
+            })
+            .collect())
+    }
+
+    /// The latest `timestamp` of the transactions applied, which is what time the engine takes
+    /// it to be, see [`open_disputes`](PaymentsEngine::open_disputes).
+    pub fn latest_timestamp(&self) -> Option<u64> {
+        self.last_activity.values().max().copied()
+    }
+
+    /// What the engine did so far.
+    pub fn stats(&self) -> &ProcessingStats {
+        &self.stats
+    }
+
+    /// The ledger entries of the applied transactions, in order.
+    /// Empty unless the engine was created [`with_journal`](PaymentsEngine::with_journal).
+    pub fn journal(&self) -> &[JournalEntry] {
+        self.journal.as_deref().unwrap_or_default()
+    }
+
+    /// The suspicious activity found so far.
+    /// Empty unless the engine was created [`with_aml`](PaymentsEngine::with_aml).
+    pub fn aml_findings(&self) -> Vec<Finding> {
+        self.aml
+            .as_ref()
+            .map(|aml| aml.findings(&self.config.aml))
+            .unwrap_or_default()
+    }
+
+    /// The activity of each period with any, by the `YYYY-MM-DD` of its first day, the one
+    /// of the transactions without a timestamp first.
+    /// Empty unless the engine was created [`with_activity`](PaymentsEngine::with_activity).
+    pub fn activity(&self) -> Vec<(Option<String>, ActivityTotals)> {
+        self.activity.as_ref().map(Activity::periods).unwrap_or_default()
+    }
+
+    /// The losses to chargebacks of the clients with any, sorted by client id.
+    pub fn losses(&self) -> Vec<(ClientId, LossTotals)> {
+        self.losses.by_client()
+    }
+
+    /// The losses to chargebacks of all the clients.
+    pub fn loss_total(&self) -> LossTotals {
+        self.losses.total()
+    }
+
+    /// All the rejected transactions, in order.
+    /// Empty unless the engine was created [`with_rejections`](PaymentsEngine::with_rejections).
+    pub fn rejections(&self) -> &[Rejection] {
+        self.rejections.as_deref().unwrap_or_default()
+    }
+
+    /// The state of the queue the transactions are applied from, when following a file or serving.
+    pub fn queue_metrics(&self) -> Option<QueueMetrics> {
+        self.queue.as_ref().map(QueueGauge::metrics)
+    }
+
+    pub(crate) fn set_queue(&mut self, queue: Option<QueueGauge>) {
+        self.queue = queue;
+    }
+
+    /// The last rejected transactions, oldest first.
+    pub fn recent_rejections(&self) -> impl DoubleEndedIterator<Item = &Transaction> {
+        self.recent_rejections.iter()
+    }
+
+    /// A SHA-256 hash of all the account balances, to check that two runs ended in the same state.
+    pub fn state_hash(&self) -> Result<String, Box<dyn Error>> {
+        Ok(state_hash(&self.balances()?))
+    }
+
+    /// Everything the engine did so far: the balances, the stats, the rejected transactions
+    /// if it was created [`with_rejections`](PaymentsEngine::with_rejections), and the hash of
+    /// the state.
+    pub fn processing_report(&self) -> Result<ProcessingReport, Box<dyn Error>> {
+        let balances = self.balances()?;
+        Ok(ProcessingReport {
+            state_hash: state_hash(&balances),
+            balances,
+            stats: self.stats.clone(),
+            rejections: self.rejections().to_vec(),
+        })
+    }
+
+    /// Generates the account balances report.
+    pub fn report(&self) -> Result<String, Box<dyn Error>> {
+        let balances = self.balances()?;
+        Ok(format_report_columns(
+            &balances,
+            &REPORT_COLUMNS,
+            self.clients.as_ref(),
+            &self.config.output,
+        ))
+    }
+}
+
+/// A SHA-256 hash of the account balances, sorted by client id, see [`PaymentsEngine::state_hash`].
+pub fn state_hash(balances: &[AccountBalance]) -> String {
+    let mut hasher = Sha256::new();
+    for account_balance in balances {
+        hasher.update(account_balance.client.to_le_bytes());
+        hasher.update(account_balance.available.to_le_bytes());
+        hasher.update(account_balance.held.to_le_bytes());
+        hasher.update(account_balance.pending.to_le_bytes());
+        hasher.update([account_balance.status as u8]);
+    }
+    hex::encode(hasher.finalize())
+}
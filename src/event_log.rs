@@ -0,0 +1,433 @@
+//! An NDJSON log of every effect of the engine on the accounts, one JSON event per line, as an
+//! audit feed, see [`PaymentsEngine::with_event_log`](crate::PaymentsEngine::with_event_log).
+//!
+//! Each posting of an applied transaction, a settlement or interest is a `posted` event, e.g.
+//! a deposit credited to the available funds or a hold placed by a dispute. The changes of the
+//! status of an account, e.g. locked by a chargeback, or by an auto-lock rule with its code as
+//! the `reason`, are `status_changed` events, and the rejected transactions `rejected` events
+//! with their reason code.
+//!
+//! The log is enough to rebuild the balances with [`replay`], independently of the engine.
+//!
+//! The events are a hash chain: each has the SHA-256 hash of the line of the one before it, so
+//! [`verify_chain`] finds any event modified, removed or inserted after the fact. Only the
+//! last events could be removed unnoticed, unless the hash of the last one is kept elsewhere.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::accounts::{AccountBalance, AccountStatus};
+use crate::custom_errors::LineError;
+use crate::dates::rfc3339;
+use crate::ledger::{self, JournalEntry, LedgerAccount};
+use crate::rejections::Rejection;
+use crate::transactions::{Amount, ClientId, TransactionType, TxId};
+
+/// What an event did.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Effect {
+    /// Moved `amount` from one ledger account to another.
+    Posted {
+        from: LedgerAccount,
+        to: LedgerAccount,
+        amount: Amount,
+        /// Why the entry was posted, for adjustments and settlements.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// The account of the client changed to this status.
+    StatusChanged {
+        status: AccountStatus,
+        /// Why it changed, if not because of the transaction itself, e.g. the code of the
+        /// auto-lock rule that locked it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// The transaction was ignored, with the code of the reason.
+    Rejected { reason: String },
+}
+
+/// A line of the event log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Event {
+    /// The position of the event in the log of this run, from 1.
+    pub seq: u64,
+    /// The hash of the line of the event before, or [`GENESIS_HASH`] for the first one.
+    pub prev_hash: String,
+    /// When the event was recorded, as an RFC 3339 UTC time.
+    pub time: String,
+    /// The line of the input of the transaction, if it was read from one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<u64>,
+    /// The type of the transaction, as in the input, or `interest`.
+    #[serde(rename = "type")]
+    pub tx_type: String,
+    pub client: ClientId,
+    pub tx: TxId,
+    #[serde(flatten)]
+    pub effect: Effect,
+}
+
+/// The `prev_hash` of the first event of a log.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// The hash of a line of the log, without its line break.
+pub(crate) fn line_hash(line: &[u8]) -> String {
+    hex::encode(Sha256::digest(line))
+}
+
+/// Writes the events as they happen.
+pub(crate) struct EventLog {
+    writer: Box<dyn Write + Send>,
+    seq: u64,
+    prev_hash: String,
+}
+
+impl EventLog {
+    pub(crate) fn new(writer: Box<dyn Write + Send>) -> Self {
+        EventLog {
+            writer,
+            seq: 0,
+            prev_hash: String::from(GENESIS_HASH),
+        }
+    }
+
+    fn write(
+        &mut self,
+        line: Option<u64>,
+        tx_type: TransactionType,
+        client: ClientId,
+        tx: TxId,
+        effect: Effect,
+    ) -> Result<(), Box<dyn Error>> {
+        self.seq += 1;
+        let event = Event {
+            seq: self.seq,
+            prev_hash: self.prev_hash.clone(),
+            time: rfc3339(SystemTime::now()),
+            line,
+            tx_type: tx_type.as_str().to_string(),
+            client,
+            tx,
+            effect,
+        };
+        let line = serde_json::to_vec(&event)?;
+        self.writer.write_all(&line)?;
+        writeln!(self.writer)?;
+        self.prev_hash = line_hash(&line);
+        Ok(())
+    }
+
+    /// Writes a `posted` event for each posting of the entry.
+    pub(crate) fn posted(&mut self, line: Option<u64>, entry: &JournalEntry) -> Result<(), Box<dyn Error>> {
+        for posting in &entry.postings {
+            let effect = Effect::Posted {
+                from: posting.from,
+                to: posting.to,
+                amount: posting.amount,
+                reason: entry.reason.clone(),
+            };
+            self.write(line, entry.tx_type, entry.client_id, entry.tx_id, effect)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn status_changed(
+        &mut self,
+        line: Option<u64>,
+        entry: &JournalEntry,
+        status: AccountStatus,
+        reason: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        let reason = reason.map(str::to_string);
+        self.write(
+            line,
+            entry.tx_type,
+            entry.client_id,
+            entry.tx_id,
+            Effect::StatusChanged { status, reason },
+        )
+    }
+
+    pub(crate) fn rejected(&mut self, rejection: &Rejection) -> Result<(), Box<dyn Error>> {
+        let transaction = &rejection.transaction;
+        let reason = rejection.reason.code().to_string();
+        self.write(
+            rejection.line,
+            transaction.tx_type,
+            transaction.client_id,
+            transaction.tx_id,
+            Effect::Rejected { reason },
+        )
+    }
+
+    /// Writes the buffered events, once a transaction is done.
+    pub(crate) fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// An event of the log doesn't follow the one before it, so the log was modified.
+#[derive(Debug)]
+pub struct BrokenChainError {
+    /// The line of the event.
+    pub line: u64,
+}
+
+impl Error for BrokenChainError {}
+
+impl fmt::Display for BrokenChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The event at line {} doesn't follow the one before it, the log was modified",
+            self.line
+        )
+    }
+}
+
+/// The end of a verified event log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainHead {
+    pub events: u64,
+    /// The hash of the last line, to compare with one kept elsewhere.
+    pub hash: String,
+}
+
+/// Checks that each event of the log has the hash of the one before it, and the next `seq`.
+pub fn verify_chain<R: BufRead>(reader: R) -> Result<ChainHead, Box<dyn Error>> {
+    let mut head = ChainHead {
+        events: 0,
+        hash: String::from(GENESIS_HASH),
+    };
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_number = index as u64 + 1;
+        let event: Event = serde_json::from_str(&line).map_err(|error| LineError {
+            line: line_number,
+            error: Box::new(error),
+        })?;
+        if event.prev_hash != head.hash || event.seq != head.events + 1 {
+            return Err(Box::new(BrokenChainError { line: line_number }));
+        }
+        head = ChainHead {
+            events: event.seq,
+            hash: line_hash(line.as_bytes()),
+        };
+    }
+    Ok(head)
+}
+
+/// The client of a ledger account of a client.
+fn client_of(account: LedgerAccount) -> Option<ClientId> {
+    match account {
+        LedgerAccount::Available(client) | LedgerAccount::Held(client) | LedgerAccount::Pending(client) => Some(client),
+        _ => None,
+    }
+}
+
+/// Rebuilds the account balances, sorted by client id, from an event log alone, written by an
+/// engine that started empty. Every client with an event has an account, as in the engine,
+/// which also keeps the accounts of the clients with only rejected transactions.
+pub fn replay<R: BufRead>(reader: R) -> Result<Vec<AccountBalance>, Box<dyn Error>> {
+    let mut balances: BTreeMap<ClientId, AccountBalance> = BTreeMap::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: Event = serde_json::from_str(&line).map_err(|error| LineError {
+            line: index as u64 + 1,
+            error: Box::new(error),
+        })?;
+        apply(&mut balances, &event);
+    }
+    Ok(balances.into_values().collect())
+}
+
+/// Applies an event to the balances, see [`replay`].
+pub(crate) fn apply(balances: &mut BTreeMap<ClientId, AccountBalance>, event: &Event) {
+    balances
+        .entry(event.client)
+        .or_insert_with(|| AccountBalance::new(event.client));
+    match event.effect {
+        Effect::Posted { from, to, amount, .. } => {
+            // Only the postings matter to the balances
+            let entry = JournalEntry::single(TransactionType::Deposit, event.client, event.tx, from, to, amount);
+            // Each side once, even when both are of the same client
+            let mut clients: Vec<ClientId> = [from, to].into_iter().filter_map(client_of).collect();
+            clients.dedup();
+            for client in clients {
+                let account_balance = balances.entry(client).or_insert_with(|| AccountBalance::new(client));
+                ledger::post(account_balance, &entry);
+            }
+        }
+        Effect::StatusChanged { status, .. } => {
+            if let Some(account_balance) = balances.get_mut(&event.client) {
+                account_balance.status = status;
+            }
+        }
+        Effect::Rejected { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    use super::{replay, verify_chain, BrokenChainError, Effect, Event};
+    use crate::accounts::AccountStatus;
+    use crate::amount::amount;
+    use crate::config::EngineConfig;
+    use crate::engine::{state_hash, PaymentsEngine};
+    use crate::ledger::LedgerAccount;
+
+    /// A writer of a buffer the test can still read.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_event_log() {
+        let input = "type, client, tx, amount
+            deposit, 1, 1, 2.0
+            dispute, 1, 1
+            chargeback, 1, 1
+            withdrawal, 1, 2, 1.0";
+        let log = Shared::default();
+        let mut engine = PaymentsEngine::new().with_event_log(Box::new(log.clone()));
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<Event> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let effects: Vec<(u64, Option<u64>, &str, Effect)> = events
+            .iter()
+            .map(|event| (event.seq, event.line, event.tx_type.as_str(), event.effect.clone()))
+            .collect();
+        let posted = |from, to| Effect::Posted {
+            from,
+            to,
+            amount: amount(2.0),
+            reason: None,
+        };
+        assert_eq!(
+            effects,
+            [
+                (
+                    1,
+                    Some(2),
+                    "deposit",
+                    posted(LedgerAccount::External, LedgerAccount::Available(1))
+                ),
+                (
+                    2,
+                    Some(3),
+                    "dispute",
+                    posted(LedgerAccount::Available(1), LedgerAccount::Held(1))
+                ),
+                (
+                    3,
+                    Some(4),
+                    "chargeback",
+                    posted(LedgerAccount::Held(1), LedgerAccount::Chargebacks)
+                ),
+                (
+                    4,
+                    Some(4),
+                    "chargeback",
+                    Effect::StatusChanged {
+                        status: AccountStatus::Locked,
+                        reason: None
+                    }
+                ),
+                (
+                    5,
+                    Some(5),
+                    "withdrawal",
+                    Effect::Rejected {
+                        reason: String::from("ACCOUNT_LOCKED")
+                    }
+                ),
+            ]
+        );
+        assert!(log.starts_with(&format!(r#"{{"seq":1,"prev_hash":"{}","time":"#, super::GENESIS_HASH)));
+        assert!(events[0].time.ends_with('Z'));
+    }
+
+    #[test]
+    fn test_replay() {
+        let config = EngineConfig {
+            admin_ops: true,
+            ..EngineConfig::from_toml("interest = { annual_rate_percent = 5.0 }").unwrap()
+        };
+        for input in [
+            "dispute_chargeback",
+            "multiple_clients",
+            "settlement",
+            "interest",
+            "freeze",
+            "close",
+            "reversal",
+            "partial_dispute",
+        ] {
+            let log = Shared::default();
+            let mut engine = PaymentsEngine::new()
+                .with_config(config.clone())
+                .with_event_log(Box::new(log.clone()));
+            engine
+                .process_csv(Path::new(&format!("sample_files/{}.csv", input)))
+                .unwrap();
+
+            // The same balances, to the bit
+            let log = log.0.lock().unwrap().clone();
+            let balances = replay(log.as_slice()).unwrap();
+            assert_eq!(state_hash(&balances), engine.state_hash().unwrap(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_verify_chain() {
+        let log = Shared::default();
+        let mut engine = PaymentsEngine::new().with_event_log(Box::new(log.clone()));
+        engine
+            .process_csv(Path::new("sample_files/dispute_chargeback.csv"))
+            .unwrap();
+        let log = String::from_utf8(log.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        let head = verify_chain(log.as_bytes()).unwrap();
+        assert_eq!(head.events, lines.len() as u64);
+
+        // Changing, removing or reordering events breaks the chain after them
+        let broken_at = |lines: &[String]| {
+            let error = verify_chain(lines.join("\n").as_bytes()).unwrap_err();
+            error.downcast_ref::<BrokenChainError>().unwrap().line
+        };
+        let mut changed: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        changed[0] = changed[0].replace(r#""amount":"#, r#""amount":1"#);
+        assert_eq!(broken_at(&changed), 2);
+        let mut removed: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        removed.remove(1);
+        assert_eq!(broken_at(&removed), 2);
+        let mut swapped: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        swapped.swap(0, 1);
+        assert_eq!(broken_at(&swapped), 1);
+    }
+}
@@ -0,0 +1,128 @@
+//! Exports the ledger entries in plain text accounting formats, for ledger-cli and beancount.
+//!
+//! Each transaction is one accounting transaction with a pair of postings. The client funds
+//! are liabilities, so a deposit is booked as:
+//!
+//! ```text
+//! 2024-06-01 * "deposit" "client 1, tx 1"
+//!   Assets:External                          1.0000 USD
+//!   Liabilities:Clients:1:Available         -1.0000 USD
+//! ```
+
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::io::Write;
+
+use crate::ledger::JournalEntry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountingFormat {
+    /// ledger-cli
+    Ledger,
+    /// beancount, which also needs the accounts to be opened before using them.
+    Beancount,
+}
+
+/// Writes the entries, all booked on `date` (`YYYY-MM-DD`) in `currency`.
+pub fn write_journal<W: Write>(
+    journal: &[JournalEntry],
+    format: AccountingFormat,
+    date: &str,
+    currency: &str,
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    if format == AccountingFormat::Beancount {
+        writeln!(writer, "option \"operating_currency\" \"{}\"", currency)?;
+        let accounts: BTreeSet<_> = journal
+            .iter()
+            .flat_map(|entry| &entry.postings)
+            .flat_map(|posting| [posting.from, posting.to])
+            .collect();
+        for account in accounts {
+            writeln!(writer, "{} open {}", date, account)?;
+        }
+    }
+
+    // Only the entries that move funds, e.g. not the freezes
+    for entry in journal.iter().filter(|entry| !entry.postings.is_empty()) {
+        let tx_type = format!("{:?}", entry.tx_type).to_lowercase();
+        let mut narration = format!("client {}, tx {}", entry.client_id, entry.tx_id);
+        if let Some(reason) = &entry.reason {
+            narration += &format!(", {}", reason.replace('"', "'"));
+        }
+        writeln!(writer)?;
+        match format {
+            AccountingFormat::Ledger => writeln!(writer, "{} {}, {}", date, tx_type, narration)?,
+            AccountingFormat::Beancount => writeln!(writer, "{} * \"{}\" \"{}\"", date, tx_type, narration)?,
+        }
+        // The funds come out of `from`, a debit, and into `to`, a credit
+        for posting in &entry.postings {
+            writeln!(
+                writer,
+                "  {:<36} {:>12.4} {}",
+                posting.from.to_string(),
+                posting.amount,
+                currency
+            )?;
+            writeln!(
+                writer,
+                "  {:<36} {:>12.4} {}",
+                posting.to.to_string(),
+                -posting.amount,
+                currency
+            )?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{write_journal, AccountingFormat};
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_export_ledger() {
+        let mut engine = PaymentsEngine::new().with_journal();
+        engine.process_csv(Path::new("sample_files/dispute.csv")).unwrap();
+
+        let mut output = vec![];
+        write_journal(
+            engine.journal(),
+            AccountingFormat::Ledger,
+            "2024-06-01",
+            "USD",
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "
+2024-06-01 deposit, client 1, tx 1
+  Assets:External                            1.0000 USD
+  Liabilities:Clients:1:Available           -1.0000 USD
+
+2024-06-01 dispute, client 1, tx 1
+  Liabilities:Clients:1:Available            1.0000 USD
+  Liabilities:Clients:1:Held                -1.0000 USD
+"
+        );
+
+        let mut output = vec![];
+        write_journal(
+            engine.journal(),
+            AccountingFormat::Beancount,
+            "2024-06-01",
+            "USD",
+            &mut output,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output
+            .starts_with("option \"operating_currency\" \"USD\"\n2024-06-01 open Liabilities:Clients:1:Available\n"));
+        assert!(output.contains("2024-06-01 * \"dispute\" \"client 1, tx 1\"\n"));
+    }
+}
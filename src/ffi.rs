@@ -0,0 +1,140 @@
+//! A C compatible API, declared in `include/payments_engine.h`.
+//!
+//! The engine is an opaque pointer owned by the caller, that must be released
+//! with `engine_free`. Strings returned by the engine must be released with
+//! `engine_string_free`.
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr;
+
+use crate::accounts::format_report_json;
+use crate::custom_errors::FailureKind;
+use crate::engine::PaymentsEngine;
+use crate::transactions::Transaction;
+
+/// The transaction was applied, or ignored because it was invalid.
+pub const ENGINE_OK: c_int = 0;
+/// A null pointer or a line that isn't valid UTF-8 was given.
+pub const ENGINE_INVALID_ARGUMENT: c_int = 2;
+/// The storage of the engine failed.
+pub const ENGINE_IO_ERROR: c_int = 3;
+/// The line isn't a valid CSV transaction record.
+pub const ENGINE_PARSE_ERROR: c_int = 4;
+/// The record is well formed but invalid, e.g. a deposit without an amount.
+pub const ENGINE_SEMANTIC_ERROR: c_int = 5;
+/// Anything else.
+pub const ENGINE_INTERNAL_ERROR: c_int = 1;
+
+/// Creates an engine that keeps all of its state in memory.
+#[no_mangle]
+pub extern "C" fn engine_new() -> *mut PaymentsEngine {
+    Box::into_raw(Box::new(PaymentsEngine::new()))
+}
+
+/// Applies a single CSV record, without a header, e.g. `deposit, 1, 1, 1.0`.
+///
+/// # Safety
+///
+/// `engine` must come from `engine_new` and `line` must be a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn engine_apply_csv_line(engine: *mut PaymentsEngine, line: *const c_char) -> c_int {
+    if engine.is_null() || line.is_null() {
+        return ENGINE_INVALID_ARGUMENT;
+    }
+    let engine = &mut *engine;
+    let line = match CStr::from_ptr(line).to_str() {
+        Ok(line) => line,
+        Err(_) => return ENGINE_INVALID_ARGUMENT,
+    };
+
+    let transaction = match Transaction::parse_csv_record(line.as_bytes()) {
+        Ok(transaction) => transaction,
+        Err(_) => return ENGINE_PARSE_ERROR,
+    };
+    match engine.apply(&transaction) {
+        Ok(_) => ENGINE_OK,
+        Err(err) => match FailureKind::of(err.as_ref()) {
+            FailureKind::BadArguments => ENGINE_INVALID_ARGUMENT,
+            FailureKind::Io => ENGINE_IO_ERROR,
+            FailureKind::Parse => ENGINE_PARSE_ERROR,
+            FailureKind::Semantic => ENGINE_SEMANTIC_ERROR,
+            FailureKind::Internal => ENGINE_INTERNAL_ERROR,
+        },
+    }
+}
+
+/// The account balances as a JSON array, sorted by client id,
+/// or null if the engine is null or its storage failed.
+///
+/// # Safety
+///
+/// `engine` must come from `engine_new`. The returned string must be released with `engine_string_free`.
+#[no_mangle]
+pub unsafe extern "C" fn engine_report_json(engine: *const PaymentsEngine) -> *mut c_char {
+    if engine.is_null() {
+        return ptr::null_mut();
+    }
+    let balances = match (*engine).balances() {
+        Ok(balances) => balances,
+        Err(_) => return ptr::null_mut(),
+    };
+
+    match CString::new(format_report_json(&balances)) {
+        Ok(report) => report.into_raw(),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Releases a string returned by the engine.
+///
+/// # Safety
+///
+/// `s` must come from the engine and not be used afterwards. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn engine_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Releases an engine.
+///
+/// # Safety
+///
+/// `engine` must come from `engine_new` and not be used afterwards. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn engine_free(engine: *mut PaymentsEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+
+    use super::*;
+
+    #[test]
+    fn test_ffi_round_trip() {
+        unsafe {
+            let engine = engine_new();
+            for line in ["deposit, 1, 1, 1.5", "withdrawal,1,2,0.5"] {
+                let line = CString::new(line).unwrap();
+                assert_eq!(engine_apply_csv_line(engine, line.as_ptr()), ENGINE_OK);
+            }
+            let invalid = CString::new("deposit, one, 1, 1.0").unwrap();
+            assert_eq!(engine_apply_csv_line(engine, invalid.as_ptr()), ENGINE_PARSE_ERROR);
+            let no_amount = CString::new("deposit, 1, 3").unwrap();
+            assert_eq!(engine_apply_csv_line(engine, no_amount.as_ptr()), ENGINE_SEMANTIC_ERROR);
+
+            let report = engine_report_json(engine);
+            assert_eq!(
+                CStr::from_ptr(report).to_str().unwrap(),
+                r#"[{"available":1.0,"client":1,"held":0.0,"locked":false,"pending":0.0,"status":"active","total":1.0}]"#
+            );
+            engine_string_free(report);
+            engine_free(engine);
+        }
+    }
+}
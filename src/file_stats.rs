@@ -0,0 +1,290 @@
+//! A pre-flight report of a CSV file of transactions, read without applying them, see
+//! [`scan`], so that a bad file can be caught before the batch window instead of during it:
+//! how many rows and clients it has, the range of its transaction ids and the ones used
+//! twice, the count of each type, what its amounts and timestamps look like, and about how
+//! much memory processing it would take.
+
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+use std::mem::size_of;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::accounts::AccountBalance;
+use crate::amount::AmountValue;
+use crate::csv_input::CsvOptions;
+use crate::dates::rfc3339;
+use crate::store::RecordedTx;
+use crate::transactions::{ClientId, TransactionType, TxId};
+
+/// The upper bounds of the buckets of the amounts, the last bucket having none.
+const AMOUNT_BUCKETS: [f64; 6] = [1.0, 10.0, 100.0, 1_000.0, 10_000.0, 100_000.0];
+
+/// The amounts of the deposits and withdrawals.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmountStats {
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub total: f64,
+    /// How many are below each of 1, 10, 100, 1k, 10k and 100k and not below the one before,
+    /// and how many are above them all.
+    pub buckets: [u64; 7],
+}
+
+impl AmountStats {
+    fn add(&mut self, amount: f64) {
+        self.count += 1;
+        self.min = self.min.min(amount);
+        self.max = self.max.max(amount);
+        self.total += amount;
+        let bucket = AMOUNT_BUCKETS.iter().position(|bound| amount.abs() < *bound);
+        self.buckets[bucket.unwrap_or(AMOUNT_BUCKETS.len())] += 1;
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.total / self.count as f64
+    }
+}
+
+/// The timestamps of the rows that have one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimestampStats {
+    pub count: u64,
+    pub first: u64,
+    pub last: u64,
+    /// How many are earlier than the one of a row before them.
+    pub out_of_order: u64,
+}
+
+/// What a file of transactions has, see [`scan`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FileStats {
+    pub rows: u64,
+    /// The rows that aren't a transaction, and the line and error of the first one.
+    pub malformed: u64,
+    pub first_malformed: Option<(u64, String)>,
+    pub clients: u64,
+    /// The lowest and highest ids of the transactions.
+    pub tx_range: Option<(TxId, TxId)>,
+    /// The deposits and withdrawals with the id of an earlier one, which would be rejected.
+    pub duplicate_txs: u64,
+    /// The rows of each type, only the ones with any.
+    pub by_type: BTreeMap<TransactionType, u64>,
+    pub amounts: Option<AmountStats>,
+    pub timestamps: Option<TimestampStats>,
+    // Kept for the estimate of the memory
+    deposits: u64,
+    withdrawals: u64,
+}
+
+/// About how many bytes a `HashMap` of `len` entries of `entry` bytes takes: a power of two
+/// of buckets at most 7/8 full, with a control byte for each.
+fn hash_map_bytes(len: u64, entry: usize) -> u64 {
+    if len == 0 {
+        return 0;
+    }
+    let buckets = (len * 8).div_ceil(7).next_power_of_two();
+    buckets * (entry as u64 + 1)
+}
+
+impl FileStats {
+    /// About how much memory a run with the in-memory stores would take for the accounts and the
+    /// deposits and withdrawals they keep, which is most of it for a large file.
+    pub fn estimated_memory_bytes(&self) -> u64 {
+        // The nodes of a BTreeMap are about two thirds full
+        let accounts = self.clients * (size_of::<ClientId>() + size_of::<AccountBalance>()) as u64 * 3 / 2;
+        // A recorded transaction for each
+        accounts
+            + hash_map_bytes(
+                self.deposits + self.withdrawals,
+                size_of::<TxId>() + size_of::<RecordedTx>(),
+            )
+    }
+}
+
+fn time(timestamp: u64) -> String {
+    rfc3339(UNIX_EPOCH + Duration::from_secs(timestamp))
+}
+
+impl fmt::Display for FileStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "rows: {}", self.rows)?;
+        match &self.first_malformed {
+            Some((line, error)) => writeln!(
+                f,
+                "malformed: {}, the first on line {}: {}",
+                self.malformed, line, error
+            )?,
+            None => writeln!(f, "malformed: 0")?,
+        }
+        writeln!(f, "clients: {}", self.clients)?;
+        match self.tx_range {
+            Some((min, max)) => writeln!(f, "tx ids: {} to {}", min, max)?,
+            None => writeln!(f, "tx ids: none")?,
+        }
+        writeln!(f, "duplicate tx ids: {}", self.duplicate_txs)?;
+        for (tx_type, count) in &self.by_type {
+            writeln!(f, "{}: {}", tx_type.as_str(), count)?;
+        }
+        match &self.amounts {
+            Some(amounts) => {
+                writeln!(
+                    f,
+                    "amounts: {} from {} to {}, mean {:.4}, total {:.4}",
+                    amounts.count,
+                    amounts.min,
+                    amounts.max,
+                    amounts.mean(),
+                    amounts.total
+                )?;
+                let labels = [
+                    "< 1",
+                    "1 to 10",
+                    "10 to 100",
+                    "100 to 1k",
+                    "1k to 10k",
+                    "10k to 100k",
+                    ">= 100k",
+                ];
+                for (label, count) in labels.iter().zip(amounts.buckets) {
+                    writeln!(f, "  {}: {}", label, count)?;
+                }
+            }
+            None => writeln!(f, "amounts: none")?,
+        }
+        match &self.timestamps {
+            Some(timestamps) => writeln!(
+                f,
+                "timestamps: {} of {} rows, from {} to {}, {} out of order",
+                timestamps.count,
+                self.rows - self.malformed,
+                time(timestamps.first),
+                time(timestamps.last),
+                timestamps.out_of_order
+            )?,
+            None => writeln!(f, "timestamps: none")?,
+        }
+        write!(
+            f,
+            "estimated memory: {:.1} MiB",
+            self.estimated_memory_bytes() as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
+
+/// Reads every row of a CSV file of transactions, with the `options` it would be processed
+/// with, without applying any. The rows that aren't a transaction are counted instead of
+/// failing the scan.
+pub fn scan<R: Read>(reader: R, options: &CsvOptions) -> Result<FileStats, Box<dyn Error>> {
+    let (mut rdr, headers) = options.reader(reader)?;
+    let mut stats = FileStats::default();
+    let mut clients: HashSet<ClientId> = HashSet::new();
+    let mut recorded: HashSet<TxId> = HashSet::new();
+    for record in rdr.records() {
+        stats.rows += 1;
+        let line = match &record {
+            Ok(record) => record.position(),
+            Err(error) => error.position(),
+        }
+        .map_or(stats.rows + 1, |position| position.line());
+        let transaction = match record.and_then(|record| options.deserialize(&record, &headers)) {
+            Ok(transaction) => transaction,
+            Err(error) => {
+                stats.malformed += 1;
+                stats.first_malformed.get_or_insert_with(|| (line, error.to_string()));
+                continue;
+            }
+        };
+
+        clients.insert(transaction.client_id);
+        let tx_id = transaction.tx_id;
+        stats.tx_range = Some(
+            stats
+                .tx_range
+                .map_or((tx_id, tx_id), |(min, max)| (min.min(tx_id), max.max(tx_id))),
+        );
+        *stats.by_type.entry(transaction.tx_type).or_default() += 1;
+        if matches!(
+            transaction.tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) {
+            if !recorded.insert(tx_id) {
+                stats.duplicate_txs += 1;
+            } else if transaction.tx_type == TransactionType::Deposit {
+                stats.deposits += 1;
+            } else {
+                stats.withdrawals += 1;
+            }
+            if let Some(amount) = transaction.amount {
+                let amount = amount.to_f64();
+                stats
+                    .amounts
+                    .get_or_insert(AmountStats {
+                        count: 0,
+                        min: amount,
+                        max: amount,
+                        total: 0.0,
+                        buckets: [0; 7],
+                    })
+                    .add(amount);
+            }
+        }
+        if let Some(timestamp) = transaction.timestamp {
+            let timestamps = stats.timestamps.get_or_insert(TimestampStats {
+                count: 0,
+                first: timestamp,
+                last: timestamp,
+                out_of_order: 0,
+            });
+            timestamps.count += 1;
+            if timestamp < timestamps.last {
+                timestamps.out_of_order += 1;
+            }
+            timestamps.first = timestamps.first.min(timestamp);
+            timestamps.last = timestamps.last.max(timestamp);
+        }
+    }
+    stats.clients = clients.len() as u64;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scan;
+    use crate::csv_input::CsvOptions;
+    use crate::transactions::TransactionType;
+
+    #[test]
+    fn test_scan() {
+        let input = "type, client, tx, amount, timestamp\n\
+                     deposit, 1, 10, 0.5, 1700000100\n\
+                     deposit, 2, 11, 250.0, 1700000000\n\
+                     withdrawal, 1, 12, 20000, \n\
+                     deposit, 2, 11, 3.0, 1700000200\n\
+                     dispute, 2, 11, , 1700000300\n\
+                     deposit, x, 13, 1.0, \n";
+        let stats = scan(input.as_bytes(), &CsvOptions::default()).unwrap();
+        assert_eq!((stats.rows, stats.malformed, stats.clients), (6, 1, 2));
+        assert_eq!(stats.first_malformed.as_ref().unwrap().0, 7);
+        assert_eq!((stats.tx_range, stats.duplicate_txs), (Some((10, 12)), 1));
+        assert_eq!(stats.by_type[&TransactionType::Deposit], 3);
+        assert_eq!(stats.by_type[&TransactionType::Withdrawal], 1);
+        assert_eq!(stats.by_type[&TransactionType::Dispute], 1);
+
+        let amounts = stats.amounts.as_ref().unwrap();
+        assert_eq!((amounts.count, amounts.min, amounts.max), (4, 0.5, 20000.0));
+        assert_eq!(amounts.buckets, [1, 1, 0, 1, 0, 1, 0]);
+        let timestamps = stats.timestamps.as_ref().unwrap();
+        assert_eq!(
+            (timestamps.count, timestamps.first, timestamps.last),
+            (4, 1700000000, 1700000300)
+        );
+        assert_eq!(timestamps.out_of_order, 1);
+
+        // Only the two deposits and the withdrawal recorded would be kept, besides the accounts
+        assert!(stats.estimated_memory_bytes() > 0);
+        assert!(stats.to_string().contains("duplicate tx ids: 1"));
+    }
+}
@@ -0,0 +1,217 @@
+//! Follows a CSV file that keeps growing, like `tail -f`.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::ControlFlow;
+use std::panic;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use csv::StringRecord;
+
+use crate::csv_input::CsvOptions;
+use crate::custom_errors::{InputSourceError, InputSourceErrorType, LineError};
+use crate::engine::PaymentsEngine;
+use crate::queue::{self, QueueGauge, QueueReceiver, QueueSender};
+use crate::transactions::Transaction;
+
+/// How long to wait before checking the file again when there are no new rows.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// What the reader of the file sends to the engine.
+enum Message {
+    /// A record, with the line of the file it is on.
+    Record(u64, Result<Transaction, csv::Error>),
+    /// All the complete rows written so far were sent.
+    CaughtUp,
+    /// The file can't be followed anymore.
+    Failed(Box<dyn Error + Send + Sync>),
+}
+
+impl PaymentsEngine {
+    /// Applies the transactions in a CSV file as they are appended to it.
+    ///
+    /// The file is read and parsed on another thread, at most `queue.capacity` records ahead
+    /// of the engine, see [`queue_metrics`](PaymentsEngine::queue_metrics).
+    /// `on_report` is called with the up-to-date engine at most once every `report_interval`,
+    /// after all the complete rows the reader had seen have been applied, and the file is
+    /// followed until it returns `ControlFlow::Break`.
+    pub fn follow_csv<F>(&mut self, path: &Path, report_interval: Duration, on_report: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&PaymentsEngine) -> Result<ControlFlow<()>, Box<dyn Error>>,
+    {
+        let options = self.config().csv.for_input(&path.to_string_lossy());
+        let file = File::open(path)?;
+        let capacity = self.config().queue.capacity;
+        let gauge = QueueGauge::new(capacity);
+        let (sender, receiver) = queue::bounded(capacity, gauge.clone());
+        let lenient = options.lenient;
+        let reader = thread::spawn(move || read_rows(file, options, sender));
+
+        self.set_queue(Some(gauge));
+        let result = self.apply_rows(&receiver, lenient, report_interval, on_report);
+        self.set_queue(None);
+        // The reader stops as soon as it can't send anything anymore
+        drop(receiver);
+        if let Err(panic) = reader.join() {
+            panic::resume_unwind(panic);
+        }
+        result
+    }
+
+    fn apply_rows<F>(
+        &mut self,
+        receiver: &QueueReceiver<Message>,
+        lenient: bool,
+        report_interval: Duration,
+        mut on_report: F,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&PaymentsEngine) -> Result<ControlFlow<()>, Box<dyn Error>>,
+    {
+        let mut last_report: Option<Instant> = None;
+        // Only fails if the reader panicked, which the caller resumes
+        while let Ok(message) = receiver.recv() {
+            match message {
+                Message::Record(line, transaction) => {
+                    // The positions of the parsing errors are in the chunk, not in the file
+                    match self.apply_record(line, transaction, lenient) {
+                        Err(error) if error.is::<csv::Error>() => return Err(Box::new(LineError { line, error })),
+                        result => result?,
+                    };
+                }
+                Message::CaughtUp => {
+                    if last_report.is_none_or(|last_report| last_report.elapsed() >= report_interval) {
+                        last_report = Some(Instant::now());
+                        if on_report(self)?.is_break() {
+                            return Ok(());
+                        }
+                    }
+                }
+                Message::Failed(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads the rows appended to the file and sends them, until the engine stops receiving them.
+fn read_rows(mut file: File, options: CsvOptions, sender: QueueSender<Message>) {
+    let mut position = 0;
+    let mut headers: Option<StringRecord> = options.positional_headers();
+    // Bytes after the last newline, which are a row that is still being written
+    let mut pending: Vec<u8> = vec![];
+    // Lines of the file already read, since each chunk of rows starts counting from one
+    let mut lines_before: u64 = 0;
+
+    loop {
+        let read = match read_chunk(&mut file, &mut position, &mut pending) {
+            Ok(read) => read,
+            Err(error) => {
+                let _ = sender.send(Message::Failed(error));
+                return;
+            }
+        };
+
+        if let Some(end) = pending.iter().rposition(|&byte| byte == b'\n') {
+            let rows: Vec<u8> = pending.drain(..=end).collect();
+            let mut rdr = options.reader_builder().has_headers(false).from_reader(rows.as_slice());
+            for record in rdr.records() {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(error) => {
+                        let _ = sender.send(Message::Failed(Box::new(error)));
+                        return;
+                    }
+                };
+                let line = lines_before + record.position().map_or(0, |position| position.line());
+                match &headers {
+                    Some(headers) => {
+                        if sender
+                            .send(Message::Record(line, options.deserialize(&record, headers)))
+                            .is_err()
+                        {
+                            return;
+                        }
+                    }
+                    None => headers = Some(options.column_map.rename(&record)),
+                }
+            }
+            lines_before += rows.iter().filter(|&&byte| byte == b'\n').count() as u64;
+        }
+
+        if sender.send(Message::CaughtUp).is_err() {
+            return;
+        }
+        if read == 0 {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Appends what was written to the file since `position` to `pending`.
+fn read_chunk(
+    file: &mut File,
+    position: &mut u64,
+    pending: &mut Vec<u8>,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    if file.metadata()?.len() < *position {
+        // Rows we already applied are gone, so we can't keep a consistent state
+        return Err(Box::new(InputSourceError {
+            error_type: InputSourceErrorType::Truncated,
+        }));
+    }
+    file.seek(SeekFrom::Start(*position))?;
+    let read = file.read_to_end(pending)?;
+    *position += read as u64;
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::ops::ControlFlow;
+    use std::time::Duration;
+
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_follow_appended_rows() {
+        let path = std::env::temp_dir().join("payments_engine_follow.csv");
+        // The last row is still being written
+        fs::write(&path, "type,client,tx,amount\ndeposit, 1, 1, 1.0\ndeposit, 1, 2,").unwrap();
+
+        let mut reports: Vec<String> = vec![];
+        let mut calls = 0;
+        PaymentsEngine::new()
+            .follow_csv(&path, Duration::ZERO, |engine| {
+                calls += 1;
+                let report = engine.report()?;
+                // The reader may still be catching up with the appended rows
+                if reports.last() != Some(&report) {
+                    reports.push(report);
+                }
+                if calls == 1 {
+                    assert_eq!(engine.queue_metrics().map(|queue| queue.capacity), Some(1024));
+                    let mut file = OpenOptions::new().append(true).open(&path)?;
+                    file.write_all(b" 2.0\ndispute, 1, 1\n")?;
+                }
+                Ok(match reports.len() == 2 || calls == 100 {
+                    true => ControlFlow::Break(()),
+                    false => ControlFlow::Continue(()),
+                })
+            })
+            .unwrap();
+
+        assert_eq!(
+            reports,
+            vec![
+                "client, available, held, pending, total, locked\n1, 1.0000, 0.0000, 0.0000, 1.0000, false",
+                "client, available, held, pending, total, locked\n1, 2.0000, 1.0000, 0.0000, 3.0000, false",
+            ]
+        );
+    }
+}
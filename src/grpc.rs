@@ -0,0 +1,672 @@
+//! A gRPC service for the engine, defined in `proto/payments_engine.proto`.
+
+use std::error::Error;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+use crate::accounts::AccountBalance;
+use crate::amount::AmountValue;
+use crate::custom_errors::FailureKind;
+use crate::engine::PaymentsEngine;
+use crate::queue::QueueGauge;
+use crate::rejections::RejectionReason;
+use crate::shard::{Shard, ShardMethod};
+use crate::store::{IdempotencyStore, MemoryIdempotencyStore};
+use crate::transactions::{Amount, ClientId, Transaction, TransactionType, TxId};
+
+pub mod proto {
+    tonic::include_proto!("payments_engine.v1");
+}
+
+use proto::payments_engine_server::PaymentsEngineServer;
+
+/// How many balance updates a slow `StreamBalances` client can lag behind before missing some.
+const UPDATES_CAPACITY: usize = 1024;
+
+/// How long idempotency keys are remembered by default.
+pub const DEFAULT_IDEMPOTENCY_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often to forget the expired idempotency keys, in seconds.
+const IDEMPOTENCY_EXPIRY_INTERVAL: u64 = 60;
+
+/// Maps an engine error to the closest gRPC status.
+fn to_status(err: Box<dyn Error>) -> Status {
+    match FailureKind::of(err.as_ref()) {
+        FailureKind::Parse | FailureKind::Semantic | FailureKind::BadArguments => {
+            Status::invalid_argument(err.to_string())
+        }
+        FailureKind::Io => Status::unavailable(err.to_string()),
+        FailureKind::Internal => Status::internal(err.to_string()),
+    }
+}
+
+impl TryFrom<proto::Transaction> for Transaction {
+    type Error = Status;
+    fn try_from(transaction: proto::Transaction) -> Result<Self, Self::Error> {
+        let tx_type = match transaction.r#type() {
+            proto::TransactionType::Deposit => TransactionType::Deposit,
+            proto::TransactionType::Withdrawal => TransactionType::Withdrawal,
+            proto::TransactionType::Dispute => TransactionType::Dispute,
+            proto::TransactionType::Resolve => TransactionType::Resolve,
+            proto::TransactionType::Chargeback => TransactionType::Chargeback,
+            proto::TransactionType::Reversal => TransactionType::Reversal,
+            proto::TransactionType::Adjustment => TransactionType::Adjustment,
+            proto::TransactionType::Fee => TransactionType::Fee,
+            proto::TransactionType::Close => TransactionType::Close,
+            proto::TransactionType::Freeze => TransactionType::Freeze,
+            proto::TransactionType::Unfreeze => TransactionType::Unfreeze,
+            proto::TransactionType::ChargebackReversal => TransactionType::ChargebackReversal,
+            proto::TransactionType::Settle => TransactionType::Settle,
+            proto::TransactionType::Unspecified => {
+                return Err(Status::invalid_argument("The transaction type is required"))
+            }
+        };
+        Ok(Transaction {
+            tx_type,
+            client_id: client_id(transaction.client)?,
+            tx_id: tx_id(transaction.tx)?,
+            amount: transaction.amount.map(|amount| Amount::from_f64(amount.into())),
+            reason: transaction.reason,
+            timestamp: transaction.timestamp,
+            settles_at: transaction.settles_at,
+        })
+    }
+}
+
+impl From<AccountBalance> for proto::Account {
+    // The client ids are already 32 bits wide with wide ids, and the amounts are narrowed
+    // unless they are floats
+    #[cfg_attr(feature = "wide-ids", allow(clippy::useless_conversion))]
+    fn from(account_balance: AccountBalance) -> Self {
+        proto::Account {
+            client: account_balance.client.into(),
+            available: account_balance.available.to_f64() as f32,
+            held: account_balance.held.to_f64() as f32,
+            pending: account_balance.pending.to_f64() as f32,
+            total: account_balance.total().to_f64() as f32,
+            locked: account_balance.is_locked(),
+            status: account_balance.status.as_str().to_string(),
+        }
+    }
+}
+
+fn client_id(client: u32) -> Result<ClientId, Status> {
+    ClientId::try_from(client).map_err(|_| Status::invalid_argument(format!("Client id {} is out of range", client)))
+}
+
+fn tx_id(tx: u64) -> Result<TxId, Status> {
+    TxId::try_from(tx).map_err(|_| Status::invalid_argument(format!("Transaction id {} is out of range", tx)))
+}
+
+/// The idempotency keys of the transactions submitted to the service.
+struct IdempotencyKeys {
+    store: Box<dyn IdempotencyStore>,
+    retention: Duration,
+    last_expiry: u64,
+}
+
+/// Seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Locks the engine, unless it panicked while another request was using it.
+fn lock(engine: &Mutex<PaymentsEngine>) -> Result<MutexGuard<'_, PaymentsEngine>, Status> {
+    engine
+        .lock()
+        .map_err(|_| Status::internal("The engine panicked while processing a transaction"))
+}
+
+/// The transactions of a request for an actor, queued to be applied, and where to send their outcomes.
+struct Submission {
+    transactions: Vec<proto::Transaction>,
+    outcomes: oneshot::Sender<Vec<Result<(bool, bool), Status>>>,
+}
+
+/// Serves the engine, shared by all the connections.
+///
+/// The clients are split between actors, each a task with an engine and a queue of its own,
+/// one by default. The requests only decode the transactions, which are routed by client to the
+/// queue of their actor, and applied one request at a time by it, without waiting for the other
+/// actors. When a queue is full, the requests wait for room in it, and so do the clients sending them.
+pub struct PaymentsEngineService {
+    applier: Applier,
+    actors: Vec<Actor>,
+    capacity: usize,
+}
+
+/// The task applying the transactions of the clients of a shard.
+struct Actor {
+    // Only locked by the task and by the queries of the clients of its shard
+    engine: Arc<Mutex<PaymentsEngine>>,
+    // Started with the first submission, since that is when there is a runtime to run it
+    queue: OnceLock<mpsc::Sender<Submission>>,
+    gauge: QueueGauge,
+}
+
+/// Applies the submitted transactions, shared by the actors.
+#[derive(Clone)]
+struct Applier {
+    // Only locked to check and record a key, since the retries of a transaction are of the same
+    // client and so are applied by the same actor, one after the other
+    idempotency_keys: Arc<Mutex<IdempotencyKeys>>,
+    updates: broadcast::Sender<proto::Account>,
+}
+
+impl PaymentsEngineService {
+    /// A service that remembers idempotency keys in memory, for [`DEFAULT_IDEMPOTENCY_RETENTION`],
+    /// and queues up to `queue.capacity` requests of the engine config.
+    pub fn new(engine: PaymentsEngine) -> Self {
+        Self::with_engines(vec![engine])
+    }
+
+    /// A service with an actor for each engine, queuing up to `queue.capacity` requests of the
+    /// config of the first one each.
+    fn with_engines(engines: Vec<PaymentsEngine>) -> Self {
+        // An empty tokio channel can't be created
+        let capacity = engines[0].config().queue.capacity.max(1);
+        PaymentsEngineService {
+            applier: Applier {
+                idempotency_keys: Arc::new(Mutex::new(IdempotencyKeys {
+                    store: Box::new(MemoryIdempotencyStore::default()),
+                    retention: DEFAULT_IDEMPOTENCY_RETENTION,
+                    last_expiry: 0,
+                })),
+                updates: broadcast::channel(UPDATES_CAPACITY).0,
+            },
+            actors: engines.into_iter().map(|engine| Actor::new(engine, capacity)).collect(),
+            capacity,
+        }
+    }
+
+    /// A service with `actors` actors, at least one, each with an engine created by `new_engine`
+    /// for the clients of its shard, see [`PaymentsEngine::with_shard`], and a queue of its own.
+    /// `new_engine` should give each of them stores of its own.
+    ///
+    /// The checks across clients are only within a shard, and the transactions of a batch are
+    /// applied by the actors of their clients: a failure only stops the ones of the same shard
+    /// after it, and the ones of other shards may still be applied.
+    pub fn with_actors(
+        actors: u32,
+        mut new_engine: impl FnMut() -> Result<PaymentsEngine, Box<dyn Error>>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let count = actors.max(1);
+        let mut engines = Vec::with_capacity(count as usize);
+        for index in 1..=count {
+            let shard = Shard {
+                index,
+                count,
+                method: ShardMethod::Hash,
+            };
+            engines.push(new_engine()?.with_shard(shard));
+        }
+        Ok(Self::with_engines(engines))
+    }
+
+    /// Keeps the idempotency keys in the given store, e.g. to remember them across restarts,
+    /// and for as long as `retention`.
+    pub fn with_idempotency_store(mut self, store: Box<dyn IdempotencyStore>, retention: Duration) -> Self {
+        self.applier.idempotency_keys = Arc::new(Mutex::new(IdempotencyKeys {
+            store,
+            retention,
+            last_expiry: 0,
+        }));
+        self
+    }
+
+    /// The engine used by the service, e.g. to monitor it while it is serving. With several
+    /// actors, the one of the first of them.
+    pub fn engine(&self) -> Arc<Mutex<PaymentsEngine>> {
+        Arc::clone(&self.actors[0].engine)
+    }
+
+    /// The index of the actor of the clients of the shard of a client.
+    fn actor_index(&self, client_id: ClientId) -> usize {
+        match self.actors.len() {
+            1 => 0,
+            count => Shard::of(client_id, count as u32, ShardMethod::Hash).index as usize - 1,
+        }
+    }
+
+    /// The actor of the clients of the shard of a client.
+    fn actor(&self, client_id: ClientId) -> &Actor {
+        &self.actors[self.actor_index(client_id)]
+    }
+
+    /// Queues the transactions of a request to the actors of their clients, waiting for room
+    /// in the queues that are full, and returns their outcomes in order once they are applied,
+    /// up to the first one that failed.
+    async fn submit(&self, transactions: Vec<proto::Transaction>) -> Result<Vec<Result<(bool, bool), Status>>, Status> {
+        let count = transactions.len();
+        // The transactions of each actor, in order, with their index, up to the first invalid client
+        let mut routed: Vec<Vec<(usize, proto::Transaction)>> = self.actors.iter().map(|_| vec![]).collect();
+        let mut invalid = None;
+        for (index, transaction) in transactions.into_iter().enumerate() {
+            match client_id(transaction.client) {
+                Ok(client) => routed[self.actor_index(client)].push((index, transaction)),
+                Err(status) => {
+                    invalid = Some((index, status));
+                    break;
+                }
+            }
+        }
+
+        let mut pending = vec![];
+        for (actor, transactions) in self.actors.iter().zip(routed) {
+            if transactions.is_empty() {
+                continue;
+            }
+            let (indexes, transactions): (Vec<usize>, Vec<proto::Transaction>) = transactions.into_iter().unzip();
+            pending.push((indexes, actor.submit(&self.applier, self.capacity, transactions).await?));
+        }
+        let mut outcomes: Vec<Option<Result<(bool, bool), Status>>> = (0..count).map(|_| None).collect();
+        if let Some((index, status)) = invalid {
+            outcomes[index] = Some(Err(status));
+        }
+        for (indexes, applied) in pending {
+            // The actor only stops if the engine panicked
+            let applied = applied
+                .await
+                .map_err(|_| Status::internal("The engine panicked while processing a transaction"))?;
+            for (index, outcome) in indexes.into_iter().zip(applied) {
+                outcomes[index] = Some(outcome);
+            }
+        }
+
+        let mut ordered = vec![];
+        for outcome in outcomes {
+            let Some(outcome) = outcome else {
+                break;
+            };
+            let failed = outcome.is_err();
+            ordered.push(outcome);
+            if failed {
+                break;
+            }
+        }
+        Ok(ordered)
+    }
+}
+
+impl Actor {
+    fn new(mut engine: PaymentsEngine, capacity: usize) -> Self {
+        let gauge = QueueGauge::new(capacity);
+        engine.set_queue(Some(gauge.clone()));
+        Actor {
+            engine: Arc::new(Mutex::new(engine)),
+            queue: OnceLock::new(),
+            gauge,
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, PaymentsEngine>, Status> {
+        lock(&self.engine)
+    }
+
+    /// Queues transactions of the clients of the actor, waiting for room in the queue if it is
+    /// full, and returns where their outcomes will be sent once they are applied.
+    async fn submit(
+        &self,
+        applier: &Applier,
+        capacity: usize,
+        transactions: Vec<proto::Transaction>,
+    ) -> Result<oneshot::Receiver<Vec<Result<(bool, bool), Status>>>, Status> {
+        let queue = self.queue.get_or_init(|| {
+            let (sender, receiver) = mpsc::channel(capacity);
+            tokio::spawn(
+                applier
+                    .clone()
+                    .run(Arc::clone(&self.engine), receiver, self.gauge.clone()),
+            );
+            sender
+        });
+        let (outcomes, applied) = oneshot::channel();
+        let submission = Submission { transactions, outcomes };
+
+        self.gauge.on_send();
+        let queued = match queue.try_send(submission) {
+            Ok(()) => true,
+            Err(TrySendError::Full(submission)) => {
+                self.gauge.on_full();
+                queue.send(submission).await.is_ok()
+            }
+            Err(TrySendError::Closed(_)) => false,
+        };
+        if !queued {
+            self.gauge.on_receive();
+        }
+        Ok(applied)
+    }
+}
+
+impl Applier {
+    /// Applies the queued submissions of an actor to its engine, until the service is gone.
+    async fn run(self, engine: Arc<Mutex<PaymentsEngine>>, mut queue: mpsc::Receiver<Submission>, gauge: QueueGauge) {
+        while let Some(submission) = queue.recv().await {
+            gauge.on_receive();
+            let outcomes = self.submit_all(&engine, submission.transactions);
+            // The request may have been cancelled, which is fine
+            let _ = submission.outcomes.send(outcomes);
+        }
+    }
+
+    /// Submits the transactions in order, up to the first one that fails.
+    fn submit_all(
+        &self,
+        engine: &Mutex<PaymentsEngine>,
+        transactions: Vec<proto::Transaction>,
+    ) -> Vec<Result<(bool, bool), Status>> {
+        let mut engine = match lock(engine) {
+            Ok(engine) => engine,
+            Err(status) => return vec![Err(status)],
+        };
+        let mut outcomes = Vec::with_capacity(transactions.len());
+        for transaction in transactions {
+            let outcome = self.submit(&mut engine, transaction);
+            let failed = outcome.is_err();
+            outcomes.push(outcome);
+            if failed {
+                break;
+            }
+        }
+        outcomes
+    }
+
+    /// Applies a transaction unless its idempotency key was already seen, and publishes
+    /// the new balance of its client. Returns whether the transaction was accepted,
+    /// and whether that is the outcome of an earlier submission.
+    fn submit(&self, engine: &mut PaymentsEngine, transaction: proto::Transaction) -> Result<(bool, bool), Status> {
+        let key = transaction.idempotency_key.clone().filter(|key| !key.is_empty());
+        let transaction = Transaction::try_from(transaction)?;
+        let Some(key) = key else {
+            return Ok((self.apply(engine, &transaction)?, false));
+        };
+
+        let lock_keys = || {
+            self.idempotency_keys
+                .lock()
+                .map_err(|_| Status::internal("The engine panicked while processing a transaction"))
+        };
+        let now = now();
+        {
+            let mut keys = lock_keys()?;
+            let since = now.saturating_sub(keys.retention.as_secs());
+            if now - keys.last_expiry >= IDEMPOTENCY_EXPIRY_INTERVAL {
+                keys.store.expire(since).map_err(to_status)?;
+                keys.last_expiry = now;
+            }
+            if let Some(accepted) = keys.store.outcome(&key, since).map_err(to_status)? {
+                return Ok((accepted, true));
+            }
+        }
+
+        let accepted = self.apply(engine, &transaction)?;
+        lock_keys()?.store.record(&key, accepted, now).map_err(to_status)?;
+        Ok((accepted, false))
+    }
+
+    /// Applies a transaction and publishes the new balance of its client.
+    /// Returns whether the transaction was accepted.
+    fn apply(&self, engine: &mut PaymentsEngine, transaction: &Transaction) -> Result<bool, Status> {
+        let outcome = engine.apply(transaction).map_err(to_status)?;
+        // Not an outcome to remember for the idempotency key, since it can be submitted again
+        if outcome
+            .as_ref()
+            .is_err_and(|rejected| rejected.reason == RejectionReason::RateLimited)
+        {
+            return Err(Status::resource_exhausted(
+                "The client is over its rate limit, try again later",
+            ));
+        }
+        let accepted = outcome.is_ok();
+        if let Ok(applied) = outcome {
+            // Nobody may be listening, which is fine
+            let _ = self.updates.send(applied.balance.into());
+        }
+        Ok(accepted)
+    }
+}
+
+#[tonic::async_trait]
+impl proto::payments_engine_server::PaymentsEngine for PaymentsEngineService {
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::Transaction>,
+    ) -> Result<Response<proto::SubmitTransactionResponse>, Status> {
+        let outcomes = self.submit(vec![request.into_inner()]).await?;
+        let (accepted, replayed) = outcomes.into_iter().next().expect("A submission has an outcome")?;
+        Ok(Response::new(proto::SubmitTransactionResponse { accepted, replayed }))
+    }
+
+    async fn submit_batch(
+        &self,
+        request: Request<proto::SubmitBatchRequest>,
+    ) -> Result<Response<proto::SubmitBatchResponse>, Status> {
+        let outcomes = self.submit(request.into_inner().transactions).await?;
+        let mut response = proto::SubmitBatchResponse::default();
+        for (index, outcome) in outcomes.into_iter().enumerate() {
+            let with_index =
+                |status: Status| Status::new(status.code(), format!("Transaction {}: {}", index, status.message()));
+            let (accepted, replayed) = outcome.map_err(with_index)?;
+            if !accepted {
+                response.rejected += 1;
+            }
+            if replayed {
+                response.replayed += 1;
+            }
+            response.processed += 1;
+        }
+        Ok(Response::new(response))
+    }
+
+    async fn get_account(
+        &self,
+        request: Request<proto::GetAccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let client = client_id(request.into_inner().client)?;
+        match self.actor(client).lock()?.account(client).map_err(to_status)? {
+            Some(account_balance) => Ok(Response::new(account_balance.into())),
+            None => Err(Status::not_found(format!("There is no account for client {}", client))),
+        }
+    }
+
+    type StreamBalancesStream = Pin<Box<dyn Stream<Item = Result<proto::Account, Status>> + Send>>;
+
+    async fn stream_balances(
+        &self,
+        _request: Request<proto::StreamBalancesRequest>,
+    ) -> Result<Response<Self::StreamBalancesStream>, Status> {
+        // Subscribe while holding the locks of all the actors, so that no update is missed between the two
+        let (balances, updates) = {
+            let engines = self.actors.iter().map(Actor::lock).collect::<Result<Vec<_>, _>>()?;
+            let mut balances = vec![];
+            for engine in &engines {
+                balances.extend(engine.balances().map_err(to_status)?);
+            }
+            balances.sort_by_key(|account_balance| account_balance.client);
+            (balances, self.applier.updates.subscribe())
+        };
+
+        let balances = tokio_stream::iter(balances.into_iter().map(|account_balance| Ok(account_balance.into())));
+        let updates = BroadcastStream::new(updates)
+            .map(|update| update.map_err(|_| Status::data_loss("The client fell behind and missed balance updates")));
+        Ok(Response::new(Box::pin(balances.chain(updates))))
+    }
+}
+
+/// Serves the engine over gRPC until the process is stopped.
+pub async fn serve(service: PaymentsEngineService, addr: SocketAddr) -> Result<(), Box<dyn Error>> {
+    tonic::transport::Server::builder()
+        .add_service(PaymentsEngineServer::new(service))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_stream::StreamExt;
+    use tonic::{Code, Request};
+
+    use super::proto::payments_engine_server::PaymentsEngine as _;
+    use super::proto::{self, TransactionType};
+    use super::PaymentsEngineService;
+    use crate::engine::PaymentsEngine;
+
+    fn transaction(tx_type: TransactionType, tx: u64, amount: Option<f32>) -> proto::Transaction {
+        proto::Transaction {
+            r#type: tx_type.into(),
+            client: 1,
+            tx,
+            amount,
+            idempotency_key: None,
+            reason: None,
+            timestamp: None,
+            settles_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grpc_service() {
+        let service = PaymentsEngineService::new(PaymentsEngine::new());
+        let mut balances = service
+            .stream_balances(Request::new(proto::StreamBalancesRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let batch = vec![
+            transaction(TransactionType::Deposit, 1, Some(2.0)),
+            transaction(TransactionType::Withdrawal, 2, Some(5.0)),
+        ];
+        let response = service
+            .submit_batch(Request::new(proto::SubmitBatchRequest { transactions: batch }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((response.processed, response.rejected), (2, 1));
+
+        let response = service
+            .submit_transaction(Request::new(transaction(TransactionType::Dispute, 1, None)))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.accepted);
+        let queue = service.engine().lock().unwrap().queue_metrics().unwrap();
+        assert_eq!((queue.enqueued, queue.depth), (2, 0));
+
+        let account = service
+            .get_account(Request::new(proto::GetAccountRequest { client: 1 }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((account.available, account.held, account.total), (0.0, 2.0, 2.0));
+
+        let missing = service
+            .get_account(Request::new(proto::GetAccountRequest { client: 2 }))
+            .await;
+        assert_eq!(missing.unwrap_err().code(), Code::NotFound);
+        let no_amount = service
+            .submit_transaction(Request::new(transaction(TransactionType::Deposit, 3, None)))
+            .await;
+        assert_eq!(no_amount.unwrap_err().code(), Code::InvalidArgument);
+
+        let update = balances.next().await.unwrap().unwrap();
+        assert_eq!((update.available, update.held), (2.0, 0.0));
+        let update = balances.next().await.unwrap().unwrap();
+        assert_eq!((update.available, update.held), (0.0, 2.0));
+    }
+
+    #[tokio::test]
+    async fn test_grpc_idempotency_keys() {
+        let service = PaymentsEngineService::new(PaymentsEngine::new());
+        let deposit = proto::Transaction {
+            idempotency_key: Some("deposit-1".to_string()),
+            ..transaction(TransactionType::Deposit, 1, Some(2.0))
+        };
+
+        let first = service
+            .submit_transaction(Request::new(deposit.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(first.accepted && !first.replayed);
+        let retry = service
+            .submit_transaction(Request::new(deposit.clone()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(retry.accepted && retry.replayed);
+
+        let batch = vec![deposit, transaction(TransactionType::Deposit, 2, Some(1.0))];
+        let response = service
+            .submit_batch(Request::new(proto::SubmitBatchRequest { transactions: batch }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((response.processed, response.replayed), (2, 1));
+
+        let account = service
+            .get_account(Request::new(proto::GetAccountRequest { client: 1 }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(account.available, 3.0);
+    }
+
+    #[tokio::test]
+    async fn test_grpc_actors() {
+        let service = PaymentsEngineService::with_actors(4, || Ok(PaymentsEngine::new())).unwrap();
+        let batch: Vec<_> = (1..=8)
+            .flat_map(|client| {
+                let tx = u64::from(client) * 10;
+                [
+                    proto::Transaction {
+                        client,
+                        ..transaction(TransactionType::Deposit, tx, Some(2.0))
+                    },
+                    proto::Transaction {
+                        client,
+                        ..transaction(TransactionType::Withdrawal, tx + 1, Some(client as f32))
+                    },
+                ]
+            })
+            .collect();
+        let response = service
+            .submit_batch(Request::new(proto::SubmitBatchRequest { transactions: batch }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((response.processed, response.rejected), (16, 6));
+
+        for client in 1..=8 {
+            let account = service
+                .get_account(Request::new(proto::GetAccountRequest { client }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(account.available, if client <= 2 { 2.0 - client as f32 } else { 2.0 });
+        }
+        let balances: Vec<_> = service
+            .stream_balances(Request::new(proto::StreamBalancesRequest {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .take(8)
+            .map(|account| account.unwrap().client)
+            .collect()
+            .await;
+        assert_eq!(balances, (1..=8).collect::<Vec<_>>());
+    }
+}
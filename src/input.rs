@@ -0,0 +1,200 @@
+//! Opens the input of a run, which can be a local file, stdin or, with the
+//! `http` and `s3` features, an object streamed over the network. With the
+//! `decrypt` feature it can be encrypted as well.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::custom_errors::{InputSourceError, InputSourceErrorType};
+use crate::engine::PaymentsEngine;
+
+/// How local input files are read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputIo {
+    /// With buffered reads.
+    #[default]
+    Buffered,
+    /// Mapped into memory, so that the parser copies them straight from the page cache
+    /// without a read call per buffer. Files that can't be mapped, e.g. pipes or on platforms
+    /// without support, are read with buffered reads instead.
+    #[cfg(feature = "mmap")]
+    Mmap,
+}
+
+/// The formats the transactions can be read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Csv,
+    #[cfg(feature = "parquet")]
+    Parquet,
+    #[cfg(feature = "avro")]
+    Avro,
+}
+
+impl InputFormat {
+    /// The usual extensions of files in this format.
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            InputFormat::Csv => &["csv", "tsv"],
+            #[cfg(feature = "parquet")]
+            InputFormat::Parquet => &["parquet"],
+            #[cfg(feature = "avro")]
+            InputFormat::Avro => &["avro"],
+        }
+    }
+}
+
+impl PaymentsEngine {
+    /// Applies all the transactions in the input at `location`, see [`open`].
+    pub fn process_input(&mut self, location: &str, format: InputFormat) -> Result<(), Box<dyn Error>> {
+        match format {
+            InputFormat::Csv => {
+                let options = self.config().csv.for_input(location);
+                self.process_csv_with(self.open_input(location)?, &options)
+            }
+            #[cfg(feature = "parquet")]
+            InputFormat::Parquet => {
+                #[cfg(feature = "decrypt")]
+                if self.decryption.is_some() {
+                    return Err(Box::new(InputSourceError {
+                        error_type: InputSourceErrorType::NotDecryptable,
+                    }));
+                }
+                self.process_parquet(local_path(location)?)
+            }
+            #[cfg(feature = "avro")]
+            InputFormat::Avro => self.process_avro_reader(self.open_input(location)?),
+        }
+    }
+
+    /// Opens the input at `location` for reading, see [`open_with`], decrypting it if the
+    /// engine was created [`with_decryption`](PaymentsEngine::with_decryption).
+    pub fn open_input(&self, location: &str) -> Result<Box<dyn Read>, Box<dyn Error>> {
+        #[cfg(feature = "decrypt")]
+        if let Some(decryption) = &self.decryption {
+            return decryption.open(location, self.input_io);
+        }
+        open_with(location, self.input_io)
+    }
+}
+
+/// The location of the input read from stdin, e.g. at the end of a shell pipeline.
+pub const STDIN: &str = "-";
+
+/// Whether the input is a URL rather than a local path.
+pub fn is_url(location: &str) -> bool {
+    location.contains("://")
+}
+
+/// Opens the input for reading.
+/// URLs are streamed as they are read, without downloading them to disk first.
+pub fn open(location: &str) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    open_with(location, InputIo::Buffered)
+}
+
+/// Opens the input for reading, reading local files with `io`.
+pub fn open_with(location: &str, io: InputIo) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    if location == STDIN {
+        return Ok(Box::new(io::stdin()));
+    }
+    if !is_url(location) {
+        let file = File::open(location)?;
+        #[cfg(feature = "mmap")]
+        if io == InputIo::Mmap && file.metadata()?.is_file() {
+            // SAFETY: the map is only read, and the file must not be truncated while it is
+            // mapped, which would raise SIGBUS on the pages past its new end. Appending to it
+            // doesn't change the mapped part.
+            if let Ok(map) = unsafe { memmap2::Mmap::map(&file) } {
+                #[cfg(unix)]
+                let _ = map.advise(memmap2::Advice::Sequential);
+                return Ok(Box::new(io::Cursor::new(map)));
+            }
+        }
+        #[cfg(not(feature = "mmap"))]
+        let _ = io;
+        return Ok(Box::new(file));
+    }
+
+    let (scheme, _) = location.split_once("://").unwrap_or_default();
+    match scheme {
+        #[cfg(feature = "http")]
+        "http" | "https" => open_http(location),
+        #[cfg(feature = "s3")]
+        "s3" => crate::s3_input::open(location),
+        _ => Err(Box::new(InputSourceError {
+            error_type: InputSourceErrorType::UnsupportedScheme(scheme.to_string()),
+        })),
+    }
+}
+
+/// The input as a local path, for the formats that need to seek around the file
+/// and so can't be streamed.
+pub fn local_path(location: &str) -> Result<&Path, InputSourceError> {
+    if is_url(location) || location == STDIN {
+        return Err(InputSourceError {
+            error_type: InputSourceErrorType::NotSeekable,
+        });
+    }
+    Ok(Path::new(location))
+}
+
+#[cfg(feature = "http")]
+fn open_http(url: &str) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    let response = ureq::get(url).call()?;
+    Ok(Box::new(response.into_body().into_reader()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::open;
+    #[cfg(feature = "mmap")]
+    use super::{open_with, InputFormat, InputIo};
+    #[cfg(feature = "mmap")]
+    use crate::engine::PaymentsEngine;
+    #[cfg(feature = "mmap")]
+    use std::io::Read;
+
+    #[test]
+    fn test_unsupported_scheme() {
+        let err = open("ftp://example.com/transactions.csv").err().unwrap();
+        assert_eq!(err.to_string(), "Reading the input from `ftp` URLs is not supported");
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap_matches_buffered() {
+        let read = |location: &str, io| {
+            let mut read = vec![];
+            open_with(location, io).unwrap().read_to_end(&mut read).unwrap();
+            read
+        };
+        let empty = std::env::temp_dir().join("payments_engine_mmap_empty.csv");
+        std::fs::write(&empty, "").unwrap();
+        let mut locations: Vec<String> = std::fs::read_dir("sample_files")
+            .unwrap()
+            .map(|entry| entry.unwrap().path().display().to_string())
+            .filter(|path| path.ends_with(".csv"))
+            .collect();
+        // Nothing to map in either, and a device that can't be mapped is read as usual
+        locations.extend([empty.display().to_string(), String::from("/dev/null")]);
+        for location in &locations {
+            assert_eq!(
+                read(location, InputIo::Mmap),
+                read(location, InputIo::Buffered),
+                "{}",
+                location
+            );
+        }
+
+        let report = |io| {
+            let mut engine = PaymentsEngine::new().with_input_io(io);
+            engine
+                .process_input("sample_files/multiple_clients.csv", InputFormat::Csv)
+                .unwrap();
+            engine.report().unwrap()
+        };
+        assert_eq!(report(InputIo::Mmap), report(InputIo::Buffered));
+    }
+}
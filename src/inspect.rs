@@ -0,0 +1,351 @@
+//! Guesses which columns of a CSV file with an unknown layout are the `type`, `client`, `tx` and
+//! `amount` of the transactions, from their names and from a sample of their values, to
+//! suggest a [`ColumnMap`](crate::csv_input::ColumnMap) for it, e.g. for the files of a new
+//! partner.
+//!
+//! A column is a better fit the closer its name is to the usual one or to a common synonym,
+//! and the more of its values look like those of the column: transaction types, integer ids
+//! repeated for the clients and unique for the transactions, and decimal numbers for the
+//! amounts. Each column is given to the column it fits best, the best fits first.
+//!
+//! Unless the delimiter is given, the one of `, ; tab |` that splits the header in the most
+//! columns, and every sampled row in as many, is taken to be it.
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Read};
+
+use crate::csv_input::{AmountLocale, CsvOptions, Delimiter};
+use crate::transactions::TransactionType;
+
+/// How many rows are sampled, unless another number is given.
+pub const DEFAULT_SAMPLE_ROWS: usize = 1000;
+
+/// The delimiters tried when the options have none, the likeliest first.
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+/// The columns that are guessed, which every input needs.
+const GUESSED_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// The common names of each of the guessed columns, besides its own.
+const SYNONYMS: [(&str, &[&str]); 4] = [
+    (
+        "type",
+        &[
+            "tx_type",
+            "txn_type",
+            "transaction_type",
+            "kind",
+            "action",
+            "operation",
+            "event",
+        ],
+    ),
+    (
+        "client",
+        &[
+            "client_id",
+            "customer",
+            "customer_id",
+            "account",
+            "account_id",
+            "user",
+            "user_id",
+            "member",
+        ],
+    ),
+    (
+        "tx",
+        &[
+            "tx_id",
+            "txn",
+            "txn_id",
+            "transaction",
+            "transaction_id",
+            "reference",
+            "ref",
+            "id",
+        ],
+    ),
+    ("amount", &["amt", "value", "sum", "total", "quantity", "amount_value"]),
+];
+
+/// The column of the input guessed to be one of the usual ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnGuess {
+    /// The name of the column in the input.
+    pub name: String,
+    /// The usual column it looks like.
+    pub column: &'static str,
+    /// Whether its name is the usual one or a common synonym.
+    pub by_name: bool,
+    /// The share of its values that look like those of the usual column, from 0 to 1.
+    pub fitting_values: f64,
+}
+
+/// What the sample of a file looks like, see [`inspect`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inspection {
+    /// The rows sampled.
+    pub rows: usize,
+    /// The delimiter of the fields, the one of the options or the one guessed.
+    pub delimiter: Delimiter,
+    /// Whether the delimiter was guessed, since the options had none.
+    pub delimiter_guessed: bool,
+    /// The guessed columns, in the order of the input.
+    pub guesses: Vec<ColumnGuess>,
+    /// The usual columns no column looks like.
+    pub missing: Vec<&'static str>,
+}
+
+impl Inspection {
+    /// The column map that renames the guessed columns, e.g. `customer=client,value=amount`,
+    /// or `None` if they already have the usual names, or if none could be guessed, see
+    /// [`guesses`](Inspection::guesses).
+    pub fn column_map(&self) -> Option<String> {
+        let renamed: Vec<String> = self
+            .guesses
+            .iter()
+            .filter(|guess| guess.name != guess.column)
+            .map(|guess| format!("{}={}", guess.name, guess.column))
+            .collect();
+        (!renamed.is_empty()).then(|| renamed.join(","))
+    }
+}
+
+/// What the values of a column of the sample look like.
+#[derive(Debug, Default)]
+struct ColumnStats {
+    values: usize,
+    types: usize,
+    integers: usize,
+    distinct: HashSet<String>,
+    numbers: usize,
+    fractional: usize,
+}
+
+impl ColumnStats {
+    fn add(&mut self, value: &str) {
+        if value.is_empty() {
+            return;
+        }
+        self.values += 1;
+        if value.parse::<TransactionType>().is_ok() {
+            self.types += 1;
+        }
+        if value.parse::<u64>().is_ok() {
+            self.integers += 1;
+            self.distinct.insert(value.to_string());
+        }
+        // In any of the locales the amounts can be read in
+        let number = [AmountLocale::Us, AmountLocale::Eu]
+            .iter()
+            .find_map(|locale| locale.normalize(value))
+            .unwrap_or_else(|| value.to_string());
+        if number.parse::<f64>().is_ok() {
+            self.numbers += 1;
+            if number.split_once('.').is_some_and(|(_, fraction)| !fraction.is_empty()) {
+                self.fractional += 1;
+            }
+        }
+    }
+
+    fn share(&self, count: usize) -> f64 {
+        match self.values {
+            0 => 0.0,
+            values => count as f64 / values as f64,
+        }
+    }
+
+    /// The share of the values that look like those of the column, and how much more they do
+    /// beyond that, e.g. how many of the ids are unique.
+    fn fit(&self, column: &str) -> (f64, f64) {
+        let unique = self.share(self.distinct.len());
+        match column {
+            "type" => (self.share(self.types), self.share(self.types)),
+            "client" => (self.share(self.integers), self.share(self.integers) * (1.0 - unique)),
+            "tx" => (self.share(self.integers), self.share(self.integers) * unique),
+            _ => (self.share(self.numbers), self.share(self.fractional)),
+        }
+    }
+}
+
+/// How close a name is to the one of the column: 2 for the same one, 1 for a synonym.
+fn name_score(name: &str, column: &str) -> f64 {
+    let name = name.trim().to_lowercase().replace([' ', '-'], "_");
+    if name == column {
+        return 2.0;
+    }
+    let synonym = SYNONYMS
+        .iter()
+        .any(|(synonym_of, synonyms)| *synonym_of == column && synonyms.contains(&name.as_str()));
+    if synonym {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// The delimiter of [`DELIMITERS`] that splits the header of `sample` in the most columns, and
+/// all of its rows in as many, or else in the most columns, the likeliest one on a tie.
+fn sniff_delimiter(sample: &[u8]) -> u8 {
+    let split = |delimiter| {
+        let mut rdr = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(sample);
+        let mut records = rdr.byte_records().map_while(Result::ok);
+        let Some(header) = records.next() else {
+            return (false, 0);
+        };
+        (records.all(|record| record.len() == header.len()), header.len())
+    };
+    // The last of the largest wins, so they are tried the likeliest last
+    DELIMITERS
+        .into_iter()
+        .rev()
+        .max_by_key(|&delimiter| split(delimiter))
+        .unwrap_or(b',')
+}
+
+/// Samples the first `rows` rows of a CSV file with a header row, read with the delimiter of
+/// `options`, or the one that fits the sample best if it has none, and guesses its columns.
+pub fn inspect<R: Read>(reader: R, options: &CsvOptions, rows: usize) -> Result<Inspection, Box<dyn Error>> {
+    // The header and the rows sampled
+    let mut sample = vec![];
+    let mut reader = BufReader::new(reader);
+    for _ in 0..=rows {
+        if reader.read_until(b'\n', &mut sample)? == 0 {
+            break;
+        }
+    }
+    let delimiter = options.delimiter.unwrap_or_else(|| Delimiter(sniff_delimiter(&sample)));
+    let mut rdr = options
+        .reader_builder()
+        .delimiter(delimiter.0)
+        .has_headers(true)
+        .trim(csv::Trim::All)
+        .from_reader(sample.as_slice());
+    let headers: Vec<String> = rdr.headers()?.iter().map(str::to_string).collect();
+    let mut stats: Vec<ColumnStats> = headers.iter().map(|_| ColumnStats::default()).collect();
+    let mut sampled = 0;
+    for record in rdr.records().take(rows) {
+        for (column, value) in stats.iter_mut().zip(record?.iter()) {
+            column.add(value);
+        }
+        sampled += 1;
+    }
+
+    // Every candidate, the best fits first
+    let mut candidates = vec![];
+    for (index, (name, column_stats)) in headers.iter().zip(&stats).enumerate() {
+        for column in GUESSED_COLUMNS {
+            let by_name = name_score(name, column);
+            let (fitting, bonus) = column_stats.fit(column);
+            // A column named like it, or with values like its, but never values unlike its
+            let score = by_name + fitting + bonus;
+            if (by_name > 0.0 || fitting >= 0.5) && (fitting > 0.0 || column_stats.values == 0) {
+                candidates.push((score, index, column, by_name > 0.0, fitting));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+
+    let mut guesses: Vec<(usize, ColumnGuess)> = vec![];
+    for (_, index, column, by_name, fitting_values) in candidates {
+        if guesses
+            .iter()
+            .any(|(guessed, guess)| *guessed == index || guess.column == column)
+        {
+            continue;
+        }
+        let name = headers[index].clone();
+        guesses.push((
+            index,
+            ColumnGuess {
+                name,
+                column,
+                by_name,
+                fitting_values,
+            },
+        ));
+    }
+    guesses.sort_by_key(|(index, _)| *index);
+    let missing = GUESSED_COLUMNS
+        .into_iter()
+        .filter(|column| guesses.iter().all(|(_, guess)| guess.column != *column))
+        .collect();
+    Ok(Inspection {
+        rows: sampled,
+        delimiter,
+        delimiter_guessed: options.delimiter.is_none(),
+        guesses: guesses.into_iter().map(|(_, guess)| guess).collect(),
+        missing,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::inspect;
+    use crate::csv_input::CsvOptions;
+
+    #[test]
+    fn test_inspect() {
+        let input = "Reference;Customer;Kind;Channel;Value;Booked\n\
+                     1001;7;deposit;web;10,50;2024-06-01\n\
+                     1002;7;withdrawal;app;2;2024-06-01\n\
+                     1003;8;deposit;web;1.234,00;2024-06-02\n\
+                     1001;7;dispute;web;;2024-06-03\n";
+        let options = CsvOptions {
+            delimiter: Some(";".parse().unwrap()),
+            ..CsvOptions::default()
+        };
+        let inspection = inspect(input.as_bytes(), &options, 100).unwrap();
+        assert_eq!(inspection.rows, 4);
+        let guessed: Vec<_> = inspection
+            .guesses
+            .iter()
+            .map(|guess| (guess.name.as_str(), guess.column))
+            .collect();
+        assert_eq!(
+            guessed,
+            [
+                ("Reference", "tx"),
+                ("Customer", "client"),
+                ("Kind", "type"),
+                ("Value", "amount")
+            ]
+        );
+        assert_eq!(
+            inspection.column_map().as_deref(),
+            Some("Reference=tx,Customer=client,Kind=type,Value=amount")
+        );
+        assert!(inspection.missing.is_empty());
+        assert!(!inspection.delimiter_guessed);
+
+        // The delimiter is guessed when it isn't given
+        let guessed = inspect(input.as_bytes(), &CsvOptions::default(), 100).unwrap();
+        assert_eq!((guessed.delimiter.0, guessed.delimiter_guessed), (b';', true));
+        assert_eq!(guessed.guesses, inspection.guesses);
+        let input = "type|client|tx|amount\ndeposit|1|1|1,5\n";
+        assert_eq!(
+            inspect(input.as_bytes(), &CsvOptions::default(), 100)
+                .unwrap()
+                .delimiter
+                .0,
+            b'|'
+        );
+
+        // Without names to go by, the values tell the ids apart
+        let input = "a,b,c\n5,deposit,1\n5,deposit,2\n6,withdrawal,3\n";
+        let inspection = inspect(input.as_bytes(), &CsvOptions::default(), 100).unwrap();
+        assert_eq!(inspection.column_map().as_deref(), Some("a=client,b=type,c=tx"));
+        assert_eq!(inspection.missing, ["amount"]);
+
+        // Nothing to go by
+        let inspection = inspect("name,note\nann,hello\n".as_bytes(), &CsvOptions::default(), 100).unwrap();
+        assert!(inspection.guesses.is_empty());
+        assert_eq!(inspection.missing.len(), 4);
+    }
+}
@@ -0,0 +1,211 @@
+//! Interest on the available funds of the clients.
+//!
+//! The engine only knows what time it is from the `timestamp` of the transactions. Whenever a
+//! transaction starts a new day, the interest of the days that ended is accrued on the available
+//! funds at the end of each day, and the accrued interest is posted at the period boundaries
+//! (every day, or on the first day of every month). Locked and closed accounts don't accrue interest.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::accounts::AccountStatus;
+use crate::amount::AmountValue;
+use crate::dates::{civil_from_days, SECS_PER_DAY};
+use crate::ledger::{self, JournalEntry, LedgerAccount};
+use crate::store::AccountStore;
+use crate::transactions::{Amount, ClientId, TransactionType};
+
+/// How the interest is accrued and posted.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InterestConfig {
+    /// The yearly interest rate, accrued daily as 1/365th of it.
+    pub annual_rate_percent: Amount,
+    #[serde(default)]
+    pub period: InterestPeriod,
+}
+
+/// How often the accrued interest is posted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum InterestPeriod {
+    Daily,
+    #[default]
+    Monthly,
+}
+
+impl TryFrom<String> for InterestPeriod {
+    type Error = InterestPeriodFromStrError;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Debug)]
+pub struct InterestPeriodFromStrError(String);
+
+impl Error for InterestPeriodFromStrError {}
+
+impl fmt::Display for InterestPeriodFromStrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid interest period `{}`, expected `daily` or `monthly`", self.0)
+    }
+}
+
+impl FromStr for InterestPeriod {
+    type Err = InterestPeriodFromStrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "daily" => Ok(InterestPeriod::Daily),
+            "monthly" => Ok(InterestPeriod::Monthly),
+            _ => Err(InterestPeriodFromStrError(s.to_string())),
+        }
+    }
+}
+
+impl InterestPeriod {
+    /// Whether a period starts on the given day.
+    fn starts_on(self, day: i64) -> bool {
+        match self {
+            InterestPeriod::Daily => true,
+            InterestPeriod::Monthly => civil_from_days(day).2 == 1,
+        }
+    }
+}
+
+/// The interest accrued but not posted yet.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct InterestAccrual {
+    // The day of the last timestamp seen, in days since the Unix epoch
+    day: Option<i64>,
+    accrued: BTreeMap<ClientId, f64>,
+}
+
+impl InterestAccrual {
+    /// The day of the last timestamp seen, and the interest accrued on each account, as kept
+    /// in snapshots.
+    pub(crate) fn state(&self) -> (Option<i64>, &BTreeMap<ClientId, f64>) {
+        (self.day, &self.accrued)
+    }
+
+    /// The accrual of a [`state`](InterestAccrual::state).
+    pub(crate) fn from_state(day: Option<i64>, accrued: BTreeMap<ClientId, f64>) -> Self {
+        InterestAccrual { day, accrued }
+    }
+
+    /// Whether a transaction at `timestamp` changes what it keeps, by starting a new day.
+    pub(crate) fn changes_at(&self, timestamp: u64) -> bool {
+        let today = (timestamp / SECS_PER_DAY) as i64;
+        self.day.is_none_or(|day| day < today)
+    }
+
+    /// Accrues the interest of all the days that ended before `timestamp`, posting it to
+    /// the accounts at the period boundaries. Returns the posted entries.
+    pub(crate) fn advance(
+        &mut self,
+        config: &InterestConfig,
+        timestamp: u64,
+        accounts: &mut dyn AccountStore,
+    ) -> Result<Vec<JournalEntry>, Box<dyn Error>> {
+        let today = (timestamp / SECS_PER_DAY) as i64;
+        let Some(mut day) = self.day.filter(|&day| day < today) else {
+            // The first timestamp starts the clock, and earlier ones don't move it back
+            self.day = Some(self.day.map_or(today, |day| day.max(today)));
+            return Ok(vec![]);
+        };
+
+        let daily_rate = config.annual_rate_percent.to_f64() / 100.0 / 365.0;
+        let mut balances = accounts.all()?;
+        let mut entries = vec![];
+        while day < today {
+            for account_balance in balances.iter().filter(|account_balance| {
+                matches!(account_balance.status, AccountStatus::Active | AccountStatus::Frozen)
+            }) {
+                *self.accrued.entry(account_balance.client).or_default() +=
+                    account_balance.available.to_f64() * daily_rate;
+            }
+            day += 1;
+
+            if config.period.starts_on(day) {
+                for account_balance in &mut balances {
+                    let Some(amount) = self.accrued.remove(&account_balance.client) else {
+                        continue;
+                    };
+                    if amount <= 0.0 {
+                        continue;
+                    }
+                    let client = account_balance.client;
+                    let entry = JournalEntry::single(
+                        TransactionType::Interest,
+                        client,
+                        0,
+                        LedgerAccount::Interest,
+                        LedgerAccount::Available(client),
+                        Amount::from_f64(amount),
+                    );
+                    ledger::post(account_balance, &entry);
+                    accounts.put(account_balance)?;
+                    entries.push(entry);
+                }
+            }
+        }
+        self.day = Some(today);
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InterestAccrual, InterestConfig, InterestPeriod};
+    use crate::accounts::{AccountBalance, AccountStatus};
+    use crate::amount::amount;
+    use crate::dates::SECS_PER_DAY;
+    use crate::store::{AccountStore, MemoryAccountStore};
+
+    #[test]
+    fn test_monthly_interest() {
+        let config = InterestConfig {
+            annual_rate_percent: amount(36.5),
+            period: InterestPeriod::Monthly,
+        };
+        let mut accounts = MemoryAccountStore::default();
+        accounts
+            .put(&AccountBalance {
+                available: amount(100.0),
+                ..AccountBalance::new(1)
+            })
+            .unwrap();
+        accounts
+            .put(&AccountBalance {
+                available: amount(100.0),
+                status: AccountStatus::Locked,
+                ..AccountBalance::new(2)
+            })
+            .unwrap();
+
+        // 2024-01-30, two days before the end of the month
+        let start = 19_752 * SECS_PER_DAY;
+        let mut interest = InterestAccrual::default();
+        assert!(interest.advance(&config, start, &mut accounts).unwrap().is_empty());
+        assert!(interest
+            .advance(&config, start + SECS_PER_DAY + 10, &mut accounts)
+            .unwrap()
+            .is_empty());
+
+        let entries = interest
+            .advance(&config, start + 3 * SECS_PER_DAY, &mut accounts)
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!((entries[0].postings[0].amount - amount(0.2)).abs() < amount(1e-4));
+        assert!((accounts.get(1).unwrap().unwrap().available - amount(100.2)).abs() < amount(1e-4));
+        assert_eq!(accounts.get(2).unwrap().unwrap().available, amount(100.0));
+
+        assert_eq!("daily".parse::<InterestPeriod>().unwrap(), InterestPeriod::Daily);
+        assert!("weekly".parse::<InterestPeriod>().is_err());
+    }
+}
@@ -0,0 +1,229 @@
+//! The double-entry ledger behind the account balances.
+//!
+//! Every applied transaction posts a [`JournalEntry`], made of postings that each move an
+//! amount from one ledger account to another, so the entries are always balanced. The account
+//! balances are the running totals of the client ledger accounts, kept up to date by [`post`].
+
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt;
+use core::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::amount::AmountValue;
+use crate::balance::AccountBalance;
+use crate::transactions::{Amount, ClientId, TransactionType, TxId};
+
+/// An account of the ledger, serialized with its name, e.g. `Liabilities:Clients:1:Held`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum LedgerAccount {
+    /// The funds a client can use.
+    Available(ClientId),
+    /// The funds of a client held because of a dispute.
+    Held(ClientId),
+    /// The deposited funds of a client that haven't settled yet.
+    Pending(ClientId),
+    /// Where deposits come from and withdrawals go to, outside of the engine.
+    External,
+    /// The funds reversed by chargebacks.
+    Chargebacks,
+    /// The counterpart of manual adjustments.
+    Adjustments,
+    /// The fees charged to the clients.
+    Fees,
+    /// The interest paid to the clients.
+    Interest,
+    /// The available funds of the closed accounts, to be paid out to their clients.
+    Payouts,
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LedgerAccount::Available(client) => write!(f, "Liabilities:Clients:{}:Available", client),
+            LedgerAccount::Held(client) => write!(f, "Liabilities:Clients:{}:Held", client),
+            LedgerAccount::Pending(client) => write!(f, "Liabilities:Clients:{}:Pending", client),
+            LedgerAccount::External => write!(f, "Assets:External"),
+            LedgerAccount::Chargebacks => write!(f, "Assets:Chargebacks"),
+            LedgerAccount::Adjustments => write!(f, "Equity:Adjustments"),
+            LedgerAccount::Fees => write!(f, "Income:Fees"),
+            LedgerAccount::Interest => write!(f, "Expenses:Interest"),
+            LedgerAccount::Payouts => write!(f, "Liabilities:Payouts"),
+        }
+    }
+}
+
+impl From<LedgerAccount> for String {
+    fn from(account: LedgerAccount) -> Self {
+        account.to_string()
+    }
+}
+
+#[derive(Debug)]
+pub struct LedgerAccountFromStrError(String);
+
+impl Error for LedgerAccountFromStrError {}
+
+impl fmt::Display for LedgerAccountFromStrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown ledger account `{}`", self.0)
+    }
+}
+
+impl FromStr for LedgerAccount {
+    type Err = LedgerAccountFromStrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || LedgerAccountFromStrError(s.to_string());
+        match s {
+            "Assets:External" => return Ok(LedgerAccount::External),
+            "Assets:Chargebacks" => return Ok(LedgerAccount::Chargebacks),
+            "Equity:Adjustments" => return Ok(LedgerAccount::Adjustments),
+            "Income:Fees" => return Ok(LedgerAccount::Fees),
+            "Expenses:Interest" => return Ok(LedgerAccount::Interest),
+            "Liabilities:Payouts" => return Ok(LedgerAccount::Payouts),
+            _ => {}
+        }
+        let (client, account) = s
+            .strip_prefix("Liabilities:Clients:")
+            .and_then(|rest| rest.split_once(':'))
+            .ok_or_else(invalid)?;
+        let client = client.parse().map_err(|_| invalid())?;
+        match account {
+            "Available" => Ok(LedgerAccount::Available(client)),
+            "Held" => Ok(LedgerAccount::Held(client)),
+            "Pending" => Ok(LedgerAccount::Pending(client)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for LedgerAccount {
+    type Error = LedgerAccountFromStrError;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Moves `amount` from one ledger account to another, i.e. debits `from` and credits `to`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Posting {
+    pub from: LedgerAccount,
+    pub to: LedgerAccount,
+    pub amount: Amount,
+}
+
+/// The postings of an applied transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    pub tx_type: TransactionType,
+    pub client_id: ClientId,
+    pub tx_id: TxId,
+    pub postings: Vec<Posting>,
+    /// Why the entry was posted, for adjustments, settlements and expired disputes.
+    pub reason: Option<String>,
+}
+
+impl JournalEntry {
+    /// The entry of a transaction of a client that moves `amount` once.
+    pub fn single(
+        tx_type: TransactionType,
+        client_id: ClientId,
+        tx_id: TxId,
+        from: LedgerAccount,
+        to: LedgerAccount,
+        amount: Amount,
+    ) -> Self {
+        JournalEntry {
+            tx_type,
+            client_id,
+            tx_id,
+            postings: vec![Posting { from, to, amount }],
+            reason: None,
+        }
+    }
+}
+
+/// How much a posting changes the balance of a ledger account of the client.
+fn change(account: LedgerAccount, posting: &Posting) -> Amount {
+    let mut change = Amount::ZERO;
+    if posting.to == account {
+        change += posting.amount;
+    }
+    if posting.from == account {
+        change -= posting.amount;
+    }
+    change
+}
+
+/// The balance of a client with the postings of an entry, or `None` if one of its amounts, or
+/// its total, would be out of the range of the amounts, see [`AmountValue::checked_add`].
+/// Postings to the ledger accounts of other clients are ignored.
+pub fn posted(account_balance: &AccountBalance, entry: &JournalEntry) -> Option<AccountBalance> {
+    let available = LedgerAccount::Available(account_balance.client);
+    let held = LedgerAccount::Held(account_balance.client);
+    let pending = LedgerAccount::Pending(account_balance.client);
+    let mut posted = account_balance.clone();
+    for posting in &entry.postings {
+        posted.available = posted.available.checked_add(change(available, posting))?;
+        posted.held = posted.held.checked_add(change(held, posting))?;
+        posted.pending = posted.pending.checked_add(change(pending, posting))?;
+    }
+    posted.available.checked_add(posted.held)?.checked_add(posted.pending)?;
+    Some(posted)
+}
+
+/// Updates the balance of a client with the postings of an entry.
+/// Postings to the ledger accounts of other clients are ignored.
+pub fn post(account_balance: &mut AccountBalance, entry: &JournalEntry) {
+    let available = LedgerAccount::Available(account_balance.client);
+    let held = LedgerAccount::Held(account_balance.client);
+    let pending = LedgerAccount::Pending(account_balance.client);
+    for posting in &entry.postings {
+        account_balance.available += change(available, posting);
+        account_balance.held += change(held, posting);
+        account_balance.pending += change(pending, posting);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    use super::{LedgerAccount, Posting};
+    use crate::amount::AmountValue;
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_ledger_is_balanced() {
+        let mut engine = PaymentsEngine::new().with_journal();
+        engine
+            .process_csv(Path::new("sample_files/dispute_chargeback.csv"))
+            .unwrap();
+        engine
+            .process_csv(Path::new("sample_files/multiple_clients.csv"))
+            .unwrap();
+
+        let mut totals: BTreeMap<LedgerAccount, f64> = BTreeMap::new();
+        for entry in engine.journal() {
+            for Posting { from, to, amount } in &entry.postings {
+                *totals.entry(*from).or_default() -= amount.to_f64();
+                *totals.entry(*to).or_default() += amount.to_f64();
+            }
+        }
+        assert!(totals.values().sum::<f64>().abs() < 1e-6);
+
+        // The balances are the totals of the client ledger accounts
+        for account_balance in engine.balances().unwrap() {
+            let available = totals.get(&LedgerAccount::Available(account_balance.client)).copied();
+            let held = totals.get(&LedgerAccount::Held(account_balance.client)).copied();
+            assert!((available.unwrap_or_default() - account_balance.available.to_f64()).abs() < 1e-4);
+            assert!((held.unwrap_or_default() - account_balance.held.to_f64()).abs() < 1e-4);
+        }
+        assert!(totals[&LedgerAccount::Chargebacks] > 0.0);
+    }
+}
@@ -0,0 +1,1169 @@
+//! A payments engine: reads the transactions of the clients, e.g. from a CSV file, and
+//! keeps their account balances, disputes and chargebacks included.
+//!
+//! Without the default `std` feature only the state machine of the accounts is built, i.e.
+//! [`balance`], [`ledger`], [`machine`], [`reasons`] and [`transactions`], with `core` and
+//! `alloc` only, for embedded targets that bring their own storage and input.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+// The two features choose different amounts, and which one a crate in the dependency graph
+// enabled would otherwise decide the amounts of all the others
+#[cfg(all(feature = "wide-amounts", feature = "fixed-amounts"))]
+compile_error!("The `wide-amounts` and `fixed-amounts` features can't be enabled together");
+
+#[cfg(feature = "std")]
+pub mod accounts;
+#[cfg(feature = "std")]
+pub mod activity;
+#[cfg(feature = "std")]
+pub mod aml;
+pub mod amount;
+#[cfg(feature = "arrow")]
+pub mod arrow_input;
+#[cfg(feature = "avro")]
+pub mod avro_input;
+pub mod balance;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "std")]
+pub mod bench;
+#[cfg(feature = "std")]
+pub mod builder;
+#[cfg(feature = "std")]
+pub mod client_filter;
+#[cfg(feature = "std")]
+pub mod clients;
+#[cfg(feature = "std")]
+pub mod compact_store;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod csv_input;
+#[cfg(feature = "std")]
+pub mod custom_errors;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+#[cfg(feature = "std")]
+pub mod dates;
+#[cfg(feature = "decrypt")]
+pub mod decrypt;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
+pub mod dispute_expiry;
+#[cfg(feature = "std")]
+pub mod dispute_graph;
+#[cfg(feature = "std")]
+pub mod engine;
+#[cfg(feature = "std")]
+pub mod event_log;
+#[cfg(feature = "std")]
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod file_stats;
+#[cfg(feature = "std")]
+pub mod follow;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "std")]
+pub mod input;
+#[cfg(feature = "std")]
+pub mod inspect;
+#[cfg(feature = "std")]
+pub mod interest;
+pub mod ledger;
+#[cfg(feature = "std")]
+pub mod losses;
+pub mod machine;
+#[cfg(feature = "std")]
+pub mod merge;
+#[cfg(feature = "std")]
+pub mod output;
+#[cfg(feature = "parquet")]
+pub mod parquet_input;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "std")]
+pub mod queue;
+#[cfg(feature = "std")]
+pub mod rate_limit;
+pub mod reasons;
+#[cfg(feature = "std")]
+pub mod reconcile;
+#[cfg(feature = "redis")]
+pub mod redis_store;
+#[cfg(feature = "std")]
+pub mod rejections;
+#[cfg(feature = "std")]
+pub mod repl;
+#[cfg(feature = "std")]
+pub mod replica;
+#[cfg(feature = "std")]
+pub mod risk;
+#[cfg(feature = "s3")]
+pub mod s3_input;
+#[cfg(feature = "std")]
+pub mod segments;
+#[cfg(feature = "std")]
+pub mod shard;
+#[cfg(feature = "std")]
+pub mod shared;
+#[cfg(feature = "signatures")]
+pub mod signatures;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
+pub mod spill_store;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_store;
+#[cfg(feature = "std")]
+pub mod store;
+#[cfg(feature = "std")]
+pub mod tenants;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod transactions;
+#[cfg(feature = "std")]
+pub mod undo;
+#[cfg(feature = "std")]
+pub mod verify;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "std")]
+pub mod watermark;
+#[cfg(feature = "std")]
+pub mod webhooks;
+
+#[cfg(feature = "std")]
+use std::error::Error;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+pub use balance::AccountBalance;
+#[cfg(feature = "std")]
+pub use engine::{Applied, Outcome, PaymentsEngine, ProcessingReport, ProcessingStats, Rejected};
+#[cfg(feature = "std")]
+pub use shared::SharedEngine;
+pub use transactions::{Amount, ClientId, Transaction, TransactionType, TxId};
+
+/// Takes the path to a CSV file with transactions and returns the account balances,
+/// the stats, the rejected transactions and the state hash. Its `Display` is the report
+/// of the balances.
+#[cfg(feature = "std")]
+pub fn process_csv(path: &Path) -> Result<ProcessingReport, Box<dyn Error>> {
+    let mut engine = PaymentsEngine::new().with_rejections();
+    engine.process_csv(path)?;
+    engine.processing_report()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::accounts::AccountStatus;
+    use crate::aml::FindingKind;
+    use crate::amount::amount;
+    use crate::clients::ClientDirectory;
+    use crate::compact_store::CompactTxStore;
+    use crate::config::{EngineConfig, OverAvailable};
+    use crate::csv_input::{CsvOptions, CsvParser};
+    use crate::custom_errors::FailureKind;
+    use crate::engine::Observer;
+    use crate::interest::{InterestConfig, InterestPeriod};
+    use crate::ledger::LedgerAccount;
+    use crate::rejections::{Rejection, RejectionReason};
+    use crate::risk::{RiskRules, RiskViolation, VelocityLimit};
+    use crate::store::{MemoryAccountStore, MemoryDedupStore, TxStatus};
+    use crate::transactions::{Amount, TxId};
+    use crate::{process_csv, PaymentsEngine, Transaction, TransactionType};
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    fn test_csv(file_path: &str, expected: &str) {
+        let output = process_csv(Path::new(file_path)).unwrap().to_string();
+        let expected_output = String::from(expected);
+        assert_eq!(output, expected_output);
+    }
+
+    #[test]
+    fn test_multiple_clients() {
+        test_csv(
+            "sample_files/multiple_clients.csv",
+            r"client, available, held, pending, total, locked
+1, 2.0000, 0.0000, 0.0000, 2.0000, true
+2, 0.5000, 0.0000, 0.0000, 0.5000, false
+3, 0.0000, 5.5000, 0.0000, 5.5000, false",
+        );
+    }
+
+    #[test]
+    fn test_deposit_withdrawal() {
+        test_csv(
+            "sample_files/deposit_withdrawal.csv",
+            r"client, available, held, pending, total, locked
+1, 1.5000, 0.0000, 0.0000, 1.5000, false
+2, 0.5000, 0.0000, 0.0000, 0.5000, false",
+        );
+    }
+
+    #[test]
+    fn test_missing_tx_dispute() {
+        let expected = r"client, available, held, pending, total, locked
+1, 1.0000, 0.0000, 0.0000, 1.0000, false";
+        test_csv("sample_files/dispute_missing_transaction.csv", expected);
+    }
+
+    #[test]
+    fn test_processing_report() {
+        let input = Path::new("sample_files/dispute_missing_transaction.csv");
+        let report = process_csv(input).unwrap();
+        assert_eq!(report.balances.len(), 1);
+        assert_eq!((report.stats.processed, report.stats.rejected), (2, 1));
+        assert_eq!(report.rejections[0].reason, RejectionReason::UnknownTransaction);
+        assert_eq!(report.rejections[0].line, Some(3));
+
+        let mut engine = PaymentsEngine::new();
+        engine.process_csv(input).unwrap();
+        assert_eq!(report.state_hash, engine.state_hash().unwrap());
+    }
+
+    #[test]
+    fn test_missing_tx_resolve() {
+        let expected = r"client, available, held, pending, total, locked
+1, 1.0000, 0.0000, 0.0000, 1.0000, false";
+        test_csv("sample_files/resolve_missing_transaction.csv", expected);
+    }
+
+    #[test]
+    fn test_missing_tx_chargeback() {
+        let expected = r"client, available, held, pending, total, locked
+1, 1.0000, 0.0000, 0.0000, 1.0000, false";
+        test_csv("sample_files/chargeback_missing_transaction.csv", expected);
+    }
+
+    #[test]
+    fn test_missing_dispute_resolve() {
+        let expected = r"client, available, held, pending, total, locked
+1, 1.0000, 0.0000, 0.0000, 1.0000, false";
+        test_csv("sample_files/resolve_missing_dispute.csv", expected);
+    }
+
+    #[test]
+    fn test_missing_dispute_chargeback() {
+        let expected = r"client, available, held, pending, total, locked
+1, 1.0000, 0.0000, 0.0000, 1.0000, false";
+        test_csv("sample_files/chargeback_missing_dispute.csv", expected);
+    }
+
+    #[test]
+    fn test_dispute_chargeback() {
+        let expected = r"client, available, held, pending, total, locked
+1, 0.0000, 0.0000, 0.0000, 0.0000, true";
+        test_csv("sample_files/dispute_chargeback.csv", expected);
+    }
+
+    #[test]
+    fn test_partial_dispute() {
+        let input = Path::new("sample_files/partial_dispute.csv");
+        let report = process_csv(input).unwrap();
+        let expected = r"client, available, held, pending, total, locked
+1, 7.5000, 0.0000, 0.0000, 7.5000, true";
+        assert_eq!(report.to_string(), expected);
+        let rejected: Vec<_> = report
+            .rejections
+            .iter()
+            .map(|rejection| (rejection.line, rejection.reason))
+            .collect();
+        assert_eq!(
+            rejected,
+            [
+                (Some(4), RejectionReason::Disputed),
+                (Some(6), RejectionReason::NotDisputed),
+                (Some(7), RejectionReason::ExceedsDeposit),
+            ]
+        );
+
+        let mut engine = PaymentsEngine::new();
+        engine
+            .apply(&Transaction::parse_csv_record(b"deposit, 1, 1, 10.0").unwrap())
+            .unwrap()
+            .unwrap();
+        assert!(engine
+            .apply(&Transaction::parse_csv_record(b"dispute, 1, 1, -1.0").unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn test_client_mismatch() {
+        let input = "type, client, tx, amount
+            deposit, 1, 1, 5.0
+            deposit, 2, 2, 3.0
+            dispute, 2, 1
+            reversal, 2, 1
+            dispute, 1, 1";
+        let mut engine = PaymentsEngine::new();
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            engine.stats().rejections.get(&RejectionReason::ClientMismatch),
+            Some(&2)
+        );
+        let expected = r"client, available, held, pending, total, locked
+1, 0.0000, 5.0000, 0.0000, 5.0000, false
+2, 3.0000, 0.0000, 0.0000, 3.0000, false";
+        assert_eq!(engine.report().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_disputes_over_available() {
+        // The deposit is mostly withdrawn before it is disputed
+        let input = "type, client, tx, amount
+            deposit, 1, 1, 10.0
+            withdrawal, 1, 2, 8.0
+            dispute, 1, 1";
+        for (policy, available, held, over_available) in [
+            (OverAvailable::Allow, -8.0, 10.0, 1),
+            (OverAvailable::Cap, 0.0, 2.0, 1),
+            (OverAvailable::Reject, 2.0, 0.0, 0),
+        ] {
+            let mut engine = PaymentsEngine::builder().disputes_over_available(policy).build();
+            engine.process_csv_reader(input.as_bytes()).unwrap();
+            let account = engine.account(1).unwrap().unwrap();
+            assert_eq!(
+                (account.available, account.held),
+                (amount(available), amount(held)),
+                "{:?}",
+                policy
+            );
+            assert_eq!(engine.stats().disputes_over_available, over_available);
+            let rejections = engine.stats().rejections.get(&RejectionReason::InsufficientFunds);
+            assert_eq!(rejections.is_some(), policy == OverAvailable::Reject);
+        }
+    }
+
+    #[test]
+    fn test_chargeback_reversal() {
+        let input = Path::new("sample_files/chargeback_reversal.csv");
+        let report = process_csv(input).unwrap();
+        let expected = r"client, available, held, pending, total, locked
+1, 3.5000, 0.0000, 0.0000, 3.5000, false";
+        assert_eq!(report.to_string(), expected);
+        let rejected: Vec<_> = report
+            .rejections
+            .iter()
+            .map(|rejection| (rejection.line, rejection.reason))
+            .collect();
+        assert_eq!(
+            rejected,
+            [
+                (Some(6), RejectionReason::AccountLocked),
+                (Some(7), RejectionReason::NotChargedBack),
+                (Some(9), RejectionReason::NotChargedBack),
+            ]
+        );
+
+        // The funds are restored, but the account stays locked
+        let mut config = EngineConfig::default();
+        config.disputes.unlock_on_chargeback_reversal = false;
+        let mut engine = PaymentsEngine::new().with_config(config);
+        engine.process_csv(input).unwrap();
+        let account = engine.account(1).unwrap().unwrap();
+        assert_eq!(
+            (account.available, account.status),
+            (amount(3.0), AccountStatus::Locked)
+        );
+    }
+
+    #[test]
+    fn test_dispute_resolve() {
+        let expected = r"client, available, held, pending, total, locked
+1, 1.0000, 0.0000, 0.0000, 1.0000, false";
+        test_csv("sample_files/dispute_resolve.csv", expected);
+    }
+
+    #[test]
+    fn test_dispute() {
+        let expected = r"client, available, held, pending, total, locked
+1, 0.0000, 1.0000, 0.0000, 1.0000, false";
+        test_csv("sample_files/dispute.csv", expected);
+    }
+
+    #[test]
+    fn test_apply_outcome() {
+        let mut engine = PaymentsEngine::new();
+        let parse = |record: &str| Transaction::parse_csv_record(record.as_bytes()).unwrap();
+
+        let applied = engine.apply(&parse("deposit, 1, 1, 2.0")).unwrap().unwrap();
+        assert_eq!((applied.balance.available, applied.hold), (amount(2.0), None));
+        let applied = engine.apply(&parse("dispute, 1, 1,")).unwrap().unwrap();
+        assert_eq!((applied.balance.held, applied.hold), (amount(2.0), Some(amount(2.0))));
+        assert_eq!(applied.entry.postings[0].to, LedgerAccount::Held(1));
+
+        let rejected = engine.apply(&parse("withdrawal, 1, 2, 1.0")).unwrap().unwrap_err();
+        assert_eq!(rejected.reason, RejectionReason::InsufficientFunds);
+        assert_eq!(rejected.balance.held, amount(2.0));
+        assert!(engine.apply(&parse("deposit, 1, 3,")).is_err());
+    }
+
+    #[test]
+    fn test_invalid_amounts() {
+        // Only floats have a NaN and an infinity, the minor units don't parse them
+        #[cfg(not(feature = "fixed-amounts"))]
+        let (input, invalid) = (
+            "type, client, tx, amount\n\
+                                 deposit, 1, 1, 1.0\n\
+                                 withdrawal, 1, 2, -5.0\n\
+                                 deposit, 1, 3, NaN\n\
+                                 deposit, 1, 4, inf\n\
+                                 deposit, 1, 5, 0.0\n",
+            4,
+        );
+        #[cfg(feature = "fixed-amounts")]
+        let (input, invalid) = (
+            "type, client, tx, amount\n\
+                                 deposit, 1, 1, 1.0\n\
+                                 withdrawal, 1, 2, -5.0\n\
+                                 deposit, 1, 5, 0.0\n",
+            2,
+        );
+        for parser in [CsvParser::Serde, CsvParser::Fast] {
+            let options = CsvOptions {
+                parser,
+                ..CsvOptions::default()
+            };
+            let mut engine = PaymentsEngine::new();
+            engine.process_csv_with(input.as_bytes(), &options).unwrap();
+            assert_eq!(engine.account(1).unwrap().unwrap().available, amount(1.0));
+            assert_eq!(
+                engine.stats().rejections.get(&RejectionReason::InvalidAmount),
+                Some(&invalid)
+            );
+        }
+        #[cfg(feature = "fixed-amounts")]
+        {
+            let nan = "type, client, tx, amount\ndeposit, 1, 3, NaN";
+            assert!(PaymentsEngine::new().process_csv_reader(nan.as_bytes()).is_err());
+        }
+
+        // Whatever produced the transaction
+        let mut engine = PaymentsEngine::new();
+        let transaction = |tx_type, amount| Transaction {
+            tx_type,
+            client_id: 1,
+            tx_id: 1,
+            amount: Some(amount),
+            reason: None,
+            timestamp: None,
+            settles_at: None,
+        };
+        // The minor units are always finite
+        #[cfg(not(feature = "fixed-amounts"))]
+        {
+            let rejected = engine
+                .apply(&transaction(TransactionType::Deposit, Amount::NAN))
+                .unwrap()
+                .unwrap_err();
+            assert_eq!(rejected.reason, RejectionReason::InvalidAmount);
+        }
+        assert!(engine
+            .apply(&transaction(TransactionType::Deposit, amount(2.0)))
+            .unwrap()
+            .is_ok());
+        #[cfg(not(feature = "fixed-amounts"))]
+        {
+            let rejected = engine
+                .apply(&transaction(TransactionType::Dispute, Amount::INFINITY))
+                .unwrap()
+                .unwrap_err();
+            assert_eq!(rejected.reason, RejectionReason::InvalidAmount);
+        }
+        assert_eq!(engine.account(1).unwrap().unwrap().held, amount(0.0));
+    }
+
+    #[test]
+    #[cfg(feature = "fixed-amounts")]
+    fn test_fixed_amounts() {
+        // An f32 would report 1234567.8750, and 0.1 + 0.2 - 0.3 isn't 0 in any float
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 1234567.89\n\
+                     deposit, 2, 2, 0.1\n\
+                     deposit, 2, 3, 0.2\n\
+                     withdrawal, 2, 4, 0.3\n";
+        let mut engine = PaymentsEngine::new();
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            crate::accounts::format_report(&engine.balances().unwrap()),
+            "client, available, held, pending, total, locked\n\
+             1, 1234567.8900, 0.0000, 0.0000, 1234567.8900, false\n\
+             2, 0.0000, 0.0000, 0.0000, 0.0000, false"
+        );
+        let too_precise = "type, client, tx, amount\ndeposit, 1, 1, 0.00001";
+        assert!(PaymentsEngine::new()
+            .process_csv_reader(too_precise.as_bytes())
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "fixed-amounts")]
+    fn test_amount_overflow() {
+        // i64::MAX minor units, then the funds that would go over it are rejected, not wrapped around
+        let input = "type, client, tx, amount, reason\n\
+                     deposit, 1, 1, 922337203685477.5807,\n\
+                     deposit, 1, 2, 0.0001,\n\
+                     adjustment, 1, 3, 1.0, correction\n\
+                     deposit, 2, 4, 500000000000000,\n\
+                     dispute, 2, 4,\n\
+                     deposit, 2, 5, 500000000000000,\n\
+                     withdrawal, 1, 6, 922337203685477.5807,\n";
+        let mut engine = PaymentsEngine::new().with_admin_ops();
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            engine.stats().rejections.get(&RejectionReason::AmountOverflow),
+            Some(&3)
+        );
+        assert_eq!(
+            engine.report().unwrap(),
+            "client, available, held, pending, total, locked\n\
+             1, 0.0000, 0.0000, 0.0000, 0.0000, false\n\
+             2, 0.0000, 500000000000000.0000, 0.0000, 500000000000000.0000, false"
+        );
+        assert_eq!(RejectionReason::AmountOverflow.code(), "AMOUNT_OVERFLOW");
+    }
+
+    #[test]
+    fn test_dedup() {
+        let input = Path::new("sample_files/dispute.csv");
+        let mut engine = PaymentsEngine::new().with_dedup(Box::new(MemoryDedupStore::default()));
+        engine.process_csv(input).unwrap();
+        let mut snapshot = vec![];
+        engine.write_snapshot(&mut snapshot).unwrap();
+
+        // Delivered again after restarting from the snapshot
+        let mut restored = PaymentsEngine::new().with_dedup(Box::new(MemoryDedupStore::default()));
+        restored.restore_snapshot(snapshot.as_slice()).unwrap();
+        restored.process_csv(input).unwrap();
+        assert_eq!(restored.report().unwrap(), process_csv(input).unwrap().to_string());
+        assert_eq!(restored.stats().rejections.get(&RejectionReason::Duplicate), Some(&2));
+
+        // Without a dedup store, there are no processed transactions
+        let mut plain = PaymentsEngine::new();
+        plain.process_csv(input).unwrap();
+        let mut plain_snapshot = vec![];
+        plain.write_snapshot(&mut plain_snapshot).unwrap();
+        // Each processed transaction is its type and id
+        let processed_len = 2 * (1 + size_of::<TxId>());
+        assert_eq!(snapshot.len() - plain_snapshot.len(), processed_len);
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        let parse = |record: &str| Transaction::parse_csv_record(record.as_bytes()).unwrap();
+        let batch: Vec<Transaction> = [
+            "deposit, 1, 1, 2.0",
+            "deposit, 2, 2,",
+            "withdrawal, 1, 3, 5.0",
+            "withdrawal, 1, 4, 0.5",
+        ]
+        .into_iter()
+        .map(parse)
+        .collect();
+        let mut engine = PaymentsEngine::new();
+        let outcomes = engine.apply_batch(&batch);
+
+        assert_eq!(outcomes.len(), 4);
+        assert_eq!(
+            outcomes[0].as_ref().unwrap().as_ref().unwrap().balance.available,
+            amount(2.0)
+        );
+        assert!(outcomes[1].is_err());
+        assert_eq!(
+            outcomes[2].as_ref().unwrap().as_ref().unwrap_err().reason,
+            RejectionReason::InsufficientFunds
+        );
+        assert_eq!(
+            outcomes[3].as_ref().unwrap().as_ref().unwrap().balance.available,
+            amount(1.5)
+        );
+        assert_eq!(engine.account(1).unwrap().unwrap().available, amount(1.5));
+        assert!(engine.account(2).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_invalid_csv() {
+        assert!(process_csv(Path::new("sample_files/invalid_csv.csv")).is_err());
+    }
+
+    #[test]
+    fn test_failure_kinds() {
+        let err = process_csv(Path::new("sample_files/invalid_csv.csv")).unwrap_err();
+        assert_eq!(FailureKind::of(err.as_ref()), FailureKind::Parse);
+        let err = process_csv(Path::new("sample_files/deposit_missing_amount.csv")).unwrap_err();
+        assert_eq!(FailureKind::of(err.as_ref()), FailureKind::Semantic);
+        assert_eq!(err.to_string(), "Line 3: A deposit must have an amount");
+        let err = process_csv(Path::new("sample_files/missing.csv")).unwrap_err();
+        assert_eq!(FailureKind::of(err.as_ref()), FailureKind::Io);
+    }
+
+    #[test]
+    fn test_withdrawal_insufficient_funds() {
+        let expected = r"client, available, held, pending, total, locked
+1, 1.0000, 0.0000, 0.0000, 1.0000, false";
+        test_csv("sample_files/withdrawal_insufficient_funds.csv", expected);
+    }
+
+    #[test]
+    fn test_reversal() {
+        let expected = r"client, available, held, pending, total, locked
+1, 0.5000, 0.0000, 0.0000, 0.5000, false
+2, 0.0000, 1.0000, 0.0000, 1.0000, false";
+        test_csv("sample_files/reversal.csv", expected);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        // Interrupted by the first rejection, as a signal handler would
+        struct Interrupt(Arc<AtomicBool>);
+        impl Observer for Interrupt {
+            fn on_rejected(&mut self, _rejection: &Rejection) {
+                self.0.store(true, Ordering::Relaxed);
+            }
+        }
+        let input = "type, client, tx, amount
+            deposit, 1, 1, 5.0
+            withdrawal, 1, 2, 8.0
+            deposit, 1, 3, 2.0";
+        for parser in [CsvParser::Serde, CsvParser::Fast] {
+            let interrupt = Arc::new(AtomicBool::new(false));
+            let mut engine = PaymentsEngine::new()
+                .with_observer(Box::new(Interrupt(Arc::clone(&interrupt))))
+                .with_interrupt(interrupt);
+            let options = CsvOptions {
+                parser,
+                ..CsvOptions::default()
+            };
+            engine.process_csv_with(input.as_bytes(), &options).unwrap();
+            assert_eq!(engine.interrupted_at().map(|position| position.line()), Some(3));
+            assert_eq!(engine.account(1).unwrap().unwrap().available, amount(5.0));
+
+            // Picked up from a snapshot, without applying the lines read again
+            let mut snapshot = vec![];
+            engine.write_snapshot(&mut snapshot).unwrap();
+            let mut resumed = PaymentsEngine::new().with_resume_after(3);
+            resumed.restore_snapshot(snapshot.as_slice()).unwrap();
+            resumed.process_csv_with(input.as_bytes(), &options).unwrap();
+            assert_eq!(resumed.account(1).unwrap().unwrap().available, amount(7.0));
+            assert_eq!(resumed.stats().processed, 1);
+        }
+
+        let mut engine = PaymentsEngine::new().with_interrupt(Arc::new(AtomicBool::new(false)));
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        assert!(engine.interrupted_at().is_none());
+        assert_eq!(engine.account(1).unwrap().unwrap().available, amount(7.0));
+    }
+
+    #[test]
+    fn test_resume_as_uninterrupted() {
+        let mut config = EngineConfig::from_toml("[disputes]\nexpire_after_days = 1").unwrap();
+        config.interest = Some(InterestConfig {
+            annual_rate_percent: amount(365.0),
+            period: InterestPeriod::Daily,
+        });
+        config.risk.velocity = Some(VelocityLimit {
+            max_transactions: 2,
+            window_secs: 600,
+        });
+        // An open dispute, a chargeback and two deposits in the velocity window before the
+        // checkpoint, and after it a third deposit in the window and one once the dispute expired
+        let lines = [
+            "type, client, tx, amount, timestamp",
+            "deposit, 1, 1, 5.0, 1000",
+            "dispute, 1, 1, , 2000",
+            "deposit, 2, 4, 10.0, 2000",
+            "dispute, 2, 4, , 2000",
+            "chargeback, 2, 4, , 2000",
+            "deposit, 3, 5, 1.0, 3000",
+            "deposit, 3, 6, 1.0, 3000",
+            "deposit, 3, 7, 1.0, 3500",
+            "deposit, 1, 2, 1.0, 500000",
+        ];
+        let input = lines.join("\n");
+        let mut engine = PaymentsEngine::new().with_config(config.clone());
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+
+        let mut checkpoint = PaymentsEngine::new().with_config(config.clone());
+        checkpoint.process_csv_reader(lines[..8].join("\n").as_bytes()).unwrap();
+        let mut snapshot = vec![];
+        checkpoint.write_snapshot(&mut snapshot).unwrap();
+        let mut resumed = PaymentsEngine::new().with_config(config).with_resume_after(8);
+        resumed.restore_snapshot(snapshot.as_slice()).unwrap();
+        resumed.process_csv_reader(input.as_bytes()).unwrap();
+
+        assert_eq!(resumed.report().unwrap(), engine.report().unwrap());
+        assert_eq!(resumed.state_hash().unwrap(), engine.state_hash().unwrap());
+        assert_eq!(resumed.account(1).unwrap().unwrap().held, amount(0.0));
+        assert_eq!(resumed.losses(), engine.losses());
+        assert_eq!(resumed.stats().risk_rejections, engine.stats().risk_rejections);
+        assert_eq!(resumed.stats().risk_rejections.len(), 1);
+    }
+
+    #[test]
+    fn test_recorded_transactions() {
+        let input = "type, client, tx, amount
+            deposit, 1, 1, 5.0
+            withdrawal, 1, 2, 1.0
+            deposit, 1, 3, 2.0
+            dispute, 1, 2
+            dispute, 1, 1, 1.0
+            reversal, 1, 2
+            deposit, 2, 1, 4.0
+            withdrawal, 1, 3, 1.0";
+        let mut engine = PaymentsEngine::new();
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        let rejections = &engine.stats().rejections;
+        // Only deposits are disputed, and the ids of deposits and withdrawals are unique
+        assert_eq!(rejections.get(&RejectionReason::UnknownTransaction), Some(&1));
+        assert_eq!(rejections.get(&RejectionReason::Duplicate), Some(&2));
+
+        let deposit = engine.transaction(1).unwrap().unwrap();
+        assert_eq!(
+            (deposit.client_id, deposit.tx_type, deposit.amount, deposit.status),
+            (
+                Some(1),
+                TransactionType::Deposit,
+                amount(5.0),
+                TxStatus::Disputed { held: amount(1.0) }
+            )
+        );
+        let withdrawal = engine.transaction(2).unwrap().unwrap();
+        assert_eq!(
+            (withdrawal.tx_type, withdrawal.status),
+            (TransactionType::Withdrawal, TxStatus::Reversed)
+        );
+        assert_eq!(engine.transaction(3).unwrap().unwrap().status, TxStatus::Settled);
+        assert!(engine.transaction(4).unwrap().is_none());
+        assert_eq!(engine.account(2).unwrap().unwrap().available, amount(0.0));
+    }
+
+    #[test]
+    fn test_undo() {
+        let before = "type, client, tx, amount
+            deposit, 1, 1, 5.0
+            withdrawal, 1, 2, 1.0
+            deposit, 2, 3, 2.0";
+        let batch = "type, client, tx, amount
+            dispute, 1, 1
+            chargeback, 1, 1
+            reversal, 1, 2
+            deposit, 3, 4, 1.0
+            withdrawal, 2, 5, 9.0
+            dispute, 2, 3";
+        let engines = [
+            PaymentsEngine::new(),
+            PaymentsEngine::with_stores(
+                Box::new(MemoryAccountStore::default()),
+                Box::new(CompactTxStore::default()),
+            ),
+        ];
+        for engine in engines {
+            let mut engine = engine.with_undo(10).with_journal();
+            engine.process_csv_reader(before.as_bytes()).unwrap();
+            let (state_hash, recorded) = (engine.state_hash().unwrap(), engine.transaction(1).unwrap());
+            engine.process_csv_reader(batch.as_bytes()).unwrap();
+            assert_eq!(engine.account(1).unwrap().unwrap().status, AccountStatus::Locked);
+
+            // The whole batch is backed out, the rejected withdrawal included
+            let undone = engine.undo(6).unwrap();
+            assert_eq!(
+                undone.iter().map(|transaction| transaction.tx_id).collect::<Vec<_>>(),
+                [3, 5, 4, 2, 1, 1]
+            );
+            assert_eq!(engine.state_hash().unwrap(), state_hash);
+            assert_eq!(engine.transaction(1).unwrap(), recorded);
+            assert!(engine.transaction(4).unwrap().is_none());
+            assert!(engine.account(3).unwrap().is_none());
+            // The journal has the reverse entries, and still adds up to the balances
+            assert_eq!(engine.journal().last().unwrap().reason.as_deref(), Some("undo"));
+            let available = LedgerAccount::Available(1);
+            let posted: Amount = engine
+                .journal()
+                .iter()
+                .flat_map(|entry| &entry.postings)
+                .map(|posting| match (posting.from == available, posting.to == available) {
+                    (true, false) => -posting.amount,
+                    (false, true) => posting.amount,
+                    _ => amount(0.0),
+                })
+                .sum();
+            assert_eq!(posted, amount(4.0));
+
+            // The same transactions apply again
+            engine.process_csv_reader(batch.as_bytes()).unwrap();
+            assert_eq!(engine.account(1).unwrap().unwrap().status, AccountStatus::Locked);
+            assert_eq!(engine.undo(20).unwrap().len(), 9);
+            assert!(engine.undo(1).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_savepoints() {
+        let mut engine = PaymentsEngine::new();
+        engine
+            .process_csv_reader("type, client, tx, amount\ndeposit, 1, 1, 5.0\n".as_bytes())
+            .unwrap();
+        engine.savepoint("b");
+        let b = "type, client, tx, amount\ndeposit, 1, 2, 3.0\n";
+        engine.process_csv_reader(b.as_bytes()).unwrap();
+        let undone = engine.rollback_to("b").unwrap();
+        assert_eq!(
+            undone.iter().map(|transaction| transaction.tx_id).collect::<Vec<_>>(),
+            [2]
+        );
+        // Only the transactions since the savepoint were kept
+        assert!(engine.undo(1).unwrap().is_empty());
+        assert_eq!(engine.account(1).unwrap().unwrap().available, amount(5.0));
+
+        engine.process_csv_reader(b.as_bytes()).unwrap();
+        engine.savepoint("c");
+        engine
+            .process_csv_reader("type, client, tx, amount\ndeposit, 1, 3, 1.0\n".as_bytes())
+            .unwrap();
+        // With the later ones, and then nothing is kept
+        engine.release_savepoint("b").unwrap();
+        assert!(engine.rollback_to("c").is_err());
+        assert!(engine.release_savepoint("b").is_err());
+        assert!(engine.undo(1).unwrap().is_empty());
+        assert_eq!(engine.account(1).unwrap().unwrap().available, amount(9.0));
+    }
+
+    #[test]
+    fn test_rollback_to_restores_side_state() {
+        let config = EngineConfig {
+            interest: Some(InterestConfig {
+                annual_rate_percent: amount(365.0),
+                period: InterestPeriod::Daily,
+            }),
+            risk: RiskRules {
+                velocity: Some(VelocityLimit {
+                    max_transactions: 1,
+                    window_secs: 60,
+                }),
+                ..RiskRules::default()
+            },
+            ..EngineConfig::default()
+        };
+        let before = "type, client, tx, amount, timestamp
+            deposit, 1, 1, 100.0, 0
+            deposit, 3, 3, 20.0, 0
+            deposit, 6, 6, 1.0, 0";
+        // Five days later, with interest, a withdrawal in the velocity window, a chargeback and
+        // an open dispute, and then a malformed record
+        let failed = "type, client, tx, amount, timestamp
+            deposit, 2, 2, 10.0, 432000
+            withdrawal, 1, 5, 50.0, 432000
+            dispute, 3, 3, , 432000
+            chargeback, 3, 3, , 432000
+            dispute, 6, 6, , 432000
+            deposit, 1, 9, , 432000";
+        let resubmitted = "type, client, tx, amount, timestamp
+            deposit, 2, 2, 10.0, 172800
+            withdrawal, 1, 7, 50.0, 172800";
+        let engine = || {
+            let dedup = MemoryDedupStore::default();
+            PaymentsEngine::new()
+                .with_config(config.clone())
+                .with_dedup(Box::new(dedup))
+        };
+
+        let mut engine_rolled_back = engine();
+        engine_rolled_back.process_csv_reader(before.as_bytes()).unwrap();
+        engine_rolled_back.savepoint("failed");
+        assert!(engine_rolled_back.process_csv_reader(failed.as_bytes()).is_err());
+        engine_rolled_back.rollback_to("failed").unwrap();
+        engine_rolled_back.process_csv_reader(resubmitted.as_bytes()).unwrap();
+
+        let mut engine = engine();
+        engine.process_csv_reader(before.as_bytes()).unwrap();
+        engine.process_csv_reader(resubmitted.as_bytes()).unwrap();
+        assert_eq!(engine_rolled_back.report().unwrap(), engine.report().unwrap());
+        assert_eq!(engine_rolled_back.state_hash().unwrap(), engine.state_hash().unwrap());
+        assert!(engine_rolled_back.losses().is_empty());
+        assert_eq!(
+            engine_rolled_back.open_disputes().unwrap(),
+            engine.open_disputes().unwrap()
+        );
+        assert_eq!(
+            engine_rolled_back.stats().risk_rejections,
+            engine.stats().risk_rejections
+        );
+    }
+
+    #[test]
+    fn test_adjustment() {
+        let input = Path::new("sample_files/adjustment.csv");
+        let err = process_csv(input).unwrap_err();
+        assert_eq!(FailureKind::of(err.as_ref()), FailureKind::Semantic);
+
+        let mut engine = PaymentsEngine::new().with_admin_ops().with_journal();
+        engine.process_csv(input).unwrap();
+        assert_eq!(
+            engine.report().unwrap(),
+            r"client, available, held, pending, total, locked
+1, 3.7500, 0.0000, 0.0000, 3.7500, false"
+        );
+        assert_eq!(engine.journal()[1].reason.as_deref(), Some("DUPLICATE_DEPOSIT"));
+        assert_eq!(engine.stats().rejected, 1);
+    }
+
+    #[test]
+    fn test_fees() {
+        let config = EngineConfig {
+            fee_rules: vec!["withdrawal:percent=1,over=20".parse().unwrap()],
+            ..EngineConfig::default()
+        };
+        let mut engine = PaymentsEngine::new().with_config(config).with_journal();
+        engine.process_csv(Path::new("sample_files/fees.csv")).unwrap();
+        assert_eq!(
+            engine.report().unwrap(),
+            r"client, available, held, pending, total, locked
+1, 48.0000, 0.0000, 0.0000, 48.0000, false"
+        );
+        assert_eq!(engine.journal()[1].postings.len(), 2);
+        assert_eq!(engine.stats().rejected, 2);
+    }
+
+    #[test]
+    fn test_interest() {
+        let config = EngineConfig {
+            interest: Some(InterestConfig {
+                annual_rate_percent: amount(36.5),
+                period: InterestPeriod::Monthly,
+            }),
+            ..EngineConfig::default()
+        };
+        let mut engine = PaymentsEngine::new().with_config(config).with_journal();
+        engine.process_csv(Path::new("sample_files/interest.csv")).unwrap();
+        assert_eq!(
+            engine.report().unwrap(),
+            r"client, available, held, pending, total, locked
+1, 100.2000, 0.0000, 0.0000, 100.2000, false
+2, 1.0000, 0.0000, 0.0000, 1.0000, false"
+        );
+        assert_eq!(engine.journal()[3].tx_type, TransactionType::Interest);
+    }
+
+    #[test]
+    fn test_risk_rules() {
+        let config = EngineConfig::from_toml(&fs::read_to_string("sample_files/risk.toml").unwrap()).unwrap();
+        let mut engine = PaymentsEngine::new().with_config(config);
+        engine.process_csv(Path::new("sample_files/risk.csv")).unwrap();
+        assert_eq!(
+            engine.report().unwrap(),
+            r"client, available, held, pending, total, locked
+1, 650.0000, 0.0000, 0.0000, 650.0000, false"
+        );
+        let violations: Vec<_> = engine.stats().risk_rejections.iter().collect();
+        assert_eq!(
+            violations,
+            [
+                (&RiskViolation::MaxWithdrawal, &1),
+                (&RiskViolation::MaxDailyWithdrawals, &1),
+                (&RiskViolation::Velocity, &1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_aml_findings() {
+        let input = Path::new("sample_files/aml.csv");
+        let mut engine = PaymentsEngine::new().with_aml();
+        engine.process_csv(input).unwrap();
+        assert_eq!(engine.report().unwrap(), process_csv(input).unwrap().to_string());
+
+        let findings: Vec<_> = engine
+            .aml_findings()
+            .iter()
+            .map(|finding| (finding.client, finding.kind, finding.tx_id))
+            .collect();
+        assert_eq!(
+            findings,
+            [
+                (1, FindingKind::Structuring, Some(3)),
+                (2, FindingKind::RapidCycle, Some(6)),
+                (3, FindingKind::HighDisputeRate, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_close() {
+        let mut engine = PaymentsEngine::new().with_journal();
+        engine.process_csv(Path::new("sample_files/close.csv")).unwrap();
+        assert_eq!(
+            engine.report().unwrap(),
+            r"client, available, held, pending, total, locked
+1, 0.0000, 0.0000, 0.0000, 0.0000, false
+2, 1.0000, 1.0000, 0.0000, 2.0000, false"
+        );
+        assert_eq!(engine.account(1).unwrap().unwrap().status, AccountStatus::Closed);
+        assert_eq!(engine.account(2).unwrap().unwrap().status, AccountStatus::Active);
+
+        let payout = engine
+            .journal()
+            .iter()
+            .find(|entry| entry.tx_type == TransactionType::Close)
+            .unwrap();
+        assert_eq!(payout.postings[0].to, LedgerAccount::Payouts);
+        assert_eq!(payout.postings[0].amount, amount(2.5));
+        assert_eq!(engine.stats().rejected, 2);
+    }
+
+    #[test]
+    fn test_freeze() {
+        let input = Path::new("sample_files/freeze.csv");
+        let err = process_csv(input).unwrap_err();
+        assert_eq!(FailureKind::of(err.as_ref()), FailureKind::Semantic);
+
+        let mut engine = PaymentsEngine::new().with_admin_ops();
+        engine.process_csv(input).unwrap();
+        assert_eq!(
+            engine.report().unwrap(),
+            r"client, available, held, pending, total, locked
+1, 2.5000, 0.0000, 0.0000, 2.5000, false
+2, 0.0000, 0.0000, 0.0000, 0.0000, true"
+        );
+        assert_eq!(engine.account(1).unwrap().unwrap().status, AccountStatus::Frozen);
+        assert_eq!(engine.account(2).unwrap().unwrap().status, AccountStatus::Locked);
+        assert_eq!(engine.stats().rejected, 3);
+    }
+
+    #[test]
+    fn test_client_metadata() {
+        let clients = ClientDirectory::read(fs::File::open("sample_files/clients.csv").unwrap()).unwrap();
+        let mut engine = PaymentsEngine::new().with_clients(clients);
+        engine
+            .process_csv(Path::new("sample_files/deposit_withdrawal.csv"))
+            .unwrap();
+        assert_eq!(
+            engine.report().unwrap(),
+            r"client, available, held, pending, total, locked, name, segment, region
+1, 1.5000, 0.0000, 0.0000, 1.5000, false, Alice, retail, eu-west
+2, 0.5000, 0.0000, 0.0000, 0.5000, false, , , "
+        );
+        assert_eq!(engine.stats().unknown_clients.iter().collect::<Vec<_>>(), [&2]);
+    }
+
+    #[test]
+    fn test_settlement() {
+        let mut engine = PaymentsEngine::new();
+        engine.process_csv(Path::new("sample_files/settlement.csv")).unwrap();
+        assert_eq!(
+            engine.report().unwrap(),
+            r"client, available, held, pending, total, locked
+1, 7.0000, 0.0000, 1.0000, 8.0000, false
+2, 3.0000, 0.0000, 0.0000, 3.0000, false"
+        );
+        assert_eq!(engine.stats().rejected, 2);
+    }
+
+    #[test]
+    fn test_account_view() {
+        let mut engine = PaymentsEngine::new();
+        let input = "type, client, tx, amount, timestamp\n\
+                     deposit, 1, 1, 5.0, 200\n\
+                     deposit, 1, 2, 3.0, 100\n\
+                     deposit, 1, 3, 1.0,\n\
+                     dispute, 1, 2, 2.0,\n\
+                     withdrawal, 1, 4, 100.0, 300\n\
+                     deposit, 2, 5, 1.0,\n";
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        let view = engine.account_view(1).unwrap().unwrap();
+        assert_eq!((view.balance.available, view.balance.held), (amount(7.0), amount(2.0)));
+        assert_eq!(
+            view.open_disputes
+                .iter()
+                .map(|dispute| (dispute.tx, dispute.held))
+                .collect::<Vec<_>>(),
+            [(2, amount(2.0))]
+        );
+        // Only by the transactions applied, in whatever order
+        assert_eq!(view.last_activity, Some(200));
+        assert_eq!(engine.account_view(2).unwrap().unwrap().last_activity, None);
+        assert!(engine.account_view(3).unwrap().is_none());
+
+        let mut snapshot = vec![];
+        engine.write_snapshot(&mut snapshot).unwrap();
+        let mut restored = PaymentsEngine::new();
+        restored.restore_snapshot(snapshot.as_slice()).unwrap();
+        let restored_view = restored.account_view(1).unwrap().unwrap();
+        assert_eq!(
+            (restored_view.open_disputes, restored_view.last_activity),
+            (view.open_disputes, Some(200))
+        );
+    }
+
+    #[test]
+    fn test_deposit_hold() {
+        let config = EngineConfig::from_toml("[deposits]\nhold_days = 2").unwrap();
+        let mut engine = PaymentsEngine::new().with_config(config).with_undo(10);
+        let input = "type, client, tx, amount, timestamp, settles_at\n\
+                     deposit, 1, 1, 5.0, 0,\n\
+                     deposit, 1, 2, 3.0, 86400,\n\
+                     deposit, 1, 3, 1.0, 86400, 86401\n\
+                     withdrawal, 1, 4, 1.0, 86400,\n\
+                     settle, 1, 2,, 86400,\n\
+                     withdrawal, 1, 5, 2.0, 86400,\n\
+                     settle, 1, 2,, 86400,\n";
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        // Only what the settle made available can be withdrawn, until the hold is over
+        let account_balance = engine.account(1).unwrap().unwrap();
+        assert_eq!(
+            (account_balance.available, account_balance.pending),
+            (amount(1.0), amount(6.0))
+        );
+        assert_eq!(
+            engine.stats().rejections.get(&RejectionReason::InsufficientFunds),
+            Some(&1)
+        );
+        assert_eq!(engine.stats().rejections.get(&RejectionReason::NotPending), Some(&1));
+
+        // Undoing the settle makes the deposit pending again, until its hold is over
+        engine.undo(3).unwrap();
+        let account_balance = engine.account(1).unwrap().unwrap();
+        assert_eq!(
+            (account_balance.available, account_balance.pending),
+            (amount(0.0), amount(9.0))
+        );
+        engine
+            .process_csv_reader("type, client, tx, amount, timestamp\ndeposit, 2, 6, 1.0, 172800".as_bytes())
+            .unwrap();
+        let account_balance = engine.account(1).unwrap().unwrap();
+        assert_eq!(
+            (account_balance.available, account_balance.pending),
+            (amount(6.0), amount(3.0))
+        );
+        engine
+            .process_csv_reader("type, client, tx, amount, timestamp\ndeposit, 2, 7, 1.0, 259200".as_bytes())
+            .unwrap();
+        assert_eq!(engine.account(1).unwrap().unwrap().available, amount(9.0));
+    }
+
+    #[cfg(feature = "wide-ids")]
+    #[test]
+    fn test_wide_ids() {
+        let mut engine = PaymentsEngine::new();
+        engine
+            .process_csv_reader(
+                "type, client, tx, amount\ndeposit, 70000, 5000000000, 1.0\ndispute, 70000, 5000000000,".as_bytes(),
+            )
+            .unwrap();
+        assert_eq!(
+            engine.report().unwrap(),
+            r"client, available, held, pending, total, locked
+70000, 0.0000, 1.0000, 0.0000, 1.0000, false"
+        );
+    }
+}
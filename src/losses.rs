@@ -0,0 +1,186 @@
+//! The losses to chargebacks of each client, for finance to book the write-offs from: the funds
+//! charged back, the fees of the card network for them, see
+//! [`DisputeRules::chargeback_fee`](crate::config::DisputeRules::chargeback_fee), and the funds
+//! recovered by the chargeback reversals won on appeal.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::accounts::AmountFormat;
+use crate::amount::AmountValue;
+use crate::ledger::JournalEntry;
+use crate::transactions::{Amount, ClientId, TransactionType};
+
+/// The chargebacks of a client, or of all of them, and what they cost.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LossTotals {
+    pub chargebacks: u64,
+    /// The funds lost to chargebacks.
+    pub charged_back: Amount,
+    /// The fees charged by the card network for the chargebacks.
+    pub fees: Amount,
+    /// The chargebacks reversed on appeal.
+    pub reversals: u64,
+    /// The funds recovered by the reversals.
+    pub recovered: Amount,
+}
+
+impl LossTotals {
+    /// What the chargebacks cost, once recovered what could be.
+    pub fn net(&self) -> Amount {
+        self.charged_back + self.fees - self.recovered
+    }
+
+    fn add(&mut self, totals: &LossTotals) {
+        self.chargebacks += totals.chargebacks;
+        self.charged_back += totals.charged_back;
+        self.fees += totals.fees;
+        self.reversals += totals.reversals;
+        self.recovered += totals.recovered;
+    }
+}
+
+/// The losses so far of the clients with any chargebacks, see [`PaymentsEngine::losses`](crate::engine::PaymentsEngine::losses).
+#[derive(Debug, Default)]
+pub(crate) struct Losses {
+    by_client: BTreeMap<ClientId, LossTotals>,
+}
+
+impl Losses {
+    /// Adds an applied transaction, with the entry it posted, if it is a chargeback or the
+    /// reversal of one.
+    pub(crate) fn record(&mut self, entry: &JournalEntry, chargeback_fee: Amount) {
+        let amount = entry.postings.first().map_or(Amount::ZERO, |posting| posting.amount);
+        match entry.tx_type {
+            TransactionType::Chargeback => {
+                let totals = self.by_client.entry(entry.client_id).or_default();
+                totals.chargebacks += 1;
+                totals.charged_back += amount;
+                totals.fees += chargeback_fee;
+            }
+            TransactionType::ChargebackReversal => {
+                let totals = self.by_client.entry(entry.client_id).or_default();
+                totals.reversals += 1;
+                totals.recovered += amount;
+            }
+            _ => {}
+        }
+    }
+
+    /// The losses of the client, if it has any chargebacks.
+    pub(crate) fn get(&self, client_id: ClientId) -> Option<LossTotals> {
+        self.by_client.get(&client_id).cloned()
+    }
+
+    /// Puts the losses of the client back as [`get`](Losses::get) returned them.
+    pub(crate) fn restore(&mut self, client_id: ClientId, totals: Option<LossTotals>) {
+        match totals {
+            Some(totals) => self.by_client.insert(client_id, totals),
+            None => self.by_client.remove(&client_id),
+        };
+    }
+
+    /// The clients with any chargebacks, sorted by client id.
+    pub(crate) fn by_client(&self) -> Vec<(ClientId, LossTotals)> {
+        self.by_client
+            .iter()
+            .map(|(client, totals)| (*client, totals.clone()))
+            .collect()
+    }
+
+    /// The losses of all the clients.
+    pub(crate) fn total(&self) -> LossTotals {
+        let mut total = LossTotals::default();
+        for totals in self.by_client.values() {
+            total.add(totals);
+        }
+        total
+    }
+}
+
+#[derive(Serialize)]
+struct LossRow {
+    client: String,
+    chargebacks: u64,
+    charged_back: String,
+    fees: String,
+    reversals: u64,
+    recovered: String,
+    net_loss: String,
+}
+
+/// Writes the losses of each client as CSV, with the amounts formatted as `amounts`, and those
+/// of all of them on a last row with the client `total`.
+pub fn write_report<W: Write>(
+    by_client: &[(ClientId, LossTotals)],
+    total: &LossTotals,
+    amounts: &AmountFormat,
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    let rows = by_client
+        .iter()
+        .map(|(client, totals)| (client.to_string(), totals))
+        .chain([(String::from("total"), total)]);
+    for (client, totals) in rows {
+        wtr.serialize(LossRow {
+            client,
+            chargebacks: totals.chargebacks,
+            charged_back: amounts.format(totals.charged_back),
+            fees: amounts.format(totals.fees),
+            reversals: totals.reversals,
+            recovered: amounts.format(totals.recovered),
+            net_loss: amounts.format(totals.net()),
+        })?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_report;
+    use crate::accounts::AmountFormat;
+    use crate::amount::amount;
+    use crate::config::EngineConfig;
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_losses() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 10.0\n\
+                     deposit, 1, 2, 5.0\n\
+                     dispute, 1, 1,\n\
+                     chargeback, 1, 1,\n\
+                     chargeback_reversal, 1, 1,\n\
+                     dispute, 1, 2, 2.0\n\
+                     chargeback, 1, 2,\n\
+                     deposit, 2, 3, 4.0\n\
+                     dispute, 2, 3,\n\
+                     chargeback, 2, 3,\n\
+                     deposit, 3, 4, 1.0\n";
+        let config = EngineConfig::from_toml("[disputes]\nchargeback_fee = 15.0").unwrap();
+        let mut engine = PaymentsEngine::new().with_config(config);
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        assert_eq!(engine.loss_total().net(), amount(51.0));
+
+        let mut report = vec![];
+        write_report(
+            &engine.losses(),
+            &engine.loss_total(),
+            &AmountFormat::default(),
+            &mut report,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,chargebacks,charged_back,fees,reversals,recovered,net_loss\n\
+             1,2,12.0000,30.0000,1,10.0000,32.0000\n\
+             2,1,4.0000,15.0000,0,0.0000,19.0000\n\
+             total,3,16.0000,45.0000,1,10.0000,51.0000\n"
+        );
+    }
+}
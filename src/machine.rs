@@ -0,0 +1,656 @@
+//! The state machine of the accounts: what a transaction does to the balance of its client,
+//! given the deposit or withdrawal it refers to, without any store, IO or clock.
+//!
+//! [`decide`] checks a transaction and returns its ledger entry with the [`Effect`] the store of
+//! the past transactions has to record, and [`apply`] posts the entry to the balance. The
+//! [`PaymentsEngine`](crate::engine::PaymentsEngine) wraps both with its stores, limits and
+//! logs, and embedded targets that build without the `std` feature can call them directly.
+
+use alloc::string::{String, ToString};
+use alloc::vec;
+use core::error::Error;
+use core::fmt;
+use core::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::amount::AmountValue;
+use crate::balance::{AccountBalance, AccountStatus};
+use crate::ledger::{self, JournalEntry, LedgerAccount, Posting};
+use crate::reasons::{RejectionReason, TransactionErrorType, TransactionRecordError};
+use crate::transactions::{Amount, ClientId, Transaction, TransactionType, TxId};
+
+// The number of seconds in a day, as in `dates`, which needs `std`
+const SECS_PER_DAY: u64 = 86_400;
+
+/// Where a deposit or withdrawal stands, as far as the transactions referring to it go.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TxStatus {
+    /// Its funds moved, and nothing refers to it.
+    Settled,
+    /// The deposit hasn't settled yet.
+    Pending,
+    /// The deposit is disputed, with `held` of it held.
+    Disputed { held: Amount },
+    /// The deposit was charged back, and the chargeback wasn't reversed. Its dispute stays open.
+    ChargedBack { held: Amount },
+    /// It was undone by a reversal.
+    Reversed,
+}
+
+impl TxStatus {
+    /// The amount held by the dispute of the deposit, if it is disputed.
+    pub fn held(&self) -> Option<Amount> {
+        match self {
+            TxStatus::Disputed { held } | TxStatus::ChargedBack { held } => Some(*held),
+            _ => None,
+        }
+    }
+
+    /// The name of the status, as the stores keep it, without the amount held.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TxStatus::Settled => "settled",
+            TxStatus::Pending => "pending",
+            TxStatus::Disputed { .. } => "disputed",
+            TxStatus::ChargedBack { .. } => "charged_back",
+            TxStatus::Reversed => "reversed",
+        }
+    }
+
+    /// The status of this [`name`](TxStatus::name), holding `held` if it is disputed.
+    pub fn from_name(name: &str, held: Amount) -> Option<Self> {
+        match name {
+            "settled" => Some(TxStatus::Settled),
+            "pending" => Some(TxStatus::Pending),
+            "disputed" => Some(TxStatus::Disputed { held }),
+            "charged_back" => Some(TxStatus::ChargedBack { held }),
+            "reversed" => Some(TxStatus::Reversed),
+            _ => None,
+        }
+    }
+}
+
+/// A deposit or withdrawal recorded by a [`TxStore`](crate::store::TxStore), with where it
+/// stands, see [`TxStore::recorded`](crate::store::TxStore::recorded). The store keeps it as
+/// one value, which [`Effect::recorded`] updates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedTx {
+    pub tx_id: TxId,
+    /// The client it belongs to, unless it was restored from a snapshot without the clients.
+    pub client_id: Option<ClientId>,
+    /// Either [`TransactionType::Deposit`] or [`TransactionType::Withdrawal`].
+    pub tx_type: TransactionType,
+    pub amount: Amount,
+    pub status: TxStatus,
+}
+
+/// What a dispute of more than the available funds does, e.g. of a deposit that was already
+/// withdrawn. The disputes applied anyway are counted in
+/// [`ProcessingStats::disputes_over_available`](crate::engine::ProcessingStats::disputes_over_available).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum OverAvailable {
+    /// Holds the whole amount, driving the available funds negative.
+    #[default]
+    Allow,
+    /// Only holds what is available, and resolves or charges back that.
+    Cap,
+    /// Rejects the dispute as `INSUFFICIENT_FUNDS`.
+    Reject,
+}
+
+impl TryFrom<String> for OverAvailable {
+    type Error = OverAvailableFromStrError;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Debug)]
+pub struct OverAvailableFromStrError(String);
+
+impl Error for OverAvailableFromStrError {}
+
+impl fmt::Display for OverAvailableFromStrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid dispute policy `{}`, expected `allow`, `cap` or `reject`",
+            self.0
+        )
+    }
+}
+
+impl FromStr for OverAvailable {
+    type Err = OverAvailableFromStrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(OverAvailable::Allow),
+            "cap" => Ok(OverAvailable::Cap),
+            "reject" => Ok(OverAvailable::Reject),
+            _ => Err(OverAvailableFromStrError(s.to_string())),
+        }
+    }
+}
+
+/// The parts of the configuration that change what a transaction does to the balance, see
+/// [`EngineConfig::rules`](crate::config::EngineConfig::rules).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rules {
+    /// Whether adjustments, freezes and unfreezes are allowed.
+    pub admin_ops: bool,
+    /// What a dispute of more than the available funds does.
+    pub over_available: OverAvailable,
+    /// Whether a `chargeback_reversal` unlocks the account locked by the chargeback.
+    pub unlock_on_chargeback_reversal: bool,
+    /// For how many days the funds of the deposits with a `timestamp` are pending, if at all.
+    pub deposit_hold_days: Option<u32>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            admin_ops: false,
+            over_available: OverAvailable::default(),
+            unlock_on_chargeback_reversal: true,
+            deposit_hold_days: None,
+        }
+    }
+}
+
+/// What the store of the past transactions records for a transaction that is applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    /// Nothing, the transaction doesn't refer to any other.
+    None,
+    /// A new deposit of the client, pending until `settles_at` if it is set.
+    RecordDeposit { amount: Amount, settles_at: Option<u64> },
+    /// A new withdrawal of the client.
+    RecordWithdrawal { amount: Amount },
+    /// The deposit is disputed, holding `held`, more than the available funds if `over_available`.
+    Dispute { held: Amount, over_available: bool },
+    /// The dispute of the deposit is resolved.
+    Resolve,
+    /// The deposit is charged back, its dispute staying open.
+    Chargeback,
+    /// The chargeback of the deposit is reversed, closing its dispute.
+    ReverseChargeback,
+    /// The deposit or withdrawal is reversed.
+    Reverse,
+    /// The deposit is settled, before its settlement date.
+    Settle,
+}
+
+impl Effect {
+    /// The deposit or withdrawal of `transaction` as the effect leaves it, given the one
+    /// `recorded` before, or `None` if there is nothing to record.
+    pub fn recorded(&self, transaction: &Transaction, recorded: Option<&RecordedTx>) -> Option<RecordedTx> {
+        let new = |tx_type, amount, status| RecordedTx {
+            tx_id: transaction.tx_id,
+            client_id: Some(transaction.client_id),
+            tx_type,
+            amount,
+            status,
+        };
+        let status = match *self {
+            Effect::None => return None,
+            Effect::RecordDeposit { amount, settles_at } => {
+                let status = if settles_at.is_some() {
+                    TxStatus::Pending
+                } else {
+                    TxStatus::Settled
+                };
+                return Some(new(TransactionType::Deposit, amount, status));
+            }
+            Effect::RecordWithdrawal { amount } => {
+                return Some(new(TransactionType::Withdrawal, amount, TxStatus::Settled))
+            }
+            Effect::Dispute { held, .. } => TxStatus::Disputed { held },
+            Effect::Resolve | Effect::ReverseChargeback | Effect::Settle => TxStatus::Settled,
+            Effect::Chargeback => TxStatus::ChargedBack {
+                held: recorded?.status.held()?,
+            },
+            Effect::Reverse => TxStatus::Reversed,
+        };
+        Some(RecordedTx {
+            status,
+            ..recorded?.clone()
+        })
+    }
+}
+
+/// What [`decide`] made of a valid transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    pub entry: JournalEntry,
+    pub effect: Effect,
+}
+
+/// Checks a transaction against the balance of its client and the deposit or withdrawal of the
+/// same id, `recorded`, returning its ledger entry with `fee` charged, or why it is ignored.
+/// Only malformed transactions return an error.
+pub fn decide(
+    account_balance: &AccountBalance,
+    transaction: &Transaction,
+    recorded: Option<&RecordedTx>,
+    fee: Amount,
+    rules: &Rules,
+) -> Result<Result<Decision, RejectionReason>, TransactionRecordError> {
+    let frozen = match account_balance.status {
+        AccountStatus::Active => false,
+        AccountStatus::Frozen => true,
+        // Only the reversal of the chargeback that locked it can still be applied
+        AccountStatus::Locked if transaction.tx_type == TransactionType::ChargebackReversal => false,
+        AccountStatus::Locked => return Ok(Err(RejectionReason::AccountLocked)),
+        AccountStatus::Closed => return Ok(Err(RejectionReason::AccountClosed)),
+    };
+
+    let client = transaction.client_id;
+    let refers_to_transaction = matches!(
+        transaction.tx_type,
+        TransactionType::Dispute
+            | TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::ChargebackReversal
+            | TransactionType::Reversal
+            | TransactionType::Settle
+    );
+    if refers_to_transaction && recorded.is_some_and(|recorded| recorded.client_id.is_some_and(|owner| owner != client))
+    {
+        // The transaction it refers to is of another client, error from the partner
+        return Ok(Err(RejectionReason::ClientMismatch));
+    }
+    let entry =
+        |from, to, amount| JournalEntry::single(transaction.tx_type, client, transaction.tx_id, from, to, amount);
+
+    let with_fee = |mut entry: JournalEntry| {
+        if fee > Amount::ZERO {
+            entry.postings.push(Posting {
+                from: LedgerAccount::Available(client),
+                to: LedgerAccount::Fees,
+                amount: fee,
+            });
+        }
+        entry
+    };
+    // The balance the entry leaves, unless one of its amounts would be out of range
+    let posted = |entry: &JournalEntry| ledger::posted(account_balance, entry);
+
+    let mut effect = Effect::None;
+    let entry = match transaction.tx_type {
+        TransactionType::Deposit => {
+            // Handle a deposit
+            if recorded.is_some() {
+                // Another deposit or withdrawal has the same id, error from the partner
+                return Ok(Err(RejectionReason::Duplicate));
+            }
+            if let Some(amount) = transaction.amount {
+                // Deposits that settle later are pending until then, by default for the hold period
+                let hold_until = rules
+                    .deposit_hold_days
+                    .zip(transaction.timestamp)
+                    .map(|(days, timestamp)| timestamp.saturating_add(u64::from(days) * SECS_PER_DAY));
+                let settles_at = transaction
+                    .settles_at
+                    .or(hold_until)
+                    .filter(|&settles_at| transaction.timestamp.is_none_or(|timestamp| timestamp < settles_at));
+                let to = match settles_at {
+                    Some(_) => LedgerAccount::Pending(client),
+                    None => LedgerAccount::Available(client),
+                };
+                let entry = with_fee(entry(LedgerAccount::External, to, amount));
+                if posted(&entry).is_some_and(|posted| posted.available < Amount::ZERO) {
+                    // Insuficient funds for the fee, ignore
+                    return Ok(Err(RejectionReason::InsufficientFunds));
+                }
+                effect = Effect::RecordDeposit { amount, settles_at };
+                entry
+            } else {
+                return Err(TransactionRecordError {
+                    error_type: TransactionErrorType::NoDepositAmount,
+                });
+            }
+        }
+        TransactionType::Withdrawal => {
+            // Handle an withdrawal
+            if recorded.is_some() {
+                // Another deposit or withdrawal has the same id, error from the partner
+                return Ok(Err(RejectionReason::Duplicate));
+            }
+            if let Some(amount) = transaction.amount {
+                if frozen {
+                    // Frozen funds can't leave the account
+                    return Ok(Err(RejectionReason::AccountFrozen));
+                }
+                let entry = with_fee(entry(LedgerAccount::Available(client), LedgerAccount::External, amount));
+                if posted(&entry).is_some_and(|posted| posted.available < Amount::ZERO) {
+                    // Insuficient funds, ignore
+                    return Ok(Err(RejectionReason::InsufficientFunds));
+                }
+                effect = Effect::RecordWithdrawal { amount };
+                entry
+            } else {
+                return Err(TransactionRecordError {
+                    error_type: TransactionErrorType::NoWithdrawalAmount,
+                });
+            }
+        }
+        TransactionType::Dispute => {
+            // Handle a dispute
+            // Get the amount from the deposit transaction
+            let Some(deposit) = recorded.filter(|recorded| recorded.tx_type == TransactionType::Deposit) else {
+                // Transaction not found, or not a deposit, error from the partner
+                return Ok(Err(RejectionReason::UnknownTransaction));
+            };
+            let deposit_amount = deposit.amount;
+            // Only part of the deposit is disputed if the dispute has an amount
+            let amount = match transaction.amount {
+                None => deposit_amount,
+                Some(amount) if amount > Amount::ZERO => amount,
+                Some(_) => {
+                    return Err(TransactionRecordError {
+                        error_type: TransactionErrorType::InvalidDisputeAmount,
+                    })
+                }
+            };
+            if amount > deposit_amount {
+                return Ok(Err(RejectionReason::ExceedsDeposit));
+            }
+            match deposit.status {
+                TxStatus::Settled => {}
+                // The deposit was already undone, there is nothing to dispute
+                TxStatus::Reversed => return Ok(Err(RejectionReason::AlreadyReversed)),
+                // The funds haven't settled yet, there is nothing to hold
+                TxStatus::Pending => return Ok(Err(RejectionReason::NotSettled)),
+                // Only one dispute at a time
+                TxStatus::Disputed { .. } | TxStatus::ChargedBack { .. } => return Ok(Err(RejectionReason::Disputed)),
+            }
+            // The funds may already be gone, e.g. withdrawn
+            let over_available = amount > account_balance.available;
+            let amount = if over_available {
+                match rules.over_available {
+                    OverAvailable::Allow => amount,
+                    OverAvailable::Cap => account_balance.available.max(Amount::ZERO),
+                    OverAvailable::Reject => return Ok(Err(RejectionReason::InsufficientFunds)),
+                }
+            } else {
+                amount
+            };
+            effect = Effect::Dispute {
+                held: amount,
+                over_available,
+            };
+            entry(LedgerAccount::Available(client), LedgerAccount::Held(client), amount)
+        }
+        TransactionType::Resolve => {
+            // Handle a dispute resolution
+            // Get the amount held by the dispute
+            let Some(amount) = recorded.and_then(|recorded| recorded.status.held()) else {
+                // Invalid resolution, transaction isn't disputed
+                return Ok(Err(RejectionReason::NotDisputed));
+            };
+            effect = Effect::Resolve;
+            entry(LedgerAccount::Held(client), LedgerAccount::Available(client), amount)
+        }
+        TransactionType::Chargeback => {
+            // Handle a chargeback
+            // Get the amount held by the dispute
+            let Some(amount) = recorded.and_then(|recorded| recorded.status.held()) else {
+                // Invalid resolution, transaction isn't disputed
+                return Ok(Err(RejectionReason::NotDisputed));
+            };
+            // The dispute stays open, with the amount charged back, until it is reversed
+            effect = Effect::Chargeback;
+            entry(LedgerAccount::Held(client), LedgerAccount::Chargebacks, amount)
+        }
+        TransactionType::ChargebackReversal => {
+            // Handle the reversal of a chargeback won on appeal
+            let Some(deposit) = recorded.filter(|recorded| recorded.tx_type == TransactionType::Deposit) else {
+                // Transaction not found, or not a deposit, error from the partner
+                return Ok(Err(RejectionReason::UnknownTransaction));
+            };
+            // The amount charged back is the one held by the dispute
+            let TxStatus::ChargedBack { held: amount } = deposit.status else {
+                // Nothing to reverse, or already reversed
+                return Ok(Err(RejectionReason::NotChargedBack));
+            };
+            effect = Effect::ReverseChargeback;
+            entry(LedgerAccount::Chargebacks, LedgerAccount::Available(client), amount)
+        }
+        TransactionType::Reversal => {
+            // Handle a reversal of a deposit or a withdrawal
+            let Some(reversed) = recorded else {
+                // Transaction not found, error from the partner
+                return Ok(Err(RejectionReason::UnknownTransaction));
+            };
+            match reversed.status {
+                TxStatus::Settled => {}
+                // Already reversed
+                TxStatus::Reversed => return Ok(Err(RejectionReason::AlreadyReversed)),
+                // The funds haven't settled yet
+                TxStatus::Pending => return Ok(Err(RejectionReason::NotSettled)),
+                // The funds are held by a dispute
+                TxStatus::Disputed { .. } | TxStatus::ChargedBack { .. } => return Ok(Err(RejectionReason::Disputed)),
+            }
+            let amount = reversed.amount;
+            if reversed.tx_type == TransactionType::Deposit {
+                if account_balance.available < amount {
+                    // The funds were already used
+                    return Ok(Err(RejectionReason::InsufficientFunds));
+                }
+                effect = Effect::Reverse;
+                entry(LedgerAccount::Available(client), LedgerAccount::External, amount)
+            } else {
+                effect = Effect::Reverse;
+                entry(LedgerAccount::External, LedgerAccount::Available(client), amount)
+            }
+        }
+        TransactionType::Settle => {
+            // Handle the settlement of a deposit, e.g. once the bank confirmed the transfer
+            let Some(deposit) = recorded.filter(|recorded| recorded.tx_type == TransactionType::Deposit) else {
+                // Transaction not found, or not a deposit, error from the partner
+                return Ok(Err(RejectionReason::UnknownTransaction));
+            };
+            if deposit.status != TxStatus::Pending {
+                // Settled already, or never pending
+                return Ok(Err(RejectionReason::NotPending));
+            }
+            effect = Effect::Settle;
+            entry(
+                LedgerAccount::Pending(client),
+                LedgerAccount::Available(client),
+                deposit.amount,
+            )
+        }
+        TransactionType::Adjustment => {
+            // Handle a manual correction, by operations
+            if !rules.admin_ops {
+                return Err(TransactionRecordError {
+                    error_type: TransactionErrorType::AdjustmentNotAllowed,
+                });
+            }
+            let Some(amount) = transaction.amount else {
+                return Err(TransactionRecordError {
+                    error_type: TransactionErrorType::NoAdjustmentAmount,
+                });
+            };
+            let Some(reason) = transaction.reason.as_ref().filter(|reason| !reason.is_empty()) else {
+                return Err(TransactionRecordError {
+                    error_type: TransactionErrorType::NoAdjustmentReason,
+                });
+            };
+            let (from, to) = if amount >= Amount::ZERO {
+                (LedgerAccount::Adjustments, LedgerAccount::Available(client))
+            } else {
+                (LedgerAccount::Available(client), LedgerAccount::Adjustments)
+            };
+            let entry = JournalEntry {
+                reason: Some(reason.clone()),
+                ..entry(from, to, amount.abs())
+            };
+            if posted(&entry).is_some_and(|posted| posted.available < Amount::ZERO) {
+                // Insuficient funds, ignore
+                return Ok(Err(RejectionReason::InsufficientFunds));
+            }
+            entry
+        }
+        TransactionType::Fee => {
+            // Handle a fee charged by the partner
+            let Some(amount) = transaction.amount else {
+                return Err(TransactionRecordError {
+                    error_type: TransactionErrorType::NoFeeAmount,
+                });
+            };
+            if account_balance.available < amount {
+                // Insuficient funds, ignore
+                return Ok(Err(RejectionReason::InsufficientFunds));
+            }
+            entry(LedgerAccount::Available(client), LedgerAccount::Fees, amount)
+        }
+        TransactionType::Close => {
+            // Handle the closure of the account
+            if frozen {
+                return Ok(Err(RejectionReason::AccountFrozen));
+            }
+            if account_balance.held != Amount::ZERO
+                || account_balance.pending != Amount::ZERO
+                || account_balance.available < Amount::ZERO
+            {
+                // Funds held by disputes, not settled yet or owed, can't close
+                return Ok(Err(RejectionReason::OutstandingFunds));
+            }
+            // The remaining funds are paid out
+            entry(
+                LedgerAccount::Available(client),
+                LedgerAccount::Payouts,
+                account_balance.available,
+            )
+        }
+        TransactionType::Freeze | TransactionType::Unfreeze => {
+            // Handle a change of the status by operations, which doesn't move any funds
+            if !rules.admin_ops {
+                return Err(TransactionRecordError {
+                    error_type: TransactionErrorType::StatusChangeNotAllowed,
+                });
+            }
+            if frozen == (transaction.tx_type == TransactionType::Freeze) {
+                // Already frozen, or not frozen
+                return Ok(Err(RejectionReason::StatusUnchanged));
+            }
+            JournalEntry {
+                tx_type: transaction.tx_type,
+                client_id: client,
+                tx_id: transaction.tx_id,
+                postings: vec![],
+                reason: None,
+            }
+        }
+        TransactionType::Interest => {
+            // Interest is only ever posted by the engine itself
+            return Ok(Err(RejectionReason::ReservedType));
+        }
+    };
+    if posted(&entry).is_none() {
+        // The funds would be out of the range of the amounts, e.g. of an i64 of minor units
+        return Ok(Err(RejectionReason::AmountOverflow));
+    }
+
+    Ok(Ok(Decision { entry, effect }))
+}
+
+/// Posts the entry of a transaction decided on to the balance, and changes the status of the
+/// account if the transaction does.
+pub fn apply(account_balance: &mut AccountBalance, entry: &JournalEntry, rules: &Rules) {
+    ledger::post(account_balance, entry);
+    match entry.tx_type {
+        TransactionType::Chargeback => account_balance.status = AccountStatus::Locked,
+        TransactionType::ChargebackReversal
+            if rules.unlock_on_chargeback_reversal && account_balance.status == AccountStatus::Locked =>
+        {
+            account_balance.status = AccountStatus::Active
+        }
+        TransactionType::Close => account_balance.status = AccountStatus::Closed,
+        TransactionType::Freeze => account_balance.status = AccountStatus::Frozen,
+        TransactionType::Unfreeze => account_balance.status = AccountStatus::Active,
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, decide, Effect, RecordedTx, Rules, TxStatus};
+    use crate::amount::amount;
+    use crate::balance::{AccountBalance, AccountStatus};
+    use crate::reasons::RejectionReason;
+    use crate::transactions::{Transaction, TransactionType};
+
+    #[test]
+    fn test_state_machine() {
+        let transaction = |tx_type, amount| Transaction {
+            tx_type,
+            client_id: 1,
+            tx_id: 1,
+            amount,
+            reason: None,
+            timestamp: None,
+            settles_at: None,
+        };
+        let rules = Rules::default();
+        let mut account_balance = AccountBalance::new(1);
+
+        let deposit = transaction(TransactionType::Deposit, Some(amount(10.0)));
+        let decision = decide(&account_balance, &deposit, None, amount(0.0), &rules)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            decision.effect,
+            Effect::RecordDeposit {
+                amount: amount(10.0),
+                settles_at: None
+            }
+        );
+        apply(&mut account_balance, &decision.entry, &rules);
+        assert_eq!(account_balance.available, amount(10.0));
+
+        // The caller keeps where the deposit stands
+        let recorded = decision.effect.recorded(&deposit, None).unwrap();
+        let expected = RecordedTx {
+            tx_id: 1,
+            client_id: Some(1),
+            tx_type: TransactionType::Deposit,
+            amount: amount(10.0),
+            status: TxStatus::Settled,
+        };
+        assert_eq!(recorded, expected);
+        let rejected = decide(&account_balance, &deposit, Some(&recorded), amount(0.0), &rules).unwrap();
+        assert_eq!(rejected.unwrap_err(), RejectionReason::Duplicate);
+
+        let dispute = transaction(TransactionType::Dispute, None);
+        let decision = decide(&account_balance, &dispute, Some(&recorded), amount(0.0), &rules)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            decision.effect,
+            Effect::Dispute {
+                held: amount(10.0),
+                over_available: false
+            }
+        );
+        apply(&mut account_balance, &decision.entry, &rules);
+        let recorded = decision.effect.recorded(&dispute, Some(&recorded)).unwrap();
+        assert_eq!(recorded.status, TxStatus::Disputed { held: amount(10.0) });
+
+        let chargeback = transaction(TransactionType::Chargeback, None);
+        let decision = decide(&account_balance, &chargeback, Some(&recorded), amount(0.0), &rules)
+            .unwrap()
+            .unwrap();
+        apply(&mut account_balance, &decision.entry, &rules);
+        assert_eq!(
+            (account_balance.held, account_balance.status),
+            (amount(0.0), AccountStatus::Locked)
+        );
+        let recorded = decision.effect.recorded(&chargeback, Some(&recorded)).unwrap();
+        assert_eq!(recorded.status, TxStatus::ChargedBack { held: amount(10.0) });
+    }
+}
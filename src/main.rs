@@ -1,258 +1,2041 @@
-mod transactions;
-mod accounts;
-mod custom_errors;
-
-use std::collections::HashMap;
-use std::collections::btree_map::{BTreeMap, Entry};
-use std::env;
+#[cfg(feature = "bench")]
+use std::alloc::{GlobalAlloc, Layout, System};
 use std::error::Error;
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, IsTerminal, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
 use std::process;
-use transactions::{Transaction, TransactionType};
-use accounts::AccountBalance;
-use custom_errors::{TransactionRecordError, TransactionErrorType};
-
-/// Takes the path to a CSV file with transactions and outputs 
-/// the account balances.
-fn process_csv(path: &Path) -> Result<String, Box<dyn Error>> {
-    // We keep a map of the account balances throughout the whole execution of the program.
-    // This is because we can get an update to a given client balance all the way to the last
-    // transaction, and we only want to output the results once, at the end.
-    // We use a BTreeMap because we want to display sorted results.
-    let mut account_balances: BTreeMap<u16, AccountBalance> = BTreeMap::new();
-
-    // We hold a record of the deposit transaction amounts, so that we can process disputes
-    let mut deposit_transaction_amounts: HashMap<u32, f32> = HashMap::new();
-    // We hold a record of the disputed transactions, since resolves and chargebacks are only valid for those
-    let mut disputed_transactions: Vec<u32> = vec![];
-
-    // Setup a reader from the given path to a CSV file.
-    let mut rdr = csv::ReaderBuilder::new()
-        .delimiter(b',')
-        .trim(csv::Trim::All)
-        .flexible(true)
-        .from_path(path)?;
-
-    for transaction_record in rdr.deserialize() {
-        let transaction: Transaction = transaction_record?;
-
-        let account_balance = match account_balances.entry(transaction.client_id) {
-            // If the client already exists, get its balance
-            Entry::Occupied(e) => e.into_mut(),
-            // If the client doesn't exist, insert it and get a new balance
-            Entry::Vacant(e) => {
-                e.insert(AccountBalance {
-                    client: transaction.client_id,
-                    available: 0.0,
-                    held: 0.0,
-                    locked: false,
-                })
-            }
-        };
+#[cfg(feature = "bench")]
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use payments_engine::accounts::{
+    format_report_columns, format_report_json_with, format_report_row, sort_balances, write_report_columns,
+    AccountBalance, AmountFormat, AmountStyle, ReportColumn, Rounding, SortKey, REPORT_COLUMNS,
+};
+use payments_engine::activity::{self, Bucket};
+use payments_engine::aml;
+use payments_engine::client_filter::ClientFilter;
+use payments_engine::clients::ClientDirectory;
+use payments_engine::compact_store::CompactTxStore;
+use payments_engine::config::{self, EngineConfig, FeeRule, OverAvailable};
+use payments_engine::csv_input::{AmountLocale, ColumnMap, CsvParser, Delimiter};
+use payments_engine::custom_errors::{self, FailureKind, FileError, InputSourceError, InputSourceErrorType, LineError};
+#[cfg(feature = "tui")]
+use payments_engine::dashboard::Dashboard;
+#[cfg(feature = "decrypt")]
+use payments_engine::decrypt::{AgeIdentity, Decryption};
+use payments_engine::export::{self, AccountingFormat};
+#[cfg(feature = "grpc")]
+use payments_engine::grpc::{self, PaymentsEngineService};
+#[cfg(feature = "mmap")]
+use payments_engine::input::InputIo;
+use payments_engine::input::{self, InputFormat};
+use payments_engine::interest::{InterestConfig, InterestPeriod};
+use payments_engine::output::{self, AtomicFile, OutputSplit};
+use payments_engine::replica::Replica;
+use payments_engine::shard::{Shard, ShardMethod};
+#[cfg(feature = "signatures")]
+use payments_engine::signatures::SignatureVerifier;
+use payments_engine::spill_store::{SpillTxStore, DEFAULT_HOT_TRANSACTIONS};
+#[cfg(feature = "grpc")]
+use payments_engine::store::{IdempotencyStore, MemoryIdempotencyStore};
+use payments_engine::store::{MemoryAccountStore, MemoryDedupStore};
+use payments_engine::tenants::{self, TenantEngines};
+use payments_engine::transactions::{Amount, ClientId};
+use payments_engine::{
+    bench, diff, dispute_expiry, dispute_graph, event_log, file_stats, inspect, losses, merge, reconcile, rejections,
+    repl, segments, snapshot, verify,
+};
+use payments_engine::{PaymentsEngine, ProcessingReport};
+use serde::{Deserialize, Serialize};
+
+/// The allocator of the binary with the `bench` feature, counting the allocations for `bench`.
+#[cfg(feature = "bench")]
+struct CountingAllocator;
+
+#[cfg(feature = "bench")]
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "bench")]
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[cfg(feature = "bench")]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// The allocations so far, if they are counted, with the `bench` feature.
+fn allocations() -> Option<u64> {
+    #[cfg(feature = "bench")]
+    return Some(ALLOCATIONS.load(Ordering::Relaxed));
+    #[cfg(not(feature = "bench"))]
+    None
+}
+
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0  success
+  1  internal error
+  2  bad arguments
+  3  IO error
+  4  parse error
+  5  semantic error
+  6  reconcile, replay, verify or verify-audit found mismatches
+  7  stopped by SIGINT, SIGTERM or --max-duration, the report is of the input until the line of the checkpoint
+
+On failure, a JSON object with the `code`, `message`, `line` and `file` of the error is printed to stderr.";
+
+/// The exit code of `reconcile`, `replay` and `verify` when some balances don't match, and of `verify-audit`
+/// when the last event isn't the expected one.
+const EXIT_MISMATCHES: i32 = 6;
+
+/// The exit code of a run stopped by SIGINT, SIGTERM or `--max-duration`, after writing the report
+/// of what it read.
+const EXIT_INTERRUPTED: i32 = 7;
+
+/// A toy payments engine.
+/// Takes a CSV with transactions and outputs account balances.
+#[derive(Debug, Parser)]
+#[command(version, about, subcommand_negates_reqs = true, after_help = EXIT_CODES_HELP)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to the file with the transactions, or `-` to read them from stdin.
+    /// With the `http` and `s3` features, this can also be a `https://` or `s3://bucket/key` URL
+    #[arg(required = true, env = "PAYMENTS_ENGINE_INPUT")]
+    input: Option<String>,
+
+    /// Keep reading rows as they are appended to the input file, like `tail -f`,
+    /// and print the balances again whenever they change
+    #[arg(long)]
+    follow: bool,
+
+    /// In follow mode, how often to check if the balances changed, in seconds
+    #[arg(long, value_name = "SECS", default_value_t = 10, requires = "follow")]
+    report_interval: u64,
+
+    /// In follow or serve mode, show a live dashboard instead of printing the balances
+    #[cfg(feature = "tui")]
+    #[arg(long, global = true)]
+    dashboard: bool,
+
+    /// Keep the accounts, transactions and disputes of each value of the `tenant` column of the
+    /// CSV input apart, as if each tenant had an engine of its own, and report them by tenant
+    #[arg(
+        long,
+        conflicts_with_all = ["follow", "output_split", "output_format", "aml_report", "rejection_report", "segment_report", "emit_dispute_graph", "event_log"]
+    )]
+    #[cfg_attr(feature = "sqlite", arg(conflicts_with = "sqlite"))]
+    #[cfg_attr(feature = "redis", arg(conflicts_with = "redis"))]
+    tenants: bool,
+
+    /// Write the row of each account to stdout as soon as it is final instead of keeping all the
+    /// accounts until the end of the input, which must be in time order with no client having a
+    /// transaction more than this many seconds after its previous one, going by the `timestamp` column
+    #[arg(
+        long,
+        value_name = "SECS",
+        conflicts_with_all = ["follow", "tenants", "output", "output_split", "output_format", "sort_by", "desc", "append_summary", "segment_report"]
+    )]
+    close_after: Option<u64>,
+
+    /// Where to write the checkpoint of a run stopped by SIGINT, SIGTERM or `--max-duration`, a JSON
+    /// object with the last line of the input it read, and its state in `FILE.state`, to `--resume`
+    /// from. Otherwise the checkpoint is only printed to stderr
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["follow", "tenants", "close_after"])]
+    checkpoint: Option<PathBuf>,
+
+    /// Pick up a run stopped before the end of the same input from its `--checkpoint`, restoring
+    /// its state and skipping the lines it read
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["follow", "tenants", "close_after"])]
+    resume: Option<PathBuf>,
+
+    /// Stop reading the input after this long, e.g. `55m`, `90s` or `1h30m`, to fit a batch window,
+    /// writing the report of what was read and the checkpoint as when interrupted
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, conflicts_with_all = ["follow", "tenants", "close_after"])]
+    max_duration: Option<Duration>,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+
+    #[command(flatten)]
+    output: OutputArgs,
+}
+
+/// Where and how to write the report.
+#[derive(Debug, clap::Args)]
+struct OutputArgs {
+    /// Write the report to this file instead of stdout,
+    /// or to this directory when splitting the report
+    #[arg(long, value_name = "PATH", env = "PAYMENTS_ENGINE_OUTPUT", global = true)]
+    output: Option<PathBuf>,
+
+    /// Split the report into several files, by `client-range:<SIZE>` or into `shards:<COUNT>`,
+    /// with an index file listing them
+    #[arg(
+        long,
+        value_name = "SPLIT",
+        requires = "output",
+        env = "PAYMENTS_ENGINE_OUTPUT_SPLIT",
+        global = true
+    )]
+    output_split: Option<OutputSplit>,
+
+    /// Only report these clients, e.g. `1,2,100-200`.
+    /// All the input is still processed, since any transaction can affect a dispute
+    #[arg(long, value_name = "IDS", env = "PAYMENTS_ENGINE_CLIENTS", global = true)]
+    clients: Option<ClientFilter>,
+
+    /// Sort the report by this column instead of by client id, e.g. `held` with `--desc`
+    /// for the largest held balances first
+    #[arg(
+        long,
+        value_enum,
+        value_name = "COLUMN",
+        env = "PAYMENTS_ENGINE_SORT_BY",
+        global = true
+    )]
+    sort_by: Option<SortBy>,
+
+    /// Sort the report in descending order
+    #[arg(long, global = true)]
+    desc: bool,
+
+    /// Only write these columns of the report, in this order, e.g. `client,held,total`
+    #[arg(
+        long,
+        value_enum,
+        value_name = "COLUMNS",
+        value_delimiter = ',',
+        conflicts_with = "output_split",
+        env = "PAYMENTS_ENGINE_COLUMNS",
+        global = true
+    )]
+    columns: Vec<Column>,
+
+    /// Write the report as CSV or as a JSON array
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value_t = ReportFormat::Csv,
+        conflicts_with_all = ["output_split", "columns"],
+        env = "PAYMENTS_ENGINE_OUTPUT_FORMAT",
+        global = true
+    )]
+    output_format: ReportFormat,
+
+    /// The number of decimal places of the amounts of the report, 4 by default
+    #[arg(long, value_name = "DIGITS", env = "PAYMENTS_ENGINE_PRECISION", global = true)]
+    precision: Option<usize>,
+
+    /// How the amounts are rounded to the `--precision`: to the nearest and the ties to an even digit,
+    /// or truncated toward zero
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        env = "PAYMENTS_ENGINE_ROUNDING",
+        global = true
+    )]
+    rounding: Option<RoundingMode>,
+
+    /// Write the amounts as decimals, or as integers of the smallest unit of the `--precision`,
+    /// e.g. `15000` basis points for `1.5000`, exact in a build with `fixed-amounts`
+    #[arg(
+        long,
+        value_enum,
+        value_name = "STYLE",
+        env = "PAYMENTS_ENGINE_AMOUNT_STYLE",
+        global = true
+    )]
+    amount_style: Option<AmountStyleArg>,
+
+    /// End the CSV report with a `#` line with the number of accounts reported, of transactions
+    /// processed and rejected, and the hash of the state, for downstream jobs to check it
+    #[arg(long, conflicts_with_all = ["output_split", "output_format"], global = true)]
+    append_summary: bool,
+
+    /// Write the amounts of the JSON report as strings, formatted as in the CSV one, instead of numbers,
+    /// for consumers that would parse them as floats
+    #[arg(long, global = true)]
+    json_strings: bool,
+
+    /// Look for suspicious activity, with the thresholds of the `[aml]` section of the `--config` file,
+    /// and write the findings to this CSV file
+    #[arg(long, value_name = "FILE", env = "PAYMENTS_ENGINE_AML_REPORT", global = true)]
+    aml_report: Option<PathBuf>,
+
+    /// Write the rejected transactions to this CSV file, with the line and reason code of each,
+    /// along with the records skipped with `--lenient`
+    #[arg(long, value_name = "FILE", env = "PAYMENTS_ENGINE_REJECTION_REPORT", global = true)]
+    rejection_report: Option<PathBuf>,
 
-        if account_balance.locked {
-            continue;
+    /// Write the balances and activity added up by the segment and region of the `--clients-file`
+    /// to this CSV file: the clients, their funds, the locked accounts, the amounts deposited and
+    /// withdrawn, and the disputes and chargebacks
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "clients_file",
+        env = "PAYMENTS_ENGINE_SEGMENT_REPORT",
+        global = true
+    )]
+    segment_report: Option<PathBuf>,
+
+    /// Write a GraphViz DOT graph of the disputes, resolves, chargebacks and their reversals to this file,
+    /// linking each to the transaction it refers to and to the clients
+    #[arg(long, value_name = "FILE", env = "PAYMENTS_ENGINE_DISPUTE_GRAPH", global = true)]
+    emit_dispute_graph: Option<PathBuf>,
+
+    /// Write every effect on the accounts to this NDJSON file as it happens, one timestamped event
+    /// per line: the postings, e.g. a deposit credited or a hold placed, the accounts locked,
+    /// and the rejections
+    #[arg(long, value_name = "FILE", env = "PAYMENTS_ENGINE_EVENT_LOG", global = true)]
+    event_log: Option<PathBuf>,
+}
+
+impl OutputArgs {
+    /// The balances of the clients to report.
+    fn balances(&self, mut balances: Vec<AccountBalance>) -> Vec<AccountBalance> {
+        if let Some(clients) = &self.clients {
+            balances.retain(|account_balance| clients.contains(account_balance.client));
+        }
+        if self.sort_by.is_some() || self.desc {
+            sort_balances(
+                &mut balances,
+                self.sort_by.map(SortKey::from).unwrap_or_default(),
+                self.desc,
+            );
+        }
+        balances
+    }
+
+    /// How the amounts are written, these options overriding the `[output]` settings.
+    fn amounts(&self, config: &EngineConfig) -> AmountFormat {
+        let mut amounts = config.output.clone();
+        if let Some(precision) = self.precision {
+            amounts.precision = precision;
+        }
+        if let Some(rounding) = self.rounding {
+            amounts.rounding = rounding.into();
         }
+        if let Some(style) = self.amount_style {
+            amounts.style = style.into();
+        }
+        amounts.json_strings |= self.json_strings;
+        amounts
+    }
 
-        match transaction.tx_type {
-            TransactionType::Deposit => {
-                // Handle a deposit
-                if let Some(amount) = transaction.amount {
-                    account_balance.available += amount;
-                    deposit_transaction_amounts.insert(transaction.tx_id, amount);
-                } else {
-                    return Err(Box::new(TransactionRecordError{ error_type: TransactionErrorType::NoDepositAmount}))
-                }
+    /// The report of the clients to report, with the metadata of the clients of the engine if there is any.
+    fn format_report(&self, balances: Vec<AccountBalance>, engine: &PaymentsEngine) -> String {
+        self.format_balances(&self.balances(balances), engine)
+    }
+
+    /// The columns of the report, in order.
+    fn report_columns(&self) -> Vec<ReportColumn> {
+        match self.columns.is_empty() {
+            true => REPORT_COLUMNS.to_vec(),
+            false => self.columns.iter().map(|&column| column.into()).collect(),
+        }
+    }
+
+    /// The report of these balances, already filtered and sorted.
+    fn format_balances(&self, balances: &[AccountBalance], engine: &PaymentsEngine) -> String {
+        let amounts = self.amounts(engine.config());
+        if self.output_format == ReportFormat::Json {
+            return format_report_json_with(balances, &amounts);
+        }
+        format_report_columns(balances, &self.report_columns(), engine.clients(), &amounts)
+    }
+
+    /// Writes the report of these balances, already filtered and sorted, and the summary line if
+    /// any. The CSV rows are written as they are formatted, flushing the writer along the way.
+    fn write_balances(
+        &self,
+        balances: &[AccountBalance],
+        engine: &PaymentsEngine,
+        summary: Option<&str>,
+        mut writer: impl Write,
+    ) -> Result<(), Box<dyn Error>> {
+        let amounts = self.amounts(engine.config());
+        match self.output_format {
+            ReportFormat::Json => writeln!(writer, "{}", format_report_json_with(balances, &amounts))?,
+            _ => write_report_columns(
+                balances,
+                &self.report_columns(),
+                engine.clients(),
+                &amounts,
+                &mut writer,
+            )?,
+        }
+        if let Some(summary) = summary {
+            writeln!(writer, "{}", summary)?;
+        }
+        Ok(writer.flush()?)
+    }
+
+    /// Writes the report of several tenants, with the stats of each in the summary.
+    fn write_tenant_report(&self, tenants: &TenantEngines) -> Result<(), Box<dyn Error>> {
+        let mut reports = vec![];
+        let mut summary = String::new();
+        for (tenant, engine) in tenants.iter() {
+            let report = engine.processing_report()?;
+            let balances = self.balances(report.balances);
+            let stats = &report.stats;
+            summary += &format!(
+                "\n# tenant: {}, accounts: {}, processed: {}, rejected: {}, state_hash: {}",
+                tenant,
+                balances.len(),
+                stats.processed,
+                stats.rejected,
+                report.state_hash
+            );
+            reports.push((tenant, balances));
+        }
+        let config = tenants
+            .iter()
+            .next()
+            .map(|(_, engine)| engine.config().clone())
+            .unwrap_or_default();
+        let mut output = tenants::format_report(&reports, &self.report_columns(), &self.amounts(&config));
+        if self.append_summary {
+            output += &summary;
+        }
+        match &self.output {
+            Some(path) => write_output(path, |file| Ok(writeln!(file, "{}", output)?))?,
+            None => print_report(&output)?,
+        }
+        Ok(())
+    }
+
+    fn write_report(&self, engine: &PaymentsEngine) -> Result<(), Box<dyn Error>> {
+        let report = engine.processing_report()?;
+        self.write_extras(engine, &report)?;
+        match (&self.output, self.output_split) {
+            (Some(dir), Some(split)) => {
+                let amounts = self.amounts(engine.config());
+                output::write_split_report(&self.balances(report.balances), engine.clients(), &amounts, split, dir)?;
             }
-            TransactionType::Withdrawal => {
-                // Handle an withdrawal
-                if let Some(amount) = transaction.amount {
-                    let new_balance = account_balance.available - amount;
-                    if new_balance >= 0.0 {
-                        account_balance.available = new_balance;
-                    } else {
-                        // Insuficient funds, ignore
-                        continue;
-                    }
-                } else {
-                    return Err(Box::new(TransactionRecordError{ error_type: TransactionErrorType::NoWithdrawalAmount}))
+            (path, None) => {
+                let balances = self.balances(report.balances);
+                let summary = self.append_summary.then(|| {
+                    let stats = &report.stats;
+                    format!(
+                        "# accounts: {}, processed: {}, rejected: {}, state_hash: {}",
+                        balances.len(),
+                        stats.processed,
+                        stats.rejected,
+                        report.state_hash
+                    )
+                });
+                match path {
+                    Some(path) => write_output(path, |file| {
+                        self.write_balances(&balances, engine, summary.as_deref(), file)
+                    })?,
+                    // Stdout is line buffered, flushed in blocks of rows instead
+                    None => self.write_balances(
+                        &balances,
+                        engine,
+                        summary.as_deref(),
+                        BufWriter::new(io::stdout().lock()),
+                    )?,
                 }
             }
-            TransactionType::Dispute => {
-                // Handle a dispute
-                // Get the amount from the deposit transaction
-                let amount = if let Some(&amount) = deposit_transaction_amounts.get(&transaction.tx_id) {
-                    amount
-                } else {
-                    // Transaction not found, error from the partner
-                    continue;
-                };
-                account_balance.available -= amount;
-                account_balance.held += amount;
-                disputed_transactions.push(transaction.tx_id);
-            }
-            TransactionType::Resolve => {
-                // Handle a dispute resolution
-                // Check if the transaction is disputed
-                if !disputed_transactions.contains(&transaction.tx_id) {
-                    // Invalid resolution, transaction isn't disputed
-                    continue;
-                }
+            (None, Some(_)) => unreachable!("--output-split requires --output"),
+        }
+        Ok(())
+    }
 
-                // Get the amount from the deposit transaction
-                let amount = if let Some(&amount) = deposit_transaction_amounts.get(&transaction.tx_id) {
-                    amount
-                } else {
-                    // Transaction not found, error from the partner
-                    continue;
-                };
-                account_balance.available += amount;
-                account_balance.held -= amount;
-            }
-            TransactionType::Chargeback => {
-                // Handle a chargeback
-                // Check if the transaction is disputed
-                if !disputed_transactions.contains(&transaction.tx_id) {
-                    // Invalid resolution, transaction isn't disputed
-                    continue;
-                }
+    /// Writes the other reports asked for, and notes on what the run did to stderr.
+    fn write_extras(&self, engine: &PaymentsEngine, report: &ProcessingReport) -> Result<(), Box<dyn Error>> {
+        if let Some(path) = &self.aml_report {
+            aml::write_findings(&engine.aml_findings(), File::create(path)?)?;
+        }
+        if let Some(path) = &self.rejection_report {
+            rejections::write_report(&report.rejections, &report.stats.malformed, File::create(path)?)?;
+        }
+        if let (Some(path), Some(clients)) = (&self.segment_report, engine.clients()) {
+            let totals = segments::aggregate(&report.balances, engine.journal(), clients);
+            segments::write_report(&totals, &self.amounts(engine.config()), File::create(path)?)?;
+        }
+        if let Some(path) = &self.emit_dispute_graph {
+            dispute_graph::write_dot(
+                engine.journal(),
+                engine.rejections(),
+                BufWriter::new(File::create(path)?),
+            )?;
+        }
+        let unknown_clients = &report.stats.unknown_clients;
+        if !unknown_clients.is_empty() {
+            let clients: Vec<String> = unknown_clients.iter().map(|client| client.to_string()).collect();
+            eprintln!(
+                "{} clients aren't in the clients file: {}",
+                clients.len(),
+                clients.join(", ")
+            );
+        }
+        let over_available = report.stats.disputes_over_available;
+        if over_available > 0 {
+            let outcome = match engine.config().disputes.over_available {
+                OverAvailable::Cap => "only held what was available",
+                _ => "drove the available funds negative",
+            };
+            eprintln!(
+                "{} disputes were for more than the available funds, and {}",
+                over_available, outcome
+            );
+        }
+        if report.stats.disputes_expired > 0 {
+            eprintln!("{} disputes expired and were resolved", report.stats.disputes_expired);
+        }
+        for (rule, count) in &report.stats.auto_locked {
+            eprintln!("{} accounts were locked by {}", count, rule.code());
+        }
+        let losses = engine.loss_total();
+        if losses.chargebacks > 0 {
+            let amounts = self.amounts(engine.config());
+            eprintln!(
+                "{} chargebacks lost {}, {} of it recovered by {} reversals",
+                losses.chargebacks,
+                amounts.format(losses.charged_back + losses.fees),
+                amounts.format(losses.recovered),
+                losses.reversals
+            );
+        }
+        let malformed = &report.stats.malformed;
+        if !malformed.is_empty() && self.rejection_report.is_none() {
+            let lines: Vec<String> = malformed.iter().map(|record| record.line.to_string()).collect();
+            eprintln!(
+                "{} malformed records were skipped, on lines {}",
+                malformed.len(),
+                lines.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
 
-                // Get the amount from the deposit transaction
-                let amount = if let Some(&amount) = deposit_transaction_amounts.get(&transaction.tx_id) {
-                    amount
-                } else {
-                    // Transaction not found, error from the partner
-                    continue;
-                };
-                account_balance.held -= amount;
-                account_balance.locked = true;
-            }
+/// Options shared by all the ways of running the engine.
+#[derive(Debug, clap::Args)]
+struct EngineArgs {
+    /// Format of the input files
+    #[arg(long, value_enum, default_value_t = Format::Csv, env = "PAYMENTS_ENGINE_FORMAT", global = true)]
+    format: Format,
+
+    /// TOML file with the settings of the engine, see `config print-default`.
+    /// It overrides the `PAYMENTS_ENGINE_*` environment variables with settings,
+    /// and the options given on the command line override it
+    #[arg(long, value_name = "FILE", env = "PAYMENTS_ENGINE_CONFIG", global = true)]
+    config: Option<PathBuf>,
+
+    /// Read the CSV columns with other names as ours, e.g. `customer=client,value=amount`
+    #[arg(long, value_name = "MAP", global = true)]
+    column_map: Option<ColumnMap>,
+
+    /// Separate the fields of the CSV input with this character, or `tab`,
+    /// instead of commas (or tabs for `.tsv` files)
+    #[arg(long, value_name = "CHAR", global = true)]
+    delimiter: Option<Delimiter>,
+
+    /// How to parse the CSV records: with `serde`, or the `fast` parser for the usual columns
+    #[arg(long, value_enum, value_name = "PARSER", global = true)]
+    parser: Option<RecordParser>,
+
+    /// Read the amounts with thousands separators, e.g. `1,234.5` in `us` or `1.234,5` in `eu`,
+    /// instead of only like `1234.5`
+    #[arg(long, value_enum, value_name = "LOCALE", global = true)]
+    amount_locale: Option<Locale>,
+
+    /// The CSV input has no header row,
+    /// and its columns are in the usual order: `type`, `client`, `tx`, `amount`, etc.
+    #[arg(long, global = true)]
+    no_headers: bool,
+
+    /// Skip the malformed CSV records instead of failing, listing them in the `--rejection-report`
+    #[arg(long, global = true)]
+    lenient: bool,
+
+    /// Only apply a transaction once, by type and `tx`, rejecting the ones seen again
+    /// as `DUPLICATE_TX`, e.g. when an at-least-once source delivers them again.
+    /// They are kept in the `--sqlite` or `--redis` database if there is one
+    #[arg(long, global = true)]
+    dedup: bool,
+
+    /// Keep the past deposits and withdrawals in a compact store, in about half the memory,
+    /// with their amounts rounded to four decimal places
+    #[arg(long, global = true)]
+    #[cfg_attr(feature = "sqlite", arg(conflicts_with = "sqlite"))]
+    #[cfg_attr(feature = "redis", arg(conflicts_with = "redis"))]
+    compact_tx_store: bool,
+
+    /// Only keep the `--hot-txs` most recently used past deposits and withdrawals in memory,
+    /// and spill the others to this file, so that the memory stays flat. The file is emptied first
+    #[arg(long, value_name = "FILE", global = true, conflicts_with = "compact_tx_store")]
+    #[cfg_attr(feature = "sqlite", arg(conflicts_with = "sqlite"))]
+    #[cfg_attr(feature = "redis", arg(conflicts_with = "redis"))]
+    spill_file: Option<PathBuf>,
+
+    /// How many past deposits and withdrawals are kept in memory with `--spill-file`, e.g. `500k`
+    #[arg(long, value_name = "N", global = true, requires = "spill_file", value_parser = parse_count::<usize>)]
+    hot_txs: Option<usize>,
+
+    /// In follow and serve modes, how many transactions (or requests, when serving) can wait
+    /// to be applied before the input waits for the engine
+    #[arg(long, value_name = "N", global = true)]
+    queue_capacity: Option<usize>,
+
+    /// CSV with the `name`, `segment` and `region` of each `client`, added to the report
+    #[arg(long, value_name = "FILE", env = "PAYMENTS_ENGINE_CLIENTS_FILE", global = true)]
+    clients_file: Option<PathBuf>,
+
+    /// Allow admin operations, i.e. `adjustment`, `freeze` and `unfreeze` transactions
+    #[arg(long, global = true)]
+    admin_ops: bool,
+
+    /// Charge a fee on qualifying deposits or withdrawals, e.g. `withdrawal:percent=1.5,fixed=0.25,over=1000`.
+    /// Can be given several times, replacing the rules of the `--config` file
+    #[arg(long = "fee-rule", value_name = "RULE", global = true)]
+    fee_rules: Vec<FeeRule>,
+
+    /// Accrue daily interest at this yearly rate on the available funds, using the `timestamp` column
+    #[arg(long, value_name = "PERCENT", global = true)]
+    interest_rate: Option<Amount>,
+
+    /// How often the accrued interest is posted: `daily` or `monthly` (the default)
+    #[arg(long, value_name = "PERIOD", global = true)]
+    interest_period: Option<InterestPeriod>,
+
+    /// What a dispute of more than the available funds does: `allow` it to drive them negative
+    /// (the default), `cap` the hold at what is available, or `reject` it
+    #[arg(long, value_name = "POLICY", global = true)]
+    disputes_over_available: Option<OverAvailable>,
+
+    /// Resolve the disputes still open this many days after they were opened, going by the
+    /// `timestamp` column
+    #[arg(long, value_name = "DAYS", global = true)]
+    dispute_expiry_days: Option<u32>,
+
+    /// Keep the funds of the deposits pending for this many days after their `timestamp`,
+    /// unless they have a `settles_at` or are settled earlier by a `settle` transaction
+    #[arg(long, value_name = "DAYS", global = true)]
+    deposit_hold_days: Option<u32>,
+
+    /// Only process the clients of this shard of a distributed run, e.g. `3/16` for the third of
+    /// sixteen, so that each machine processes the whole input and reports its own clients
+    #[arg(long, value_name = "N/COUNT", env = "PAYMENTS_ENGINE_SHARD", global = true)]
+    shard: Option<Shard>,
+
+    /// How the clients are assigned to the shards: by a `hash` of their id, or by `range` of ids
+    #[arg(long, value_enum, default_value_t = ShardBy::Hash, global = true)]
+    shard_by: ShardBy,
+
+    /// The source of the input, one of the `[sources]` of the config, rejecting the
+    /// transactions of the types it isn't allowed
+    #[arg(long, value_name = "NAME", global = true)]
+    source: Option<String>,
+
+    /// Check the Ed25519 signature of each CSV record, in its `signature` column, with the
+    /// `--pubkey` of the partner, and reject the records without a valid one
+    #[cfg(feature = "signatures")]
+    #[arg(long, requires = "pubkey", global = true)]
+    verify_signatures: bool,
+
+    /// The public key of the partner signing the records, in PEM
+    #[cfg(feature = "signatures")]
+    #[arg(long, value_name = "FILE", global = true)]
+    pubkey: Option<PathBuf>,
+
+    /// Decrypt the input, all of it before applying any row: an `age` file, with the `--identity`
+    /// file, or a `pgp` one with `gpg` and the keys of its keyring, keeping the plaintext in memory only
+    #[cfg(feature = "decrypt")]
+    #[arg(long, value_enum, value_name = "WITH", requires_if("age", "identity"), global = true)]
+    decrypt: Option<DecryptWith>,
+
+    /// The age identity file to decrypt the input with, e.g. the one written by `age-keygen`
+    #[cfg(feature = "decrypt")]
+    #[arg(long, value_name = "FILE", global = true)]
+    identity: Option<PathBuf>,
+
+    /// How to read local input files: with `buffered` reads, or `mmap` to map them into memory
+    #[cfg(feature = "mmap")]
+    #[arg(long, value_enum, default_value_t = Io::Buffered, global = true)]
+    io: Io,
+
+    /// Keep the engine state in the given SQLite database instead of in memory
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "DB", env = "PAYMENTS_ENGINE_SQLITE", global = true)]
+    sqlite: Option<PathBuf>,
+
+    /// Keep the engine state in the Redis database at this URL, e.g. `redis://127.0.0.1/`, instead
+    /// of in memory. Only one engine may apply transactions to it at a time
+    #[cfg(feature = "redis")]
+    #[arg(long, value_name = "URL", env = "PAYMENTS_ENGINE_REDIS", global = true)]
+    #[cfg_attr(feature = "sqlite", arg(conflicts_with = "sqlite"))]
+    redis: Option<String>,
+
+    /// The prefix of the Redis keys of the state, to keep several of them in the same database
+    #[cfg(feature = "redis")]
+    #[arg(long, value_name = "PREFIX", default_value = payments_engine::redis_store::DEFAULT_PREFIX, global = true)]
+    redis_prefix: String,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Process all the input files of a directory, in lexical order, against the same state,
+    /// and record the outcome of each file in a manifest
+    ProcessDir {
+        /// Directory with the input files
+        dir: PathBuf,
+
+        /// Where to write the CSV manifest, with the rows, rejects and state hash after each file
+        #[arg(long, value_name = "FILE")]
+        manifest: PathBuf,
+
+        /// Roll back a file that fails halfway through, and go on with the next ones
+        #[arg(long)]
+        skip_failed: bool,
+    },
+
+    /// Export the processed activity as ledger-cli or beancount transactions,
+    /// to stdout or to the `--output` file
+    Export {
+        /// Path or URL of the file with the transactions
+        input: String,
+
+        /// Plain text accounting format to write
+        #[arg(long, value_enum, default_value_t = ExportFormat::Ledger)]
+        to: ExportFormat,
+
+        /// Date to book all the transactions on
+        #[arg(long, value_name = "YYYY-MM-DD", value_parser = parse_date)]
+        date: String,
+
+        /// Currency of the amounts
+        #[arg(long, default_value = "USD")]
+        currency: String,
+    },
+
+    /// Process the transactions and report the deposits, withdrawals, disputes and chargebacks
+    /// of each day, week or month, going by their `timestamp`, to stdout or to the `--output` file
+    Report {
+        /// Path or URL of the file with the transactions
+        input: String,
+
+        /// How long the periods of the report are
+        #[arg(long, value_enum, default_value_t = BucketSize::Daily)]
+        bucket: BucketSize,
+    },
+
+    /// Process the transactions and list the disputes still open: the client, the deposit, the
+    /// funds held and how many days the dispute has been open, to stdout or to the `--output` file
+    Disputes {
+        /// Path or URL of the file with the transactions
+        input: String,
+    },
+
+    /// Sample a CSV file with an unknown layout, guess which of its columns are the `type`,
+    /// `client`, `tx` and `amount`, and its delimiter unless `--delimiter` is given, and
+    /// suggest a `--column-map` for it
+    Inspect {
+        /// Path or URL of the CSV file, with a header row
+        input: String,
+
+        /// How many rows to sample
+        #[arg(long, value_name = "N", default_value_t = inspect::DEFAULT_SAMPLE_ROWS, value_parser = parse_count::<usize>)]
+        rows: usize,
+    },
+
+    /// Read a CSV file of transactions without applying them and report, to stdout or to the
+    /// `--output` file, its rows, clients, tx id range and duplicates, the count of each type,
+    /// its amounts and timestamps, and about how much memory processing it would take
+    Stats {
+        /// Path or URL of the CSV file
+        input: String,
+    },
+
+    /// Process the transactions and report the losses to chargebacks of each client and in total:
+    /// the funds charged back, the `chargeback_fee`s and the funds recovered by reversals, to
+    /// stdout or to the `--output` file
+    Losses {
+        /// Path or URL of the file with the transactions
+        input: String,
+    },
+
+    /// Process a CSV of transactions and compare the balances with an expected statement,
+    /// listing the clients that don't match. Exits with 6 if there are any
+    Reconcile {
+        /// Path or URL of the CSV file with the transactions
+        input: String,
+
+        /// CSV with the expected balances, in the same format as the report
+        #[arg(long, value_name = "FILE")]
+        expected: PathBuf,
+    },
+
+    /// Process the transactions and compare every column of the report with a golden one, e.g. of a
+    /// regression corpus, listing how each client differs to stdout or to the `--output` file.
+    /// Exits with 6 if any does
+    Verify {
+        /// Path or URL of the file with the transactions
+        #[arg(long)]
+        input: String,
+
+        /// CSV with the expected balances, in the same format as the report
+        #[arg(long, value_name = "FILE")]
+        expected: PathBuf,
+    },
+
+    /// List the clients whose available or held funds, or locked status, differ between two states,
+    /// to stdout or to the `--output` file
+    Diff {
+        /// The state before, a snapshot or a report CSV
+        before: PathBuf,
+
+        /// The state after, a snapshot or a report CSV
+        after: PathBuf,
+    },
+
+    /// Rebuild the balances from an `--event-log` alone and report them, checking that they match
+    /// the expected state if any. Exits with 6 if they don't
+    Replay {
+        /// NDJSON event log of a run that started from an empty state
+        events: PathBuf,
+
+        /// The expected state, a snapshot or a report CSV, listing the clients that differ on stderr
+        #[arg(long, value_name = "STATE")]
+        expected: Option<PathBuf>,
+
+        /// The expected state hash, e.g. from the `--append-summary` or a `process-dir` manifest
+        #[arg(long, value_name = "HASH")]
+        state_hash: Option<String>,
+    },
+
+    /// Check the hash chain of an `--event-log`, failing at the first event modified, removed or
+    /// inserted, and print the number of events and the hash of the last one
+    VerifyAudit {
+        /// NDJSON event log
+        events: PathBuf,
+
+        /// The hash of the last event, kept elsewhere, to also find the last events removed.
+        /// Exits with 6 if it isn't the one of the log
+        #[arg(long, value_name = "HASH")]
+        head: Option<String>,
+    },
+
+    /// Merge the partial reports of a run with `--shard` into one, to stdout or to the `--output` file,
+    /// checking that no client is in more than one of them
+    Merge {
+        /// The partial reports, one per shard
+        #[arg(required = true)]
+        reports: Vec<PathBuf>,
+
+        /// The partial rejection reports, merged into the `--rejection-report` file
+        #[arg(long, value_name = "FILE", num_args = 1.., requires = "rejection_report")]
+        rejections: Vec<PathBuf>,
+    },
+
+    /// Generate transactions in memory and process them, reporting the rows per second, the peak
+    /// memory and the allocations, to compare the engine options like `--parser` or `--sqlite`
+    Bench {
+        /// Number of transactions to generate, e.g. `500k` or `10M`
+        #[arg(long, default_value = "1M", value_parser = parse_count::<u64>)]
+        rows: u64,
+
+        /// Number of clients the transactions are spread over, e.g. `50k`.
+        /// Not `--clients`, which only reports some of the clients
+        #[arg(long, default_value = "10k", value_parser = parse_count::<ClientId>)]
+        client_count: ClientId,
+
+        /// Seed of the generated transactions, the same one giving the same transactions
+        #[arg(long, default_value_t = 1)]
+        seed: u64,
+    },
+
+    /// Work with the `--config` files
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Type transactions and commands like `show 1`, `undo` or `save state.bin` one per line,
+    /// and see the resulting state right away
+    Repl,
+
+    /// Keep a read-only copy of the balances of another engine from the `--event-log` it writes,
+    /// following it like `tail -f`, and print them again, or rewrite the `--output` file,
+    /// whenever they change
+    Replica {
+        /// NDJSON event log of the engine, which must have started from an empty state
+        events: PathBuf,
+
+        /// How often to check if the balances changed, in seconds
+        #[arg(long, value_name = "SECS", default_value_t = 10)]
+        report_interval: u64,
+    },
+
+    /// Print the balance of a client from a state snapshot, with its open disputes and last
+    /// activity, as JSON, without processing any input
+    Account {
+        /// The client
+        client: ClientId,
+
+        /// Snapshot of the state, e.g. saved with `save` in the REPL or the `.state` of a `--checkpoint`
+        #[arg(long, value_name = "FILE")]
+        state: PathBuf,
+    },
+
+    /// Rewrite a state snapshot, e.g. saved with `save` in the REPL, in the format of this version
+    MigrateState {
+        /// Snapshot written by this or an earlier version
+        old: PathBuf,
+
+        /// Where to write the migrated snapshot, which can be the same file
+        new: PathBuf,
+    },
+
+    /// Serve the engine over gRPC, as defined in `proto/payments_engine.proto`
+    #[cfg(feature = "grpc")]
+    Serve {
+        /// Address to listen on
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:50051")]
+        listen: std::net::SocketAddr,
+
+        /// How long to remember the idempotency keys of the submitted transactions, in seconds.
+        /// They are kept in the `--sqlite` or `--redis` database if there is one
+        #[arg(long, value_name = "SECS", default_value_t = grpc::DEFAULT_IDEMPOTENCY_RETENTION.as_secs())]
+        idempotency_retention: u64,
+
+        /// How many tasks apply the transactions, each for the clients of a shard of them with an
+        /// engine and a queue of its own. The dashboard only shows the clients of the first one
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        actors: u32,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Print a commented config file with the default settings
+    PrintDefault,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Csv,
+    #[cfg(feature = "parquet")]
+    Parquet,
+    #[cfg(feature = "avro")]
+    Avro,
+}
+
+impl From<Format> for InputFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Csv => InputFormat::Csv,
+            #[cfg(feature = "parquet")]
+            Format::Parquet => InputFormat::Parquet,
+            #[cfg(feature = "avro")]
+            Format::Avro => InputFormat::Avro,
         }
     }
+}
 
-    // Generate account balances string
-    let mut output = vec![String::from("client, available, held, total, locked")];
-    for (_client_id, account_balance) in account_balances {
-        output.push(format!("{}", account_balance));
+#[cfg(feature = "mmap")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Io {
+    Buffered,
+    Mmap,
+}
+
+#[cfg(feature = "mmap")]
+impl From<Io> for InputIo {
+    fn from(io: Io) -> Self {
+        match io {
+            Io::Buffered => InputIo::Buffered,
+            Io::Mmap => InputIo::Mmap,
+        }
     }
+}
 
-    Ok(output.join("\n"))
+#[cfg(feature = "decrypt")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DecryptWith {
+    Age,
+    Pgp,
 }
 
-fn main() {
-    // Get CSV path from the command arguments
-    
-    let csv_file = if let Some(file_path) = env::args().nth(1) {file_path} else {
-        println!("No file path in the input arguments");
-        // We cannot continue without a CSV file, so we exit with an error code.
-        process::exit(1);
-    };
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum BucketSize {
+    Daily,
+    Weekly,
+    Monthly,
+}
 
-    // Process the CSV and abort on uncaught errors
-    match process_csv(&Path::new(&csv_file)) {
-        Ok(output) => {
-            println!("{}", output);
+impl From<BucketSize> for Bucket {
+    fn from(size: BucketSize) -> Self {
+        match size {
+            BucketSize::Daily => Bucket::Daily,
+            BucketSize::Weekly => Bucket::Weekly,
+            BucketSize::Monthly => Bucket::Monthly,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ShardBy {
+    Hash,
+    Range,
+}
+
+impl From<ShardBy> for ShardMethod {
+    fn from(shard_by: ShardBy) -> Self {
+        match shard_by {
+            ShardBy::Hash => ShardMethod::Hash,
+            ShardBy::Range => ShardMethod::Range,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum SortBy {
+    Client,
+    Total,
+    Held,
+}
+
+impl From<SortBy> for SortKey {
+    fn from(sort_by: SortBy) -> Self {
+        match sort_by {
+            SortBy::Client => SortKey::Client,
+            SortBy::Total => SortKey::Total,
+            SortBy::Held => SortKey::Held,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Column {
+    Client,
+    Available,
+    Held,
+    Pending,
+    Total,
+    Locked,
+}
+
+impl From<Column> for ReportColumn {
+    fn from(column: Column) -> Self {
+        match column {
+            Column::Client => ReportColumn::Client,
+            Column::Available => ReportColumn::Available,
+            Column::Held => ReportColumn::Held,
+            Column::Pending => ReportColumn::Pending,
+            Column::Total => ReportColumn::Total,
+            Column::Locked => ReportColumn::Locked,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ReportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum RoundingMode {
+    HalfEven,
+    Truncate,
+}
+
+impl From<RoundingMode> for Rounding {
+    fn from(mode: RoundingMode) -> Self {
+        match mode {
+            RoundingMode::HalfEven => Rounding::HalfEven,
+            RoundingMode::Truncate => Rounding::Truncate,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum AmountStyleArg {
+    Decimal,
+    MinorUnits,
+}
+
+impl From<AmountStyleArg> for AmountStyle {
+    fn from(style: AmountStyleArg) -> Self {
+        match style {
+            AmountStyleArg::Decimal => AmountStyle::Decimal,
+            AmountStyleArg::MinorUnits => AmountStyle::MinorUnits,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum RecordParser {
+    Serde,
+    Fast,
+}
+
+impl From<RecordParser> for CsvParser {
+    fn from(parser: RecordParser) -> Self {
+        match parser {
+            RecordParser::Serde => CsvParser::Serde,
+            RecordParser::Fast => CsvParser::Fast,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Locale {
+    Strict,
+    Us,
+    Eu,
+}
+
+impl From<Locale> for AmountLocale {
+    fn from(locale: Locale) -> Self {
+        match locale {
+            Locale::Strict => AmountLocale::Strict,
+            Locale::Us => AmountLocale::Us,
+            Locale::Eu => AmountLocale::Eu,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ExportFormat {
+    Ledger,
+    Beancount,
+}
+
+impl From<ExportFormat> for AccountingFormat {
+    fn from(format: ExportFormat) -> Self {
+        match format {
+            ExportFormat::Ledger => AccountingFormat::Ledger,
+            ExportFormat::Beancount => AccountingFormat::Beancount,
+        }
+    }
+}
+
+/// Prints a report to stdout, with an error rather than a panic if it can't, e.g. if the
+/// reader at the other end of a pipe exited.
+fn print_report(output: &str) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{}", output)?;
+    stdout.flush()
+}
+
+/// Writes an `--output` file with `write`, only replacing the file once it succeeded.
+fn write_output(
+    path: &Path,
+    write: impl FnOnce(&mut AtomicFile) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut file = AtomicFile::create(path)?;
+    write(&mut file)?;
+    Ok(file.commit()?)
+}
+
+fn parse_date(s: &str) -> Result<String, String> {
+    let digits = |part: &str, len: usize| part.len() == len && part.bytes().all(|byte| byte.is_ascii_digit());
+    match s.split('-').collect::<Vec<_>>()[..] {
+        [year, month, day] if digits(year, 4) && digits(month, 2) && digits(day, 2) => Ok(s.to_string()),
+        _ => Err(format!("`{}` is not a date like 2024-06-01", s)),
+    }
+}
+
+/// Parses a duration of hours, minutes and seconds, e.g. `55m`, `90s` or `1h30m`.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("`{}` is not a duration like 90s, 55m or 1h30m", s);
+    let mut secs: u64 = 0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+        let count: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        let unit = match rest[digits..].chars().next() {
+            Some('h') => 3600,
+            Some('m') => 60,
+            Some('s') => 1,
+            _ => return Err(invalid()),
+        };
+        secs = count
+            .checked_mul(unit)
+            .and_then(|part| secs.checked_add(part))
+            .ok_or_else(invalid)?;
+        rest = &rest[digits + 1..];
+    }
+    match secs {
+        0 => Err(invalid()),
+        secs => Ok(Duration::from_secs(secs)),
+    }
+}
+
+/// Parses a count with an optional `k` or `M` suffix, e.g. `50k` or `10M`.
+fn parse_count<T: TryFrom<u64>>(s: &str) -> Result<T, String> {
+    let invalid = || format!("`{}` is not a count like 5000, 50k or 10M", s);
+    let (digits, multiplier) = match s.strip_suffix(['k', 'K']) {
+        Some(digits) => (digits, 1_000),
+        None => match s.strip_suffix('M') {
+            Some(digits) => (digits, 1_000_000),
+            None => (s, 1),
         },
-        Err(err) => {
-            println!("{}", err);
-            process::exit(1);
+    };
+    let count = digits
+        .parse::<u64>()
+        .map_err(|_| invalid())?
+        .checked_mul(multiplier)
+        .ok_or_else(invalid)?;
+    T::try_from(count).map_err(|_| format!("`{}` is too large", s))
+}
+
+impl EngineArgs {
+    fn engine(&self) -> Result<PaymentsEngine, Box<dyn Error>> {
+        let vars: Vec<_> = std::env::vars().collect();
+        let mut config = EngineConfig::from_env_and_toml(vars.clone(), None).map_err(|error| FileError {
+            file: format!("{}* environment variables", config::ENV_PREFIX),
+            error: Box::new(error),
+        })?;
+        if let Some(path) = &self.config {
+            config =
+                EngineConfig::from_env_and_toml(vars, Some(&fs::read_to_string(path)?)).map_err(|error| FileError {
+                    file: path.display().to_string(),
+                    error: Box::new(error),
+                })?;
+        }
+        let mut engine = self.stores()?;
+        if let Some(path) = &self.clients_file {
+            let clients = ClientDirectory::read(File::open(path)?).map_err(|error| FileError {
+                file: path.display().to_string(),
+                error,
+            })?;
+            engine = engine.with_clients(clients);
+        }
+        #[cfg(feature = "signatures")]
+        if let Some(path) = self.pubkey.as_ref().filter(|_| self.verify_signatures) {
+            let verifier = SignatureVerifier::from_pem(&fs::read_to_string(path)?).map_err(|error| FileError {
+                file: path.display().to_string(),
+                error: Box::new(error),
+            })?;
+            engine = engine.with_signature_verifier(verifier);
+        }
+        #[cfg(feature = "decrypt")]
+        match (self.decrypt, &self.identity) {
+            (Some(DecryptWith::Age), Some(path)) => {
+                let identities = AgeIdentity::read_file(&fs::read_to_string(path)?).map_err(|error| FileError {
+                    file: path.display().to_string(),
+                    error: Box::new(error),
+                })?;
+                engine = engine.with_decryption(Decryption::Age(identities));
+            }
+            (Some(DecryptWith::Pgp), _) => engine = engine.with_decryption(Decryption::Pgp),
+            _ => {}
+        }
+        #[cfg(feature = "mmap")]
+        {
+            engine = engine.with_input_io(self.io.into());
         }
+        config.admin_ops |= self.admin_ops;
+        if self.no_headers {
+            config.csv.headers = false;
+        }
+        config.csv.lenient |= self.lenient;
+        if let Some(capacity) = self.queue_capacity {
+            config.queue.capacity = capacity;
+        }
+        if let Some(parser) = self.parser {
+            config.csv.parser = parser.into();
+        }
+        if let Some(locale) = self.amount_locale {
+            config.csv.amount_locale = locale.into();
+        }
+        if let Some(delimiter) = self.delimiter {
+            config.csv.delimiter = Some(delimiter);
+        }
+        if let Some(column_map) = &self.column_map {
+            config.csv.column_map = column_map.clone();
+        }
+        if !self.fee_rules.is_empty() {
+            config.fee_rules = self.fee_rules.clone();
+        }
+        if let Some(annual_rate_percent) = self.interest_rate {
+            let period = config.interest.map(|interest| interest.period).unwrap_or_default();
+            config.interest = Some(InterestConfig {
+                annual_rate_percent,
+                period,
+            });
+        }
+        if let (Some(interest), Some(period)) = (&mut config.interest, self.interest_period) {
+            interest.period = period;
+        }
+        if let Some(policy) = self.disputes_over_available {
+            config.disputes.over_available = policy;
+        }
+        if let Some(days) = self.dispute_expiry_days {
+            config.disputes.expire_after_days = Some(days);
+        }
+        if let Some(days) = self.deposit_hold_days {
+            config.deposits.hold_days = Some(days);
+        }
+        if let Some(shard) = self.shard {
+            engine = engine.with_shard(shard.with_method(self.shard_by.into()));
+        }
+        if let Some(source) = &self.source {
+            if !config.sources.contains_key(source) {
+                return Err(format!("There is no source `{}` in the config", source).into());
+            }
+            engine = engine.with_source(source);
+        }
+        Ok(engine.with_config(config))
+    }
+
+    fn stores(&self) -> Result<PaymentsEngine, Box<dyn Error>> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db_path) = &self.sqlite {
+            // The stores share a connection, to commit together
+            use payments_engine::sqlite_store::{open_engine_in, SqliteDatabase, SqliteDedupStore};
+            let database = SqliteDatabase::open(db_path)?;
+            let engine = open_engine_in(&database)?;
+            return Ok(match self.dedup {
+                true => engine.with_dedup(Box::new(SqliteDedupStore::open_in(&database)?)),
+                false => engine,
+            });
+        }
+        #[cfg(feature = "redis")]
+        if let Some(url) = &self.redis {
+            // The stores share a connection, to commit together
+            use payments_engine::redis_store::{open_engine_in, RedisDatabase, RedisDedupStore};
+            let database = RedisDatabase::open(url, &self.redis_prefix)?;
+            let engine = open_engine_in(&database);
+            return Ok(match self.dedup {
+                true => engine.with_dedup(Box::new(RedisDedupStore::open_in(&database))),
+                false => engine,
+            });
+        }
+
+        let engine = match (&self.spill_file, self.compact_tx_store) {
+            (Some(path), _) => PaymentsEngine::with_stores(
+                Box::new(MemoryAccountStore::default()),
+                Box::new(SpillTxStore::create(
+                    path,
+                    self.hot_txs.unwrap_or(DEFAULT_HOT_TRANSACTIONS),
+                )?),
+            ),
+            (None, true) => PaymentsEngine::with_stores(
+                Box::new(MemoryAccountStore::default()),
+                Box::new(CompactTxStore::default()),
+            ),
+            (None, false) => PaymentsEngine::new(),
+        };
+        Ok(match self.dedup {
+            true => engine.with_dedup(Box::new(MemoryDedupStore::default())),
+            false => engine,
+        })
+    }
+
+    #[cfg(feature = "grpc")]
+    fn idempotency_store(&self) -> Result<Box<dyn IdempotencyStore>, Box<dyn Error>> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db_path) = &self.sqlite {
+            return Ok(Box::new(payments_engine::sqlite_store::SqliteIdempotencyStore::open(
+                db_path,
+            )?));
+        }
+        #[cfg(feature = "redis")]
+        if let Some(url) = &self.redis {
+            let store = payments_engine::redis_store::RedisIdempotencyStore::open(url, &self.redis_prefix)?;
+            return Ok(Box::new(store));
+        }
+
+        Ok(Box::new(MemoryIdempotencyStore::default()))
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use std::path::Path;
-    use crate::process_csv;
+/// Processes the input with `--close-after`, writing the row of each account to stdout as soon as
+/// it is final, and then the rows of the accounts still open at the end of the input.
+fn process_closing(cli: &Cli, input: &str, format: InputFormat, engine: PaymentsEngine) -> Result<(), Box<dyn Error>> {
+    let close_after = cli.close_after.expect("only with --close-after");
+    let columns = cli.output.report_columns();
+    let amounts = cli.output.amounts(engine.config());
+    let clients = engine.clients().cloned();
+    print_report(&format_report_columns(&[], &columns, clients.as_ref(), &amounts))?;
 
-    fn test_csv(file_path: &str, expected: &str) {
-        let output = process_csv(&Path::new(file_path)).unwrap();
-        let expected_output = String::from(expected);
-        assert_eq!(output, expected_output);
+    let filter = cli.output.clients.clone();
+    let (row_columns, row_amounts, row_clients) = (columns.clone(), amounts.clone(), clients.clone());
+    let mut engine = engine.with_close_after(close_after, move |account_balance| {
+        if filter
+            .as_ref()
+            .is_none_or(|filter| filter.contains(account_balance.client))
+        {
+            // Stdout is line buffered, so each row is flushed as it is written
+            let row = format_report_row(account_balance, &row_columns, row_clients.as_ref(), &row_amounts);
+            writeln!(io::stdout().lock(), "{}", row)?;
+        }
+        Ok(())
+    });
+    engine.process_input(input, format)?;
+
+    let report = engine.processing_report()?;
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    for account_balance in cli.output.balances(report.balances.clone()) {
+        writeln!(
+            stdout,
+            "{}",
+            format_report_row(&account_balance, &columns, clients.as_ref(), &amounts)
+        )?;
     }
+    stdout.flush()?;
+    cli.output.write_extras(&engine, &report)
+}
 
+fn follow(cli: &Cli, input: &str, engine: &mut PaymentsEngine) -> Result<(), Box<dyn Error>> {
+    #[cfg(feature = "decrypt")]
+    let encrypted = cli.engine.decrypt.is_some();
+    #[cfg(not(feature = "decrypt"))]
+    let encrypted = false;
+    if cli.engine.format != Format::Csv || encrypted {
+        return Err(Box::new(InputSourceError {
+            error_type: InputSourceErrorType::NotFollowable,
+        }));
+    }
 
-    #[test]
-    fn test_multiple_clients() {
-        test_csv("sample_files/multiple_clients.csv", r"client, available, held, total, locked
-1, 2.0000, 0.0000, 2.0000, true
-2, 0.5000, 0.0000, 0.5000, false
-3, 0.0000, 5.5000, 5.5000, false");
+    #[cfg(feature = "tui")]
+    if cli.dashboard {
+        let mut dashboard = Dashboard::new()?;
+        engine.follow_csv(input::local_path(input)?, Dashboard::REFRESH_INTERVAL, |engine| {
+            dashboard.draw(engine)?;
+            Ok(match dashboard.quit_requested()? {
+                true => ControlFlow::Break(()),
+                false => ControlFlow::Continue(()),
+            })
+        })?;
+        // Leave the dashboard before printing the final balances
+        drop(dashboard);
+        return cli.output.write_report(engine);
     }
 
-    #[test]
-    fn test_deposit_withdrawal() {
-        test_csv("sample_files/deposit_withdrawal.csv", r"client, available, held, total, locked
-1, 1.5000, 0.0000, 1.5000, false
-2, 0.5000, 0.0000, 0.5000, false");
+    let mut last_output: Option<String> = None;
+    engine.follow_csv(
+        input::local_path(input)?,
+        Duration::from_secs(cli.report_interval),
+        |engine| {
+            let output = cli.output.format_report(engine.balances()?, engine);
+            if last_output.as_ref() != Some(&output) {
+                // Leave an empty line between reports
+                print_report(&format!("{}\n", output))?;
+                last_output = Some(output);
+            }
+            Ok(ControlFlow::Continue(()))
+        },
+    )
+}
+
+/// Notifies the `[webhooks]` of the config of the events of the engine, in the live modes.
+fn with_webhooks(engine: PaymentsEngine) -> Result<PaymentsEngine, Box<dyn Error>> {
+    if engine.config().webhooks.urls.is_empty() {
+        return Ok(engine);
+    }
+    #[cfg(feature = "http")]
+    {
+        let webhooks = payments_engine::webhooks::Webhooks::new(&engine.config().webhooks);
+        Ok(engine.with_observer(Box::new(webhooks)))
     }
+    #[cfg(not(feature = "http"))]
+    Err("The webhooks need the `http` feature".into())
+}
+
+/// Runs the gRPC server in the background, and shows the dashboard until it is closed.
+#[cfg(all(feature = "grpc", feature = "tui"))]
+fn serve_with_dashboard(service: PaymentsEngineService, listen: std::net::SocketAddr) -> Result<(), Box<dyn Error>> {
+    let engine = service.engine();
+    let runtime = tokio::runtime::Runtime::new()?;
+    let server = runtime.spawn(async move { grpc::serve(service, listen).await.map_err(|err| err.to_string()) });
 
-    #[test]
-    fn test_missing_tx_dispute() {
-        let expected = r"client, available, held, total, locked
-1, 1.0000, 0.0000, 1.0000, false";
-        test_csv("sample_files/dispute_missing_transaction.csv", expected);
+    let mut dashboard = Dashboard::new()?;
+    while !dashboard.quit_requested()? {
+        if server.is_finished() {
+            drop(dashboard);
+            return Ok(runtime.block_on(server)??);
+        }
+        let engine = engine.lock().map_err(|err| err.to_string())?;
+        dashboard.draw(&engine)?;
+        drop(engine);
+        std::thread::sleep(Dashboard::REFRESH_INTERVAL);
     }
+    Ok(())
+}
 
-    #[test]
-    fn test_missing_tx_resolve() {
-        let expected = r"client, available, held, total, locked
-1, 1.0000, 0.0000, 1.0000, false";
-        test_csv("sample_files/resolve_missing_transaction.csv", expected);
+fn run(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    if let Some(Command::Config {
+        command: ConfigCommand::PrintDefault,
+    }) = &cli.command
+    {
+        print!("{}", config::DEFAULT_CONFIG);
+        return Ok(());
+    }
+    if let Some(Command::Account { client, state }) = &cli.command {
+        let mut engine = PaymentsEngine::new();
+        engine.restore_snapshot(BufReader::new(File::open(state)?))?;
+        let Some(view) = engine.account_view(*client)? else {
+            return Err(format!("There is no account for client {} in {}", client, state.display()).into());
+        };
+        println!("{}", serde_json::to_string_pretty(&view)?);
+        return Ok(());
+    }
+    if let Some(Command::MigrateState { old, new }) = &cli.command {
+        // Read it all first, in case it is migrated in place
+        let old_snapshot = fs::read(old)?;
+        let version = snapshot::migrate_snapshot(old_snapshot.as_slice(), BufWriter::new(File::create(new)?))?;
+        eprintln!(
+            "Migrated {} from version {} to {}",
+            old.display(),
+            version,
+            snapshot::SNAPSHOT_VERSION
+        );
+        return Ok(());
     }
 
-    #[test]
-    fn test_missing_tx_chargeback() {
-        let expected = r"client, available, held, total, locked
-1, 1.0000, 0.0000, 1.0000, false";
-        test_csv("sample_files/chargeback_missing_transaction.csv", expected);
+    let started = Instant::now();
+    let mut engine = cli.engine.engine()?;
+    if cli.output.aml_report.is_some() {
+        engine = engine.with_aml();
+    }
+    if cli.output.rejection_report.is_some() {
+        engine = engine.with_rejections();
+    }
+    if cli.output.segment_report.is_some() {
+        engine = engine.with_journal();
     }
+    if cli.output.emit_dispute_graph.is_some() {
+        engine = engine.with_journal().with_rejections();
+    }
+    if let Some(path) = &cli.output.event_log {
+        engine = engine.with_event_log(Box::new(BufWriter::new(File::create(path)?)));
+    }
+    let format = InputFormat::from(cli.engine.format);
 
-    #[test]
-    fn test_missing_dispute_resolve() {
-        let expected = r"client, available, held, total, locked
-1, 1.0000, 0.0000, 1.0000, false";
-        test_csv("sample_files/resolve_missing_dispute.csv", expected);
+    match (&cli.command, &cli.input) {
+        (
+            Some(Command::ProcessDir {
+                dir,
+                manifest,
+                skip_failed,
+            }),
+            _,
+        ) => {
+            for skipped in engine.process_dir(dir, format, File::create(manifest)?, *skip_failed)? {
+                eprintln!("Rolled back {}", skipped);
+            }
+        }
+        (
+            Some(Command::Export {
+                input,
+                to,
+                date,
+                currency,
+            }),
+            _,
+        ) => {
+            let mut engine = engine.with_journal();
+            engine.process_input(input, format)?;
+            return match &cli.output.output {
+                Some(path) => write_output(path, |file| {
+                    export::write_journal(engine.journal(), (*to).into(), date, currency, file)
+                }),
+                None => export::write_journal(engine.journal(), (*to).into(), date, currency, io::stdout().lock()),
+            };
+        }
+        (Some(Command::Report { input, bucket }), _) => {
+            let mut engine = engine.with_activity((*bucket).into());
+            engine.process_input(input, format)?;
+            let amounts = cli.output.amounts(engine.config());
+            return match &cli.output.output {
+                Some(path) => write_output(path, |file| activity::write_report(&engine.activity(), &amounts, file)),
+                None => activity::write_report(&engine.activity(), &amounts, io::stdout().lock()),
+            };
+        }
+        (Some(Command::Disputes { input }), _) => {
+            engine.process_input(input, format)?;
+            let amounts = cli.output.amounts(engine.config());
+            let (disputes, now) = (engine.open_disputes()?, engine.latest_timestamp());
+            return match &cli.output.output {
+                Some(path) => write_output(path, |file| {
+                    dispute_expiry::write_report(&disputes, now, &amounts, file)
+                }),
+                None => dispute_expiry::write_report(&disputes, now, &amounts, io::stdout().lock()),
+            };
+        }
+        (Some(Command::Inspect { input, rows }), _) => {
+            let options = engine.config().csv.for_input(input);
+            let inspection = inspect::inspect(engine.open_input(input)?, &options, *rows)?;
+            let mut stdout = io::stdout().lock();
+            writeln!(stdout, "Sampled {} rows", inspection.rows)?;
+            let delimiter = match inspection.delimiter.0 {
+                b'\t' => String::from("tab"),
+                byte => char::from(byte).to_string(),
+            };
+            if inspection.delimiter_guessed && inspection.delimiter.0 != b',' {
+                writeln!(
+                    stdout,
+                    "The fields look separated by `{}`: --delimiter '{}'",
+                    delimiter, delimiter
+                )?;
+            }
+            for guess in &inspection.guesses {
+                let by_name = if guess.by_name { "by its name, " } else { "" };
+                let fitting = (guess.fitting_values * 100.0).round();
+                writeln!(
+                    stdout,
+                    "{}: {} ({}{}% of the values fit)",
+                    guess.name, guess.column, by_name, fitting
+                )?;
+            }
+            for column in &inspection.missing {
+                writeln!(stdout, "No column looks like `{}`", column)?;
+            }
+            match inspection.column_map() {
+                Some(column_map) => writeln!(stdout, "--column-map '{}'", column_map)?,
+                None if inspection.guesses.is_empty() => writeln!(stdout, "No column could be guessed")?,
+                None => writeln!(stdout, "The columns guessed already have the usual names")?,
+            }
+            return Ok(());
+        }
+        (Some(Command::Stats { input }), _) => {
+            let options = engine.config().csv.for_input(input);
+            let stats = file_stats::scan(engine.open_input(input)?, &options)?;
+            return match &cli.output.output {
+                Some(path) => write_output(path, |file| Ok(writeln!(file, "{}", stats)?)),
+                None => Ok(print_report(&stats.to_string())?),
+            };
+        }
+        (Some(Command::Losses { input }), _) => {
+            engine.process_input(input, format)?;
+            let amounts = cli.output.amounts(engine.config());
+            let (by_client, total) = (engine.losses(), engine.loss_total());
+            return match &cli.output.output {
+                Some(path) => write_output(path, |file| losses::write_report(&by_client, &total, &amounts, file)),
+                None => losses::write_report(&by_client, &total, &amounts, io::stdout().lock()),
+            };
+        }
+        (Some(Command::Reconcile { input, expected }), _) => {
+            let expected = reconcile::read_expected(File::open(expected)?)?;
+            let mismatches = engine.reconcile_csv_reader(engine.open_input(input)?, &expected)?;
+            match &cli.output.output {
+                Some(path) => write_output(path, |file| reconcile::write_mismatches(&mismatches, file))?,
+                None => reconcile::write_mismatches(&mismatches, io::stdout().lock())?,
+            }
+            if !mismatches.is_empty() {
+                process::exit(EXIT_MISMATCHES);
+            }
+            return Ok(());
+        }
+        (Some(Command::Verify { input, expected }), _) => {
+            let expected = reconcile::read_expected(File::open(expected)?)?;
+            engine.process_input(input, format)?;
+            let amounts = cli.output.amounts(engine.config());
+            let differences = verify::compare(&expected, &engine.balances()?, &amounts);
+            match &cli.output.output {
+                Some(path) => write_output(path, |file| verify::write_differences(&differences, file))?,
+                None => verify::write_differences(&differences, io::stdout().lock())?,
+            }
+            if !differences.is_empty() {
+                eprintln!("{} clients differ from the expected report", differences.len());
+                process::exit(EXIT_MISMATCHES);
+            }
+            return Ok(());
+        }
+        (Some(Command::Diff { before, after }), _) => {
+            let before = diff::read_state(File::open(before)?)?;
+            let after = diff::read_state(File::open(after)?)?;
+            let changes = diff::diff(&cli.output.balances(before), &cli.output.balances(after));
+            return match &cli.output.output {
+                Some(path) => write_output(path, |file| diff::write_changes(&changes, file)),
+                None => diff::write_changes(&changes, io::stdout().lock()),
+            };
+        }
+        (
+            Some(Command::Replay {
+                events,
+                expected,
+                state_hash,
+            }),
+            _,
+        ) => {
+            let balances = event_log::replay(BufReader::new(File::open(events)?))?;
+            let mut matches = true;
+            if let Some(expected) = state_hash {
+                let actual = payments_engine::engine::state_hash(&balances);
+                if &actual != expected {
+                    eprintln!("The replayed state has the hash {}, not {}", actual, expected);
+                    matches = false;
+                }
+            }
+            if let Some(expected) = expected {
+                let changes = diff::diff(&diff::read_state(File::open(expected)?)?, &balances);
+                if !changes.is_empty() {
+                    eprintln!("{} clients differ from the expected state:", changes.len());
+                    diff::write_changes(&changes, io::stderr().lock())?;
+                    matches = false;
+                }
+            }
+            match &cli.output.output {
+                Some(path) => write_output(path, |file| {
+                    Ok(writeln!(file, "{}", cli.output.format_report(balances, &engine))?)
+                })?,
+                None => print_report(&cli.output.format_report(balances, &engine))?,
+            }
+            if !matches {
+                process::exit(EXIT_MISMATCHES);
+            }
+            return Ok(());
+        }
+        (Some(Command::VerifyAudit { events, head }), _) => {
+            let chain = event_log::verify_chain(BufReader::new(File::open(events)?))?;
+            print_report(&format!(
+                "{} events, the last one with the hash {}",
+                chain.events, chain.hash
+            ))?;
+            if head.as_ref().is_some_and(|head| head != &chain.hash) {
+                eprintln!("The last event isn't the expected one, the log was truncated or replaced");
+                process::exit(EXIT_MISMATCHES);
+            }
+            return Ok(());
+        }
+        (Some(Command::Merge { reports, rejections }), _) => {
+            let mut parts = vec![];
+            for path in reports {
+                parts.push((path.display().to_string(), reconcile::read_expected(File::open(path)?)?));
+            }
+            let balances = merge::merge_reports(parts)?;
+            if let Some(path) = cli.output.rejection_report.as_ref().filter(|_| !rejections.is_empty()) {
+                let parts = rejections.iter().map(File::open).collect::<Result<Vec<_>, _>>()?;
+                merge::merge_rejection_reports(parts, File::create(path)?)?;
+            }
+            match &cli.output.output {
+                Some(path) => write_output(path, |file| {
+                    Ok(writeln!(file, "{}", cli.output.format_report(balances, &engine))?)
+                })?,
+                None => print_report(&cli.output.format_report(balances, &engine))?,
+            }
+            return Ok(());
+        }
+        (
+            Some(Command::Bench {
+                rows,
+                client_count,
+                seed,
+            }),
+            _,
+        ) => {
+            let csv = bench::generate_csv(*rows, *client_count, *seed);
+            // The generated input is resident already, only what is added to it is measured
+            let rss = bench::reset_peak_rss().then(bench::rss_bytes).flatten();
+            let allocations_before = allocations();
+            let start = Instant::now();
+            engine.process_csv_reader(csv.as_slice())?;
+            let report = bench::BenchReport {
+                rows: *rows,
+                elapsed: start.elapsed(),
+                peak_memory_bytes: rss
+                    .zip(bench::peak_rss_bytes())
+                    .map(|(before, peak)| peak.saturating_sub(before)),
+                allocations: allocations()
+                    .zip(allocations_before)
+                    .map(|(after, before)| after - before),
+            };
+            print_report(&report.to_string())?;
+            return Ok(());
+        }
+        // Done before reading the config file, which may not exist yet
+        (Some(Command::Config { .. } | Command::Account { .. } | Command::MigrateState { .. }), _) => unreachable!(),
+        (
+            Some(Command::Replica {
+                events,
+                report_interval,
+            }),
+            _,
+        ) => {
+            let mut last_output: Option<String> = None;
+            return Replica::new().follow(events, Duration::from_secs(*report_interval), |replica| {
+                let output = cli.output.format_report(replica.balances(), &engine);
+                if last_output.as_ref() != Some(&output) {
+                    match &cli.output.output {
+                        Some(path) => write_output(path, |file| Ok(writeln!(file, "{}", output)?))?,
+                        // Leave an empty line between reports
+                        None => print_report(&format!(
+                            "{}
+",
+                            output
+                        ))?,
+                    }
+                    last_output = Some(output);
+                }
+                Ok(ControlFlow::Continue(()))
+            });
+        }
+        (Some(Command::Repl), _) => {
+            let prompt = if io::stdin().is_terminal() { "> " } else { "" };
+            return repl::run(io::stdin().lock(), io::stdout().lock(), prompt);
+        }
+        #[cfg(feature = "grpc")]
+        (
+            Some(Command::Serve {
+                listen,
+                idempotency_retention,
+                actors,
+            }),
+            _,
+        ) => {
+            let service = match *actors {
+                1 => PaymentsEngineService::new(with_webhooks(engine)?),
+                #[cfg(feature = "sqlite")]
+                _ if cli.engine.sqlite.is_some() => return Err("--actors can't be used with --sqlite".into()),
+                actors => PaymentsEngineService::with_actors(actors, || with_webhooks(cli.engine.engine()?))?,
+            };
+            let service = service.with_idempotency_store(
+                cli.engine.idempotency_store()?,
+                Duration::from_secs(*idempotency_retention),
+            );
+            #[cfg(feature = "tui")]
+            if cli.dashboard {
+                return serve_with_dashboard(service, *listen);
+            }
+            let runtime = tokio::runtime::Runtime::new()?;
+            return runtime.block_on(grpc::serve(service, *listen));
+        }
+        (None, Some(input)) if cli.follow => return follow(cli, input, &mut with_webhooks(engine)?),
+        (None, Some(input)) if cli.close_after.is_some() => return process_closing(cli, input, format, engine),
+        (None, Some(input)) if cli.tenants => {
+            if format != InputFormat::Csv {
+                return Err(Box::new(InputSourceError {
+                    error_type: InputSourceErrorType::NotTenanted,
+                }));
+            }
+            let options = engine.config().csv.for_input(input);
+            let mut tenants = TenantEngines::new(|| cli.engine.engine());
+            tenants.process_csv_reader(engine.open_input(input)?, &options)?;
+            return cli.output.write_tenant_report(&tenants);
+        }
+        (None, Some(input)) => {
+            let interrupt = interrupt_on_signals()?;
+            if let Some(max_duration) = cli.max_duration {
+                let interrupt = Arc::clone(&interrupt);
+                thread::spawn(move || {
+                    thread::sleep(max_duration);
+                    interrupt.store(true, Ordering::Relaxed);
+                });
+            }
+            engine = engine.with_interrupt(interrupt);
+            if let Some(path) = &cli.resume {
+                let checkpoint: Checkpoint = serde_json::from_str(&fs::read_to_string(path)?)?;
+                if checkpoint.input != *input {
+                    return Err(format!(
+                        "The checkpoint {} is of {}, not of {}",
+                        path.display(),
+                        checkpoint.input,
+                        input
+                    )
+                    .into());
+                }
+                let state = checkpoint
+                    .state
+                    .ok_or_else(|| format!("The checkpoint {} has no state", path.display()))?;
+                engine.restore_snapshot(BufReader::new(File::open(state)?))?;
+                engine = engine.with_resume_after(checkpoint.line);
+            }
+            engine.process_input(input, format)?;
+        }
+        // clap requires the input when there is no subcommand
+        (None, None) => unreachable!(),
     }
 
-    #[test]
-    fn test_missing_dispute_chargeback() {
-        let expected = r"client, available, held, total, locked
-1, 1.0000, 0.0000, 1.0000, false";
-        test_csv("sample_files/chargeback_missing_dispute.csv", expected);
+    cli.output.write_report(&engine)?;
+    if let (Some(position), Some(input)) = (engine.interrupted_at(), &cli.input) {
+        let out_of_time = cli
+            .max_duration
+            .is_some_and(|max_duration| started.elapsed() >= max_duration);
+        let stats = engine.stats();
+        let mut checkpoint = Checkpoint {
+            input: input.clone(),
+            reason: if out_of_time { "max_duration" } else { "interrupted" }.to_string(),
+            line: position.line(),
+            processed: stats.processed,
+            rejected: stats.rejected,
+            remaining_estimate: remaining_estimate(input, position),
+            state_hash: engine.state_hash()?,
+            state: None,
+        };
+        let stopped = if out_of_time { "Out of time" } else { "Interrupted" };
+        let remaining = match checkpoint.remaining_estimate {
+            Some(remaining) => format!(", about {} rows left", remaining),
+            None => String::new(),
+        };
+        eprintln!(
+            "{} after line {} of {}, {} transactions processed{}, the report is of the transactions until there",
+            stopped, checkpoint.line, input, stats.processed, remaining
+        );
+        match &cli.checkpoint {
+            Some(path) => {
+                let state = format!("{}.state", path.display());
+                let mut writer = BufWriter::new(File::create(&state)?);
+                engine.write_snapshot(&mut writer)?;
+                writer.flush()?;
+                checkpoint.state = Some(state);
+                fs::write(path, serde_json::to_string(&checkpoint)? + "\n")?;
+            }
+            None => eprintln!("{}", serde_json::to_string(&checkpoint)?),
+        }
+        process::exit(EXIT_INTERRUPTED);
     }
+    Ok(())
+}
 
-    #[test]
-    fn test_dispute_chargeback() {
-        let expected = r"client, available, held, total, locked
-1, 0.0000, 0.0000, 0.0000, true";
-        test_csv("sample_files/dispute_chargeback.csv", expected);
+/// Where a run stopped before the end of its input was, so that it can be picked up after `line`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Checkpoint {
+    input: String,
+    /// Why it stopped, `interrupted` by a signal or `max_duration`.
+    reason: String,
+    /// The last line of the input read, 0 if it stopped before the first record.
+    line: u64,
+    processed: u64,
+    rejected: u64,
+    /// About how many rows of the input are left, going by the size of the rows read, if it is
+    /// a local file.
+    remaining_estimate: Option<u64>,
+    state_hash: String,
+    /// The snapshot of the state until `line`, when the checkpoint is written to a file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state: Option<String>,
+}
+
+/// About how many rows of the local file `input` are left after `position`.
+fn remaining_estimate(input: &str, position: &csv::Position) -> Option<u64> {
+    if input == input::STDIN || input::is_url(input) || position.byte() == 0 {
+        return None;
     }
+    let size = fs::metadata(input).ok()?.len();
+    let rows_per_byte = position.line() as f64 / position.byte() as f64;
+    Some((size.saturating_sub(position.byte()) as f64 * rows_per_byte).round() as u64)
+}
 
-    #[test]
-    fn test_dispute_resolve() {
-        let expected = r"client, available, held, total, locked
-1, 1.0000, 0.0000, 1.0000, false";
-        test_csv("sample_files/dispute_resolve.csv", expected);
+/// A flag set by the first SIGINT or SIGTERM. A second one exits right away, like without it.
+fn interrupt_on_signals() -> io::Result<Arc<AtomicBool>> {
+    let interrupt = Arc::new(AtomicBool::new(false));
+    #[cfg(any(unix, windows))]
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        signal_hook::flag::register_conditional_shutdown(signal, 128 + signal, Arc::clone(&interrupt))?;
+        signal_hook::flag::register(signal, Arc::clone(&interrupt))?;
     }
+    Ok(interrupt)
+}
+
+/// The error printed to stderr when a run fails.
+#[derive(Debug, Serialize)]
+struct Failure {
+    #[serde(skip)]
+    kind: FailureKind,
+    code: &'static str,
+    message: String,
+    line: Option<u64>,
+    file: Option<String>,
+}
 
-    #[test]
-    fn test_dispute() {
-        let expected = r"client, available, held, total, locked
-1, 0.0000, 1.0000, 1.0000, false";
-        test_csv("sample_files/dispute.csv", expected);
+impl Failure {
+    fn new(err: &(dyn Error + 'static), input: Option<&str>) -> Self {
+        let kind = FailureKind::of(err);
+        let mut file = input.map(String::from);
+        let mut line = None;
+
+        // Unwrap the errors that only say where the failure happened
+        let mut err = err;
+        if let Some(file_error) = err.downcast_ref::<FileError>() {
+            file = Some(file_error.file.clone());
+            err = file_error.error.as_ref();
+        }
+        if let Some(line_error) = err.downcast_ref::<LineError>() {
+            line = Some(line_error.line);
+            err = line_error.error.as_ref();
+        }
+        if let Some(csv_error) = err.downcast_ref::<csv::Error>() {
+            line = line.or_else(|| csv_error.position().map(|position| position.line()));
+        }
+
+        Failure {
+            kind,
+            code: kind.code(),
+            message: err.to_string(),
+            line,
+            file,
+        }
     }
 
-    #[test]
-    fn test_invalid_csv() {
-        assert!(process_csv(&Path::new("sample_files/invalid_csv.csv")).is_err());
+    fn exit(&self) -> ! {
+        eprintln!(
+            "{}",
+            serde_json::to_string(self).unwrap_or_else(|_| self.message.clone())
+        );
+        process::exit(match self.kind {
+            FailureKind::Internal => 1,
+            FailureKind::BadArguments => 2,
+            FailureKind::Io => 3,
+            FailureKind::Parse => 4,
+            FailureKind::Semantic => 5,
+        })
     }
+}
 
-    #[test]
-    fn test_withdrawal_insufficient_funds() {
-        let expected = r"client, available, held, total, locked
-1, 1.0000, 0.0000, 1.0000, false";
-        test_csv("sample_files/withdrawal_insufficient_funds.csv", expected);
+fn main() {
+    // Get CSV path from the command arguments
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(err) if !err.use_stderr() => err.exit(),
+        Err(err) => Failure {
+            kind: FailureKind::BadArguments,
+            code: FailureKind::BadArguments.code(),
+            // The error and its details, e.g. the arguments missing, without the usage after them
+            message: err
+                .to_string()
+                .lines()
+                .take_while(|line| !line.starts_with("Usage:") && !line.starts_with("For more information"))
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ")
+                .trim_start_matches("error: ")
+                .to_string(),
+            line: None,
+            file: None,
+        }
+        .exit(),
+    };
+
+    // Process the CSV and abort on uncaught errors
+    if let Err(err) = run(&cli) {
+        // Rust ignores SIGPIPE, so a reader that stopped reading, e.g. `head`, surfaces as an
+        // error writing the output, after which there is no one left to report to
+        if custom_errors::is_broken_pipe(err.as_ref()) {
+            process::exit(0);
+        }
+        Failure::new(err.as_ref(), cli.input.as_deref()).exit();
     }
-    
 }
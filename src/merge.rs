@@ -0,0 +1,124 @@
+//! Merges the partial reports of a sharded run, see [`Shard`](crate::shard::Shard), into the
+//! report of a single run.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
+
+use crate::accounts::AccountBalance;
+use crate::transactions::ClientId;
+
+/// A client is in two of the partial reports, so they aren't of disjoint shards.
+#[derive(Debug)]
+pub struct DuplicateClientError {
+    pub client: ClientId,
+    pub first: String,
+    pub second: String,
+}
+
+impl Error for DuplicateClientError {}
+
+impl fmt::Display for DuplicateClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Client {} is in both {} and {}, which aren't of different shards",
+            self.client, self.first, self.second
+        )
+    }
+}
+
+/// Merges the balances of the named partial reports, sorted by client id.
+pub fn merge_reports(parts: Vec<(String, Vec<AccountBalance>)>) -> Result<Vec<AccountBalance>, DuplicateClientError> {
+    let mut merged: BTreeMap<ClientId, (usize, AccountBalance)> = BTreeMap::new();
+    for (part, (_, balances)) in parts.iter().enumerate() {
+        for account_balance in balances {
+            if let Some((first, _)) = merged.insert(account_balance.client, (part, account_balance.clone())) {
+                return Err(DuplicateClientError {
+                    client: account_balance.client,
+                    first: parts[first].0.clone(),
+                    second: parts[part].0.clone(),
+                });
+            }
+        }
+    }
+    Ok(merged
+        .into_values()
+        .map(|(_, account_balance)| account_balance)
+        .collect())
+}
+
+/// Merges partial rejection reports, as written by
+/// [`rejections::write_report`](crate::rejections::write_report), in the order of their lines.
+/// The malformed records, which all the shards skip, are only listed once.
+pub fn merge_rejection_reports<R: Read, W: Write>(parts: Vec<R>, writer: W) -> Result<(), Box<dyn Error>> {
+    let mut rows: BTreeSet<(Option<u64>, Vec<String>)> = BTreeSet::new();
+    for part in parts {
+        let mut rdr = csv::Reader::from_reader(part);
+        for record in rdr.records() {
+            let record = record?;
+            let line = record.get(0).and_then(|line| line.parse().ok());
+            rows.insert((line, record.iter().map(String::from).collect()));
+        }
+    }
+
+    let mut wtr = csv::Writer::from_writer(writer);
+    wtr.write_record(["line", "type", "client", "tx", "reason", "error"])?;
+    for (_, row) in rows {
+        wtr.write_record(row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{merge_rejection_reports, merge_reports};
+    use crate::accounts::format_report;
+    use crate::engine::PaymentsEngine;
+    use crate::reconcile::read_expected;
+    use crate::rejections::write_report;
+    use crate::shard::{Shard, ShardMethod};
+
+    #[test]
+    fn test_merge() {
+        let input = Path::new("sample_files/multiple_clients.csv");
+        let mut reports = vec![];
+        let mut rejections = vec![];
+        for index in 1..=2 {
+            let mut engine = PaymentsEngine::new()
+                .with_shard(Shard {
+                    index,
+                    count: 2,
+                    method: ShardMethod::Hash,
+                })
+                .with_rejections();
+            engine.process_csv(input).unwrap();
+            let report = format_report(&engine.balances().unwrap());
+            reports.push((format!("part-{}.csv", index), read_expected(report.as_bytes()).unwrap()));
+            let mut rejection_report = vec![];
+            write_report(engine.rejections(), &engine.stats().malformed, &mut rejection_report).unwrap();
+            rejections.push(rejection_report);
+        }
+
+        // The same report and rejections as a single run
+        let merged = merge_reports(reports.clone()).unwrap();
+        assert_eq!(format_report(&merged), crate::process_csv(input).unwrap().to_string());
+        let mut engine = PaymentsEngine::new().with_rejections();
+        engine.process_csv(input).unwrap();
+        let mut expected = vec![];
+        write_report(engine.rejections(), &engine.stats().malformed, &mut expected).unwrap();
+        let mut merged = vec![];
+        merge_rejection_reports(rejections.iter().map(Vec::as_slice).collect(), &mut merged).unwrap();
+        assert_eq!(String::from_utf8(merged).unwrap(), String::from_utf8(expected).unwrap());
+
+        // A client can't be in two parts
+        let duplicate = reports[0].1[0].clone();
+        reports[1].1.push(duplicate);
+        let err = merge_reports(reports).unwrap_err();
+        assert_eq!((err.first.as_str(), err.second.as_str()), ("part-1.csv", "part-2.csv"));
+    }
+}
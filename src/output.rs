@@ -0,0 +1,325 @@
+//! Writes the account balances report as several files, for very wide outputs, and the output
+//! files atomically.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::accounts::{AccountBalance, AmountFormat, REPORT_COLUMNS, REPORT_HEADER};
+use crate::clients::{ClientDirectory, CLIENT_COLUMNS};
+use crate::transactions::ClientId;
+
+/// The name of the file listing the partitions of a split report.
+pub const INDEX_FILE: &str = "index.csv";
+
+/// A file written under a temporary name next to its path, and only renamed to it by
+/// [`commit`](AtomicFile::commit), so that a run that fails or crashes midway never leaves
+/// a truncated file there. The temporary file is removed if it isn't committed.
+pub struct AtomicFile {
+    path: PathBuf,
+    temp: PathBuf,
+    file: BufWriter<File>,
+    committed: bool,
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let temp = path.with_file_name(format!(".{}.{}.tmp", name, process::id()));
+        Ok(AtomicFile {
+            path: path.to_path_buf(),
+            file: BufWriter::new(File::create(&temp)?),
+            temp,
+            committed: false,
+        })
+    }
+
+    /// Replaces the file at the path with everything written, once it is on disk.
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+        fs::rename(&self.temp, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// How to partition the report into several files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputSplit {
+    /// Consecutive ranges of this many client ids per file.
+    ClientRange(u32),
+    /// This many files, with the clients assigned by `client % shards`.
+    Shards(u16),
+}
+
+#[derive(Debug)]
+pub struct OutputSplitFromStrError;
+
+impl Error for OutputSplitFromStrError {}
+
+impl fmt::Display for OutputSplitFromStrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Expected `client-range:<SIZE>` or `shards:<COUNT>`, with a positive number")
+    }
+}
+
+impl FromStr for OutputSplit {
+    type Err = OutputSplitFromStrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("client-range", size)) => match size.parse() {
+                Ok(size) if size > 0 => Ok(OutputSplit::ClientRange(size)),
+                _ => Err(OutputSplitFromStrError),
+            },
+            Some(("shards", count)) => match count.parse() {
+                Ok(count) if count > 0 => Ok(OutputSplit::Shards(count)),
+                _ => Err(OutputSplitFromStrError),
+            },
+            _ => Err(OutputSplitFromStrError),
+        }
+    }
+}
+
+impl OutputSplit {
+    /// The file a client is written to.
+    fn file_name(&self, client: ClientId) -> String {
+        match *self {
+            OutputSplit::ClientRange(size) => {
+                let start = client as u64 / size as u64 * size as u64;
+                let end = (start + size as u64 - 1).min(ClientId::MAX as u64);
+                format!("clients-{:05}-{:05}.csv", start, end)
+            }
+            OutputSplit::Shards(count) => format!("shard-{:05}-of-{:05}.csv", client as u64 % count as u64, count),
+        }
+    }
+
+    /// Whether a file is a partition of any split, e.g. of an earlier run with another one.
+    fn is_partition(file_name: &str) -> bool {
+        let numbered = |name: &str, separator: &str| {
+            name.split_once(separator).is_some_and(|(first, second)| {
+                [first, second]
+                    .iter()
+                    .all(|number| !number.is_empty() && number.bytes().all(|digit| digit.is_ascii_digit()))
+            })
+        };
+        match file_name.strip_suffix(".csv") {
+            Some(name) => {
+                name.strip_prefix("clients-").is_some_and(|range| numbered(range, "-"))
+                    || name.strip_prefix("shard-").is_some_and(|shard| numbered(shard, "-of-"))
+            }
+            None => false,
+        }
+    }
+}
+
+/// An entry of the index file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Partition {
+    pub file: String,
+    pub accounts: u64,
+    pub first_client: ClientId,
+    pub last_client: ClientId,
+}
+
+/// Writes the balances as one report file per (non-empty) partition in `dir`, with the amounts
+/// formatted as `amounts`, plus an index file listing the partitions. The partitions that the
+/// index doesn't list, left by an earlier run with another split, are removed once it is written.
+pub fn write_split_report(
+    balances: &[AccountBalance],
+    clients: Option<&ClientDirectory>,
+    amounts: &AmountFormat,
+    split: OutputSplit,
+    dir: &Path,
+) -> Result<Vec<Partition>, Box<dyn Error>> {
+    fs::create_dir_all(dir)?;
+
+    let mut partitions: BTreeMap<String, Vec<&AccountBalance>> = BTreeMap::new();
+    for account_balance in balances {
+        partitions
+            .entry(split.file_name(account_balance.client))
+            .or_default()
+            .push(account_balance);
+    }
+
+    let mut index = vec![];
+    for (file_name, accounts) in partitions {
+        let mut wtr = AtomicFile::create(&dir.join(&file_name))?;
+        match clients {
+            Some(_) => writeln!(wtr, "{}, {}", REPORT_HEADER, CLIENT_COLUMNS)?,
+            None => writeln!(wtr, "{}", REPORT_HEADER)?,
+        }
+        for account_balance in &accounts {
+            let mut row: Vec<String> = REPORT_COLUMNS
+                .iter()
+                .map(|column| column.value(account_balance, amounts))
+                .collect();
+            row.extend(clients.map(|clients| clients.columns(account_balance.client)));
+            writeln!(wtr, "{}", row.join(", "))?;
+        }
+        wtr.commit()?;
+
+        index.push(Partition {
+            file: file_name,
+            accounts: accounts.len() as u64,
+            first_client: accounts.iter().map(|account| account.client).min().unwrap_or_default(),
+            last_client: accounts.iter().map(|account| account.client).max().unwrap_or_default(),
+        });
+    }
+
+    let mut file = AtomicFile::create(&dir.join(INDEX_FILE))?;
+    let mut wtr = csv::Writer::from_writer(&mut file);
+    for partition in &index {
+        wtr.serialize(partition)?;
+    }
+    wtr.flush()?;
+    drop(wtr);
+    file.commit()?;
+
+    let listed: BTreeSet<&str> = index.iter().map(|partition| partition.file.as_str()).collect();
+    for entry in fs::read_dir(dir)? {
+        let file_name = entry?.file_name();
+        let file_name = file_name.to_string_lossy();
+        if OutputSplit::is_partition(&file_name) && !listed.contains(file_name.as_ref()) {
+            fs::remove_file(dir.join(file_name.as_ref()))?;
+        }
+    }
+
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use std::io::Write;
+
+    use super::{write_split_report, AtomicFile, OutputSplit, INDEX_FILE};
+    use crate::accounts::{AccountBalance, AmountFormat};
+    use crate::transactions::ClientId;
+
+    #[test]
+    fn test_parse_output_split() {
+        assert_eq!(
+            "client-range:10000".parse::<OutputSplit>().unwrap(),
+            OutputSplit::ClientRange(10000)
+        );
+        assert_eq!("shards:16".parse::<OutputSplit>().unwrap(), OutputSplit::Shards(16));
+        assert!("client-range:0".parse::<OutputSplit>().is_err());
+        assert!("clients:10".parse::<OutputSplit>().is_err());
+    }
+
+    #[test]
+    fn test_split_by_client_range() {
+        let dir = std::env::temp_dir().join("payments_engine_split_report");
+        let _ = fs::remove_dir_all(&dir);
+        let balances: Vec<AccountBalance> = [1, 9, 25, ClientId::MAX]
+            .iter()
+            .map(|&client| AccountBalance::new(client))
+            .collect();
+
+        write_split_report(
+            &balances,
+            None,
+            &AmountFormat::default(),
+            OutputSplit::ClientRange(10),
+            &dir,
+        )
+        .unwrap();
+
+        // The last range stops at the largest client id
+        let last = ClientId::MAX;
+        assert_eq!(
+            fs::read_to_string(dir.join(INDEX_FILE)).unwrap(),
+            format!(
+                "file,accounts,first_client,last_client\n\
+                 clients-00000-00009.csv,2,1,9\n\
+                 clients-00020-00029.csv,1,25,25\n\
+                 clients-{}-{}.csv,1,{},{}\n",
+                last / 10 * 10,
+                last,
+                last,
+                last
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("clients-00000-00009.csv")).unwrap(),
+            "client, available, held, pending, total, locked\n\
+             1, 0.0000, 0.0000, 0.0000, 0.0000, false\n\
+             9, 0.0000, 0.0000, 0.0000, 0.0000, false\n"
+        );
+    }
+
+    #[test]
+    fn test_split_again() {
+        let dir = std::env::temp_dir().join("payments_engine_split_again");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("clients.csv"), "not a partition").unwrap();
+        let balances: Vec<AccountBalance> = [1, 9, 25].iter().map(|&client| AccountBalance::new(client)).collect();
+        let split = |split| write_split_report(&balances, None, &AmountFormat::default(), split, &dir).unwrap();
+
+        split(OutputSplit::ClientRange(10));
+        let partitions = split(OutputSplit::Shards(2));
+        // Only the files of the last split are left, with the ones that aren't partitions
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["clients.csv", INDEX_FILE, "shard-00001-of-00002.csv"]);
+        assert_eq!(partitions.len(), 1);
+        assert!(!OutputSplit::is_partition("shard-1.csv"));
+        assert!(OutputSplit::is_partition("clients-00010-00019.csv"));
+    }
+
+    #[test]
+    fn test_atomic_file() {
+        let dir = std::env::temp_dir().join("payments_engine_atomic_file");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("accounts.csv");
+        fs::write(&path, "old").unwrap();
+
+        // Until it is committed, the old file is there, and a failed write leaves it
+        let mut file = AtomicFile::create(&path).unwrap();
+        write!(file, "partial").unwrap();
+        drop(file);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        write!(file, "new").unwrap();
+        file.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
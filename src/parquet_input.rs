@@ -0,0 +1,77 @@
+//! Reads transactions from Parquet files.
+//!
+//! The file is read as Arrow record batches, so it has the same column
+//! requirements as [`PaymentsEngine::apply_record_batch`].
+
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+use crate::engine::PaymentsEngine;
+
+impl PaymentsEngine {
+    /// Applies all the transactions in a Parquet file.
+    pub fn process_parquet(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let file = File::open(path)?;
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)?.build()?;
+
+        for batch in reader {
+            self.apply_record_batch(&batch?)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use arrow_array::{Float64Array, Int64Array, RecordBatch, StringArray};
+    use parquet::arrow::ArrowWriter;
+
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_parquet_matches_csv() {
+        // Same transactions as sample_files/deposit_withdrawal.csv, with the wider
+        // column types most data lake tools write by default
+        let batch = RecordBatch::try_from_iter(vec![
+            (
+                "type",
+                Arc::new(StringArray::from(vec![
+                    "deposit",
+                    "deposit",
+                    "deposit",
+                    "withdrawal",
+                    "withdrawal",
+                ])) as _,
+            ),
+            ("client", Arc::new(Int64Array::from(vec![1, 1, 2, 1, 2])) as _),
+            ("tx", Arc::new(Int64Array::from(vec![1, 2, 3, 4, 5])) as _),
+            (
+                "amount",
+                Arc::new(Float64Array::from(vec![1.0, 2.0, 2.0, 1.5, 1.5])) as _,
+            ),
+        ])
+        .unwrap();
+
+        let path = std::env::temp_dir().join("payments_engine_deposit_withdrawal.parquet");
+        let mut writer = ArrowWriter::try_new(File::create(&path).unwrap(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let mut engine = PaymentsEngine::new();
+        engine.process_parquet(&path).unwrap();
+        assert_eq!(
+            engine.report().unwrap(),
+            crate::process_csv(Path::new("sample_files/deposit_withdrawal.csv"))
+                .unwrap()
+                .to_string()
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Python bindings, built as the `payments_engine` package with maturin.
+//!
+//! ```python
+//! from payments_engine import PaymentsEngine
+//!
+//! engine = PaymentsEngine()
+//! engine.process_csv("transactions.csv")
+//! engine.apply({"type": "deposit", "client": 1, "tx": 42, "amount": 1.5})  # None, applied
+//! engine.apply({"type": "withdrawal", "client": 1, "tx": 43, "amount": 9.0})  # "INSUFFICIENT_FUNDS"
+//! engine.balances()
+//! ```
+
+use std::error::Error;
+use std::path::PathBuf;
+
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+
+use crate::accounts::AccountBalance;
+use crate::amount::AmountValue;
+use crate::custom_errors::FailureKind;
+use crate::engine::PaymentsEngine;
+use crate::transactions::{Amount, Transaction, TransactionType};
+
+/// Maps an engine error to the closest Python exception.
+fn to_py_err(err: Box<dyn Error>) -> PyErr {
+    match FailureKind::of(err.as_ref()) {
+        FailureKind::Io => PyIOError::new_err(err.to_string()),
+        _ => PyValueError::new_err(err.to_string()),
+    }
+}
+
+/// Get a required key of a transaction dict.
+fn required<'py>(dict: &Bound<'py, PyDict>, key: &str) -> PyResult<Bound<'py, PyAny>> {
+    dict.get_item(key)?
+        .ok_or_else(|| PyValueError::new_err(format!("The transaction is missing the `{}` key", key)))
+}
+
+/// A balance as a dict with the columns of the report and the `status`.
+fn balance_dict<'py>(py: Python<'py>, account_balance: &AccountBalance) -> PyResult<Bound<'py, PyDict>> {
+    let balance = PyDict::new(py);
+    balance.set_item("client", account_balance.client)?;
+    balance.set_item("available", account_balance.available.to_f64())?;
+    balance.set_item("held", account_balance.held.to_f64())?;
+    balance.set_item("pending", account_balance.pending.to_f64())?;
+    balance.set_item("total", account_balance.total().to_f64())?;
+    balance.set_item("locked", account_balance.is_locked())?;
+    balance.set_item("status", account_balance.status.as_str())?;
+    Ok(balance)
+}
+
+/// The payments engine, keeping all of its state in memory.
+#[pyclass(name = "PaymentsEngine", unsendable)]
+pub struct PyPaymentsEngine {
+    engine: PaymentsEngine,
+}
+
+#[pymethods]
+impl PyPaymentsEngine {
+    #[new]
+    fn new() -> Self {
+        PyPaymentsEngine {
+            engine: PaymentsEngine::new(),
+        }
+    }
+
+    /// Applies a transaction given as a dict with `type`, `client`, `tx` and an optional `amount`, `reason`, `timestamp` and `settles_at`.
+    /// Returns `None` if it was applied, or the code of why it was rejected, e.g. `INSUFFICIENT_FUNDS`.
+    fn apply(&mut self, transaction: &Bound<'_, PyDict>) -> PyResult<Option<&'static str>> {
+        let tx_type: String = required(transaction, "type")?.extract()?;
+        let amount = match transaction.get_item("amount")? {
+            Some(amount) if !amount.is_none() => Some(Amount::from_f64(amount.extract()?)),
+            _ => None,
+        };
+        let transaction = Transaction {
+            tx_type: tx_type
+                .trim()
+                .parse::<TransactionType>()
+                .map_err(|err| PyValueError::new_err(err.to_string()))?,
+            client_id: required(transaction, "client")?.extract()?,
+            tx_id: required(transaction, "tx")?.extract()?,
+            amount,
+            reason: match transaction.get_item("reason")? {
+                Some(reason) if !reason.is_none() => Some(reason.extract()?),
+                _ => None,
+            },
+            timestamp: match transaction.get_item("timestamp")? {
+                Some(timestamp) if !timestamp.is_none() => Some(timestamp.extract()?),
+                _ => None,
+            },
+            settles_at: match transaction.get_item("settles_at")? {
+                Some(settles_at) if !settles_at.is_none() => Some(settles_at.extract()?),
+                _ => None,
+            },
+        };
+        let outcome = self.engine.apply(&transaction).map_err(to_py_err)?;
+        Ok(outcome.err().map(|rejected| rejected.reason.code()))
+    }
+
+    /// Applies all the transactions in a CSV file.
+    fn process_csv(&mut self, path: PathBuf) -> PyResult<()> {
+        self.engine.process_csv(&path).map_err(to_py_err)
+    }
+
+    /// All the account balances, sorted by client id, as a list of dicts.
+    fn balances<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        let balances = PyList::empty(py);
+        for account_balance in self.engine.balances().map_err(to_py_err)? {
+            balances.append(balance_dict(py, &account_balance)?)?;
+        }
+        Ok(balances)
+    }
+
+    /// The account balances report, as printed by the CLI.
+    fn report(&self) -> PyResult<String> {
+        self.engine.report().map_err(to_py_err)
+    }
+}
+
+#[pymodule]
+fn payments_engine(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyPaymentsEngine>()?;
+    Ok(())
+}
@@ -0,0 +1,179 @@
+//! Bounded queues between reading the transactions and applying them, in follow and serve modes,
+//! so that an input faster than the engine waits for it instead of piling up in memory.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvError, SendError, SyncSender, TrySendError};
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+/// The settings of the queues.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueConfig {
+    /// How many transactions, or batches of them in serve mode, can wait to be applied
+    /// before the input waits for the engine.
+    pub capacity: usize,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig { capacity: 1024 }
+    }
+}
+
+/// The state of a queue, at the time it was looked at.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueMetrics {
+    pub capacity: u64,
+    /// The items in the queue, including the ones waiting for room in it.
+    pub depth: u64,
+    /// The largest depth so far.
+    pub max_depth: u64,
+    /// The items queued so far.
+    pub enqueued: u64,
+    /// How many times an item had to wait for room in the queue.
+    pub full: u64,
+}
+
+/// Counts what goes through a queue, and is shared by both of its ends.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct QueueGauge(Arc<Counters>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    capacity: AtomicU64,
+    depth: AtomicU64,
+    max_depth: AtomicU64,
+    enqueued: AtomicU64,
+    full: AtomicU64,
+}
+
+impl QueueGauge {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let gauge = QueueGauge::default();
+        gauge.0.capacity.store(capacity as u64, Ordering::Relaxed);
+        gauge
+    }
+
+    /// Counts an item about to be queued.
+    pub(crate) fn on_send(&self) {
+        let depth = self.0.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.0.max_depth.fetch_max(depth, Ordering::Relaxed);
+        self.0.enqueued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts an item that has to wait for room in the queue.
+    pub(crate) fn on_full(&self) {
+        self.0.full.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts an item taken out of the queue.
+    pub(crate) fn on_receive(&self) {
+        self.0.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn metrics(&self) -> QueueMetrics {
+        QueueMetrics {
+            capacity: self.0.capacity.load(Ordering::Relaxed),
+            depth: self.0.depth.load(Ordering::Relaxed),
+            max_depth: self.0.max_depth.load(Ordering::Relaxed),
+            enqueued: self.0.enqueued.load(Ordering::Relaxed),
+            full: self.0.full.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The sending end of a [`bounded`] queue.
+pub(crate) struct QueueSender<T> {
+    sender: SyncSender<T>,
+    gauge: QueueGauge,
+}
+
+impl<T> QueueSender<T> {
+    /// Queues an item, waiting for room in the queue if it is full.
+    /// Fails if the receiving end is gone.
+    pub(crate) fn send(&self, item: T) -> Result<(), SendError<T>> {
+        self.gauge.on_send();
+        let result = match self.sender.try_send(item) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(item)) => {
+                self.gauge.on_full();
+                self.sender.send(item)
+            }
+            Err(TrySendError::Disconnected(item)) => Err(SendError(item)),
+        };
+        if result.is_err() {
+            self.gauge.on_receive();
+        }
+        result
+    }
+}
+
+/// The receiving end of a [`bounded`] queue.
+pub(crate) struct QueueReceiver<T> {
+    receiver: Receiver<T>,
+    gauge: QueueGauge,
+}
+
+impl<T> QueueReceiver<T> {
+    /// The next item, waiting for one. Fails once the queue is empty and the sending end is gone.
+    pub(crate) fn recv(&self) -> Result<T, RecvError> {
+        let item = self.receiver.recv()?;
+        self.gauge.on_receive();
+        Ok(item)
+    }
+}
+
+/// A queue with room for `capacity` items, which are counted by `gauge`.
+pub(crate) fn bounded<T>(capacity: usize, gauge: QueueGauge) -> (QueueSender<T>, QueueReceiver<T>) {
+    let (sender, receiver) = mpsc::sync_channel(capacity);
+    (
+        QueueSender {
+            sender,
+            gauge: gauge.clone(),
+        },
+        QueueReceiver { receiver, gauge },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::{bounded, QueueGauge, QueueMetrics};
+
+    #[test]
+    fn test_queue_metrics() {
+        let gauge = QueueGauge::new(2);
+        let (sender, receiver) = bounded(2, gauge.clone());
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
+        let expected = QueueMetrics {
+            capacity: 2,
+            depth: 2,
+            max_depth: 2,
+            enqueued: 2,
+            full: 0,
+        };
+        assert_eq!(gauge.metrics(), expected);
+
+        // The third item waits until there is room for it
+        let sending = thread::spawn(move || sender.send(3).unwrap());
+        while gauge.metrics().full == 0 {
+            thread::yield_now();
+        }
+        assert_eq!(receiver.recv(), Ok(1));
+        sending.join().unwrap();
+        assert_eq!(receiver.recv(), Ok(2));
+        assert_eq!(receiver.recv(), Ok(3));
+        let expected = QueueMetrics {
+            depth: 0,
+            max_depth: 3,
+            enqueued: 3,
+            full: 1,
+            ..expected
+        };
+        assert_eq!(gauge.metrics(), expected);
+    }
+}
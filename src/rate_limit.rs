@@ -0,0 +1,121 @@
+//! Per-client rate limits of the transactions submitted in follow and serve modes, so that a
+//! misbehaving integrator can't flood the engine. The transactions beyond them are rejected
+//! as [`RejectionReason::RateLimited`](crate::rejections::RejectionReason::RateLimited),
+//! and aren't kept as processed, so they can be submitted again later.
+//! They are set in the `[rate_limit]` section of the config file:
+//!
+//! ```toml
+//! [rate_limit]
+//! per_second = 10
+//! per_minute = 300
+//! ```
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::transactions::ClientId;
+
+/// How many transactions each client can submit, in bursts of up to the limit.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+    pub per_second: Option<u32>,
+    pub per_minute: Option<u32>,
+}
+
+impl RateLimits {
+    /// The limits that are set, with the time each of them is over.
+    fn limits(&self) -> impl Iterator<Item = (u32, Duration)> {
+        [
+            (self.per_second, Duration::from_secs(1)),
+            (self.per_minute, Duration::from_secs(60)),
+        ]
+        .into_iter()
+        .filter_map(|(limit, period)| Some((limit?, period)))
+    }
+}
+
+/// The transactions each client can still submit, by limit, refilled over time.
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    buckets: HashMap<ClientId, Vec<(f64, Instant)>>,
+}
+
+impl RateLimiter {
+    /// Whether the client can submit a transaction at `now`, which then counts towards its limits.
+    pub(crate) fn allow(&mut self, limits: &RateLimits, client: ClientId, now: Instant) -> bool {
+        if limits.limits().next().is_none() {
+            return true;
+        }
+        let buckets = self
+            .buckets
+            .entry(client)
+            .or_insert_with(|| limits.limits().map(|(limit, _)| (limit as f64, now)).collect());
+        for ((tokens, updated), (limit, period)) in buckets.iter_mut().zip(limits.limits()) {
+            let refill = now.saturating_duration_since(*updated).as_secs_f64() / period.as_secs_f64() * limit as f64;
+            *tokens = (*tokens + refill).min(limit as f64);
+            *updated = now;
+        }
+        if buckets.iter().any(|(tokens, _)| *tokens < 1.0) {
+            return false;
+        }
+        for (tokens, _) in buckets.iter_mut() {
+            *tokens -= 1.0;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{RateLimiter, RateLimits};
+    use crate::amount::amount;
+    use crate::config::EngineConfig;
+    use crate::engine::PaymentsEngine;
+    use crate::queue::QueueGauge;
+    use crate::rejections::RejectionReason;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_rate_limits() {
+        let limits = RateLimits {
+            per_second: Some(2),
+            per_minute: Some(3),
+        };
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        assert!(limiter.allow(&limits, 1, at(0)));
+        assert!(limiter.allow(&limits, 1, at(0)));
+        assert!(!limiter.allow(&limits, 1, at(100)));
+        // Each client has its own limits
+        assert!(limiter.allow(&limits, 2, at(100)));
+        // Back under the limit per second, but not per minute once it's used
+        assert!(limiter.allow(&limits, 1, at(1000)));
+        assert!(!limiter.allow(&limits, 1, at(2000)));
+        assert!(limiter.allow(&limits, 1, at(21_000)));
+        assert!(RateLimiter::default().allow(&RateLimits::default(), 1, at(0)));
+
+        // Only in follow and serve modes, when applying from a queue
+        let config = EngineConfig {
+            rate_limit: RateLimits {
+                per_second: None,
+                per_minute: Some(1),
+            },
+            ..EngineConfig::default()
+        };
+        let deposit = |tx: u32| Transaction::parse_csv_record(format!("deposit, 1, {}, 1.0", tx).as_bytes()).unwrap();
+        let mut engine = PaymentsEngine::new().with_config(config);
+        assert!(engine.apply(&deposit(1)).unwrap().is_ok());
+        assert!(engine.apply(&deposit(2)).unwrap().is_ok());
+        engine.set_queue(Some(QueueGauge::new(1)));
+        assert!(engine.apply(&deposit(3)).unwrap().is_ok());
+        let rejected = engine.apply(&deposit(4)).unwrap().unwrap_err();
+        assert_eq!(rejected.reason, RejectionReason::RateLimited);
+        assert_eq!(engine.account(1).unwrap().unwrap().available, amount(3.0));
+    }
+}
@@ -0,0 +1,187 @@
+//! Why the engine doesn't apply a transaction: the rejections of the well formed ones, which
+//! are ignored, and the errors of the malformed ones.
+
+use core::error::Error;
+use core::fmt;
+
+/// Why a well formed transaction was ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RejectionReason {
+    /// The account was locked by a chargeback.
+    AccountLocked,
+    /// The account was closed.
+    AccountClosed,
+    /// The account was already reported as final, its client idle for longer than the
+    /// watermark allows, see [`PaymentsEngine::with_close_after`](crate::PaymentsEngine::with_close_after).
+    AccountFinalized,
+    /// The account is frozen, so no funds can leave it.
+    AccountFrozen,
+    /// The available funds don't cover the transaction and its fees.
+    InsufficientFunds,
+    /// The balance, or its total, would be out of the range of the amounts, e.g. over 922
+    /// trillion with the `fixed-amounts` feature.
+    AmountOverflow,
+    /// The transaction it refers to isn't known, or can't be disputed.
+    UnknownTransaction,
+    /// The transaction it refers to is of another client.
+    ClientMismatch,
+    /// The transaction it refers to was already reversed.
+    AlreadyReversed,
+    /// The transaction it refers to is disputed.
+    Disputed,
+    /// The dispute is for more than the deposit it refers to.
+    ExceedsDeposit,
+    /// The resolve or chargeback refers to a transaction that isn't disputed.
+    NotDisputed,
+    /// The chargeback reversal refers to a transaction that wasn't charged back, or whose
+    /// chargeback was already reversed.
+    NotChargedBack,
+    /// The funds of the deposit it refers to haven't settled yet.
+    NotSettled,
+    /// The deposit it settles isn't pending, e.g. it already settled.
+    NotPending,
+    /// The account can't be closed with held or pending funds, or owing money.
+    OutstandingFunds,
+    /// The account is already frozen, or not frozen.
+    StatusUnchanged,
+    /// Transactions of this type are only posted by the engine itself.
+    ReservedType,
+    /// A transaction of the same type and id was already processed.
+    Duplicate,
+    /// The client is of another shard, see [`PaymentsEngine::with_shard`](crate::PaymentsEngine::with_shard).
+    /// These are skipped, and not counted as rejections.
+    OtherShard,
+    /// The signature of the record doesn't verify with the public key of the partner, or it has none.
+    /// Only when checking the signatures, with the `signatures` feature.
+    InvalidSignature,
+    /// The amount isn't a number, or is negative or zero, other than in an adjustment or a fee,
+    /// which can be negative or zero.
+    InvalidAmount,
+    /// The amount of the deposit or withdrawal is under the smallest one allowed, see
+    /// [`EngineConfig::limits`](crate::config::EngineConfig::limits).
+    BelowMinAmount,
+    /// The amount of the deposit or withdrawal is over the largest one allowed.
+    AboveMaxAmount,
+    /// The client submitted more transactions than its rate limits allow, in follow and serve
+    /// modes, see [`rate_limit`](crate::rate_limit). It can be submitted again later.
+    RateLimited,
+    /// The source of the input isn't allowed transactions of this type, see
+    /// [`EngineConfig::sources`](crate::config::EngineConfig::sources).
+    TypeNotAllowed,
+    /// The transaction broke one of the risk rules.
+    Risk(RiskViolation),
+}
+
+impl RejectionReason {
+    /// A stable reason code for the rejection.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RejectionReason::AccountLocked => "ACCOUNT_LOCKED",
+            RejectionReason::AccountClosed => "ACCOUNT_CLOSED",
+            RejectionReason::AccountFinalized => "ACCOUNT_FINALIZED",
+            RejectionReason::AccountFrozen => "ACCOUNT_FROZEN",
+            RejectionReason::InsufficientFunds => "INSUFFICIENT_FUNDS",
+            RejectionReason::AmountOverflow => "AMOUNT_OVERFLOW",
+            RejectionReason::UnknownTransaction => "UNKNOWN_TX",
+            RejectionReason::ClientMismatch => "CLIENT_MISMATCH",
+            RejectionReason::AlreadyReversed => "ALREADY_REVERSED",
+            RejectionReason::Disputed => "DISPUTED",
+            RejectionReason::ExceedsDeposit => "EXCEEDS_DEPOSIT",
+            RejectionReason::NotDisputed => "NOT_DISPUTED",
+            RejectionReason::NotChargedBack => "NOT_CHARGED_BACK",
+            RejectionReason::NotSettled => "NOT_SETTLED",
+            RejectionReason::NotPending => "NOT_PENDING",
+            RejectionReason::OutstandingFunds => "OUTSTANDING_FUNDS",
+            RejectionReason::StatusUnchanged => "STATUS_UNCHANGED",
+            RejectionReason::ReservedType => "RESERVED_TYPE",
+            RejectionReason::Duplicate => "DUPLICATE_TX",
+            RejectionReason::OtherShard => "OTHER_SHARD",
+            RejectionReason::InvalidSignature => "INVALID_SIGNATURE",
+            RejectionReason::InvalidAmount => "INVALID_AMOUNT",
+            RejectionReason::BelowMinAmount => "AMOUNT_BELOW_MIN",
+            RejectionReason::AboveMaxAmount => "AMOUNT_ABOVE_MAX",
+            RejectionReason::RateLimited => "RATE_LIMITED",
+            RejectionReason::TypeNotAllowed => "TYPE_NOT_ALLOWED",
+            RejectionReason::Risk(violation) => violation.code(),
+        }
+    }
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// Why a transaction was rejected by the risk rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RiskViolation {
+    /// The withdrawal is over [`RiskRules::max_withdrawal`](crate::risk::RiskRules::max_withdrawal).
+    MaxWithdrawal,
+    /// The withdrawal would go over [`RiskRules::max_daily_withdrawals`](crate::risk::RiskRules::max_daily_withdrawals).
+    MaxDailyWithdrawals,
+    /// The client is over its [`RiskRules::velocity`](crate::risk::RiskRules::velocity) limit.
+    Velocity,
+}
+
+impl RiskViolation {
+    /// A stable reason code for the violation.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RiskViolation::MaxWithdrawal => "RISK_MAX_WITHDRAWAL",
+            RiskViolation::MaxDailyWithdrawals => "RISK_MAX_DAILY_WITHDRAWALS",
+            RiskViolation::Velocity => "RISK_VELOCITY",
+        }
+    }
+}
+
+impl fmt::Display for RiskViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[derive(Debug)]
+pub enum TransactionErrorType {
+    NoDepositAmount,
+    NoWithdrawalAmount,
+    NoAdjustmentAmount,
+    NoAdjustmentReason,
+    AdjustmentNotAllowed,
+    NoFeeAmount,
+    StatusChangeNotAllowed,
+    InvalidDisputeAmount,
+    InvalidAmount,
+    NotSingleRecord,
+}
+
+#[derive(Debug)]
+pub struct TransactionRecordError {
+    pub error_type: TransactionErrorType,
+}
+
+impl Error for TransactionRecordError {}
+
+impl fmt::Display for TransactionRecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.error_type {
+            TransactionErrorType::NoDepositAmount => write!(f, "A deposit must have an amount"),
+            TransactionErrorType::NoWithdrawalAmount => write!(f, "An withdrawal must have an amount"),
+            TransactionErrorType::NoAdjustmentAmount => write!(f, "An adjustment must have an amount"),
+            TransactionErrorType::NoAdjustmentReason => write!(f, "An adjustment must have a reason"),
+            TransactionErrorType::AdjustmentNotAllowed => {
+                write!(f, "Adjustments are only allowed with admin operations enabled")
+            }
+            TransactionErrorType::NoFeeAmount => write!(f, "A fee must have an amount"),
+            TransactionErrorType::StatusChangeNotAllowed => {
+                write!(f, "Freezing accounts is only allowed with admin operations enabled")
+            }
+            TransactionErrorType::InvalidDisputeAmount => write!(f, "The amount of a dispute must be positive"),
+            TransactionErrorType::InvalidAmount => write!(
+                f,
+                "The amount must be a positive number, or any number in an adjustment"
+            ),
+            TransactionErrorType::NotSingleRecord => write!(f, "The line must have a single CSV record"),
+        }
+    }
+}
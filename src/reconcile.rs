@@ -0,0 +1,179 @@
+//! Compares the computed balances with a statement of the expected ones.
+
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::io::{Read, Write};
+
+use csv::StringRecord;
+use serde::Serialize;
+
+use crate::accounts::AccountBalance;
+use crate::engine::{csv_reader_builder, PaymentsEngine};
+use crate::transactions::{Amount, ClientId, TxId};
+
+/// Reads expected balances in the same format as the report. The `total` column is ignored,
+/// the `pending` one is optional, and the `#` lines like the `--append-summary` are skipped.
+pub fn read_expected<R: Read>(reader: R) -> Result<Vec<AccountBalance>, Box<dyn Error>> {
+    let mut rdr = csv_reader_builder().comment(Some(b'#')).from_reader(reader);
+    let mut balances = vec![];
+    for account_balance in rdr.deserialize() {
+        balances.push(account_balance?);
+    }
+    Ok(balances)
+}
+
+/// The transaction after which the balance of a client stopped matching the expected one for good.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    pub line: u64,
+    pub tx_id: TxId,
+}
+
+/// A client whose computed balance doesn't match the expected one.
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    pub client: ClientId,
+    /// `None` if the client isn't in the expected balances.
+    pub expected: Option<AccountBalance>,
+    /// `None` if the client has no transactions.
+    pub actual: Option<AccountBalance>,
+    /// The first transaction since which the balance never matched the expected one again.
+    /// `None` if the client has no transactions.
+    pub first_divergence: Option<Divergence>,
+}
+
+/// Whether the balances are the same, as far as the report can tell.
+fn matches(actual: &AccountBalance, expected: Option<&AccountBalance>) -> bool {
+    expected.is_some_and(|expected| expected.to_string() == actual.to_string())
+}
+
+impl PaymentsEngine {
+    /// Applies all the transactions read as CSV, like `process_csv_reader`, and returns
+    /// the clients whose final balance doesn't match the expected one, sorted by client id.
+    pub fn reconcile_csv_reader<R: Read>(
+        &mut self,
+        reader: R,
+        expected: &[AccountBalance],
+    ) -> Result<Vec<Mismatch>, Box<dyn Error>> {
+        let expected: HashMap<ClientId, &AccountBalance> = expected
+            .iter()
+            .map(|account_balance| (account_balance.client, account_balance))
+            .collect();
+        // For each client with transactions, since when its balance doesn't match, if it doesn't
+        let mut divergences: HashMap<ClientId, Option<Divergence>> = HashMap::new();
+
+        let options = self.config().csv.clone();
+        let (mut rdr, headers) = options.reader(reader)?;
+        let mut record = StringRecord::new();
+        while rdr.read_record(&mut record)? {
+            let line = record.position().map_or(0, |position| position.line());
+            let Some(transaction) = self.apply_record(line, options.deserialize(&record, &headers), options.lenient)?
+            else {
+                continue;
+            };
+
+            let client = transaction.client_id;
+            let actual = self.account(client)?.unwrap_or_else(|| AccountBalance::new(client));
+            let divergence = divergences.entry(client).or_default();
+            if matches(&actual, expected.get(&client).copied()) {
+                *divergence = None;
+            } else if divergence.is_none() {
+                *divergence = Some(Divergence {
+                    line,
+                    tx_id: transaction.tx_id,
+                });
+            }
+        }
+
+        let clients: BTreeSet<ClientId> = expected.keys().chain(divergences.keys()).copied().collect();
+        let mut mismatches = vec![];
+        for client in clients {
+            let actual = self.account(client)?;
+            let expected = expected.get(&client).map(|&account_balance| account_balance.clone());
+            let matched = match &actual {
+                Some(actual) => matches(actual, expected.as_ref()),
+                None => false,
+            };
+            if !matched {
+                mismatches.push(Mismatch {
+                    client,
+                    expected,
+                    actual,
+                    first_divergence: divergences.get(&client).copied().flatten(),
+                });
+            }
+        }
+        Ok(mismatches)
+    }
+}
+
+/// A row of the mismatches report.
+#[derive(Debug, Serialize)]
+struct MismatchRow {
+    client: ClientId,
+    expected_available: Option<String>,
+    expected_held: Option<String>,
+    expected_pending: Option<String>,
+    expected_locked: Option<bool>,
+    available: Option<String>,
+    held: Option<String>,
+    pending: Option<String>,
+    locked: Option<bool>,
+    first_divergent_line: Option<u64>,
+    first_divergent_tx: Option<TxId>,
+}
+
+/// Writes the mismatches as CSV, with the expected and the actual balances side by side.
+pub fn write_mismatches<W: Write>(mismatches: &[Mismatch], writer: W) -> Result<(), Box<dyn Error>> {
+    let amount = |amount: Amount| format!("{:.4}", amount);
+    let mut wtr = csv::Writer::from_writer(writer);
+    for mismatch in mismatches {
+        wtr.serialize(MismatchRow {
+            client: mismatch.client,
+            expected_available: mismatch.expected.as_ref().map(|expected| amount(expected.available)),
+            expected_held: mismatch.expected.as_ref().map(|expected| amount(expected.held)),
+            expected_pending: mismatch.expected.as_ref().map(|expected| amount(expected.pending)),
+            expected_locked: mismatch.expected.as_ref().map(|expected| expected.is_locked()),
+            available: mismatch.actual.as_ref().map(|actual| amount(actual.available)),
+            held: mismatch.actual.as_ref().map(|actual| amount(actual.held)),
+            pending: mismatch.actual.as_ref().map(|actual| amount(actual.pending)),
+            locked: mismatch.actual.as_ref().map(|actual| actual.is_locked()),
+            first_divergent_line: mismatch.first_divergence.map(|divergence| divergence.line),
+            first_divergent_tx: mismatch.first_divergence.map(|divergence| divergence.tx_id),
+        })?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::{read_expected, Divergence};
+    use crate::engine::PaymentsEngine;
+    use crate::transactions::ClientId;
+
+    #[test]
+    fn test_reconcile() {
+        // Client 1 matches until the chargeback, client 2 never does, client 3 matches
+        // and client 4 has no transactions
+        let expected = "client, available, held, total, locked
+1, 3.0, 0.0, 3.0, false
+2, 1.0, 0.0, 1.0, false
+3, 0.0, 5.5, 5.5, false
+4, 1.0, 0.0, 1.0, false";
+        let expected = read_expected(expected.as_bytes()).unwrap();
+
+        let mut engine = PaymentsEngine::new();
+        let input = File::open("sample_files/multiple_clients.csv").unwrap();
+        let mismatches = engine.reconcile_csv_reader(input, &expected).unwrap();
+
+        let clients: Vec<ClientId> = mismatches.iter().map(|mismatch| mismatch.client).collect();
+        assert_eq!(clients, vec![1, 2, 4]);
+        assert_eq!(mismatches[0].first_divergence, Some(Divergence { line: 5, tx_id: 1 }));
+        assert_eq!(mismatches[1].first_divergence, Some(Divergence { line: 4, tx_id: 3 }));
+        assert!(mismatches[2].actual.is_none());
+        assert!(mismatches[2].first_divergence.is_none());
+    }
+}
@@ -0,0 +1,798 @@
+//! Redis backed stores, so that the accounts, past transactions, processed transactions and
+//! idempotency keys of the engines outlive them, e.g. a server restarted or moved to another
+//! host, and can be shared by several engines, e.g. servers behind a load balancer.
+//!
+//! All the keys of the stores start with a prefix, `payments-engine` by default, so that
+//! several sets of state can share a Redis database. The stores of an engine share a
+//! connection, a [`RedisDatabase`], which keeps all of their writes for a transaction, or a
+//! chunk of the transactions of a file, until the commit, and then makes them at once with
+//! `MULTI`/`EXEC`, so that a crash never leaves half of them. Meanwhile the engine holds the
+//! lock of the prefix, so the engines sharing the state take turns, each reading the balances
+//! that the others committed. What isn't kept in the stores, e.g. the stats, the windows of the
+//! risk rules and the open times of the disputes, is counted by each engine on its own.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use redis::{Cmd, Commands, Connection};
+
+use crate::accounts::AccountBalance;
+use crate::amount::AmountValue;
+use crate::engine::PaymentsEngine;
+use crate::store::{
+    AccountStore, Database, DedupStore, IdempotencyStore, PendingSettlement, RecordedTx, StoreResult, TxStatus, TxStore,
+};
+use crate::transactions::{Amount, ClientId, TransactionType, TxId};
+
+/// The prefix of the keys, unless another one is given.
+pub const DEFAULT_PREFIX: &str = "payments-engine";
+
+/// How long an engine waits for the lock of a prefix that another one holds.
+pub const LOCK_WAIT: Duration = Duration::from_secs(30);
+
+/// How long an engine holds the lock of a prefix at most, so that the others go on after one
+/// crashed. The writes of an engine that held it longer are discarded when it commits.
+pub const LOCK_TTL: Duration = Duration::from_secs(30);
+
+/// Creates an engine that keeps the balances and past transactions in the Redis database at
+/// `url`, e.g. `redis://127.0.0.1/`, under the keys starting with `prefix`.
+pub fn open_engine(url: &str, prefix: &str) -> Result<PaymentsEngine, Box<dyn Error>> {
+    Ok(open_engine_in(&RedisDatabase::open(url, prefix)?))
+}
+
+/// Creates an engine that keeps the balances and past transactions in `database`, and commits
+/// there once for each transaction, or each chunk of the transactions of a file.
+pub fn open_engine_in(database: &RedisDatabase) -> PaymentsEngine {
+    PaymentsEngine::with_stores(
+        Box::new(RedisAccountStore::open_in(database)),
+        Box::new(RedisTxStore::open_in(database)),
+    )
+    .with_database(Box::new(database.clone()))
+}
+
+fn connect(url: &str) -> redis::RedisResult<Connection> {
+    redis::Client::open(url)?.get_connection()
+}
+
+/// Parses a value read from Redis.
+fn parse<T: FromStr>(value: &str, key: &str) -> StoreResult<T> {
+    value
+        .parse()
+        .map_err(|_| format!("Malformed value `{}` in the Redis key `{}`", value, key).into())
+}
+
+/// A write to a field of a hash, or to a member of a set or sorted set.
+#[derive(Debug, Clone)]
+enum Write {
+    HSet(String),
+    HDel,
+    SAdd,
+    SRem,
+    ZAdd(u64),
+    ZRem,
+}
+
+impl Write {
+    fn command(&self, key: &str, field: &str) -> Cmd {
+        let mut command = redis::cmd(match self {
+            Write::HSet(_) => "HSET",
+            Write::HDel => "HDEL",
+            Write::SAdd => "SADD",
+            Write::SRem => "SREM",
+            Write::ZAdd(_) => "ZADD",
+            Write::ZRem => "ZREM",
+        });
+        command.arg(key);
+        match self {
+            Write::HSet(value) => command.arg(field).arg(value),
+            Write::ZAdd(score) => command.arg(*score).arg(field),
+            _ => command.arg(field),
+        };
+        command
+    }
+}
+
+/// The connection of the stores of a database, with the writes of its database transaction.
+struct Shared {
+    conn: Connection,
+    // The key of the lock of the prefix, and the value that is ours
+    lock: String,
+    token: String,
+    // The last write to each field of each key since the database transaction started
+    writes: HashMap<(String, String), Write>,
+    // The writes each one replaced, to roll back the nested transactions, and where each started
+    undo: Vec<((String, String), Option<Write>)>,
+    nested: Vec<usize>,
+}
+
+impl Shared {
+    fn written(&self, key: &str, field: &str) -> Option<&Write> {
+        self.writes.get(&(key.to_string(), field.to_string()))
+    }
+
+    fn written_in<'a>(&'a self, key: &'a str) -> impl Iterator<Item = (&'a str, &'a Write)> {
+        self.writes
+            .iter()
+            .filter(move |((written, _), _)| written == key)
+            .map(|((_, field), write)| (field.as_str(), write))
+    }
+
+    fn hget(&mut self, key: &str, field: &str) -> StoreResult<Option<String>> {
+        Ok(match self.written(key, field) {
+            Some(Write::HSet(value)) => Some(value.clone()),
+            Some(_) => None,
+            None => self.conn.hget(key, field)?,
+        })
+    }
+
+    fn hgetall(&mut self, key: &str) -> StoreResult<HashMap<String, String>> {
+        let mut values: HashMap<String, String> = self.conn.hgetall(key)?;
+        for (field, write) in self.written_in(key) {
+            match write {
+                Write::HSet(value) => values.insert(field.to_string(), value.clone()),
+                _ => values.remove(field),
+            };
+        }
+        Ok(values)
+    }
+
+    fn sismember(&mut self, key: &str, member: &str) -> StoreResult<bool> {
+        Ok(match self.written(key, member) {
+            Some(write) => matches!(write, Write::SAdd),
+            None => self.conn.sismember(key, member)?,
+        })
+    }
+
+    fn smembers(&mut self, key: &str) -> StoreResult<HashSet<String>> {
+        let mut members: HashSet<String> = self.conn.smembers(key)?;
+        for (member, write) in self.written_in(key) {
+            match write {
+                Write::SAdd => members.insert(member.to_string()),
+                _ => members.remove(member),
+            };
+        }
+        Ok(members)
+    }
+
+    /// The members of the sorted set with a score of at most `until`.
+    fn zrangebyscore(&mut self, key: &str, until: u64) -> StoreResult<HashSet<String>> {
+        let mut members: HashSet<String> = self.conn.zrangebyscore(key, 0, until)?;
+        for (member, write) in self.written_in(key) {
+            match write {
+                Write::ZAdd(score) if *score <= until => members.insert(member.to_string()),
+                _ => members.remove(member),
+            };
+        }
+        Ok(members)
+    }
+
+    /// Makes the writes at once, or keeps them until the commit in a database transaction.
+    fn write(&mut self, writes: &[(&str, String, Write)]) -> StoreResult<()> {
+        if self.nested.is_empty() {
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            for (key, field, write) in writes {
+                pipe.add_command(write.command(key, field)).ignore();
+            }
+            return Ok(pipe.exec(&mut self.conn)?);
+        }
+        for (key, field, write) in writes {
+            let field = (key.to_string(), field.clone());
+            let replaced = self.writes.insert(field.clone(), write.clone());
+            self.undo.push((field, replaced));
+        }
+        Ok(())
+    }
+
+    /// Takes the lock of the prefix, waiting for the engine that holds it if any.
+    fn take_lock(&mut self) -> StoreResult<()> {
+        let waited = Instant::now();
+        loop {
+            let taken: Option<String> = redis::cmd("SET")
+                .arg(&self.lock)
+                .arg(&self.token)
+                .arg("NX")
+                .arg("PX")
+                .arg(LOCK_TTL.as_millis() as u64)
+                .query(&mut self.conn)?;
+            if taken.is_some() {
+                return Ok(());
+            }
+            if waited.elapsed() >= LOCK_WAIT {
+                return Err(format!(
+                    "Another engine held the Redis lock `{}` for over {} seconds",
+                    self.lock,
+                    LOCK_WAIT.as_secs()
+                )
+                .into());
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Makes the writes and releases the lock of the prefix at once, unless the lock expired,
+    /// since another engine may have changed what they were decided from.
+    fn release_lock(&mut self, writes: &HashMap<(String, String), Write>) -> StoreResult<()> {
+        let expired = || {
+            format!(
+                "The Redis lock `{}` expired before the commit, and its writes were discarded",
+                self.lock
+            )
+        };
+        // Any change of the lock from now on fails the transaction
+        redis::cmd("WATCH").arg(&self.lock).exec(&mut self.conn)?;
+        let holder: Option<String> = self.conn.get(&self.lock)?;
+        if holder.as_ref() != Some(&self.token) {
+            redis::cmd("UNWATCH").exec(&mut self.conn)?;
+            return Err(expired().into());
+        }
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for ((key, field), write) in writes {
+            pipe.add_command(write.command(key, field)).ignore();
+        }
+        pipe.del(&self.lock).ignore();
+        let committed: Option<()> = pipe.query(&mut self.conn)?;
+        committed.ok_or_else(|| expired().into())
+    }
+}
+
+/// A connection to a Redis database, shared by the stores opened in it under a prefix. Its
+/// writes are made as each store makes them, unless the engine of the stores starts a
+/// database transaction, see [`PaymentsEngine::with_database`]. The engine then holds the lock
+/// of the prefix, the `{prefix}:lock` key, until the commit, so the database transactions of
+/// the engines sharing the prefix are made one after the other.
+#[derive(Clone)]
+pub struct RedisDatabase {
+    shared: Arc<Mutex<Shared>>,
+    prefix: String,
+}
+
+impl RedisDatabase {
+    pub fn open(url: &str, prefix: &str) -> redis::RedisResult<Self> {
+        static OPENED: AtomicU64 = AtomicU64::new(0);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Ok(RedisDatabase {
+            shared: Arc::new(Mutex::new(Shared {
+                conn: connect(url)?,
+                lock: format!("{}:lock", prefix),
+                // Unique to this database, so that it never releases the lock of another one
+                token: format!(
+                    "{}-{}-{}",
+                    std::process::id(),
+                    now.as_nanos(),
+                    OPENED.fetch_add(1, Ordering::Relaxed)
+                ),
+                writes: HashMap::new(),
+                undo: vec![],
+                nested: vec![],
+            })),
+            prefix: prefix.to_string(),
+        })
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}:{}", self.prefix, name)
+    }
+
+    fn shared(&self) -> MutexGuard<'_, Shared> {
+        // A command can't leave the connection half changed
+        self.shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Database for RedisDatabase {
+    fn begin(&mut self) -> StoreResult<()> {
+        let mut shared = self.shared();
+        if shared.nested.is_empty() {
+            shared.take_lock()?;
+        }
+        let started = shared.undo.len();
+        shared.nested.push(started);
+        Ok(())
+    }
+
+    fn commit(&mut self) -> StoreResult<()> {
+        let mut shared = self.shared();
+        // The writes of a nested transaction are kept for the one it is nested in
+        if shared.nested.pop().is_none() || !shared.nested.is_empty() {
+            return Ok(());
+        }
+        shared.undo.clear();
+        let writes = std::mem::take(&mut shared.writes);
+        shared.release_lock(&writes)
+    }
+
+    fn rollback(&mut self) -> StoreResult<()> {
+        let mut shared = self.shared();
+        let Some(started) = shared.nested.pop() else {
+            return Ok(());
+        };
+        while shared.undo.len() > started {
+            let Some((field, replaced)) = shared.undo.pop() else {
+                break;
+            };
+            match replaced {
+                Some(write) => shared.writes.insert(field, write),
+                None => shared.writes.remove(&field),
+            };
+        }
+        match shared.nested.is_empty() {
+            true => shared.release_lock(&HashMap::new()),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Keeps the account balances as JSON in the `{prefix}:accounts` hash, by client.
+pub struct RedisAccountStore {
+    database: RedisDatabase,
+    key: String,
+}
+
+impl RedisAccountStore {
+    pub fn open(url: &str, prefix: &str) -> redis::RedisResult<Self> {
+        Ok(Self::open_in(&RedisDatabase::open(url, prefix)?))
+    }
+
+    /// Opens the store in a database shared with others.
+    pub fn open_in(database: &RedisDatabase) -> Self {
+        RedisAccountStore {
+            database: database.clone(),
+            key: database.key("accounts"),
+        }
+    }
+}
+
+impl AccountStore for RedisAccountStore {
+    fn get(&self, client_id: ClientId) -> StoreResult<Option<AccountBalance>> {
+        let account = self.database.shared().hget(&self.key, &client_id.to_string())?;
+        Ok(account.map(|account| serde_json::from_str(&account)).transpose()?)
+    }
+
+    fn put(&mut self, account: &AccountBalance) -> StoreResult<()> {
+        let write = Write::HSet(serde_json::to_string(account)?);
+        self.database
+            .shared()
+            .write(&[(&self.key, account.client.to_string(), write)])
+    }
+
+    fn all(&self) -> StoreResult<Vec<AccountBalance>> {
+        let accounts = self.database.shared().hgetall(&self.key)?;
+        let mut accounts = accounts
+            .values()
+            .map(|account| serde_json::from_str(account))
+            .collect::<Result<Vec<AccountBalance>, _>>()?;
+        accounts.sort_by_key(|account_balance| account_balance.client);
+        Ok(accounts)
+    }
+
+    fn remove(&mut self, client_id: ClientId) -> StoreResult<Option<AccountBalance>> {
+        let account = self.get(client_id)?;
+        self.database
+            .shared()
+            .write(&[(&self.key, client_id.to_string(), Write::HDel)])?;
+        Ok(account)
+    }
+}
+
+/// Keeps the deposits and withdrawals in the `{prefix}:transactions` hash, by transaction, as
+/// their type, client, amount, status and the amount held by their dispute separated by
+/// spaces, with a `-` for an unknown client or nothing held. The deposits that haven't settled
+/// yet are also in the `{prefix}:settlements` hash, as their client, amount and time separated
+/// by spaces, and in the `{prefix}:settles_at` sorted set by when they settle.
+pub struct RedisTxStore {
+    database: RedisDatabase,
+    transactions: String,
+    settlements: String,
+    settles_at: String,
+}
+
+impl RedisTxStore {
+    pub fn open(url: &str, prefix: &str) -> redis::RedisResult<Self> {
+        Ok(Self::open_in(&RedisDatabase::open(url, prefix)?))
+    }
+
+    /// Opens the store in a database shared with others.
+    pub fn open_in(database: &RedisDatabase) -> Self {
+        RedisTxStore {
+            database: database.clone(),
+            transactions: database.key("transactions"),
+            settlements: database.key("settlements"),
+            settles_at: database.key("settles_at"),
+        }
+    }
+
+    fn parse_recorded(&self, tx_id: TxId, value: &str) -> StoreResult<RecordedTx> {
+        let mut fields = value.split(' ');
+        let mut field = || fields.next().unwrap_or_default();
+        let tx_type = parse(field(), &self.transactions)?;
+        let client_id = match field() {
+            "-" => None,
+            client_id => Some(parse(client_id, &self.transactions)?),
+        };
+        let amount = parse(field(), &self.transactions)?;
+        let (status, held) = (field(), field());
+        let held = match held {
+            "-" => Amount::ZERO,
+            held => parse(held, &self.transactions)?,
+        };
+        let status = TxStatus::from_name(status, held)
+            .ok_or_else(|| format!("Malformed value `{}` in the Redis key `{}`", value, self.transactions))?;
+        Ok(RecordedTx {
+            tx_id,
+            client_id,
+            tx_type,
+            amount,
+            status,
+        })
+    }
+
+    fn parse_settlement(&self, tx_id: TxId, value: &str) -> StoreResult<PendingSettlement> {
+        let mut fields = value.split(' ');
+        let mut field = || fields.next().unwrap_or_default();
+        Ok(PendingSettlement {
+            tx_id,
+            client_id: parse(field(), &self.settlements)?,
+            amount: parse(field(), &self.settlements)?,
+            settles_at: parse(field(), &self.settlements)?,
+        })
+    }
+
+    /// Removes the settlement of the deposit, unless it was removed already.
+    fn claim_settlement(&self, tx_id: TxId) -> StoreResult<Option<PendingSettlement>> {
+        let tx_id_field = tx_id.to_string();
+        let mut shared = self.database.shared();
+        let Some(settlement) = shared.hget(&self.settlements, &tx_id_field)? else {
+            return Ok(None);
+        };
+        shared.write(&[
+            (&self.settlements, tx_id_field.clone(), Write::HDel),
+            (&self.settles_at, tx_id_field, Write::ZRem),
+        ])?;
+        drop(shared);
+        self.parse_settlement(tx_id, &settlement).map(Some)
+    }
+}
+
+impl TxStore for RedisTxStore {
+    fn recorded(&self, tx_id: TxId) -> StoreResult<Option<RecordedTx>> {
+        let recorded = self.database.shared().hget(&self.transactions, &tx_id.to_string())?;
+        recorded
+            .map(|recorded| self.parse_recorded(tx_id, &recorded))
+            .transpose()
+    }
+
+    fn record(&mut self, recorded: &RecordedTx) -> StoreResult<()> {
+        let or_none = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+        let value = format!(
+            "{} {} {} {} {}",
+            recorded.tx_type.as_str(),
+            or_none(recorded.client_id.map(|client_id| client_id.to_string())),
+            recorded.amount,
+            recorded.status.name(),
+            or_none(recorded.status.held().map(|held| held.to_string())),
+        );
+        self.database
+            .shared()
+            .write(&[(&self.transactions, recorded.tx_id.to_string(), Write::HSet(value))])
+    }
+
+    fn all(&self) -> StoreResult<Vec<RecordedTx>> {
+        let values = self.database.shared().hgetall(&self.transactions)?;
+        let mut recorded = values
+            .iter()
+            .map(|(tx_id, value)| self.parse_recorded(parse(tx_id, &self.transactions)?, value))
+            .collect::<StoreResult<Vec<_>>>()?;
+        recorded.sort_by_key(|recorded| recorded.tx_id);
+        Ok(recorded)
+    }
+
+    fn record_settlement(&mut self, settlement: &PendingSettlement) -> StoreResult<()> {
+        let tx_id = settlement.tx_id.to_string();
+        let value = format!(
+            "{} {} {}",
+            settlement.client_id, settlement.amount, settlement.settles_at
+        );
+        self.database.shared().write(&[
+            (&self.settlements, tx_id.clone(), Write::HSet(value)),
+            (&self.settles_at, tx_id, Write::ZAdd(settlement.settles_at)),
+        ])
+    }
+
+    fn take_settled(&mut self, until: u64) -> StoreResult<Vec<PendingSettlement>> {
+        let due = self.database.shared().zrangebyscore(&self.settles_at, until)?;
+        let mut settled = vec![];
+        for tx_id in due {
+            if let Some(settlement) = self.claim_settlement(parse(&tx_id, &self.settles_at)?)? {
+                settled.push(settlement);
+            }
+        }
+        settled.sort_by_key(|settlement| (settlement.settles_at, settlement.tx_id));
+        Ok(settled)
+    }
+
+    fn settlements(&self) -> StoreResult<Vec<PendingSettlement>> {
+        let values = self.database.shared().hgetall(&self.settlements)?;
+        let mut settlements = values
+            .iter()
+            .map(|(tx_id, settlement)| self.parse_settlement(parse(tx_id, &self.settlements)?, settlement))
+            .collect::<StoreResult<Vec<_>>>()?;
+        settlements.sort_by_key(|settlement| settlement.tx_id);
+        Ok(settlements)
+    }
+
+    fn settlement(&self, tx_id: TxId) -> StoreResult<Option<PendingSettlement>> {
+        let settlement = self.database.shared().hget(&self.settlements, &tx_id.to_string())?;
+        settlement
+            .map(|settlement| self.parse_settlement(tx_id, &settlement))
+            .transpose()
+    }
+
+    fn take_settlement(&mut self, tx_id: TxId) -> StoreResult<Option<PendingSettlement>> {
+        self.claim_settlement(tx_id)
+    }
+
+    fn forget(&mut self, tx_id: TxId) -> StoreResult<()> {
+        let tx_id = tx_id.to_string();
+        self.database.shared().write(&[
+            (&self.transactions, tx_id.clone(), Write::HDel),
+            (&self.settlements, tx_id.clone(), Write::HDel),
+            (&self.settles_at, tx_id, Write::ZRem),
+        ])
+    }
+}
+
+/// Keeps the idempotency keys in the `{prefix}:idempotency` hash, with whether the transaction
+/// was accepted and when it was seen separated by a space.
+pub struct RedisIdempotencyStore {
+    conn: RefCell<Connection>,
+    key: String,
+}
+
+impl RedisIdempotencyStore {
+    pub fn open(url: &str, prefix: &str) -> redis::RedisResult<Self> {
+        Ok(RedisIdempotencyStore {
+            conn: RefCell::new(connect(url)?),
+            key: format!("{}:idempotency", prefix),
+        })
+    }
+
+    fn parse_outcome(&self, value: &str) -> StoreResult<(bool, u64)> {
+        let (accepted, seen_at) = value.split_once(' ').unwrap_or_default();
+        Ok((parse(accepted, &self.key)?, parse(seen_at, &self.key)?))
+    }
+}
+
+impl IdempotencyStore for RedisIdempotencyStore {
+    fn outcome(&self, key: &str, since: u64) -> StoreResult<Option<bool>> {
+        let outcome: Option<String> = self.conn.borrow_mut().hget(&self.key, key)?;
+        let Some(outcome) = outcome else {
+            return Ok(None);
+        };
+        let (accepted, seen_at) = self.parse_outcome(&outcome)?;
+        Ok((seen_at >= since).then_some(accepted))
+    }
+
+    fn record(&mut self, key: &str, accepted: bool, seen_at: u64) -> StoreResult<()> {
+        let () = self
+            .conn
+            .get_mut()
+            .hset(&self.key, key, format!("{} {}", accepted, seen_at))?;
+        Ok(())
+    }
+
+    fn expire(&mut self, before: u64) -> StoreResult<()> {
+        let outcomes: HashMap<String, String> = self.conn.get_mut().hgetall(&self.key)?;
+        for (key, outcome) in outcomes {
+            if self.parse_outcome(&outcome)?.1 < before {
+                let () = self.conn.get_mut().hdel(&self.key, key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the processed transactions in the `{prefix}:processed_transactions` set, as their
+/// type and id separated by a colon.
+pub struct RedisDedupStore {
+    database: RedisDatabase,
+    key: String,
+}
+
+impl RedisDedupStore {
+    pub fn open(url: &str, prefix: &str) -> redis::RedisResult<Self> {
+        Ok(Self::open_in(&RedisDatabase::open(url, prefix)?))
+    }
+
+    /// Opens the store in a database shared with others, so that a transaction is recorded as
+    /// processed in the same commit as its effects.
+    pub fn open_in(database: &RedisDatabase) -> Self {
+        RedisDedupStore {
+            database: database.clone(),
+            key: database.key("processed_transactions"),
+        }
+    }
+}
+
+fn member(tx_type: TransactionType, tx_id: TxId) -> String {
+    format!("{}:{}", tx_type.as_str(), tx_id)
+}
+
+impl DedupStore for RedisDedupStore {
+    fn contains(&self, tx_type: TransactionType, tx_id: TxId) -> StoreResult<bool> {
+        self.database.shared().sismember(&self.key, &member(tx_type, tx_id))
+    }
+
+    fn insert(&mut self, tx_type: TransactionType, tx_id: TxId) -> StoreResult<()> {
+        self.database
+            .shared()
+            .write(&[(&self.key, member(tx_type, tx_id), Write::SAdd)])
+    }
+
+    fn remove(&mut self, tx_type: TransactionType, tx_id: TxId) -> StoreResult<()> {
+        self.database
+            .shared()
+            .write(&[(&self.key, member(tx_type, tx_id), Write::SRem)])
+    }
+
+    fn all(&self) -> StoreResult<Vec<(TransactionType, TxId)>> {
+        let members = self.database.shared().smembers(&self.key)?;
+        let mut processed = members
+            .iter()
+            .map(|member| {
+                let (tx_type, tx_id) = member.split_once(':').unwrap_or_default();
+                Ok((parse(tx_type, &self.key)?, parse(tx_id, &self.key)?))
+            })
+            .collect::<StoreResult<Vec<_>>>()?;
+        processed.sort();
+        Ok(processed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::thread;
+
+    use redis::Commands;
+
+    use super::{open_engine, open_engine_in, RedisAccountStore, RedisDatabase, RedisDedupStore, RedisTxStore};
+    use crate::accounts::AccountBalance;
+    use crate::amount::amount;
+    use crate::engine::PaymentsEngine;
+    use crate::store::{AccountStore, Database, DedupStore, TxStore};
+    use crate::transactions::{ClientId, Transaction, TransactionType, TxId};
+
+    /// The Redis server to test against, with `PAYMENTS_ENGINE_TEST_REDIS=redis://127.0.0.1/`,
+    /// or none to skip the tests. Their keys are deleted first.
+    fn test_server(prefix: &str) -> Option<String> {
+        let url = std::env::var("PAYMENTS_ENGINE_TEST_REDIS").ok()?;
+        let mut conn = redis::Client::open(url.as_str()).unwrap().get_connection().unwrap();
+        let keys: Vec<String> = redis::cmd("KEYS")
+            .arg(format!("{}:*", prefix))
+            .query(&mut conn)
+            .unwrap();
+        for key in keys {
+            let () = redis::cmd("DEL").arg(key).query(&mut conn).unwrap();
+        }
+        Some(url)
+    }
+
+    #[test]
+    fn test_redis_matches_memory() {
+        let Some(url) = test_server("payments-engine-test") else {
+            return;
+        };
+        for input in [
+            "sample_files/multiple_clients.csv",
+            "sample_files/reversal.csv",
+            "sample_files/settlement.csv",
+            "sample_files/close.csv",
+            "sample_files/chargeback_reversal.csv",
+            "sample_files/partial_dispute.csv",
+        ] {
+            let input = Path::new(input);
+            let prefix = format!("payments-engine-test:{}", input.display());
+            let mut engine = open_engine(&url, &prefix).unwrap();
+            engine.process_csv(input).unwrap();
+            assert_eq!(engine.report().unwrap(), crate::process_csv(input).unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn test_redis_dedup() {
+        let Some(url) = test_server("payments-engine-dedup") else {
+            return;
+        };
+        let input = Path::new("sample_files/deposit_withdrawal.csv");
+        // An engine started again on the state doesn't apply the transactions again
+        for _ in 0..2 {
+            let database = RedisDatabase::open(&url, "payments-engine-dedup").unwrap();
+            let mut engine = open_engine_in(&database).with_dedup(Box::new(RedisDedupStore::open_in(&database)));
+            engine.process_csv(input).unwrap();
+            assert_eq!(engine.report().unwrap(), crate::process_csv(input).unwrap().to_string());
+        }
+        let store = RedisDedupStore::open(&url, "payments-engine-dedup").unwrap();
+        assert!(store.contains(TransactionType::Withdrawal, 4).unwrap());
+        assert!(!store.contains(TransactionType::Dispute, 4).unwrap());
+        assert_eq!(store.all().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_redis_engines_share_prefix() {
+        let Some(url) = test_server("payments-engine-shared") else {
+            return;
+        };
+        let deposit = |client_id: ClientId, tx_id: TxId| Transaction {
+            tx_type: TransactionType::Deposit,
+            client_id,
+            tx_id,
+            amount: Some(amount(1.0)),
+            reason: None,
+            timestamp: None,
+            settles_at: None,
+        };
+        // Two engines depositing to the same clients at the same time, e.g. behind a load balancer
+        let engines = [0, 1000].map(|first| {
+            let url = url.clone();
+            thread::spawn(move || {
+                let mut engine = open_engine(&url, "payments-engine-shared").unwrap();
+                for tx_id in first..first + 200 {
+                    assert!(engine
+                        .apply(&deposit(tx_id as ClientId % 4 + 1, tx_id))
+                        .unwrap()
+                        .is_ok());
+                }
+            })
+        });
+        for engine in engines {
+            engine.join().unwrap();
+        }
+        let mut expected = PaymentsEngine::new();
+        for tx_id in (0..200).chain(1000..1200) {
+            expected
+                .apply(&deposit(tx_id as ClientId % 4 + 1, tx_id))
+                .unwrap()
+                .unwrap();
+        }
+        let engine = open_engine(&url, "payments-engine-shared").unwrap();
+        assert_eq!(engine.report().unwrap(), expected.report().unwrap());
+        let transactions = RedisTxStore::open(&url, "payments-engine-shared").unwrap();
+        assert_eq!(transactions.all().unwrap().len(), 400);
+        let mut conn = redis::Client::open(url.as_str()).unwrap().get_connection().unwrap();
+        assert!(!conn.exists::<_, bool>("payments-engine-shared:lock").unwrap());
+    }
+
+    #[test]
+    fn test_redis_commits_at_once() {
+        let Some(url) = test_server("payments-engine-atomic") else {
+            return;
+        };
+        let mut database = RedisDatabase::open(&url, "payments-engine-atomic").unwrap();
+        let mut store = RedisAccountStore::open_in(&database);
+        let other = RedisAccountStore::open(&url, "payments-engine-atomic").unwrap();
+        let mut account = AccountBalance::new(1);
+        account.available = amount(2.0);
+        database.begin().unwrap();
+        store.put(&account).unwrap();
+        let available = |store: &RedisAccountStore| store.get(1).unwrap().map(|account| account.available);
+        // Read back in the transaction, but not written before the commit
+        assert_eq!(available(&store), Some(amount(2.0)));
+        assert_eq!(available(&other), None);
+        database.commit().unwrap();
+        assert_eq!(available(&other), Some(amount(2.0)));
+
+        // A rolled back transaction writes nothing
+        database.begin().unwrap();
+        store.remove(1).unwrap();
+        assert_eq!(available(&store), None);
+        database.rollback().unwrap();
+        assert_eq!(available(&other), Some(amount(2.0)));
+    }
+}
@@ -0,0 +1,200 @@
+//! What the engine didn't apply: the transactions it rejected, and why, and the input records
+//! it skipped when reading CSV input with `lenient = true`.
+
+use std::error::Error;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::custom_errors::{LineError, TransactionRecordError};
+pub use crate::reasons::RejectionReason;
+use crate::transactions::{ClientId, Transaction, TxId};
+
+/// A transaction the engine ignored.
+#[derive(Debug, Clone)]
+pub struct Rejection {
+    /// The line of the input the transaction was read from, when reading CSV.
+    pub line: Option<u64>,
+    pub transaction: Transaction,
+    pub reason: RejectionReason,
+}
+
+/// A record of the input that couldn't be read as a transaction, and was skipped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MalformedRecord {
+    /// The line of the input the record starts on.
+    pub line: u64,
+    pub error: String,
+}
+
+impl MalformedRecord {
+    /// The malformed record at `line` if `error` is about the record itself, e.g. a field of
+    /// the wrong type or a deposit without an amount, rather than about reading the input.
+    pub(crate) fn from_error(line: u64, error: &(dyn Error + 'static)) -> Option<Self> {
+        let error: &(dyn Error + 'static) = match error.downcast_ref::<LineError>() {
+            Some(line_error) => line_error.error.as_ref(),
+            None => error,
+        };
+        let message = if let Some(error) = error.downcast_ref::<csv::Error>() {
+            match error.kind() {
+                csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+                csv::ErrorKind::Utf8 { err, .. } => err.to_string(),
+                csv::ErrorKind::UnequalLengths { .. } => error.to_string(),
+                _ => return None,
+            }
+        } else if error.is::<TransactionRecordError>() {
+            error.to_string()
+        } else {
+            return None;
+        };
+        Some(MalformedRecord { line, error: message })
+    }
+}
+
+#[derive(Serialize)]
+struct ReportRow<'a> {
+    line: Option<u64>,
+    #[serde(rename = "type")]
+    tx_type: Option<String>,
+    client: Option<ClientId>,
+    tx: Option<TxId>,
+    reason: &'a str,
+    error: &'a str,
+}
+
+/// Writes the rejected transactions and the skipped records as a CSV report, in the order of
+/// their lines, with the `reason` code of each (`MALFORMED` for the records) and the `error`
+/// of the malformed records.
+pub fn write_report<W: Write>(
+    rejections: &[Rejection],
+    malformed: &[MalformedRecord],
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut rows: Vec<ReportRow> = rejections
+        .iter()
+        .map(|rejection| ReportRow {
+            line: rejection.line,
+            tx_type: Some(format!("{:?}", rejection.transaction.tx_type).to_lowercase()),
+            client: Some(rejection.transaction.client_id),
+            tx: Some(rejection.transaction.tx_id),
+            reason: rejection.reason.code(),
+            error: "",
+        })
+        .chain(malformed.iter().map(|record| ReportRow {
+            line: Some(record.line),
+            tx_type: None,
+            client: None,
+            tx: None,
+            reason: "MALFORMED",
+            error: &record.error,
+        }))
+        .collect();
+    rows.sort_by_key(|row| row.line);
+
+    let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(writer);
+    wtr.write_record(["line", "type", "client", "tx", "reason", "error"])?;
+    for row in rows {
+        wtr.serialize(row)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Sender};
+
+    use super::{write_report, Rejection, RejectionReason};
+    use crate::amount::amount;
+    use crate::config::EngineConfig;
+    use crate::csv_input::{CsvOptions, CsvParser};
+    use crate::engine::{Observer, PaymentsEngine};
+
+    struct Rejections(Sender<(Option<u64>, RejectionReason)>);
+
+    impl Observer for Rejections {
+        fn on_rejected(&mut self, rejection: &Rejection) {
+            self.0.send((rejection.line, rejection.reason)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rejection_reasons() {
+        let input = "type, client, tx, amount
+            deposit, 1, 1, 2.0
+            withdrawal, 1, 2, 5.0
+            dispute, 1, 7
+            resolve, 1, 1
+            dispute, 1, 1
+            reversal, 1, 1
+            chargeback, 1, 1
+            deposit, 1, 3, 1.0";
+        let (sender, receiver) = mpsc::channel();
+        let mut engine = PaymentsEngine::new()
+            .with_observer(Box::new(Rejections(sender)))
+            .with_rejections();
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+
+        let expected = vec![
+            (Some(3), RejectionReason::InsufficientFunds),
+            (Some(4), RejectionReason::UnknownTransaction),
+            (Some(5), RejectionReason::NotDisputed),
+            (Some(7), RejectionReason::Disputed),
+            (Some(9), RejectionReason::AccountLocked),
+        ];
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), expected);
+        assert_eq!(engine.rejections().len(), 5);
+        assert_eq!(engine.stats().rejected, 5);
+        assert_eq!(
+            engine.stats().rejections.get(&RejectionReason::InsufficientFunds),
+            Some(&1)
+        );
+
+        let mut report = vec![];
+        write_report(engine.rejections(), &[], &mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap().lines().nth(1),
+            Some("3,withdrawal,1,2,INSUFFICIENT_FUNDS,")
+        );
+    }
+
+    #[test]
+    fn test_lenient_input() {
+        let input = "type, client, tx, amount
+            deposit, 1, 1, 2.5
+            deposit, one, 2, 1.0
+            withdrawal, 1, 3
+            deposit, 1, 4, 1.0";
+        for parser in [CsvParser::Serde, CsvParser::Fast] {
+            let csv = CsvOptions {
+                parser,
+                ..CsvOptions::default()
+            };
+            assert!(PaymentsEngine::new()
+                .with_config(EngineConfig {
+                    csv: csv.clone(),
+                    ..EngineConfig::default()
+                })
+                .process_csv_reader(input.as_bytes())
+                .is_err());
+
+            let config = EngineConfig {
+                csv: CsvOptions { lenient: true, ..csv },
+                ..EngineConfig::default()
+            };
+            let mut engine = PaymentsEngine::new().with_config(config);
+            engine.process_csv_reader(input.as_bytes()).unwrap();
+            assert_eq!(engine.account(1).unwrap().unwrap().available, amount(3.5));
+            let lines: Vec<u64> = engine.stats().malformed.iter().map(|record| record.line).collect();
+            assert_eq!(lines, vec![3, 4]);
+            assert_eq!(engine.stats().malformed[1].error, "An withdrawal must have an amount");
+
+            let mut report = vec![];
+            write_report(&[], &engine.stats().malformed, &mut report).unwrap();
+            let report = String::from_utf8(report).unwrap();
+            assert!(report.starts_with(
+                "line,type,client,tx,reason,error\n3,,,,MALFORMED,field 1: invalid digit found in string\n"
+            ));
+        }
+    }
+}
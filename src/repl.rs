@@ -0,0 +1,190 @@
+//! An interactive session, to apply transactions one at a time and look at the resulting state.
+//!
+//! Transactions are typed as CSV records, `deposit, 1, 1001, 5.0`, or separated by spaces,
+//! `deposit 1 1001 5.0`. Everything else is one of the commands listed in [`HELP`].
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Write};
+
+use crate::accounts::format_report;
+use crate::engine::PaymentsEngine;
+use crate::transactions::{ClientId, Transaction, TransactionType};
+
+pub const HELP: &str = "Transactions:
+  deposit <client> <tx> <amount>    (or a CSV record, e.g. `deposit, 1, 1001, 5.0`)
+  withdrawal <client> <tx> <amount>
+  dispute | resolve | chargeback | chargeback_reversal | settle <client> <tx>
+Commands:
+  show [client]    the balance of a client, or of all of them
+  undo [n]         revert the last transaction, or the last n
+  save <file>      save the state to a snapshot file
+  load <file>      replace the state with a snapshot file
+  help             this message
+  quit";
+
+/// How many of the last transactions of a session can be undone.
+pub const UNDO_DEPTH: usize = 1_000;
+
+/// The state of an interactive session.
+pub struct Repl {
+    engine: PaymentsEngine,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl {
+            engine: PaymentsEngine::new().with_undo(UNDO_DEPTH),
+        }
+    }
+
+    pub fn engine(&self) -> &PaymentsEngine {
+        &self.engine
+    }
+
+    /// Runs a line of input, and returns what to print, or `None` to end the session.
+    pub fn execute(&mut self, line: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let line = line.trim();
+        let mut words = line
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|word| !word.is_empty());
+        let command = words.next().unwrap_or_default();
+
+        let output = match (command, words.next()) {
+            ("", _) => String::new(),
+            ("quit" | "exit", _) => return Ok(None),
+            ("help", _) => HELP.to_string(),
+            ("show", None) => self.engine.report()?,
+            ("show", Some(client)) => self.show(client.parse()?)?,
+            ("undo", None) => self.undo(1)?,
+            ("undo", Some(n)) => self.undo(n.parse()?)?,
+            ("save", Some(path)) => {
+                self.engine.write_snapshot(BufWriter::new(File::create(path)?))?;
+                format!("Saved the state to {}", path)
+            }
+            ("load", Some(path)) => {
+                let mut engine = PaymentsEngine::new().with_undo(UNDO_DEPTH);
+                engine.restore_snapshot(File::open(path)?)?;
+                self.engine = engine;
+                format!("Loaded the state from {}", path)
+            }
+            (command, _) if command.parse::<TransactionType>().is_ok() => self.apply(line)?,
+            (command, _) => format!("Unknown command `{}`, type `help` for the list of commands", command),
+        };
+        Ok(Some(output))
+    }
+
+    fn show(&self, client: ClientId) -> Result<String, Box<dyn Error>> {
+        match self.engine.account(client)? {
+            Some(account_balance) => Ok(format_report(&[account_balance])),
+            None => Ok(format!("There is no account for client {}", client)),
+        }
+    }
+
+    fn apply(&mut self, line: &str) -> Result<String, Box<dyn Error>> {
+        let record = if line.contains(',') {
+            line.to_string()
+        } else {
+            line.split_whitespace().collect::<Vec<_>>().join(",")
+        };
+        let transaction = Transaction::parse_csv_record(record.as_bytes())?;
+
+        let accepted = self.engine.apply(&transaction)?.is_ok();
+        let client = transaction.client_id;
+
+        let account = self.show(client)?;
+        Ok(if accepted {
+            account
+        } else {
+            format!("Ignored, the account is unchanged\n{}", account)
+        })
+    }
+
+    /// Puts the state back as it was before the last `n` transactions.
+    fn undo(&mut self, n: usize) -> Result<String, Box<dyn Error>> {
+        let undone = self.engine.undo(n)?;
+        if undone.is_empty() {
+            return Ok("Nothing to undo".to_string());
+        }
+        let lines: Vec<String> = undone
+            .iter()
+            .map(|transaction| {
+                let tx_type = format!("{:?}", transaction.tx_type).to_lowercase();
+                format!(
+                    "Undid {} {} of client {}",
+                    tx_type, transaction.tx_id, transaction.client_id
+                )
+            })
+            .collect();
+        Ok(lines.join("\n"))
+    }
+}
+
+/// Runs a session until the input ends or `quit`, writing `prompt` before reading each line.
+/// Errors are printed and the session goes on.
+pub fn run<R: BufRead, W: Write>(input: R, mut output: W, prompt: &str) -> Result<(), Box<dyn Error>> {
+    let mut repl = Repl::new();
+    write!(output, "{}", prompt)?;
+    output.flush()?;
+
+    for line in input.lines() {
+        match repl.execute(&line?) {
+            Ok(None) => break,
+            Ok(Some(text)) if text.is_empty() => {}
+            Ok(Some(text)) => writeln!(output, "{}", text)?,
+            Err(err) => writeln!(output, "Error: {}", err)?,
+        }
+        write!(output, "{}", prompt)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Repl;
+
+    #[test]
+    fn test_repl() {
+        let mut repl = Repl::new();
+        let mut run = |line: &str| repl.execute(line).unwrap().unwrap();
+
+        assert_eq!(
+            run("deposit 1 1001 5.0"),
+            "client, available, held, pending, total, locked\n1, 5.0000, 0.0000, 0.0000, 5.0000, false"
+        );
+        assert_eq!(
+            run("withdrawal, 1, 1002, 2.5"),
+            "client, available, held, pending, total, locked\n1, 2.5000, 0.0000, 0.0000, 2.5000, false"
+        );
+        assert!(run("withdrawal 1 1003 10").starts_with("Ignored"));
+        assert_eq!(run("undo"), "Undid withdrawal 1003 of client 1");
+        assert_eq!(run("undo"), "Undid withdrawal 1002 of client 1");
+        assert_eq!(
+            run("show 1"),
+            "client, available, held, pending, total, locked\n1, 5.0000, 0.0000, 0.0000, 5.0000, false"
+        );
+        run("dispute 1 1001");
+        run("deposit 2 2001 1.0");
+        assert_eq!(
+            run("undo 2"),
+            "Undid deposit 2001 of client 2\nUndid dispute 1001 of client 1"
+        );
+        assert_eq!(
+            run("show 1"),
+            "client, available, held, pending, total, locked\n1, 5.0000, 0.0000, 0.0000, 5.0000, false"
+        );
+        assert!(run("dispute 1 1001").contains("0.0000, 5.0000, 0.0000, 5.0000"));
+        assert_eq!(run("show 2"), "There is no account for client 2");
+        assert!(run("frobnicate").starts_with("Unknown command"));
+
+        assert!(repl.execute("deposit 1 1004").is_err());
+        assert!(repl.execute("quit").unwrap().is_none());
+    }
+}
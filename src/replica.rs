@@ -0,0 +1,191 @@
+//! A read-only copy of the balances of an engine, kept up to date from the event log it writes,
+//! see [`PaymentsEngine::with_event_log`](crate::PaymentsEngine::with_event_log), so that
+//! another process can answer the balance queries and write the reports instead of the one
+//! applying the transactions.
+//!
+//! The replica applies the events as [`replay`](crate::event_log::replay) does, and checks that
+//! each one follows the one before it, so that a log modified or started over by a new run of
+//! the engine is an error instead of a wrong copy. An event log only has the postings and the
+//! status changes, so the replica has the balances, and nothing of the past transactions.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::accounts::AccountBalance;
+use crate::custom_errors::{InputSourceError, InputSourceErrorType, LineError};
+use crate::event_log::{self, BrokenChainError, ChainHead, Event, GENESIS_HASH};
+use crate::transactions::ClientId;
+
+/// How long to wait before checking the log again when there are no new events.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The balances of the events of a log applied so far.
+#[derive(Debug)]
+pub struct Replica {
+    balances: BTreeMap<ClientId, AccountBalance>,
+    head: ChainHead,
+    // Lines of the log applied, blank ones included
+    lines: u64,
+    // Bytes of the log read, and the ones after the last newline, which are an event still
+    // being written
+    position: u64,
+    pending: Vec<u8>,
+}
+
+impl Default for Replica {
+    fn default() -> Self {
+        Replica {
+            balances: BTreeMap::new(),
+            head: ChainHead {
+                events: 0,
+                hash: String::from(GENESIS_HASH),
+            },
+            lines: 0,
+            position: 0,
+            pending: vec![],
+        }
+    }
+}
+
+impl Replica {
+    /// A replica of an engine that started empty, with no events applied yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The balance of a client, if it has had any events.
+    pub fn account(&self, client_id: ClientId) -> Option<AccountBalance> {
+        self.balances.get(&client_id).cloned()
+    }
+
+    /// All the account balances, sorted by client id.
+    pub fn balances(&self) -> Vec<AccountBalance> {
+        self.balances.values().cloned().collect()
+    }
+
+    /// How many events were applied, and the hash of the last one.
+    pub fn head(&self) -> &ChainHead {
+        &self.head
+    }
+
+    /// Applies the next line of the log, which must follow the last one applied.
+    pub fn apply_line(&mut self, line: &str) -> Result<(), Box<dyn Error>> {
+        self.lines += 1;
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        let event: Event = serde_json::from_str(line).map_err(|error| LineError {
+            line: self.lines,
+            error: Box::new(error),
+        })?;
+        if event.prev_hash != self.head.hash || event.seq != self.head.events + 1 {
+            return Err(Box::new(BrokenChainError { line: self.lines }));
+        }
+        event_log::apply(&mut self.balances, &event);
+        self.head = ChainHead {
+            events: event.seq,
+            hash: event_log::line_hash(line.as_bytes()),
+        };
+        Ok(())
+    }
+
+    /// Applies the complete lines appended to the log since it was last read, returning how
+    /// many events were applied.
+    pub fn catch_up(&mut self, file: &mut File) -> Result<u64, Box<dyn Error>> {
+        if file.metadata()?.len() < self.position {
+            // Events we already applied are gone
+            return Err(Box::new(InputSourceError {
+                error_type: InputSourceErrorType::Truncated,
+            }));
+        }
+        file.seek(SeekFrom::Start(self.position))?;
+        self.position += file.read_to_end(&mut self.pending)? as u64;
+        let Some(end) = self.pending.iter().rposition(|&byte| byte == b'\n') else {
+            return Ok(0);
+        };
+        let lines: Vec<u8> = self.pending.drain(..=end).collect();
+        let events = self.head.events;
+        for line in String::from_utf8(lines)?.lines() {
+            self.apply_line(line)?;
+        }
+        Ok(self.head.events - events)
+    }
+
+    /// Applies the events of the log at `path` as they are appended to it, like `tail -f`.
+    ///
+    /// `on_report` is called with the up-to-date replica at most once every `report_interval`,
+    /// after all the events written so far have been applied, and the log is followed until it
+    /// returns `ControlFlow::Break`.
+    pub fn follow<F>(&mut self, path: &Path, report_interval: Duration, mut on_report: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&Replica) -> Result<ControlFlow<()>, Box<dyn Error>>,
+    {
+        let mut file = File::open(path)?;
+        let mut last_report: Option<Instant> = None;
+        loop {
+            let applied = self.catch_up(&mut file)?;
+            if last_report.is_none_or(|last_report| last_report.elapsed() >= report_interval) {
+                last_report = Some(Instant::now());
+                if on_report(self)?.is_break() {
+                    return Ok(());
+                }
+            }
+            if applied == 0 {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File, OpenOptions};
+    use std::io::Write;
+
+    use super::Replica;
+    use crate::amount::amount;
+    use crate::engine::PaymentsEngine;
+    use crate::event_log::BrokenChainError;
+
+    #[test]
+    fn test_replica() {
+        let path = std::env::temp_dir().join("payments_engine_replica.ndjson");
+        let mut engine = PaymentsEngine::new().with_event_log(Box::new(File::create(&path).unwrap()));
+        let mut replica = Replica::new();
+        let mut log = File::open(&path).unwrap();
+
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 10.0\n\
+                     deposit, 2, 2, 5.0\n\
+                     dispute, 1, 1,\n";
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        assert_eq!(replica.catch_up(&mut log).unwrap(), 3);
+        engine
+            .process_csv_reader("type, client, tx, amount\nchargeback, 1, 1,\n".as_bytes())
+            .unwrap();
+
+        // An event still being written waits for the rest of its line
+        let first = fs::read_to_string(&path).unwrap().lines().next().unwrap().to_string();
+        let mut partial = OpenOptions::new().append(true).open(&path).unwrap();
+        partial.write_all(&first.as_bytes()[..3]).unwrap();
+        assert_eq!(replica.catch_up(&mut log).unwrap(), 2);
+        assert_eq!(replica.catch_up(&mut log).unwrap(), 0);
+        assert_eq!(replica.head().events, 5);
+        assert_eq!(replica.balances().len(), 2);
+        let account = replica.account(1).unwrap();
+        assert!(account.is_locked());
+        assert_eq!((account.available, account.held), (amount(0.0), amount(0.0)));
+        assert_eq!(replica.account(2).unwrap().available, amount(5.0));
+
+        // An event that doesn't follow the last one is a broken chain
+        writeln!(partial, "{}", &first[3..]).unwrap();
+        let error = replica.catch_up(&mut log).unwrap_err();
+        assert!(error.is::<BrokenChainError>());
+    }
+}
@@ -0,0 +1,356 @@
+//! Risk rules, which reject the transactions of a client beyond some limits, or lock its
+//! account once its disputes or holds are beyond others.
+//!
+//! The limits over time use the `timestamp` of the transactions, so they only apply to
+//! timestamped ones. They are set in the `[risk]` section of the config file:
+//!
+//! ```toml
+//! [risk]
+//! max_withdrawal = 1000.0
+//! max_daily_withdrawals = 2500.0
+//!
+//! [risk.velocity]
+//! max_transactions = 10
+//! window_secs = 60
+//!
+//! [risk.auto_lock]
+//! disputes = { max_disputes = 3, window_days = 30 }
+//! max_held_percent = 50.0
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+
+use serde::Deserialize;
+
+use crate::accounts::AccountBalance;
+use crate::amount::AmountValue;
+use crate::dates::SECS_PER_DAY;
+pub use crate::reasons::RiskViolation;
+use crate::transactions::{Amount, ClientId, Transaction, TransactionType, TxId};
+
+/// The limits of the transactions of each client.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RiskRules {
+    /// The largest amount of a single withdrawal.
+    pub max_withdrawal: Option<Amount>,
+    /// The largest total of the withdrawals of a client in a day (UTC).
+    pub max_daily_withdrawals: Option<Amount>,
+    /// How many deposits and withdrawals a client can make in a time window.
+    pub velocity: Option<VelocityLimit>,
+    /// When the account of a client is locked, beyond the chargebacks.
+    pub auto_lock: AutoLockRules,
+}
+
+/// At most `max_transactions` deposits and withdrawals within `window_secs` seconds.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VelocityLimit {
+    pub max_transactions: u32,
+    pub window_secs: u64,
+}
+
+/// The limits beyond which the account of a client is locked after a transaction is applied.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoLockRules {
+    /// How many disputes the client can have in a time window.
+    pub disputes: Option<DisputeLimit>,
+    /// The largest share of the total funds of the client the disputes can hold, in percent.
+    pub max_held_percent: Option<Amount>,
+}
+
+/// Locks the account once it has `max_disputes` disputes within `window_days` days.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DisputeLimit {
+    pub max_disputes: u32,
+    pub window_days: u32,
+}
+
+/// The auto-lock rule an account was locked by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AutoLockRule {
+    /// The client reached its [`AutoLockRules::disputes`] limit.
+    Disputes,
+    /// The disputes hold more than [`AutoLockRules::max_held_percent`] of the funds.
+    HeldShare,
+}
+
+impl AutoLockRule {
+    /// A stable code for the rule, also the `reason` of its `status_changed` event.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AutoLockRule::Disputes => "AUTO_LOCK_DISPUTES",
+            AutoLockRule::HeldShare => "AUTO_LOCK_HELD_SHARE",
+        }
+    }
+}
+
+/// An account locked by an auto-lock rule, see [`Observer::on_auto_locked`](crate::engine::Observer::on_auto_locked).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoLock {
+    pub client: ClientId,
+    /// The transaction after which it was locked.
+    pub tx_id: TxId,
+    pub rule: AutoLockRule,
+}
+
+/// What the risk rules remember about the recent transactions of a client, to
+/// [`restore`](RiskState::restore) it later.
+#[derive(Debug, Clone)]
+pub(crate) struct ClientRisk {
+    /// The day and the total of its last withdrawals.
+    pub(crate) daily_withdrawals: Option<(u64, Amount)>,
+    /// The timestamps of its deposits and withdrawals within the velocity window.
+    pub(crate) recent: Option<VecDeque<u64>>,
+    /// The timestamps of its disputes within the auto-lock window.
+    pub(crate) disputes: Option<VecDeque<u64>>,
+}
+
+/// What the risk rules need to remember about the recent transactions of the clients.
+#[derive(Debug, Default)]
+pub(crate) struct RiskState {
+    // The day and the total of the last withdrawals of each client
+    daily_withdrawals: HashMap<ClientId, (u64, Amount)>,
+    // The timestamps of the deposits and withdrawals of each client within the velocity window
+    recent: HashMap<ClientId, VecDeque<u64>>,
+    // The timestamps of the disputes applied of each client within the auto-lock window
+    disputes: HashMap<ClientId, VecDeque<u64>>,
+}
+
+impl RiskState {
+    /// Whether the transaction breaks a rule. Deposits and withdrawals count towards
+    /// the velocity limit even when they are rejected.
+    pub(crate) fn check(&mut self, rules: &RiskRules, transaction: &Transaction) -> Option<RiskViolation> {
+        if !matches!(
+            transaction.tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) {
+            return None;
+        }
+        let client = transaction.client_id;
+
+        if let (Some(velocity), Some(timestamp)) = (&rules.velocity, transaction.timestamp) {
+            let recent = self.recent.entry(client).or_default();
+            while recent
+                .front()
+                .is_some_and(|&seen_at| seen_at.saturating_add(velocity.window_secs) <= timestamp)
+            {
+                recent.pop_front();
+            }
+            let over_limit = recent.len() >= velocity.max_transactions as usize;
+            recent.push_back(timestamp);
+            if over_limit {
+                return Some(RiskViolation::Velocity);
+            }
+        }
+
+        if transaction.tx_type != TransactionType::Withdrawal {
+            return None;
+        }
+        let amount = transaction.amount.unwrap_or_default();
+        if rules.max_withdrawal.is_some_and(|max| amount > max) {
+            return Some(RiskViolation::MaxWithdrawal);
+        }
+        if let (Some(max), Some(timestamp)) = (rules.max_daily_withdrawals, transaction.timestamp) {
+            if self.withdrawn_on(client, timestamp / SECS_PER_DAY) + amount > max {
+                return Some(RiskViolation::MaxDailyWithdrawals);
+            }
+        }
+        None
+    }
+
+    /// Records an applied withdrawal, towards the daily limit.
+    pub(crate) fn record_withdrawal(&mut self, transaction: &Transaction) {
+        if let Some(timestamp) = transaction.timestamp {
+            let day = timestamp / SECS_PER_DAY;
+            let total = self.withdrawn_on(transaction.client_id, day) + transaction.amount.unwrap_or_default();
+            self.daily_withdrawals.insert(transaction.client_id, (day, total));
+        }
+    }
+
+    /// The rule the account of the client breaks, now that the transaction was applied to its
+    /// balance, if any. The disputes count towards the limit whether the account is locked or not.
+    pub(crate) fn auto_lock(
+        &mut self,
+        rules: &AutoLockRules,
+        transaction: &Transaction,
+        account_balance: &AccountBalance,
+    ) -> Option<AutoLockRule> {
+        if let (Some(limit), Some(timestamp), TransactionType::Dispute) =
+            (&rules.disputes, transaction.timestamp, transaction.tx_type)
+        {
+            let disputes = self.disputes.entry(transaction.client_id).or_default();
+            let window_secs = u64::from(limit.window_days) * SECS_PER_DAY;
+            while disputes
+                .front()
+                .is_some_and(|&seen_at| seen_at.saturating_add(window_secs) <= timestamp)
+            {
+                disputes.pop_front();
+            }
+            disputes.push_back(timestamp);
+            if disputes.len() >= limit.max_disputes as usize {
+                return Some(AutoLockRule::Disputes);
+            }
+        }
+        let held = account_balance.held;
+        // Holds of more than the total, e.g. of funds already withdrawn, are over any share
+        if rules
+            .max_held_percent
+            .is_some_and(|percent| held > Amount::ZERO && held > account_balance.total().percent(percent))
+        {
+            return Some(AutoLockRule::HeldShare);
+        }
+        None
+    }
+
+    /// What is remembered about the client.
+    pub(crate) fn client(&self, client: ClientId) -> ClientRisk {
+        ClientRisk {
+            daily_withdrawals: self.daily_withdrawals.get(&client).copied(),
+            recent: self.recent.get(&client).cloned(),
+            disputes: self.disputes.get(&client).cloned(),
+        }
+    }
+
+    /// What is remembered about each client with anything, sorted by client id.
+    pub(crate) fn clients(&self) -> Vec<(ClientId, ClientRisk)> {
+        let mut clients: Vec<ClientId> = self
+            .daily_withdrawals
+            .keys()
+            .chain(self.recent.keys())
+            .chain(self.disputes.keys())
+            .copied()
+            .collect();
+        clients.sort_unstable();
+        clients.dedup();
+        clients
+            .into_iter()
+            .map(|client| (client, self.client(client)))
+            .collect()
+    }
+
+    /// Puts what is remembered about the client back as [`client`](RiskState::client) returned it.
+    pub(crate) fn restore(&mut self, client: ClientId, risk: ClientRisk) {
+        fn put<T>(map: &mut HashMap<ClientId, T>, client: ClientId, value: Option<T>) {
+            match value {
+                Some(value) => map.insert(client, value),
+                None => map.remove(&client),
+            };
+        }
+        put(&mut self.daily_withdrawals, client, risk.daily_withdrawals);
+        put(&mut self.recent, client, risk.recent);
+        put(&mut self.disputes, client, risk.disputes);
+    }
+
+    fn withdrawn_on(&self, client: ClientId, day: u64) -> Amount {
+        match self.daily_withdrawals.get(&client) {
+            Some(&(withdrawals_day, total)) if withdrawals_day == day => total,
+            _ => Amount::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{AutoLock, AutoLockRule, RiskRules, RiskState, RiskViolation, VelocityLimit};
+    use crate::accounts::AccountStatus;
+    use crate::config::EngineConfig;
+    use crate::engine::{Observer, PaymentsEngine};
+    use crate::rejections::RejectionReason;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_risk_rules() {
+        let rules: RiskRules = toml::from_str(
+            "max_withdrawal = 100.0
+             max_daily_withdrawals = 150.0
+             velocity = { max_transactions = 3, window_secs = 60 }",
+        )
+        .unwrap();
+        assert_eq!(
+            rules.velocity,
+            Some(VelocityLimit {
+                max_transactions: 3,
+                window_secs: 60
+            })
+        );
+
+        let mut risk = RiskState::default();
+        let mut check = |record: &str| {
+            let transaction = Transaction::parse_csv_record(record.as_bytes()).unwrap();
+            let violation = risk.check(&rules, &transaction);
+            if violation.is_none() && transaction.amount.is_some() {
+                risk.record_withdrawal(&transaction);
+            }
+            violation
+        };
+        assert_eq!(check("withdrawal, 1, 1, 101, , 0"), Some(RiskViolation::MaxWithdrawal));
+        assert_eq!(check("withdrawal, 1, 2, 100, , 10"), None);
+        assert_eq!(
+            check("withdrawal, 1, 3, 60, , 20"),
+            Some(RiskViolation::MaxDailyWithdrawals)
+        );
+        assert_eq!(check("withdrawal, 1, 4, 50, , 30"), Some(RiskViolation::Velocity));
+        assert_eq!(check("withdrawal, 2, 5, 50, , 30"), None);
+        assert_eq!(check("withdrawal, 1, 6, 50, , 86400"), None);
+
+        assert!(toml::from_str::<RiskRules>("max_withdrawals = 1.0").is_err());
+    }
+
+    #[test]
+    fn test_auto_lock() {
+        struct Locks(Arc<Mutex<Vec<AutoLock>>>);
+        impl Observer for Locks {
+            fn on_auto_locked(&mut self, lock: &AutoLock) {
+                self.0.lock().unwrap().push(lock.clone());
+            }
+        }
+
+        let config = EngineConfig::from_toml(
+            "[risk.auto_lock]
+             disputes = { max_disputes = 2, window_days = 30 }
+             max_held_percent = 50.0",
+        )
+        .unwrap();
+        let locks = Arc::new(Mutex::new(vec![]));
+        let mut engine = PaymentsEngine::new()
+            .with_config(config)
+            .with_observer(Box::new(Locks(Arc::clone(&locks))));
+        let day = 86_400;
+        let input = format!(
+            "type, client, tx, amount, timestamp\n\
+             deposit, 1, 1, 10.0, 0\n\
+             deposit, 1, 2, 10.0, 0\n\
+             deposit, 1, 3, 10.0, 0\n\
+             dispute, 1, 1, , 0\n\
+             resolve, 1, 1, , 1\n\
+             dispute, 1, 2, , {}\n\
+             dispute, 1, 3, , {}\n\
+             deposit, 2, 4, 10.0, 0\n\
+             dispute, 2, 4, 6.0, 0\n\
+             withdrawal, 2, 5, 1.0, 0\n",
+            31 * day,
+            32 * day,
+        );
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+
+        // The first dispute of client 1 was out of the window by its third one
+        let locks = locks.lock().unwrap();
+        let locked: Vec<_> = locks.iter().map(|lock| (lock.client, lock.tx_id, lock.rule)).collect();
+        assert_eq!(
+            locked,
+            [(1, 3, AutoLockRule::Disputes), (2, 4, AutoLockRule::HeldShare)]
+        );
+        assert!(engine
+            .balances()
+            .unwrap()
+            .iter()
+            .all(|account_balance| account_balance.status == AccountStatus::Locked));
+        assert_eq!(engine.stats().auto_locked.values().sum::<u64>(), 2);
+        assert_eq!(engine.stats().rejections.get(&RejectionReason::AccountLocked), Some(&1));
+    }
+}
@@ -0,0 +1,155 @@
+//! The balances and activity of the clients added up by the `segment` and `region` of their
+//! metadata, see [`clients`](crate::clients), for a view of the whole book next to the report
+//! of each client.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+
+use serde::Serialize;
+
+use crate::accounts::{AccountBalance, AmountFormat};
+use crate::amount::AmountValue;
+use crate::clients::ClientDirectory;
+use crate::ledger::JournalEntry;
+use crate::transactions::{Amount, TransactionType};
+
+/// The totals of the clients of a segment in a region.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SegmentTotals {
+    pub segment: String,
+    pub region: String,
+    pub clients: u64,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    /// The clients with a locked account.
+    pub locked: u64,
+    /// The amount of the deposits and withdrawals applied.
+    pub deposited: Amount,
+    pub withdrawn: Amount,
+    /// The disputes and chargebacks applied.
+    pub disputes: u64,
+    pub chargebacks: u64,
+}
+
+/// Adds up the balances, and the activity of the journal, by segment and region, sorted by them.
+/// The clients that aren't in the metadata are in the segment and region with empty names.
+pub fn aggregate(
+    balances: &[AccountBalance],
+    journal: &[JournalEntry],
+    clients: &ClientDirectory,
+) -> Vec<SegmentTotals> {
+    let key = |client| {
+        let info = clients.get(client).cloned().unwrap_or_default();
+        (info.segment, info.region)
+    };
+    let mut totals: BTreeMap<(String, String), SegmentTotals> = BTreeMap::new();
+    for account_balance in balances {
+        let totals = totals.entry(key(account_balance.client)).or_default();
+        totals.clients += 1;
+        totals.available += account_balance.available;
+        totals.held += account_balance.held;
+        totals.total += account_balance.total();
+        totals.locked += account_balance.is_locked() as u64;
+    }
+    for entry in journal {
+        let totals = totals.entry(key(entry.client_id)).or_default();
+        let amount = entry.postings.first().map_or(Amount::ZERO, |posting| posting.amount);
+        match entry.tx_type {
+            // Not the settlements of earlier deposits, posted by the engine
+            TransactionType::Deposit if entry.reason.is_none() => totals.deposited += amount,
+            TransactionType::Withdrawal => totals.withdrawn += amount,
+            TransactionType::Dispute => totals.disputes += 1,
+            TransactionType::Chargeback => totals.chargebacks += 1,
+            _ => {}
+        }
+    }
+    totals
+        .into_iter()
+        .map(|((segment, region), totals)| SegmentTotals {
+            segment,
+            region,
+            ..totals
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct SegmentRow<'a> {
+    segment: &'a str,
+    region: &'a str,
+    clients: u64,
+    available: String,
+    held: String,
+    total: String,
+    locked: u64,
+    deposited: String,
+    withdrawn: String,
+    disputes: u64,
+    chargebacks: u64,
+}
+
+/// Writes the totals as CSV, with the amounts formatted as `amounts`.
+pub fn write_report<W: Write>(
+    totals: &[SegmentTotals],
+    amounts: &AmountFormat,
+    writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut wtr = csv::Writer::from_writer(writer);
+    for totals in totals {
+        wtr.serialize(SegmentRow {
+            segment: &totals.segment,
+            region: &totals.region,
+            clients: totals.clients,
+            available: amounts.format(totals.available),
+            held: amounts.format(totals.held),
+            total: amounts.format(totals.total),
+            locked: totals.locked,
+            deposited: amounts.format(totals.deposited),
+            withdrawn: amounts.format(totals.withdrawn),
+            disputes: totals.disputes,
+            chargebacks: totals.chargebacks,
+        })?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{aggregate, write_report};
+    use crate::accounts::AmountFormat;
+    use crate::clients::ClientDirectory;
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_segments() {
+        let clients = "client, name, segment, region\n\
+                       1, Alice, retail, eu-west\n\
+                       2, Bob, retail, eu-west\n\
+                       3, Carol, business, us-east\n";
+        let clients = ClientDirectory::read(clients.as_bytes()).unwrap();
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 10.0\n\
+                     deposit, 2, 2, 5.0\n\
+                     withdrawal, 2, 3, 1.5\n\
+                     dispute, 2, 2,\n\
+                     chargeback, 2, 2,\n\
+                     deposit, 3, 4, 100.0\n\
+                     deposit, 4, 5, 1.0\n";
+        let mut engine = PaymentsEngine::new().with_journal();
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+
+        let totals = aggregate(&engine.balances().unwrap(), engine.journal(), &clients);
+        let mut report = vec![];
+        write_report(&totals, &AmountFormat::default(), &mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "segment,region,clients,available,held,total,locked,deposited,withdrawn,disputes,chargebacks\n\
+             ,,1,1.0000,0.0000,1.0000,0,1.0000,0.0000,0,0\n\
+             business,us-east,1,100.0000,0.0000,100.0000,0,100.0000,0.0000,0,0\n\
+             retail,eu-west,2,8.5000,0.0000,8.5000,1,15.0000,1.5000,1,1\n"
+        );
+    }
+}
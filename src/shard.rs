@@ -0,0 +1,145 @@
+//! Splits the clients between the machines of a distributed run, so that each of them can
+//! process the whole input and report the clients of its own shard.
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::transactions::ClientId;
+
+/// How the client ids are assigned to the shards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShardMethod {
+    /// By a hash of the id, which spreads consecutive ids evenly. It is the same on all machines.
+    #[default]
+    Hash,
+    /// By ranges of ids of the same size, in order, the lowest ids in the first shard.
+    Range,
+}
+
+/// One of `count` shards of the clients, numbered from 1, parsed from e.g. `3/16`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: u32,
+    pub count: u32,
+    pub method: ShardMethod,
+}
+
+impl Shard {
+    /// The same shard with the clients assigned by `method`.
+    pub fn with_method(self, method: ShardMethod) -> Self {
+        Shard { method, ..self }
+    }
+
+    /// The shard of a client, of `count` shards with the clients assigned by `method`.
+    pub fn of(client: ClientId, count: u32, method: ShardMethod) -> Self {
+        let shard = match method {
+            ShardMethod::Hash => ((client as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) % count as u64,
+            ShardMethod::Range => ((client as u128 * count as u128) >> ClientId::BITS) as u64,
+        };
+        Shard {
+            index: shard as u32 + 1,
+            count,
+            method,
+        }
+    }
+
+    /// Whether the client is in this shard.
+    pub fn contains(&self, client: ClientId) -> bool {
+        Shard::of(client, self.count, self.method).index == self.index
+    }
+}
+
+#[derive(Debug)]
+pub struct ShardFromStrError(String);
+
+impl Error for ShardFromStrError {}
+
+impl fmt::Display for ShardFromStrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Invalid shard `{}`, expected e.g. `3/16` for the third of sixteen",
+            self.0
+        )
+    }
+}
+
+impl FromStr for Shard {
+    type Err = ShardFromStrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ShardFromStrError(s.to_string());
+        let (index, count) = s.split_once('/').ok_or_else(invalid)?;
+        let index: u32 = index.trim().parse().map_err(|_| invalid())?;
+        let count: u32 = count.trim().parse().map_err(|_| invalid())?;
+        if index == 0 || index > count {
+            return Err(invalid());
+        }
+        Ok(Shard {
+            index,
+            count,
+            method: ShardMethod::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{Shard, ShardMethod};
+    use crate::accounts::format_report;
+    use crate::engine::PaymentsEngine;
+    use crate::transactions::ClientId;
+
+    #[test]
+    fn test_shards() {
+        assert_eq!(
+            "3/16".parse::<Shard>().unwrap(),
+            Shard {
+                index: 3,
+                count: 16,
+                method: ShardMethod::Hash
+            }
+        );
+        assert!("0/16".parse::<Shard>().is_err());
+        assert!("17/16".parse::<Shard>().is_err());
+        assert!("3".parse::<Shard>().is_err());
+
+        // Each client is in exactly one shard
+        for method in [ShardMethod::Hash, ShardMethod::Range] {
+            let shards: Vec<Shard> = (1..=4)
+                .map(|index| Shard {
+                    index,
+                    count: 4,
+                    method,
+                })
+                .collect();
+            for client in (0..1000).chain([ClientId::MAX]) {
+                assert_eq!(shards.iter().filter(|shard| shard.contains(client)).count(), 1);
+            }
+        }
+        let range = |index| Shard {
+            index,
+            count: 4,
+            method: ShardMethod::Range,
+        };
+        assert!(range(1).contains(0) && range(4).contains(ClientId::MAX));
+
+        // The shards together report the same balances as a single run
+        let input = Path::new("sample_files/multiple_clients.csv");
+        let mut balances = vec![];
+        for index in 1..=2 {
+            let mut engine = PaymentsEngine::new().with_shard(Shard {
+                index,
+                count: 2,
+                method: ShardMethod::Hash,
+            });
+            engine.process_csv(input).unwrap();
+            assert!(engine.stats().other_shards > 0);
+            balances.extend(engine.balances().unwrap());
+        }
+        balances.sort_by_key(|account_balance| account_balance.client);
+        assert_eq!(format_report(&balances), crate::process_csv(input).unwrap().to_string());
+    }
+}
@@ -0,0 +1,264 @@
+//! An engine shared by several threads, e.g. the workers of a server, without wrapping every
+//! call in a `Mutex<PaymentsEngine>` of their own.
+//!
+//! The clients are split into shards, each with an engine of its own behind its own lock, see
+//! [`PaymentsEngine::with_shard`], so that the transactions and queries of clients of different
+//! shards don't wait for each other. What is checked across clients only is within a shard: a
+//! dispute of a transaction of a client of another shard is rejected as an unknown transaction
+//! rather than as a client mismatch, and the settlements, dispute expiry and interest of a shard
+//! only go by the timestamps of the transactions of its own clients.
+//!
+//! The ids of the deposits and withdrawals are the exception, they are shared by the shards so
+//! that an id recorded by one of them is rejected as a [`RejectionReason::Duplicate`] by the
+//! others too. An id is claimed by a shard while it applies the transaction, so the same id of
+//! a client of another shard is rejected then even if the transaction turns out rejected too.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::accounts::AccountBalance;
+use crate::engine::{Outcome, PaymentsEngine, ProcessingStats};
+use crate::rejections::RejectionReason;
+use crate::shard::{Shard, ShardMethod};
+use crate::transactions::{ClientId, Transaction, TransactionType, TxId};
+
+/// The error of the calls to a shard whose engine panicked while another thread was using it,
+/// leaving it in an unknown state.
+#[derive(Debug)]
+pub struct EnginePanicked;
+
+impl Error for EnginePanicked {}
+
+impl fmt::Display for EnginePanicked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("The engine panicked while processing a transaction")
+    }
+}
+
+/// A handle to engines of shards of the clients, cheap to clone and to send to other threads.
+#[derive(Clone)]
+pub struct SharedEngine {
+    shards: Arc<[Mutex<PaymentsEngine>]>,
+    // The index of the shard that recorded each deposit and withdrawal, or is applying it
+    tx_shards: Arc<Mutex<HashMap<TxId, usize>>>,
+}
+
+impl SharedEngine {
+    /// `shards` engines that keep all of their state in memory, at least one.
+    pub fn new(shards: u32) -> Self {
+        Self::with_engines(shards, || Ok(PaymentsEngine::new())).expect("in-memory engines are always created")
+    }
+
+    /// `shards` engines created by `new_engine`, which should give each of them stores of its own,
+    /// at least one.
+    pub fn with_engines(
+        shards: u32,
+        mut new_engine: impl FnMut() -> Result<PaymentsEngine, Box<dyn Error>>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let count = shards.max(1);
+        let mut engines = Vec::with_capacity(count as usize);
+        let mut tx_shards = HashMap::new();
+        for index in 1..=count {
+            let shard = Shard {
+                index,
+                count,
+                method: ShardMethod::Hash,
+            };
+            let engine = new_engine()?.with_shard(shard);
+            // The stores may have recorded transactions already
+            for recorded in engine.transactions.all()? {
+                tx_shards.insert(recorded.tx_id, engines.len());
+            }
+            engines.push(Mutex::new(engine));
+        }
+        Ok(SharedEngine {
+            shards: engines.into(),
+            tx_shards: Arc::new(Mutex::new(tx_shards)),
+        })
+    }
+
+    /// How many shards the clients are split into.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// The index of the shard of a client in `shards`.
+    fn shard_of(&self, client_id: ClientId) -> usize {
+        Shard::of(client_id, self.shards.len() as u32, ShardMethod::Hash).index as usize - 1
+    }
+
+    /// The engine of the shard of a client, locked.
+    fn lock(&self, client_id: ClientId) -> Result<MutexGuard<'_, PaymentsEngine>, EnginePanicked> {
+        self.shards[self.shard_of(client_id)].lock().map_err(|_| EnginePanicked)
+    }
+
+    /// Applies a single transaction, see [`PaymentsEngine::apply`], only waiting for the other
+    /// transactions and queries of the clients of the same shard. A deposit or withdrawal with
+    /// the id of one of another shard is rejected as a [`RejectionReason::Duplicate`].
+    pub fn apply(&self, transaction: &Transaction) -> Result<Outcome, Box<dyn Error>> {
+        if !matches!(
+            transaction.tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) {
+            return self.lock(transaction.client_id)?.apply(transaction);
+        }
+        let shard = self.shard_of(transaction.client_id);
+        let claimed = *self
+            .tx_shards
+            .lock()
+            .map_err(|_| EnginePanicked)?
+            .entry(transaction.tx_id)
+            .or_insert(shard);
+        let mut engine = self.lock(transaction.client_id)?;
+        if claimed != shard {
+            return engine.reject_transaction(transaction, RejectionReason::Duplicate);
+        }
+        let outcome = engine.apply(transaction);
+        // A transaction that wasn't recorded, e.g. because it was rejected, gives up its id
+        if engine.transaction(transaction.tx_id)?.is_none() {
+            self.tx_shards
+                .lock()
+                .map_err(|_| EnginePanicked)?
+                .remove(&transaction.tx_id);
+        }
+        outcome
+    }
+
+    /// The balance of a client, if we have seen it before.
+    pub fn account(&self, client_id: ClientId) -> Result<Option<AccountBalance>, Box<dyn Error>> {
+        self.lock(client_id)?.account(client_id)
+    }
+
+    /// All the account balances, sorted by client id. The shards are read one after the other,
+    /// so the balances of different shards may be from slightly different times.
+    pub fn balances(&self) -> Result<Vec<AccountBalance>, Box<dyn Error>> {
+        let mut balances = vec![];
+        for shard in self.shards.iter() {
+            balances.extend(shard.lock().map_err(|_| EnginePanicked)?.balances()?);
+        }
+        balances.sort_by_key(|account_balance| account_balance.client);
+        Ok(balances)
+    }
+
+    /// What the engines of all the shards did so far, added up.
+    pub fn stats(&self) -> Result<ProcessingStats, EnginePanicked> {
+        let mut total = ProcessingStats::default();
+        for shard in self.shards.iter() {
+            let engine = shard.lock().map_err(|_| EnginePanicked)?;
+            let stats = engine.stats();
+            total.processed += stats.processed;
+            total.rejected += stats.rejected;
+            for (reason, count) in &stats.rejections {
+                *total.rejections.entry(*reason).or_default() += count;
+            }
+            for (violation, count) in &stats.risk_rejections {
+                *total.risk_rejections.entry(*violation).or_default() += count;
+            }
+            total.disputes_over_available += stats.disputes_over_available;
+            total.disputes_expired += stats.disputes_expired;
+            for (rule, count) in &stats.auto_locked {
+                *total.auto_locked.entry(*rule).or_default() += count;
+            }
+            total.unknown_clients.extend(&stats.unknown_clients);
+            total.malformed.extend(stats.malformed.iter().cloned());
+        }
+        Ok(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::SharedEngine;
+    use crate::amount::amount;
+    use crate::rejections::RejectionReason;
+    use crate::transactions::{ClientId, Transaction, TransactionType, TxId};
+
+    #[test]
+    fn test_shared_engine() {
+        let engine = SharedEngine::new(4);
+        let workers: Vec<_> = (0..8)
+            .map(|worker| {
+                let engine = engine.clone();
+                thread::spawn(move || {
+                    for n in 0..100 {
+                        let client_id: ClientId = 1 + worker * 10 + n % 10;
+                        let transaction = Transaction {
+                            tx_type: TransactionType::Deposit,
+                            client_id,
+                            tx_id: 1 + TxId::from(worker) * 100 + TxId::from(n),
+                            amount: Some(amount(1.0)),
+                            reason: None,
+                            timestamp: None,
+                            settles_at: None,
+                        };
+                        assert!(engine.apply(&transaction).unwrap().is_ok());
+                        assert!(engine.account(client_id).unwrap().is_some());
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let balances = engine.balances().unwrap();
+        assert_eq!(balances.len(), 80);
+        assert!(balances.windows(2).all(|pair| pair[0].client < pair[1].client));
+        assert!(balances
+            .iter()
+            .all(|account_balance| account_balance.available == amount(10.0)));
+        let stats = engine.stats().unwrap();
+        assert_eq!((stats.processed, stats.rejected, stats.other_shards), (800, 0, 0));
+
+        // The transactions of a client are checked against its own shard
+        let withdrawal = Transaction {
+            tx_type: TransactionType::Withdrawal,
+            client_id: 1,
+            tx_id: 10_000,
+            amount: Some(amount(20.0)),
+            reason: None,
+            timestamp: None,
+            settles_at: None,
+        };
+        let rejected = engine.apply(&withdrawal).unwrap().unwrap_err();
+        assert_eq!(rejected.reason, RejectionReason::InsufficientFunds);
+    }
+
+    #[test]
+    fn test_shared_tx_ids() {
+        let engine = SharedEngine::new(4);
+        let deposit = |client_id: ClientId, tx_id: TxId, value: f64| Transaction {
+            tx_type: TransactionType::Deposit,
+            client_id,
+            tx_id,
+            amount: Some(amount(value)),
+            reason: None,
+            timestamp: None,
+            settles_at: None,
+        };
+        // Two clients of different shards
+        let other = (2..)
+            .find(|&client_id| engine.shard_of(client_id) != engine.shard_of(1))
+            .unwrap();
+        assert!(engine.apply(&deposit(1, 1, 5.0)).unwrap().is_ok());
+        let rejected = engine.apply(&deposit(other, 1, 3.0)).unwrap().unwrap_err();
+        assert_eq!(rejected.reason, RejectionReason::Duplicate);
+        assert_eq!(engine.account(other).unwrap().unwrap().available, amount(0.0));
+
+        // The id of a transaction that was rejected is free again
+        let withdrawal = Transaction {
+            tx_type: TransactionType::Withdrawal,
+            amount: Some(amount(10.0)),
+            ..deposit(1, 2, 0.0)
+        };
+        assert!(engine.apply(&withdrawal).unwrap().is_err());
+        assert!(engine.apply(&deposit(other, 2, 3.0)).unwrap().is_ok());
+        let stats = engine.stats().unwrap();
+        assert_eq!((stats.processed, stats.rejected), (4, 2));
+        assert_eq!(stats.rejections[&RejectionReason::Duplicate], 1);
+    }
+}
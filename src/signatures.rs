@@ -0,0 +1,147 @@
+//! Checks the Ed25519 signatures partners add to each CSV record, in a `signature` column.
+//!
+//! The signed message is the other fields of the record, trimmed, in the order of the columns of
+//! the file, each one after its length in bytes as a 4-byte big-endian integer, see
+//! [`signed_message`]. A row `deposit, 1, 7, 1.0, <signature>` signs
+//! `\0\0\0\x07deposit\0\0\0\x011\0\0\0\x017\0\0\0\x031.0`. With the lengths, no two records
+//! sign the same message, even when their fields have commas. The signature is the base64 of
+//! its 64 bytes.
+
+use std::error::Error;
+use std::fmt;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::signature::{UnparsedPublicKey, ED25519};
+
+/// The DER of the SubjectPublicKeyInfo of an Ed25519 key, before its 32 bytes.
+const SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+
+/// The message signed for the fields of a record, the signature excluded: each field, trimmed,
+/// after its length.
+pub fn signed_message<'a>(fields: impl IntoIterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut message = vec![];
+    for field in fields {
+        let field = field.trim_ascii();
+        message.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        message.extend_from_slice(field);
+    }
+    message
+}
+
+/// Checks the signatures of the records with the public key of a partner.
+#[derive(Debug, Clone)]
+pub struct SignatureVerifier {
+    key: UnparsedPublicKey<[u8; 32]>,
+}
+
+impl SignatureVerifier {
+    /// A verifier with an Ed25519 public key in PEM, i.e. `-----BEGIN PUBLIC KEY-----`.
+    pub fn from_pem(pem: &str) -> Result<Self, PublicKeyError> {
+        let body: String = pem
+            .lines()
+            .map(str::trim)
+            .skip_while(|line| *line != "-----BEGIN PUBLIC KEY-----")
+            .skip(1)
+            .take_while(|line| *line != "-----END PUBLIC KEY-----")
+            .collect();
+        let der = STANDARD.decode(body).map_err(|_| PublicKeyError)?;
+        let key = der
+            .strip_prefix(&SPKI_PREFIX[..])
+            .and_then(|key| key.try_into().ok())
+            .ok_or(PublicKeyError)?;
+        Ok(SignatureVerifier {
+            key: UnparsedPublicKey::new(&ED25519, key),
+        })
+    }
+
+    /// Whether the record has a valid signature in its field `signature_column`.
+    pub fn verify_record<'a>(&self, fields: impl Iterator<Item = &'a [u8]>, signature_column: Option<usize>) -> bool {
+        let Some(signature_column) = signature_column else {
+            return false;
+        };
+        let mut fields: Vec<&[u8]> = fields.collect();
+        if signature_column >= fields.len() {
+            return false;
+        }
+        let signature = fields.remove(signature_column).trim_ascii();
+        STANDARD
+            .decode(signature)
+            .is_ok_and(|signature| self.key.verify(&signed_message(fields), &signature).is_ok())
+    }
+}
+
+/// The public key isn't an Ed25519 one in PEM.
+#[derive(Debug)]
+pub struct PublicKeyError;
+
+impl Error for PublicKeyError {}
+
+impl fmt::Display for PublicKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Invalid public key, expected an Ed25519 one in PEM (-----BEGIN PUBLIC KEY-----)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    use super::{signed_message, SignatureVerifier, SPKI_PREFIX};
+    use crate::amount::amount;
+    use crate::engine::PaymentsEngine;
+    use crate::rejections::RejectionReason;
+
+    #[test]
+    fn test_signatures() {
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(&[7; 32]).unwrap();
+        let pem = format!(
+            "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+            STANDARD.encode([&SPKI_PREFIX[..], key_pair.public_key().as_ref()].concat())
+        );
+        let verifier = SignatureVerifier::from_pem(&pem).unwrap();
+        assert!(SignatureVerifier::from_pem("-----BEGIN PUBLIC KEY-----\nAAAA\n-----END PUBLIC KEY-----").is_err());
+
+        let sign = |fields: &[&str]| {
+            STANDARD.encode(key_pair.sign(&signed_message(fields.iter().map(|field| field.as_bytes()))))
+        };
+        let input = format!(
+            "type, client, tx, amount, signature\n\
+             deposit, 1, 1, 2.0, {}\n\
+             deposit, 1, 2, 3.0, {}\n\
+             withdrawal, 1, 3, 1.0, {}\n\
+             withdrawal, 1, 4, 1.0,\n",
+            sign(&["deposit", "1", "1", "2.0"]),
+            // Signed for another amount
+            sign(&["deposit", "1", "2", "30.0"]),
+            sign(&["withdrawal", "1", "3", "1.0"]),
+        );
+        let mut engine = PaymentsEngine::new()
+            .with_signature_verifier(verifier.clone())
+            .with_rejections();
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        assert_eq!(engine.account(1).unwrap().unwrap().available, amount(1.0));
+        let rejected: Vec<_> = engine
+            .rejections()
+            .iter()
+            .map(|rejection| (rejection.line, rejection.reason))
+            .collect();
+        assert_eq!(
+            rejected,
+            [
+                (Some(3), RejectionReason::InvalidSignature),
+                (Some(5), RejectionReason::InvalidSignature)
+            ]
+        );
+
+        // Fields that would be joined the same way are signed differently
+        let (one, other) = ([&b"a,b"[..], b"c"], [&b"a"[..], b"b,c"]);
+        assert_ne!(signed_message(one), signed_message(other));
+        let signature = STANDARD.encode(key_pair.sign(&signed_message(one)));
+        let fields = |fields: [&'static [u8]; 2]| fields.into_iter().chain([signature.as_bytes()]);
+        assert!(verifier.verify_record(fields(one), Some(2)));
+        assert!(!verifier.verify_record(fields(other), Some(2)));
+    }
+}
@@ -0,0 +1,592 @@
+//! A compact binary snapshot of the engine state, to save a session and restore it later.
+//!
+//! All the numbers are little endian, and the ids are a `u16` client and `u32` tx, or a `u32`
+//! client and `u64` tx with the `wide-ids` feature. The amounts are `f32`, `f64` with the
+//! `wide-amounts` feature, or an `i64` of minor units with `fixed-amounts`. Version 2, the one
+//! written:
+//!
+//! ```text
+//! magic    b"PESTAT"
+//! u16      version
+//! u8       width of the ids, 0 or 1 with `wide-ids`, plus 2 with `wide-amounts` or 4 with
+//!          `fixed-amounts`
+//! u32      number of accounts, then for each: client, available, held, pending,
+//!          u8 status (0 active, 1 frozen, 2 locked, 3 closed)
+//! u32      number of deposits and withdrawals, then for each: tx, u8 type (0 deposit,
+//!          1 withdrawal), u8 whether the client is known, client, amount, u8 status
+//!          (0 settled, 1 pending, 2 disputed, 3 charged back, 4 reversed), amount held
+//! u32      number of pending deposits, then for each: tx, client, amount, u64 settles_at
+//! u32      number of processed transactions, then for each: u8 type, tx
+//! u32      number of clients active with a timestamp, then for each: client, u64 last timestamp
+//! u32      number of timestamped open disputes, then for each: tx, client, u64 opened at
+//! u8       whether the interest has a day, i64 the day of the last timestamp, in days since
+//!          the Unix epoch
+//! u32      number of clients with interest accrued and not posted, then for each: client,
+//!          f64 accrued
+//! u32      number of clients remembered by the risk rules, then for each: client, u8 whether
+//!          it has daily withdrawals, u64 their day, their total, u32 number of deposits and
+//!          withdrawals in the velocity window, then for each u64 timestamp, u32 number of
+//!          disputes in the auto-lock window, then for each u64 timestamp
+//! u32      number of clients with chargebacks, then for each: client, u64 chargebacks, charged
+//!          back, fees, u64 reversals, recovered
+//! ```
+//!
+//! So that an engine restored from a snapshot goes on as the one that wrote it would have, the
+//! disputes still expire, the interest accrued is posted, the risk rules count the transactions
+//! before it and the losses are of all the chargebacks. Only the stats start over.
+//!
+//! The processed transactions are the ones of the dedup store, see [`PaymentsEngine::with_dedup`],
+//! and their type is its position in [`TransactionType::ALL`].
+//!
+//! Version 1 had no version or id width: its magic was `b"PESNAP"`, or `b"PESNPW"` with wide
+//! ids. Its deposits were a tx, an amount, a u8 disputed and a u8 reversed, and its withdrawals
+//! a tx, an amount and a u8 reversed, without their client, so the transactions that refer to
+//! those aren't checked to be of the same client. It had no dispute charged back or held for
+//! part of its amount, nor the last activity of the clients, and the processed transactions
+//! were only there with a dedup store. It can still be restored, except with `fixed-amounts`,
+//! and [`migrate_snapshot`] rewrites it as the current version.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::error::Error;
+use std::io::{self, Read, Write};
+
+use crate::accounts::{AccountBalance, AccountStatus};
+use crate::custom_errors::{SnapshotError, SnapshotErrorType};
+use crate::engine::PaymentsEngine;
+use crate::interest::InterestAccrual;
+use crate::losses::LossTotals;
+use crate::risk::ClientRisk;
+use crate::store::{MemoryDedupStore, PendingSettlement, RecordedTx, TxStatus};
+use crate::transactions::{Amount, ClientId, TransactionType, TxId};
+
+const MAGIC: &[u8; 6] = b"PESTAT";
+
+/// The version of the snapshots written by this build.
+pub const SNAPSHOT_VERSION: u16 = 2;
+
+#[cfg(not(feature = "wide-ids"))]
+const ID_WIDTH: u8 = 0;
+#[cfg(feature = "wide-ids")]
+const ID_WIDTH: u8 = 1;
+
+// In the same byte as the width of the ids, so that the snapshots of the builds with the other
+// amounts are rejected by the older builds too
+#[cfg(not(any(feature = "wide-amounts", feature = "fixed-amounts")))]
+const AMOUNT_WIDTH: u8 = 0;
+#[cfg(all(feature = "wide-amounts", not(feature = "fixed-amounts")))]
+const AMOUNT_WIDTH: u8 = 2;
+#[cfg(feature = "fixed-amounts")]
+const AMOUNT_WIDTH: u8 = 4;
+
+/// The magic of the version 1 snapshots of this build, and of the build with the other ids.
+#[cfg(not(feature = "wide-ids"))]
+const V1_MAGICS: (&[u8; 6], &[u8; 6]) = (b"PESNAP", b"PESNPW");
+#[cfg(feature = "wide-ids")]
+const V1_MAGICS: (&[u8; 6], &[u8; 6]) = (b"PESNPW", b"PESNAP");
+
+fn read_bytes<const N: usize, R: Read>(reader: &mut R) -> Result<[u8; N], Box<dyn Error>> {
+    let mut bytes = [0; N];
+    match reader.read_exact(&mut bytes) {
+        Ok(()) => Ok(bytes),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Err(Box::new(SnapshotError {
+            error_type: SnapshotErrorType::Truncated,
+        })),
+        Err(err) => Err(Box::new(err)),
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, Box<dyn Error>> {
+    Ok(u32::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_client_id<R: Read>(reader: &mut R) -> Result<ClientId, Box<dyn Error>> {
+    Ok(ClientId::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_tx_id<R: Read>(reader: &mut R) -> Result<TxId, Box<dyn Error>> {
+    Ok(TxId::from_le_bytes(read_bytes(reader)?))
+}
+
+/// Whether the bytes look like the start of a snapshot, of any version or width of the ids.
+pub(crate) fn is_snapshot(bytes: &[u8]) -> bool {
+    [MAGIC, V1_MAGICS.0, V1_MAGICS.1]
+        .iter()
+        .any(|magic| bytes.starts_with(&magic[..]))
+}
+
+/// Like [`read_u32`], but `None` at the end of the snapshot.
+fn read_optional_u32<R: Read>(reader: &mut R) -> Result<Option<u32>, Box<dyn Error>> {
+    let mut first = [0];
+    if reader.read(&mut first)? == 0 {
+        return Ok(None);
+    }
+    let rest: [u8; 3] = read_bytes(reader)?;
+    Ok(Some(u32::from_le_bytes([first[0], rest[0], rest[1], rest[2]])))
+}
+
+fn read_amount<R: Read>(reader: &mut R) -> Result<Amount, Box<dyn Error>> {
+    Ok(Amount::from_le_bytes(read_bytes(reader)?))
+}
+
+fn read_bool<R: Read>(reader: &mut R) -> Result<bool, Box<dyn Error>> {
+    Ok(read_bytes::<1, R>(reader)?[0] != 0)
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64, Box<dyn Error>> {
+    Ok(u64::from_le_bytes(read_bytes(reader)?))
+}
+
+/// Writes the timestamps of a risk window, none if it has none.
+fn write_timestamps<W: Write>(writer: &mut W, timestamps: Option<&VecDeque<u64>>) -> io::Result<()> {
+    writer.write_all(&(timestamps.map_or(0, VecDeque::len) as u32).to_le_bytes())?;
+    for timestamp in timestamps.into_iter().flatten() {
+        writer.write_all(&timestamp.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Reads the timestamps written by [`write_timestamps`].
+fn read_timestamps<R: Read>(reader: &mut R) -> Result<Option<VecDeque<u64>>, Box<dyn Error>> {
+    let mut timestamps = VecDeque::new();
+    for _ in 0..read_u32(reader)? {
+        timestamps.push_back(read_u64(reader)?);
+    }
+    Ok((!timestamps.is_empty()).then_some(timestamps))
+}
+
+/// The code of the status of a deposit or withdrawal.
+fn status_code(status: &TxStatus) -> u8 {
+    match status {
+        TxStatus::Settled => 0,
+        TxStatus::Pending => 1,
+        TxStatus::Disputed { .. } => 2,
+        TxStatus::ChargedBack { .. } => 3,
+        TxStatus::Reversed => 4,
+    }
+}
+
+fn not_a_snapshot() -> Box<dyn Error> {
+    Box::new(SnapshotError {
+        error_type: SnapshotErrorType::NotASnapshot,
+    })
+}
+
+impl PaymentsEngine {
+    /// Writes all the account balances and recorded transactions.
+    pub fn write_snapshot<W: Write>(&self, mut writer: W) -> Result<(), Box<dyn Error>> {
+        let accounts = self.accounts.all()?;
+        let recorded = self.transactions.all()?;
+        let settlements = self.transactions.settlements()?;
+
+        writer.write_all(MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        writer.write_all(&[ID_WIDTH | AMOUNT_WIDTH])?;
+        writer.write_all(&(accounts.len() as u32).to_le_bytes())?;
+        for account_balance in accounts {
+            writer.write_all(&account_balance.client.to_le_bytes())?;
+            writer.write_all(&account_balance.available.to_le_bytes())?;
+            writer.write_all(&account_balance.held.to_le_bytes())?;
+            writer.write_all(&account_balance.pending.to_le_bytes())?;
+            writer.write_all(&[account_balance.status as u8])?;
+        }
+        writer.write_all(&(recorded.len() as u32).to_le_bytes())?;
+        for recorded in recorded {
+            writer.write_all(&recorded.tx_id.to_le_bytes())?;
+            let withdrawal = recorded.tx_type == TransactionType::Withdrawal;
+            writer.write_all(&[withdrawal as u8, recorded.client_id.is_some() as u8])?;
+            writer.write_all(&recorded.client_id.unwrap_or_default().to_le_bytes())?;
+            writer.write_all(&recorded.amount.to_le_bytes())?;
+            writer.write_all(&[status_code(&recorded.status)])?;
+            writer.write_all(&recorded.status.held().unwrap_or_default().to_le_bytes())?;
+        }
+        writer.write_all(&(settlements.len() as u32).to_le_bytes())?;
+        for settlement in settlements {
+            writer.write_all(&settlement.tx_id.to_le_bytes())?;
+            writer.write_all(&settlement.client_id.to_le_bytes())?;
+            writer.write_all(&settlement.amount.to_le_bytes())?;
+            writer.write_all(&settlement.settles_at.to_le_bytes())?;
+        }
+        let processed = match &self.dedup {
+            Some(dedup) => dedup.all()?,
+            None => vec![],
+        };
+        writer.write_all(&(processed.len() as u32).to_le_bytes())?;
+        for (tx_type, tx_id) in processed {
+            writer.write_all(&[tx_type as u8])?;
+            writer.write_all(&tx_id.to_le_bytes())?;
+        }
+        let mut last_activity: Vec<(ClientId, u64)> =
+            self.last_activity.iter().map(|(client, at)| (*client, *at)).collect();
+        last_activity.sort_unstable();
+        writer.write_all(&(last_activity.len() as u32).to_le_bytes())?;
+        for (client_id, timestamp) in last_activity {
+            writer.write_all(&client_id.to_le_bytes())?;
+            writer.write_all(&timestamp.to_le_bytes())?;
+        }
+        let open_disputes: Vec<_> = self.open_disputes.all().collect();
+        writer.write_all(&(open_disputes.len() as u32).to_le_bytes())?;
+        for (tx_id, client_id, opened_at) in open_disputes {
+            writer.write_all(&tx_id.to_le_bytes())?;
+            writer.write_all(&client_id.to_le_bytes())?;
+            writer.write_all(&opened_at.to_le_bytes())?;
+        }
+        let (day, accrued) = self.interest.state();
+        writer.write_all(&[day.is_some() as u8])?;
+        writer.write_all(&day.unwrap_or_default().to_le_bytes())?;
+        writer.write_all(&(accrued.len() as u32).to_le_bytes())?;
+        for (client_id, amount) in accrued {
+            writer.write_all(&client_id.to_le_bytes())?;
+            writer.write_all(&amount.to_le_bytes())?;
+        }
+        let risk = self.risk.clients();
+        writer.write_all(&(risk.len() as u32).to_le_bytes())?;
+        for (client_id, risk) in risk {
+            writer.write_all(&client_id.to_le_bytes())?;
+            let (day, total) = risk.daily_withdrawals.unwrap_or_default();
+            writer.write_all(&[risk.daily_withdrawals.is_some() as u8])?;
+            writer.write_all(&day.to_le_bytes())?;
+            writer.write_all(&total.to_le_bytes())?;
+            write_timestamps(&mut writer, risk.recent.as_ref())?;
+            write_timestamps(&mut writer, risk.disputes.as_ref())?;
+        }
+        let losses = self.losses.by_client();
+        writer.write_all(&(losses.len() as u32).to_le_bytes())?;
+        for (client_id, totals) in losses {
+            writer.write_all(&client_id.to_le_bytes())?;
+            writer.write_all(&totals.chargebacks.to_le_bytes())?;
+            writer.write_all(&totals.charged_back.to_le_bytes())?;
+            writer.write_all(&totals.fees.to_le_bytes())?;
+            writer.write_all(&totals.reversals.to_le_bytes())?;
+            writer.write_all(&totals.recovered.to_le_bytes())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Restores a snapshot written by [`PaymentsEngine::write_snapshot`], of this version or
+    /// an earlier one, and returns its version.
+    /// The state is added to the current one, so this is meant for a new engine.
+    /// The processed transactions are only restored if the engine has a dedup store.
+    pub fn restore_snapshot<R: Read>(&mut self, mut reader: R) -> Result<u16, Box<dyn Error>> {
+        let magic = read_bytes::<6, R>(&mut reader)?;
+        let version = if &magic == MAGIC {
+            let version = u16::from_le_bytes(read_bytes(&mut reader)?);
+            if version > SNAPSHOT_VERSION {
+                return Err(Box::new(SnapshotError {
+                    error_type: SnapshotErrorType::UnsupportedVersion(version),
+                }));
+            }
+            if read_bytes::<1, R>(&mut reader)?[0] != ID_WIDTH | AMOUNT_WIDTH {
+                return Err(Box::new(SnapshotError {
+                    error_type: SnapshotErrorType::OtherIdWidth,
+                }));
+            }
+            version
+        } else if &magic == V1_MAGICS.0 && !cfg!(feature = "fixed-amounts") {
+            1
+        } else if &magic == V1_MAGICS.0 || &magic == V1_MAGICS.1 {
+            // Only the builds with float amounts wrote version 1
+            return Err(Box::new(SnapshotError {
+                error_type: SnapshotErrorType::OtherIdWidth,
+            }));
+        } else {
+            return Err(not_a_snapshot());
+        };
+        // The transactions before the snapshot can't be undone anymore
+        if let Some(undo) = &mut self.undo {
+            undo.clear();
+        }
+
+        for _ in 0..read_u32(&mut reader)? {
+            let account_balance = AccountBalance {
+                client: read_client_id(&mut reader)?,
+                available: read_amount(&mut reader)?,
+                held: read_amount(&mut reader)?,
+                pending: read_amount(&mut reader)?,
+                status: match read_bytes::<1, R>(&mut reader)?[0] {
+                    0 => AccountStatus::Active,
+                    1 => AccountStatus::Frozen,
+                    2 => AccountStatus::Locked,
+                    3 => AccountStatus::Closed,
+                    _ => return Err(not_a_snapshot()),
+                },
+            };
+            self.accounts.put(&account_balance)?;
+        }
+        let mut recorded = match version {
+            1 => read_v1_transactions(&mut reader)?,
+            _ => read_transactions(&mut reader)?,
+        };
+        let mut settlements = vec![];
+        for _ in 0..read_u32(&mut reader)? {
+            settlements.push(PendingSettlement {
+                tx_id: read_tx_id(&mut reader)?,
+                client_id: read_client_id(&mut reader)?,
+                amount: read_amount(&mut reader)?,
+                settles_at: u64::from_le_bytes(read_bytes(&mut reader)?),
+            });
+        }
+        // Version 1 didn't have the status of the pending deposits
+        if version == 1 {
+            let pending: BTreeSet<TxId> = settlements.iter().map(|settlement| settlement.tx_id).collect();
+            for deposit in recorded.iter_mut().filter(|deposit| pending.contains(&deposit.tx_id)) {
+                deposit.status = TxStatus::Pending;
+            }
+        }
+        for recorded in &recorded {
+            self.transactions.record(recorded)?;
+        }
+        for settlement in &settlements {
+            self.transactions.record_settlement(settlement)?;
+        }
+        // Only there with a dedup store in version 1
+        let processed = match version {
+            1 => read_optional_u32(&mut reader)?.unwrap_or(0),
+            _ => read_u32(&mut reader)?,
+        };
+        for _ in 0..processed {
+            let Some(&tx_type) = TransactionType::ALL.get(read_bytes::<1, R>(&mut reader)?[0] as usize) else {
+                return Err(not_a_snapshot());
+            };
+            let tx_id = read_tx_id(&mut reader)?;
+            if let Some(dedup) = &mut self.dedup {
+                dedup.insert(tx_type, tx_id)?;
+            }
+        }
+        if version == 1 {
+            return Ok(version);
+        }
+        for _ in 0..read_u32(&mut reader)? {
+            let client_id = read_client_id(&mut reader)?;
+            self.last_activity.insert(client_id, read_u64(&mut reader)?);
+        }
+        for _ in 0..read_u32(&mut reader)? {
+            let tx_id = read_tx_id(&mut reader)?;
+            let client_id = read_client_id(&mut reader)?;
+            self.open_disputes.open(tx_id, client_id, read_u64(&mut reader)?);
+        }
+        let has_day = read_bool(&mut reader)?;
+        let day = i64::from_le_bytes(read_bytes(&mut reader)?);
+        let mut accrued = BTreeMap::new();
+        for _ in 0..read_u32(&mut reader)? {
+            let client_id = read_client_id(&mut reader)?;
+            accrued.insert(client_id, f64::from_le_bytes(read_bytes(&mut reader)?));
+        }
+        self.interest = InterestAccrual::from_state(has_day.then_some(day), accrued);
+        for _ in 0..read_u32(&mut reader)? {
+            let client_id = read_client_id(&mut reader)?;
+            let has_daily_withdrawals = read_bool(&mut reader)?;
+            let daily_withdrawals = (read_u64(&mut reader)?, read_amount(&mut reader)?);
+            let risk = ClientRisk {
+                daily_withdrawals: has_daily_withdrawals.then_some(daily_withdrawals),
+                recent: read_timestamps(&mut reader)?,
+                disputes: read_timestamps(&mut reader)?,
+            };
+            self.risk.restore(client_id, risk);
+        }
+        for _ in 0..read_u32(&mut reader)? {
+            let client_id = read_client_id(&mut reader)?;
+            let totals = LossTotals {
+                chargebacks: read_u64(&mut reader)?,
+                charged_back: read_amount(&mut reader)?,
+                fees: read_amount(&mut reader)?,
+                reversals: read_u64(&mut reader)?,
+                recovered: read_amount(&mut reader)?,
+            };
+            self.losses.restore(client_id, Some(totals));
+        }
+        Ok(version)
+    }
+}
+
+/// Reads the deposits and withdrawals of a snapshot of the current version.
+fn read_transactions<R: Read>(reader: &mut R) -> Result<Vec<RecordedTx>, Box<dyn Error>> {
+    let mut recorded = vec![];
+    for _ in 0..read_u32(reader)? {
+        let tx_id = read_tx_id(reader)?;
+        let tx_type = match read_bytes::<1, R>(reader)?[0] {
+            0 => TransactionType::Deposit,
+            1 => TransactionType::Withdrawal,
+            _ => return Err(not_a_snapshot()),
+        };
+        let known_client = read_bool(reader)?;
+        let client_id = read_client_id(reader)?;
+        let amount = read_amount(reader)?;
+        let status = read_bytes::<1, R>(reader)?[0];
+        let held = read_amount(reader)?;
+        recorded.push(RecordedTx {
+            tx_id,
+            client_id: known_client.then_some(client_id),
+            tx_type,
+            amount,
+            status: match status {
+                0 => TxStatus::Settled,
+                1 => TxStatus::Pending,
+                2 => TxStatus::Disputed { held },
+                3 => TxStatus::ChargedBack { held },
+                4 => TxStatus::Reversed,
+                _ => return Err(not_a_snapshot()),
+            },
+        });
+    }
+    Ok(recorded)
+}
+
+/// Reads the deposits and withdrawals of a version 1 snapshot, sorted by id.
+fn read_v1_transactions<R: Read>(reader: &mut R) -> Result<Vec<RecordedTx>, Box<dyn Error>> {
+    let mut recorded = BTreeMap::new();
+    let mut record = |tx_id, tx_type, amount, status| {
+        let deposit_or_withdrawal = RecordedTx {
+            tx_id,
+            client_id: None,
+            tx_type,
+            amount,
+            status,
+        };
+        recorded.insert(tx_id, deposit_or_withdrawal);
+    };
+    for _ in 0..read_u32(reader)? {
+        let tx_id = read_tx_id(reader)?;
+        let amount = read_amount(reader)?;
+        let disputed = read_bool(reader)?;
+        let status = match read_bool(reader)? {
+            true => TxStatus::Reversed,
+            false if disputed => TxStatus::Disputed { held: amount },
+            false => TxStatus::Settled,
+        };
+        record(tx_id, TransactionType::Deposit, amount, status);
+    }
+    for _ in 0..read_u32(reader)? {
+        let tx_id = read_tx_id(reader)?;
+        let amount = read_amount(reader)?;
+        let status = if read_bool(reader)? {
+            TxStatus::Reversed
+        } else {
+            TxStatus::Settled
+        };
+        record(tx_id, TransactionType::Withdrawal, amount, status);
+    }
+    Ok(recorded.into_values().collect())
+}
+
+/// Rewrites a snapshot of an earlier version as the current one, and returns its version.
+pub fn migrate_snapshot<R: Read, W: Write>(reader: R, writer: W) -> Result<u16, Box<dyn Error>> {
+    // Keeps the processed transactions, if there are any
+    let mut engine = PaymentsEngine::new().with_dedup(Box::new(MemoryDedupStore::default()));
+    let version = engine.restore_snapshot(reader)?;
+    engine.write_snapshot(writer)?;
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{SNAPSHOT_VERSION, V1_MAGICS};
+    use crate::amount::amount;
+    use crate::engine::PaymentsEngine;
+    use crate::transactions::{Amount, ClientId, Transaction, TxId};
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let mut engine = PaymentsEngine::new();
+        engine.process_csv(Path::new("sample_files/dispute.csv")).unwrap();
+        let mut snapshot = vec![];
+        engine.write_snapshot(&mut snapshot).unwrap();
+
+        let mut restored = PaymentsEngine::new();
+        restored.restore_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(restored.state_hash().unwrap(), engine.state_hash().unwrap());
+
+        // The dispute must still be open after restoring
+        let resolve = Transaction::parse_csv_record(b"resolve, 1, 1").unwrap();
+        engine.apply(&resolve).unwrap().unwrap();
+        restored.apply(&resolve).unwrap().unwrap();
+        assert_eq!(restored.report().unwrap(), engine.report().unwrap());
+        assert_eq!(restored.stats().rejected, 0);
+
+        // Partial disputes keep the amount they hold
+        let mut engine = PaymentsEngine::new();
+        engine
+            .process_csv_reader("type, client, tx, amount\ndeposit, 1, 1, 2.0\ndispute, 1, 1, 0.5".as_bytes())
+            .unwrap();
+        let mut partial = vec![];
+        engine.write_snapshot(&mut partial).unwrap();
+        let mut partially_restored = PaymentsEngine::new();
+        partially_restored.restore_snapshot(partial.as_slice()).unwrap();
+        partially_restored.apply(&resolve).unwrap().unwrap();
+        assert_eq!(partially_restored.account(1).unwrap().unwrap().available, amount(2.0));
+
+        assert!(restored.restore_snapshot(&snapshot[..snapshot.len() - 1]).is_err());
+        assert!(restored.restore_snapshot(&b"client,available"[..]).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_versions() {
+        let mut engine = PaymentsEngine::new();
+        let input = "type, client, tx, amount
+            deposit, 1, 1, 2.0
+            deposit, 1, 3, 1.0
+            dispute, 1, 3,
+            withdrawal, 1, 2, 0.5";
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        let mut snapshot = vec![];
+        engine.write_snapshot(&mut snapshot).unwrap();
+
+        // The same state in version 1, without the clients of the transactions
+        let account = [
+            &(1 as ClientId).to_le_bytes()[..],
+            &amount(1.5).to_le_bytes(),
+            &amount(1.0).to_le_bytes(),
+            &amount(0.0).to_le_bytes(),
+            &[0],
+        ]
+        .concat();
+        let deposit = |tx_id: TxId, amount: Amount, disputed: u8| {
+            [&tx_id.to_le_bytes()[..], &amount.to_le_bytes(), &[disputed, 0]].concat()
+        };
+        let withdrawal = [&(2 as TxId).to_le_bytes()[..], &amount(0.5).to_le_bytes(), &[0]].concat();
+        let v1 = [
+            &V1_MAGICS.0[..],
+            &1u32.to_le_bytes(),
+            &account,
+            &2u32.to_le_bytes(),
+            &deposit(1, amount(2.0), 0),
+            &deposit(3, amount(1.0), 1),
+            &1u32.to_le_bytes(),
+            &withdrawal,
+            &0u32.to_le_bytes(),
+        ]
+        .concat();
+        // Only the builds with float amounts wrote version 1
+        #[cfg(feature = "fixed-amounts")]
+        assert!(PaymentsEngine::new().restore_snapshot(v1.as_slice()).is_err());
+        #[cfg(not(feature = "fixed-amounts"))]
+        {
+            let mut restored = PaymentsEngine::new();
+            assert_eq!(restored.restore_snapshot(v1.as_slice()).unwrap(), 1);
+            assert_eq!(restored.state_hash().unwrap(), engine.state_hash().unwrap());
+
+            let mut migrated = vec![];
+            assert_eq!(super::migrate_snapshot(v1.as_slice(), &mut migrated).unwrap(), 1);
+            assert_eq!(migrated[6..8], SNAPSHOT_VERSION.to_le_bytes());
+            let mut restored = PaymentsEngine::new();
+            assert_eq!(
+                restored.restore_snapshot(migrated.as_slice()).unwrap(),
+                SNAPSHOT_VERSION
+            );
+            assert_eq!(restored.state_hash().unwrap(), engine.state_hash().unwrap());
+            assert_eq!(restored.transaction(1).unwrap().unwrap().client_id, None);
+        }
+
+        let mut newer = snapshot.clone();
+        newer[6..8].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        let err = PaymentsEngine::new().restore_snapshot(newer.as_slice()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The snapshot is of version 3, newer than this engine supports"
+        );
+        let other_ids = [&V1_MAGICS.1[..], &v1[6..]].concat();
+        assert!(PaymentsEngine::new().restore_snapshot(other_ids.as_slice()).is_err());
+    }
+}
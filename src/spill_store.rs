@@ -0,0 +1,397 @@
+//! A [`TxStore`] whose memory stays flat however many transactions it records: the recently
+//! used ones are kept in memory, up to a number of them, and the others are spilled to a file.
+//!
+//! Disputes, resolves, chargebacks and reversals almost always refer to recent transactions,
+//! which are then found in memory. The older ones are read back from the file, which is
+//! indexed by the transaction id: the record of each id is at a fixed offset, so that the file
+//! is sparse, without space taken for the ids in between, and a record is found in a single
+//! read. The file only lives as long as the store, it is not a way to persist the state, see
+//! the [`sqlite_store`](crate::sqlite_store) for that.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::amount::AmountValue;
+use crate::store::{PendingSettlement, RecordedTx, StoreResult, TxStatus, TxStore};
+use crate::transactions::{Amount, ClientId, TransactionType, TxId};
+
+/// How many transactions are kept in memory by default.
+pub const DEFAULT_HOT_TRANSACTIONS: usize = 1_000_000;
+
+const AMOUNT_SIZE: usize = std::mem::size_of::<Amount>();
+const CLIENT_SIZE: usize = std::mem::size_of::<ClientId>();
+// The flags, the amount, the amount held by its dispute, and the client
+const RECORD_SIZE: usize = (1 + 2 * AMOUNT_SIZE + CLIENT_SIZE).next_power_of_two();
+
+// What is recorded of a transaction, in its flags, a withdrawal being a recorded one that isn't a deposit
+const RECORDED: u8 = 1;
+const DEPOSIT: u8 = 1 << 1;
+const OWNED: u8 = 1 << 2;
+const PENDING: u8 = 1 << 3;
+const DISPUTED: u8 = 1 << 4;
+const CHARGED_BACK: u8 = 1 << 5;
+const REVERSED: u8 = 1 << 6;
+
+/// What is recorded of a transaction id, the same in memory and in its record of the file.
+/// Nothing is recorded of the ids without flags, e.g. the holes of the file.
+#[derive(Debug, Clone, Copy, Default)]
+struct Entry {
+    flags: u8,
+    amount: Amount,
+    held: Amount,
+    client: ClientId,
+}
+
+impl Entry {
+    fn pack(recorded: &RecordedTx) -> Self {
+        let mut flags = RECORDED;
+        if recorded.tx_type == TransactionType::Deposit {
+            flags |= DEPOSIT;
+        }
+        if recorded.client_id.is_some() {
+            flags |= OWNED;
+        }
+        flags |= match recorded.status {
+            TxStatus::Settled => 0,
+            TxStatus::Pending => PENDING,
+            TxStatus::Disputed { .. } => DISPUTED,
+            TxStatus::ChargedBack { .. } => CHARGED_BACK,
+            TxStatus::Reversed => REVERSED,
+        };
+        Entry {
+            flags,
+            amount: recorded.amount,
+            held: recorded.status.held().unwrap_or(Amount::ZERO),
+            client: recorded.client_id.unwrap_or(0),
+        }
+    }
+
+    fn unpack(&self, tx_id: TxId) -> Option<RecordedTx> {
+        if self.flags & RECORDED == 0 {
+            return None;
+        }
+        let status = if self.flags & REVERSED != 0 {
+            TxStatus::Reversed
+        } else if self.flags & PENDING != 0 {
+            TxStatus::Pending
+        } else if self.flags & CHARGED_BACK != 0 {
+            TxStatus::ChargedBack { held: self.held }
+        } else if self.flags & DISPUTED != 0 {
+            TxStatus::Disputed { held: self.held }
+        } else {
+            TxStatus::Settled
+        };
+        Some(RecordedTx {
+            tx_id,
+            client_id: (self.flags & OWNED != 0).then_some(self.client),
+            tx_type: if self.flags & DEPOSIT != 0 {
+                TransactionType::Deposit
+            } else {
+                TransactionType::Withdrawal
+            },
+            amount: self.amount,
+            status,
+        })
+    }
+
+    fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut record = [0; RECORD_SIZE];
+        let (held, client) = (1 + AMOUNT_SIZE, 1 + 2 * AMOUNT_SIZE);
+        record[0] = self.flags;
+        record[1..held].copy_from_slice(&self.amount.to_le_bytes());
+        record[held..client].copy_from_slice(&self.held.to_le_bytes());
+        record[client..client + CLIENT_SIZE].copy_from_slice(&self.client.to_le_bytes());
+        record
+    }
+
+    fn decode(record: &[u8; RECORD_SIZE]) -> Self {
+        let amount_at = |at: usize| Amount::from_le_bytes(record[at..at + AMOUNT_SIZE].try_into().expect("an amount"));
+        let client = 1 + 2 * AMOUNT_SIZE;
+        Entry {
+            flags: record[0],
+            amount: amount_at(1),
+            held: amount_at(1 + AMOUNT_SIZE),
+            client: ClientId::from_le_bytes(record[client..client + CLIENT_SIZE].try_into().expect("a client id")),
+        }
+    }
+}
+
+/// The offset of the record of a transaction id in the file.
+#[cfg_attr(feature = "wide-ids", allow(clippy::useless_conversion))]
+fn offset(tx_id: TxId) -> io::Result<u64> {
+    u64::from(tx_id).checked_mul(RECORD_SIZE as u64).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Transaction {} is too large to spill", tx_id),
+        )
+    })
+}
+
+/// The recently used entries in memory, and the file with the others.
+struct Tiers {
+    capacity: usize,
+    // Each entry with when it was last used, and whether it changed since it was read from the file
+    hot: HashMap<TxId, (Entry, u64, bool)>,
+    by_use: BTreeMap<u64, TxId>,
+    uses: u64,
+    file: File,
+    // The highest id written to the file, if any
+    spilled_until: Option<TxId>,
+}
+
+impl Tiers {
+    fn read(&mut self, tx_id: TxId) -> io::Result<Entry> {
+        if self.spilled_until.is_none_or(|until| tx_id > until) {
+            return Ok(Entry::default());
+        }
+        self.file.seek(SeekFrom::Start(offset(tx_id)?))?;
+        let mut record = [0; RECORD_SIZE];
+        match self.file.read_exact(&mut record) {
+            Ok(()) => Ok(Entry::decode(&record)),
+            // A hole at the end of the file
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(Entry::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn touch(&mut self, tx_id: TxId) {
+        self.uses += 1;
+        if let Some((_, used, _)) = self.hot.get_mut(&tx_id) {
+            self.by_use.remove(used);
+            *used = self.uses;
+            self.by_use.insert(self.uses, tx_id);
+        }
+    }
+
+    /// The entry of a transaction id, brought into memory if it was recorded.
+    fn get(&mut self, tx_id: TxId) -> io::Result<Entry> {
+        if let Some((entry, _, _)) = self.hot.get(&tx_id) {
+            let entry = *entry;
+            self.touch(tx_id);
+            return Ok(entry);
+        }
+        let entry = self.read(tx_id)?;
+        if entry.flags != 0 {
+            self.insert(tx_id, entry, false)?;
+        }
+        Ok(entry)
+    }
+
+    /// Sets the entry of a transaction id in memory, spilling the least recently used ones
+    /// to the file if there are too many.
+    fn insert(&mut self, tx_id: TxId, entry: Entry, changed: bool) -> io::Result<()> {
+        self.uses += 1;
+        let changed = changed || self.hot.get(&tx_id).is_some_and(|(_, _, changed)| *changed);
+        if let Some((_, used, _)) = self.hot.insert(tx_id, (entry, self.uses, changed)) {
+            self.by_use.remove(&used);
+        }
+        self.by_use.insert(self.uses, tx_id);
+        while self.hot.len() > self.capacity {
+            let Some((_, coldest)) = self.by_use.pop_first() else {
+                break;
+            };
+            if let Some((entry, _, true)) = self.hot.remove(&coldest) {
+                self.file.seek(SeekFrom::Start(offset(coldest)?))?;
+                self.file.write_all(&entry.encode())?;
+                self.spilled_until = Some(self.spilled_until.map_or(coldest, |until| until.max(coldest)));
+            }
+        }
+        Ok(())
+    }
+
+    /// All the recorded entries, in memory and in the file, sorted by id.
+    fn entries(&mut self) -> io::Result<Vec<(TxId, Entry)>> {
+        let mut entries: BTreeMap<TxId, Entry> = BTreeMap::new();
+        if let Some(until) = self.spilled_until {
+            self.file.seek(SeekFrom::Start(0))?;
+            let mut reader = BufReader::new(&self.file);
+            let mut record = [0; RECORD_SIZE];
+            let mut tx_id: TxId = 0;
+            while reader.read_exact(&mut record).is_ok() {
+                if record[0] != 0 {
+                    entries.insert(tx_id, Entry::decode(&record));
+                }
+                if tx_id == until {
+                    break;
+                }
+                tx_id += 1;
+            }
+        }
+        entries.extend(self.hot.iter().map(|(tx_id, (entry, _, _))| (*tx_id, *entry)));
+        Ok(entries.into_iter().filter(|(_, entry)| entry.flags != 0).collect())
+    }
+}
+
+/// Keeps up to a number of the recently used transactions in memory, and spills the others
+/// to a file, see the [module](self). The settlements of the pending deposits, of which there
+/// are few at a time, are kept in memory.
+pub struct SpillTxStore {
+    // Also changed by the lookups, which bring the transactions back into memory
+    tiers: RefCell<Tiers>,
+    // The pending deposits by when they settle, and when each of them does
+    pending: BTreeMap<(u64, TxId), PendingSettlement>,
+    pending_txs: HashMap<TxId, u64>,
+}
+
+impl SpillTxStore {
+    /// A store that keeps up to `hot` transactions in memory, at least one, and spills the
+    /// others to the file at `path`, which is created, or emptied if it exists.
+    pub fn create(path: &Path, hot: usize) -> StoreResult<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(SpillTxStore {
+            tiers: RefCell::new(Tiers {
+                capacity: hot.max(1),
+                hot: HashMap::new(),
+                by_use: BTreeMap::new(),
+                uses: 0,
+                file,
+                spilled_until: None,
+            }),
+            pending: BTreeMap::new(),
+            pending_txs: HashMap::new(),
+        })
+    }
+}
+
+impl TxStore for SpillTxStore {
+    fn recorded(&self, tx_id: TxId) -> StoreResult<Option<RecordedTx>> {
+        Ok(self.tiers.borrow_mut().get(tx_id)?.unpack(tx_id))
+    }
+
+    fn record(&mut self, recorded: &RecordedTx) -> StoreResult<()> {
+        Ok(self
+            .tiers
+            .get_mut()
+            .insert(recorded.tx_id, Entry::pack(recorded), true)?)
+    }
+
+    fn all(&self) -> StoreResult<Vec<RecordedTx>> {
+        let entries = self.tiers.borrow_mut().entries()?;
+        Ok(entries
+            .into_iter()
+            .filter_map(|(tx_id, entry)| entry.unpack(tx_id))
+            .collect())
+    }
+
+    fn record_settlement(&mut self, settlement: &PendingSettlement) -> StoreResult<()> {
+        if let Some(settles_at) = self.pending_txs.insert(settlement.tx_id, settlement.settles_at) {
+            self.pending.remove(&(settles_at, settlement.tx_id));
+        }
+        self.pending
+            .insert((settlement.settles_at, settlement.tx_id), settlement.clone());
+        Ok(())
+    }
+
+    fn take_settled(&mut self, until: u64) -> StoreResult<Vec<PendingSettlement>> {
+        let later = match until.checked_add(1) {
+            Some(after) => self.pending.split_off(&(after, 0)),
+            None => BTreeMap::new(),
+        };
+        let settled = std::mem::replace(&mut self.pending, later);
+        for settlement in settled.values() {
+            self.pending_txs.remove(&settlement.tx_id);
+        }
+        Ok(settled.into_values().collect())
+    }
+
+    fn settlements(&self) -> StoreResult<Vec<PendingSettlement>> {
+        let mut settlements: Vec<_> = self.pending.values().cloned().collect();
+        settlements.sort_by_key(|settlement| settlement.tx_id);
+        Ok(settlements)
+    }
+
+    fn settlement(&self, tx_id: TxId) -> StoreResult<Option<PendingSettlement>> {
+        Ok(self
+            .pending_txs
+            .get(&tx_id)
+            .and_then(|settles_at| self.pending.get(&(*settles_at, tx_id)))
+            .cloned())
+    }
+
+    fn take_settlement(&mut self, tx_id: TxId) -> StoreResult<Option<PendingSettlement>> {
+        Ok(self
+            .pending_txs
+            .remove(&tx_id)
+            .and_then(|settles_at| self.pending.remove(&(settles_at, tx_id))))
+    }
+
+    fn forget(&mut self, tx_id: TxId) -> StoreResult<()> {
+        if self.recorded(tx_id)?.is_some() {
+            self.tiers.get_mut().insert(tx_id, Entry::default(), true)?;
+        }
+        if let Some(settles_at) = self.pending_txs.remove(&tx_id) {
+            self.pending.remove(&(settles_at, tx_id));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpillTxStore;
+    use crate::amount::amount;
+    use crate::bench::generate_csv;
+    use crate::engine::PaymentsEngine;
+    use crate::store::{MemoryAccountStore, MemoryTxStore, RecordedTx, TxStatus, TxStore};
+    use crate::transactions::TransactionType;
+
+    #[test]
+    fn test_spill_tx_store() {
+        let path = std::env::temp_dir().join("payments_engine_spill_tx_store");
+        let csv = generate_csv(20_000, 50, 5);
+        let mut spilled = PaymentsEngine::with_stores(
+            Box::new(MemoryAccountStore::default()),
+            Box::new(SpillTxStore::create(&path, 100).unwrap()),
+        );
+        spilled.process_csv_reader(csv.as_slice()).unwrap();
+        let mut memory = PaymentsEngine::new();
+        memory.process_csv_reader(csv.as_slice()).unwrap();
+        assert_eq!(spilled.report().unwrap(), memory.report().unwrap());
+        assert_eq!(spilled.stats().rejected, memory.stats().rejected);
+
+        // The transactions spilled to the file and the ones still in memory
+        let mut spilled = SpillTxStore::create(&path, 3).unwrap();
+        let mut memory = MemoryTxStore::default();
+        let deposit = |tx_id, status| RecordedTx {
+            tx_id,
+            client_id: Some(1),
+            tx_type: TransactionType::Deposit,
+            amount: amount(1.5),
+            status,
+        };
+        for store in [&mut spilled as &mut dyn TxStore, &mut memory] {
+            for tx_id in [7, 1, 30, 4, 12, 2] {
+                store.record(&deposit(tx_id, TxStatus::Settled)).unwrap();
+            }
+            store.record(&deposit(7, TxStatus::Reversed)).unwrap();
+            store
+                .record(&deposit(1, TxStatus::ChargedBack { held: amount(0.5) }))
+                .unwrap();
+            store
+                .record(&RecordedTx {
+                    tx_type: TransactionType::Withdrawal,
+                    ..deposit(5, TxStatus::Settled)
+                })
+                .unwrap();
+            store.forget(12).unwrap();
+        }
+        assert_eq!(spilled.all().unwrap(), memory.all().unwrap());
+        assert_eq!(
+            spilled.recorded(1).unwrap(),
+            Some(deposit(1, TxStatus::ChargedBack { held: amount(0.5) }))
+        );
+        assert_eq!(
+            (spilled.recorded(3).unwrap(), spilled.recorded(12).unwrap()),
+            (None, None)
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,703 @@
+//! SQLite backed stores, for durable state and bounded memory on huge datasets.
+//!
+//! The stores of an engine share a connection to the database, a [`SqliteDatabase`], which
+//! commits all of their writes for a transaction, or a chunk of the transactions of a file,
+//! at once, so that it costs a single commit instead of one per statement, and a crash never
+//! leaves half of it. The state can be
+//! queried with plain SQL after (or during) a run:
+//!
+//! ```sql
+//! SELECT * FROM accounts WHERE locked = 1;
+//! ```
+
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::accounts::AccountBalance;
+use crate::amount::AmountValue;
+use crate::engine::PaymentsEngine;
+use crate::store::{
+    AccountStore, Database, DedupStore, IdempotencyStore, PendingSettlement, RecordedTx, StoreResult, TxStatus, TxStore,
+};
+use crate::transactions::{Amount, ClientId, TransactionType, TxId};
+
+/// Creates an engine that keeps all of its state in the SQLite database at `path`.
+pub fn open_engine(path: &Path) -> Result<PaymentsEngine, Box<dyn Error>> {
+    open_engine_in(&SqliteDatabase::open(path)?)
+}
+
+/// Creates an engine that keeps all of its state in `database`, and commits there once for
+/// each transaction, or each chunk of the transactions of a file.
+pub fn open_engine_in(database: &SqliteDatabase) -> Result<PaymentsEngine, Box<dyn Error>> {
+    Ok(PaymentsEngine::with_stores(
+        Box::new(SqliteAccountStore::open_in(database)?),
+        Box::new(SqliteTxStore::open_in(database)?),
+    )
+    .with_database(Box::new(database.clone())))
+}
+
+fn connect(path: &Path) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    // We don't need a fsync after every single transaction, only a consistent database.
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    // Another connection may be writing, e.g. of another process
+    conn.busy_timeout(Duration::from_secs(5))?;
+    Ok(conn)
+}
+
+/// A connection to a SQLite database, shared by the stores opened in it. Its writes are
+/// committed one statement at a time, unless the engine of the stores starts a database
+/// transaction, see [`PaymentsEngine::with_database`].
+#[derive(Clone)]
+pub struct SqliteDatabase {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteDatabase {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Ok(SqliteDatabase {
+            conn: Arc::new(Mutex::new(connect(path)?)),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        // A statement can't leave the connection half changed
+        self.conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// A savepoint outside of a transaction starts one, and releasing it commits it
+impl Database for SqliteDatabase {
+    fn begin(&mut self) -> StoreResult<()> {
+        self.conn().prepare_cached("SAVEPOINT engine")?.execute([])?;
+        Ok(())
+    }
+
+    fn commit(&mut self) -> StoreResult<()> {
+        self.conn().prepare_cached("RELEASE engine")?.execute([])?;
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> StoreResult<()> {
+        Ok(self.conn().execute_batch("ROLLBACK TO engine; RELEASE engine")?)
+    }
+}
+
+/// Keeps the account balances in the `accounts` table. Its `locked` column is
+/// whether the `status` is `locked`, as in the report.
+pub struct SqliteAccountStore {
+    database: SqliteDatabase,
+}
+
+impl SqliteAccountStore {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::open_in(&SqliteDatabase::open(path)?)
+    }
+
+    /// Opens the store in a database shared with others.
+    pub fn open_in(database: &SqliteDatabase) -> rusqlite::Result<Self> {
+        let conn = database.conn();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS accounts (
+                client INTEGER PRIMARY KEY,
+                available REAL NOT NULL,
+                held REAL NOT NULL,
+                pending REAL NOT NULL DEFAULT 0,
+                locked INTEGER NOT NULL,
+                status TEXT NOT NULL DEFAULT 'active'
+            )",
+            [],
+        )?;
+        // Databases created by earlier versions don't have all the columns
+        add_missing_column(&conn, "accounts", "pending", "REAL NOT NULL DEFAULT 0")?;
+        if add_missing_column(&conn, "accounts", "status", "TEXT NOT NULL DEFAULT 'active'")? {
+            conn.execute("UPDATE accounts SET status = 'locked' WHERE locked = 1", [])?;
+        }
+        drop(conn);
+        Ok(SqliteAccountStore {
+            database: database.clone(),
+        })
+    }
+}
+
+/// Adds a column to a table if it doesn't have it yet, returning whether it was added.
+fn add_missing_column(conn: &Connection, table: &str, column: &str, definition: &str) -> rusqlite::Result<bool> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2",
+        params![table, column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+    Ok(!exists)
+}
+
+fn account_from_row(row: &rusqlite::Row) -> rusqlite::Result<AccountBalance> {
+    let status: String = row.get(4)?;
+    Ok(AccountBalance {
+        client: row.get(0)?,
+        available: Amount::from_f64(row.get(1)?),
+        held: Amount::from_f64(row.get(2)?),
+        pending: Amount::from_f64(row.get(3)?),
+        status: status
+            .parse()
+            .map_err(|err| rusqlite::Error::FromSqlConversionFailure(4, rusqlite::types::Type::Text, Box::new(err)))?,
+    })
+}
+
+impl AccountStore for SqliteAccountStore {
+    fn get(&self, client_id: ClientId) -> StoreResult<Option<AccountBalance>> {
+        let conn = self.database.conn();
+        let mut stmt =
+            conn.prepare_cached("SELECT client, available, held, pending, status FROM accounts WHERE client = ?1")?;
+        Ok(stmt.query_row(params![client_id], account_from_row).optional()?)
+    }
+
+    fn put(&mut self, account: &AccountBalance) -> StoreResult<()> {
+        let conn = self.database.conn();
+        let mut stmt = conn.prepare_cached(
+            "INSERT OR REPLACE INTO accounts (client, available, held, pending, locked, status)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        stmt.execute(params![
+            account.client,
+            account.available.to_f64(),
+            account.held.to_f64(),
+            account.pending.to_f64(),
+            account.is_locked(),
+            account.status.as_str()
+        ])?;
+        Ok(())
+    }
+
+    fn all(&self) -> StoreResult<Vec<AccountBalance>> {
+        let conn = self.database.conn();
+        let mut stmt =
+            conn.prepare_cached("SELECT client, available, held, pending, status FROM accounts ORDER BY client")?;
+        let accounts = stmt
+            .query_map([], account_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(accounts)
+    }
+
+    fn remove(&mut self, client_id: ClientId) -> StoreResult<Option<AccountBalance>> {
+        let account = self.get(client_id)?;
+        let conn = self.database.conn();
+        let mut stmt = conn.prepare_cached("DELETE FROM accounts WHERE client = ?1")?;
+        stmt.execute(params![client_id])?;
+        Ok(account)
+    }
+}
+
+/// Keeps the deposits and withdrawals in the `recorded_transactions` table, a row each with
+/// its `type`, `client`, `amount`, `status` and the amount `held` by its dispute, and the
+/// deposits that haven't settled yet in the `settlements` table.
+pub struct SqliteTxStore {
+    database: SqliteDatabase,
+}
+
+impl SqliteTxStore {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::open_in(&SqliteDatabase::open(path)?)
+    }
+
+    /// Opens the store in a database shared with others.
+    pub fn open_in(database: &SqliteDatabase) -> rusqlite::Result<Self> {
+        let conn = database.conn();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS recorded_transactions (
+                tx INTEGER PRIMARY KEY,
+                client INTEGER,
+                type TEXT NOT NULL,
+                amount REAL NOT NULL,
+                status TEXT NOT NULL,
+                held REAL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS settlements (
+                tx INTEGER PRIMARY KEY,
+                client INTEGER NOT NULL,
+                amount REAL NOT NULL,
+                settles_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS settlements_by_date ON settlements (settles_at)",
+            [],
+        )?;
+        migrate_recorded_transactions(&conn)?;
+        drop(conn);
+        Ok(SqliteTxStore {
+            database: database.clone(),
+        })
+    }
+}
+
+/// Moves the deposits and withdrawals of the databases created by earlier versions, which kept
+/// them in a table for each of their amounts, disputes, reversals, chargebacks and clients, to
+/// `recorded_transactions`. A deposit wins over a withdrawal of the same id, as it did then.
+fn migrate_recorded_transactions(conn: &Connection) -> rusqlite::Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'transactions'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Ok(());
+    }
+    // Not all of them were there from the start
+    add_missing_column(conn, "transactions", "disputed", "INTEGER NOT NULL DEFAULT 0")?;
+    conn.execute_batch(
+        "BEGIN;
+         CREATE TABLE IF NOT EXISTS withdrawals (tx INTEGER PRIMARY KEY, amount REAL NOT NULL);
+         CREATE TABLE IF NOT EXISTS disputes (tx INTEGER PRIMARY KEY, amount REAL NOT NULL);
+         CREATE TABLE IF NOT EXISTS reversals (tx INTEGER PRIMARY KEY);
+         CREATE TABLE IF NOT EXISTS chargebacks (tx INTEGER PRIMARY KEY);
+         CREATE TABLE IF NOT EXISTS owners (tx INTEGER PRIMARY KEY, client INTEGER NOT NULL);
+         INSERT OR REPLACE INTO recorded_transactions (tx, client, type, amount, status)
+             SELECT tx, owners.client, 'withdrawal', amount,
+                    CASE WHEN tx IN (SELECT tx FROM reversals) THEN 'reversed' ELSE 'settled' END
+             FROM withdrawals LEFT JOIN owners USING (tx);
+         INSERT OR REPLACE INTO recorded_transactions (tx, client, type, amount, status, held)
+             SELECT tx, owners.client, 'deposit', transactions.amount,
+                    CASE
+                        WHEN tx IN (SELECT tx FROM reversals) THEN 'reversed'
+                        WHEN tx IN (SELECT tx FROM settlements) THEN 'pending'
+                        WHEN held IS NULL THEN 'settled'
+                        WHEN tx IN (SELECT tx FROM chargebacks) THEN 'charged_back'
+                        ELSE 'disputed'
+                    END,
+                    held
+             FROM (SELECT *, COALESCE(
+                       (SELECT amount FROM disputes WHERE disputes.tx = transactions.tx),
+                       CASE WHEN disputed = 1 THEN amount END
+                   ) AS held FROM transactions) AS transactions
+             LEFT JOIN owners USING (tx);
+         DROP TABLE transactions;
+         DROP TABLE withdrawals;
+         DROP TABLE disputes;
+         DROP TABLE reversals;
+         DROP TABLE chargebacks;
+         DROP TABLE owners;
+         COMMIT;",
+    )
+}
+
+fn recorded_from_row(row: &rusqlite::Row) -> rusqlite::Result<RecordedTx> {
+    let conversion = |column, err| rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, err);
+    let tx_type: String = row.get(2)?;
+    let status: String = row.get(4)?;
+    let held = Amount::from_f64(row.get::<_, Option<f64>>(5)?.unwrap_or_default());
+    Ok(RecordedTx {
+        tx_id: row.get(0)?,
+        client_id: row.get(1)?,
+        tx_type: tx_type.parse().map_err(|err| conversion(2, Box::new(err)))?,
+        amount: Amount::from_f64(row.get(3)?),
+        status: TxStatus::from_name(&status, held)
+            .ok_or_else(|| conversion(4, format!("Unknown status `{}`", status).into()))?,
+    })
+}
+
+impl TxStore for SqliteTxStore {
+    fn recorded(&self, tx_id: TxId) -> StoreResult<Option<RecordedTx>> {
+        let conn = self.database.conn();
+        let mut stmt = conn
+            .prepare_cached("SELECT tx, client, type, amount, status, held FROM recorded_transactions WHERE tx = ?1")?;
+        Ok(stmt.query_row(params![tx_id], recorded_from_row).optional()?)
+    }
+
+    fn record(&mut self, recorded: &RecordedTx) -> StoreResult<()> {
+        let conn = self.database.conn();
+        let mut stmt = conn.prepare_cached(
+            "INSERT OR REPLACE INTO recorded_transactions (tx, client, type, amount, status, held)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        stmt.execute(params![
+            recorded.tx_id,
+            recorded.client_id,
+            recorded.tx_type.as_str(),
+            recorded.amount.to_f64(),
+            recorded.status.name(),
+            recorded.status.held().map(Amount::to_f64)
+        ])?;
+        Ok(())
+    }
+
+    fn all(&self) -> StoreResult<Vec<RecordedTx>> {
+        let conn = self.database.conn();
+        let mut stmt = conn
+            .prepare_cached("SELECT tx, client, type, amount, status, held FROM recorded_transactions ORDER BY tx")?;
+        let recorded = stmt
+            .query_map([], recorded_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(recorded)
+    }
+
+    fn record_settlement(&mut self, settlement: &PendingSettlement) -> StoreResult<()> {
+        let conn = self.database.conn();
+        let mut stmt = conn.prepare_cached(
+            "INSERT OR REPLACE INTO settlements (tx, client, amount, settles_at) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        stmt.execute(params![
+            settlement.tx_id,
+            settlement.client_id,
+            settlement.amount.to_f64(),
+            settlement.settles_at as i64
+        ])?;
+        Ok(())
+    }
+
+    fn take_settled(&mut self, until: u64) -> StoreResult<Vec<PendingSettlement>> {
+        let conn = self.database.conn();
+        let until = until.min(i64::MAX as u64) as i64;
+        let settled = {
+            let mut stmt = conn.prepare_cached(
+                "SELECT tx, client, amount, settles_at FROM settlements WHERE settles_at <= ?1 ORDER BY settles_at, tx",
+            )?;
+            let settled = stmt
+                .query_map(params![until], settlement_from_row)?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            settled
+        };
+        if !settled.is_empty() {
+            let mut stmt = conn.prepare_cached("DELETE FROM settlements WHERE settles_at <= ?1")?;
+            stmt.execute(params![until])?;
+        }
+        Ok(settled)
+    }
+
+    fn settlements(&self) -> StoreResult<Vec<PendingSettlement>> {
+        let conn = self.database.conn();
+        let mut stmt = conn.prepare_cached("SELECT tx, client, amount, settles_at FROM settlements ORDER BY tx")?;
+        let settlements = stmt
+            .query_map([], settlement_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(settlements)
+    }
+
+    fn settlement(&self, tx_id: TxId) -> StoreResult<Option<PendingSettlement>> {
+        let conn = self.database.conn();
+        let mut stmt = conn.prepare_cached("SELECT tx, client, amount, settles_at FROM settlements WHERE tx = ?1")?;
+        Ok(stmt.query_row(params![tx_id], settlement_from_row).optional()?)
+    }
+
+    fn take_settlement(&mut self, tx_id: TxId) -> StoreResult<Option<PendingSettlement>> {
+        let settlement = self.settlement(tx_id)?;
+        if settlement.is_some() {
+            let conn = self.database.conn();
+            let mut stmt = conn.prepare_cached("DELETE FROM settlements WHERE tx = ?1")?;
+            stmt.execute(params![tx_id])?;
+        }
+        Ok(settlement)
+    }
+
+    fn forget(&mut self, tx_id: TxId) -> StoreResult<()> {
+        let conn = self.database.conn();
+        for table in ["recorded_transactions", "settlements"] {
+            let mut stmt = conn.prepare_cached(&format!("DELETE FROM {} WHERE tx = ?1", table))?;
+            stmt.execute(params![tx_id])?;
+        }
+        Ok(())
+    }
+}
+
+fn settlement_from_row(row: &rusqlite::Row) -> rusqlite::Result<PendingSettlement> {
+    Ok(PendingSettlement {
+        tx_id: row.get(0)?,
+        client_id: row.get(1)?,
+        amount: Amount::from_f64(row.get(2)?),
+        settles_at: row.get::<_, i64>(3)? as u64,
+    })
+}
+
+/// Keeps the idempotency keys of a server in the `idempotency_keys` table.
+pub struct SqliteIdempotencyStore {
+    database: SqliteDatabase,
+}
+
+impl SqliteIdempotencyStore {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::open_in(&SqliteDatabase::open(path)?)
+    }
+
+    /// Opens the store in a database shared with others.
+    pub fn open_in(database: &SqliteDatabase) -> rusqlite::Result<Self> {
+        database.conn().execute(
+            "CREATE TABLE IF NOT EXISTS idempotency_keys (
+                key TEXT PRIMARY KEY,
+                accepted INTEGER NOT NULL,
+                seen_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        Ok(SqliteIdempotencyStore {
+            database: database.clone(),
+        })
+    }
+}
+
+impl IdempotencyStore for SqliteIdempotencyStore {
+    fn outcome(&self, key: &str, since: u64) -> StoreResult<Option<bool>> {
+        let conn = self.database.conn();
+        let mut stmt = conn.prepare_cached("SELECT accepted FROM idempotency_keys WHERE key = ?1 AND seen_at >= ?2")?;
+        Ok(stmt
+            .query_row(params![key, since as i64], |row| row.get(0))
+            .optional()?)
+    }
+
+    fn record(&mut self, key: &str, accepted: bool, seen_at: u64) -> StoreResult<()> {
+        let conn = self.database.conn();
+        let mut stmt = conn
+            .prepare_cached("INSERT OR REPLACE INTO idempotency_keys (key, accepted, seen_at) VALUES (?1, ?2, ?3)")?;
+        stmt.execute(params![key, accepted, seen_at as i64])?;
+        Ok(())
+    }
+
+    fn expire(&mut self, before: u64) -> StoreResult<()> {
+        let conn = self.database.conn();
+        let mut stmt = conn.prepare_cached("DELETE FROM idempotency_keys WHERE seen_at < ?1")?;
+        stmt.execute(params![before as i64])?;
+        Ok(())
+    }
+}
+
+/// Keeps the processed transactions in the `processed_transactions` table, with the `type`
+/// named as in the input.
+pub struct SqliteDedupStore {
+    database: SqliteDatabase,
+}
+
+impl SqliteDedupStore {
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        Self::open_in(&SqliteDatabase::open(path)?)
+    }
+
+    /// Opens the store in a database shared with others.
+    pub fn open_in(database: &SqliteDatabase) -> rusqlite::Result<Self> {
+        database.conn().execute(
+            "CREATE TABLE IF NOT EXISTS processed_transactions (
+                type TEXT NOT NULL,
+                tx INTEGER NOT NULL,
+                PRIMARY KEY (type, tx)
+            )",
+            [],
+        )?;
+        Ok(SqliteDedupStore {
+            database: database.clone(),
+        })
+    }
+}
+
+fn type_name(tx_type: TransactionType) -> String {
+    format!("{:?}", tx_type).to_lowercase()
+}
+
+impl DedupStore for SqliteDedupStore {
+    fn contains(&self, tx_type: TransactionType, tx_id: TxId) -> StoreResult<bool> {
+        let conn = self.database.conn();
+        let mut stmt = conn.prepare_cached("SELECT 1 FROM processed_transactions WHERE type = ?1 AND tx = ?2")?;
+        Ok(stmt.exists(params![type_name(tx_type), tx_id])?)
+    }
+
+    fn insert(&mut self, tx_type: TransactionType, tx_id: TxId) -> StoreResult<()> {
+        let conn = self.database.conn();
+        let mut stmt =
+            conn.prepare_cached("INSERT OR IGNORE INTO processed_transactions (type, tx) VALUES (?1, ?2)")?;
+        stmt.execute(params![type_name(tx_type), tx_id])?;
+        Ok(())
+    }
+
+    fn remove(&mut self, tx_type: TransactionType, tx_id: TxId) -> StoreResult<()> {
+        let conn = self.database.conn();
+        let mut stmt = conn.prepare_cached("DELETE FROM processed_transactions WHERE type = ?1 AND tx = ?2")?;
+        stmt.execute(params![type_name(tx_type), tx_id])?;
+        Ok(())
+    }
+
+    fn all(&self) -> StoreResult<Vec<(TransactionType, TxId)>> {
+        let conn = self.database.conn();
+        let mut stmt = conn.prepare_cached("SELECT type, tx FROM processed_transactions")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?;
+        let mut processed = vec![];
+        for row in rows {
+            let (name, tx_id) = row?;
+            // Every type that was stored has a name
+            let tx_type = TransactionType::ALL
+                .into_iter()
+                .find(|&tx_type| type_name(tx_type) == name)
+                .ok_or_else(|| format!("Unknown transaction type `{}` in processed_transactions", name))?;
+            processed.push((tx_type, tx_id));
+        }
+        processed.sort_unstable();
+        Ok(processed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{open_engine, open_engine_in, SqliteAccountStore, SqliteDatabase, SqliteDedupStore, SqliteTxStore};
+    use crate::amount::amount;
+    use crate::engine::DATABASE_CHUNK;
+    use crate::store::{AccountStore, DedupStore, RecordedTx, TxStatus, TxStore};
+    use crate::transactions::{Transaction, TransactionType};
+
+    #[test]
+    fn test_sqlite_matches_memory() {
+        for input in [
+            "sample_files/multiple_clients.csv",
+            "sample_files/reversal.csv",
+            "sample_files/settlement.csv",
+            "sample_files/close.csv",
+            "sample_files/chargeback_reversal.csv",
+            "sample_files/partial_dispute.csv",
+        ] {
+            let input = Path::new(input);
+            let mut engine = open_engine(Path::new(":memory:")).unwrap();
+            engine.process_csv(input).unwrap();
+            assert_eq!(engine.report().unwrap(), crate::process_csv(input).unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn test_sqlite_dedup() {
+        let path = std::env::temp_dir().join("payments_engine_dedup.db");
+        let _ = std::fs::remove_file(&path);
+        let input = Path::new("sample_files/deposit_withdrawal.csv");
+        for _ in 0..2 {
+            let database = SqliteDatabase::open(&path).unwrap();
+            let dedup = SqliteDedupStore::open_in(&database).unwrap();
+            let mut engine = open_engine_in(&database).unwrap().with_dedup(Box::new(dedup));
+            engine.process_csv(input).unwrap();
+            assert_eq!(engine.report().unwrap(), crate::process_csv(input).unwrap().to_string());
+        }
+        let store = SqliteDedupStore::open(&path).unwrap();
+        assert!(store.contains(TransactionType::Withdrawal, 4).unwrap());
+        assert!(!store.contains(TransactionType::Dispute, 4).unwrap());
+    }
+
+    #[test]
+    fn test_sqlite_commits_chunks() {
+        let path = std::env::temp_dir().join("payments_engine_chunks.db");
+        let _ = std::fs::remove_file(&path);
+        let mut input = String::from("type, client, tx, amount\n");
+        for tx in 1..=DATABASE_CHUNK + 5 {
+            input.push_str(&format!("deposit, 1, {}, 1.0\n", tx));
+        }
+        let mut engine = open_engine(&path).unwrap();
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        let withdrawal = format!("withdrawal, 1, {}, 1.0", DATABASE_CHUNK + 6);
+        engine
+            .apply(&Transaction::parse_csv_line(&withdrawal).unwrap())
+            .unwrap()
+            .unwrap();
+
+        // Another connection sees all of them while the engine is still open
+        let store = SqliteTxStore::open(&path).unwrap();
+        assert_eq!(store.all().unwrap().len(), DATABASE_CHUNK + 6);
+        let balance = SqliteAccountStore::open(&path).unwrap().get(1).unwrap().unwrap();
+        assert_eq!(balance.available, amount((DATABASE_CHUNK + 4) as f64));
+    }
+
+    #[test]
+    fn test_sqlite_rolls_back_failed_transactions() {
+        let database = SqliteDatabase::open(Path::new(":memory:")).unwrap();
+        let dedup = SqliteDedupStore::open_in(&database).unwrap();
+        let mut engine = open_engine_in(&database).unwrap().with_dedup(Box::new(dedup));
+        engine
+            .apply(&Transaction::parse_csv_line("deposit, 1, 1, 2.0").unwrap())
+            .unwrap()
+            .unwrap();
+        // Recording the deposit as processed fails after its balance and record were written
+        database
+            .conn()
+            .execute_batch(
+                "CREATE TRIGGER crash BEFORE INSERT ON processed_transactions BEGIN SELECT RAISE(ABORT, 'crash'); END",
+            )
+            .unwrap();
+        assert!(engine
+            .apply(&Transaction::parse_csv_line("deposit, 1, 2, 3.0").unwrap())
+            .is_err());
+
+        let accounts = SqliteAccountStore::open_in(&database).unwrap();
+        assert_eq!(accounts.get(1).unwrap().unwrap().available, amount(2.0));
+        let transactions = SqliteTxStore::open_in(&database).unwrap();
+        assert!(transactions.recorded(2).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_sqlite_migrates_recorded_transactions() {
+        // The tables of the databases created by earlier versions
+        let path = std::env::temp_dir().join("payments_engine_migrate.db");
+        let _ = std::fs::remove_file(&path);
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE transactions (tx INTEGER PRIMARY KEY, amount REAL NOT NULL,
+                                        disputed INTEGER NOT NULL DEFAULT 0);
+             CREATE TABLE withdrawals (tx INTEGER PRIMARY KEY, amount REAL NOT NULL);
+             CREATE TABLE disputes (tx INTEGER PRIMARY KEY, amount REAL NOT NULL);
+             CREATE TABLE reversals (tx INTEGER PRIMARY KEY);
+             CREATE TABLE chargebacks (tx INTEGER PRIMARY KEY);
+             CREATE TABLE owners (tx INTEGER PRIMARY KEY, client INTEGER NOT NULL);
+             CREATE TABLE settlements (tx INTEGER PRIMARY KEY, client INTEGER NOT NULL, amount REAL NOT NULL,
+                                       settles_at INTEGER NOT NULL);
+             INSERT INTO transactions (tx, amount, disputed) VALUES (1, 2.0, 0), (2, 1.0, 0), (4, 3.0, 0), (5, 1.5, 1);
+             INSERT INTO disputes VALUES (1, 0.5);
+             INSERT INTO chargebacks VALUES (1);
+             INSERT INTO reversals VALUES (2);
+             INSERT INTO withdrawals VALUES (3, 0.25);
+             INSERT INTO owners VALUES (1, 7), (3, 7);
+             INSERT INTO settlements VALUES (4, 8, 3.0, 1700000000);",
+        )
+        .unwrap();
+        drop(conn);
+
+        let store = SqliteTxStore::open(&path).unwrap();
+        let recorded = |tx_id, client_id, tx_type, amount, status| RecordedTx {
+            tx_id,
+            client_id,
+            tx_type,
+            amount,
+            status,
+        };
+        assert_eq!(
+            store.all().unwrap(),
+            [
+                recorded(
+                    1,
+                    Some(7),
+                    TransactionType::Deposit,
+                    amount(2.0),
+                    TxStatus::ChargedBack { held: amount(0.5) }
+                ),
+                recorded(2, None, TransactionType::Deposit, amount(1.0), TxStatus::Reversed),
+                recorded(3, Some(7), TransactionType::Withdrawal, amount(0.25), TxStatus::Settled),
+                recorded(4, None, TransactionType::Deposit, amount(3.0), TxStatus::Pending),
+                recorded(
+                    5,
+                    None,
+                    TransactionType::Deposit,
+                    amount(1.5),
+                    TxStatus::Disputed { held: amount(1.5) }
+                ),
+            ]
+        );
+        assert_eq!(store.settlement(4).unwrap().unwrap().settles_at, 1700000000);
+        // Only once
+        drop(store);
+        assert_eq!(SqliteTxStore::open(&path).unwrap().all().unwrap().len(), 5);
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+
+use crate::accounts::AccountBalance;
+pub use crate::machine::{RecordedTx, TxStatus};
+use crate::transactions::{Amount, ClientId, TransactionType, TxId};
+
+/// Result type shared by all the storage backends.
+pub type StoreResult<T> = Result<T, Box<dyn Error>>;
+
+/// Where the engine keeps the account balances.
+pub trait AccountStore: Send {
+    /// Get the balance of a client, if we have seen it before.
+    fn get(&self, client_id: ClientId) -> StoreResult<Option<AccountBalance>>;
+    /// Insert or replace the balance of a client.
+    fn put(&mut self, account: &AccountBalance) -> StoreResult<()>;
+    /// All the balances, sorted by client id.
+    fn all(&self) -> StoreResult<Vec<AccountBalance>>;
+    /// Forget the balance of a client, returning it if there was one.
+    fn remove(&mut self, client_id: ClientId) -> StoreResult<Option<AccountBalance>>;
+}
+
+/// A deposit whose funds are pending until it settles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingSettlement {
+    pub tx_id: TxId,
+    pub client_id: ClientId,
+    pub amount: Amount,
+    /// In seconds since the Unix epoch.
+    pub settles_at: u64,
+}
+
+/// Where the engine keeps what it needs to know about past transactions
+/// in order to process disputes, resolves, chargebacks, reversals and settlements.
+pub trait TxStore: Send {
+    /// The deposit or withdrawal with this id, its client and where it stands, if it was recorded.
+    /// This is what the transactions referring to it are checked against.
+    fn recorded(&self, tx_id: TxId) -> StoreResult<Option<RecordedTx>>;
+    /// Insert or replace a deposit or withdrawal, with where it stands.
+    fn record(&mut self, recorded: &RecordedTx) -> StoreResult<()>;
+    /// All the recorded deposits and withdrawals, sorted by transaction id.
+    fn all(&self) -> StoreResult<Vec<RecordedTx>>;
+    /// Record when a pending deposit settles.
+    fn record_settlement(&mut self, settlement: &PendingSettlement) -> StoreResult<()>;
+    /// Remove and return the deposits that settle at or before `until`, the earliest first.
+    fn take_settled(&mut self, until: u64) -> StoreResult<Vec<PendingSettlement>>;
+    /// All the deposits that haven't settled yet, sorted by transaction id.
+    fn settlements(&self) -> StoreResult<Vec<PendingSettlement>>;
+    /// The settlement of the deposit, if it hasn't settled yet.
+    fn settlement(&self, tx_id: TxId) -> StoreResult<Option<PendingSettlement>>;
+    /// Remove and return the settlement of the deposit, e.g. when it is settled early.
+    fn take_settlement(&mut self, tx_id: TxId) -> StoreResult<Option<PendingSettlement>>;
+    /// Forget the deposit or withdrawal and its settlement, e.g. when the transaction that
+    /// recorded it is undone.
+    fn forget(&mut self, tx_id: TxId) -> StoreResult<()>;
+}
+
+/// A database that the stores of an engine share, e.g. the one of
+/// [`sqlite_store::open_engine`](crate::sqlite_store::open_engine), so that all their writes
+/// for a transaction are committed together, see
+/// [`PaymentsEngine::with_database`](crate::engine::PaymentsEngine::with_database).
+pub trait Database: Send {
+    /// Starts a database transaction, so that the writes until the commit are made at once, or
+    /// a nested one in the transaction started.
+    fn begin(&mut self) -> StoreResult<()>;
+    /// Commits the last database transaction started, into the one it is nested in if any.
+    fn commit(&mut self) -> StoreResult<()>;
+    /// Discards the writes of the last database transaction started.
+    fn rollback(&mut self) -> StoreResult<()>;
+}
+
+/// Where a server keeps the idempotency keys of the transactions it applied,
+/// so that a retried submission returns the prior outcome instead of applying it again.
+/// Times are in seconds since the Unix epoch.
+pub trait IdempotencyStore: Send {
+    /// Whether the transaction with this key was accepted, if it was seen at or after `since`.
+    fn outcome(&self, key: &str, since: u64) -> StoreResult<Option<bool>>;
+    /// Record the outcome of the transaction with this key.
+    fn record(&mut self, key: &str, accepted: bool, seen_at: u64) -> StoreResult<()>;
+    /// Forget the keys seen before `before`.
+    fn expire(&mut self, before: u64) -> StoreResult<()>;
+}
+
+/// Where the engine keeps the transactions it already processed, by type and id, so that
+/// the ones delivered again by an at-least-once source are only applied once.
+pub trait DedupStore: Send {
+    /// Whether a transaction of this type and id was already processed.
+    fn contains(&self, tx_type: TransactionType, tx_id: TxId) -> StoreResult<bool>;
+    /// Record that a transaction of this type and id was processed.
+    fn insert(&mut self, tx_type: TransactionType, tx_id: TxId) -> StoreResult<()>;
+    /// Forget that a transaction of this type and id was processed, once it is undone.
+    fn remove(&mut self, tx_type: TransactionType, tx_id: TxId) -> StoreResult<()>;
+    /// All the processed transactions, sorted by type and id.
+    fn all(&self) -> StoreResult<Vec<(TransactionType, TxId)>>;
+}
+
+/// Keeps the account balances in memory.
+#[derive(Debug, Default)]
+pub struct MemoryAccountStore {
+    // We use a BTreeMap because we want to display sorted results.
+    accounts: BTreeMap<ClientId, AccountBalance>,
+}
+
+impl AccountStore for MemoryAccountStore {
+    fn get(&self, client_id: ClientId) -> StoreResult<Option<AccountBalance>> {
+        Ok(self.accounts.get(&client_id).cloned())
+    }
+
+    fn put(&mut self, account: &AccountBalance) -> StoreResult<()> {
+        self.accounts.insert(account.client, account.clone());
+        Ok(())
+    }
+
+    fn all(&self) -> StoreResult<Vec<AccountBalance>> {
+        Ok(self.accounts.values().cloned().collect())
+    }
+
+    fn remove(&mut self, client_id: ClientId) -> StoreResult<Option<AccountBalance>> {
+        Ok(self.accounts.remove(&client_id))
+    }
+}
+
+/// Keeps the deposits and withdrawals, and the settlements of the pending deposits, in memory.
+#[derive(Debug, Default)]
+pub struct MemoryTxStore {
+    recorded: HashMap<TxId, RecordedTx>,
+    // The pending deposits by when they settle, and when each of them does
+    pending: BTreeMap<(u64, TxId), PendingSettlement>,
+    pending_txs: HashMap<TxId, u64>,
+}
+
+impl TxStore for MemoryTxStore {
+    fn recorded(&self, tx_id: TxId) -> StoreResult<Option<RecordedTx>> {
+        Ok(self.recorded.get(&tx_id).cloned())
+    }
+
+    fn record(&mut self, recorded: &RecordedTx) -> StoreResult<()> {
+        self.recorded.insert(recorded.tx_id, recorded.clone());
+        Ok(())
+    }
+
+    fn all(&self) -> StoreResult<Vec<RecordedTx>> {
+        let mut recorded: Vec<_> = self.recorded.values().cloned().collect();
+        recorded.sort_by_key(|recorded| recorded.tx_id);
+        Ok(recorded)
+    }
+
+    fn record_settlement(&mut self, settlement: &PendingSettlement) -> StoreResult<()> {
+        if let Some(settles_at) = self.pending_txs.insert(settlement.tx_id, settlement.settles_at) {
+            self.pending.remove(&(settles_at, settlement.tx_id));
+        }
+        self.pending
+            .insert((settlement.settles_at, settlement.tx_id), settlement.clone());
+        Ok(())
+    }
+
+    fn take_settled(&mut self, until: u64) -> StoreResult<Vec<PendingSettlement>> {
+        let later = match until.checked_add(1) {
+            Some(after) => self.pending.split_off(&(after, 0)),
+            None => BTreeMap::new(),
+        };
+        let settled = std::mem::replace(&mut self.pending, later);
+        for settlement in settled.values() {
+            self.pending_txs.remove(&settlement.tx_id);
+        }
+        Ok(settled.into_values().collect())
+    }
+
+    fn settlements(&self) -> StoreResult<Vec<PendingSettlement>> {
+        let mut settlements: Vec<_> = self.pending.values().cloned().collect();
+        settlements.sort_by_key(|settlement| settlement.tx_id);
+        Ok(settlements)
+    }
+
+    fn settlement(&self, tx_id: TxId) -> StoreResult<Option<PendingSettlement>> {
+        Ok(self
+            .pending_txs
+            .get(&tx_id)
+            .and_then(|settles_at| self.pending.get(&(*settles_at, tx_id)))
+            .cloned())
+    }
+
+    fn take_settlement(&mut self, tx_id: TxId) -> StoreResult<Option<PendingSettlement>> {
+        Ok(self
+            .pending_txs
+            .remove(&tx_id)
+            .and_then(|settles_at| self.pending.remove(&(settles_at, tx_id))))
+    }
+
+    fn forget(&mut self, tx_id: TxId) -> StoreResult<()> {
+        self.recorded.remove(&tx_id);
+        if let Some(settles_at) = self.pending_txs.remove(&tx_id) {
+            self.pending.remove(&(settles_at, tx_id));
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the idempotency keys in memory, so they are forgotten when the server stops.
+#[derive(Debug, Default)]
+pub struct MemoryIdempotencyStore {
+    outcomes: HashMap<String, (bool, u64)>,
+}
+
+impl IdempotencyStore for MemoryIdempotencyStore {
+    fn outcome(&self, key: &str, since: u64) -> StoreResult<Option<bool>> {
+        Ok(self
+            .outcomes
+            .get(key)
+            .filter(|(_, seen_at)| *seen_at >= since)
+            .map(|(accepted, _)| *accepted))
+    }
+
+    fn record(&mut self, key: &str, accepted: bool, seen_at: u64) -> StoreResult<()> {
+        self.outcomes.insert(key.to_string(), (accepted, seen_at));
+        Ok(())
+    }
+
+    fn expire(&mut self, before: u64) -> StoreResult<()> {
+        self.outcomes.retain(|_, (_, seen_at)| *seen_at >= before);
+        Ok(())
+    }
+}
+
+/// Keeps the processed transactions in memory.
+#[derive(Debug, Default)]
+pub struct MemoryDedupStore {
+    processed: HashSet<(TransactionType, TxId)>,
+}
+
+impl DedupStore for MemoryDedupStore {
+    fn contains(&self, tx_type: TransactionType, tx_id: TxId) -> StoreResult<bool> {
+        Ok(self.processed.contains(&(tx_type, tx_id)))
+    }
+
+    fn insert(&mut self, tx_type: TransactionType, tx_id: TxId) -> StoreResult<()> {
+        self.processed.insert((tx_type, tx_id));
+        Ok(())
+    }
+
+    fn remove(&mut self, tx_type: TransactionType, tx_id: TxId) -> StoreResult<()> {
+        self.processed.remove(&(tx_type, tx_id));
+        Ok(())
+    }
+
+    fn all(&self) -> StoreResult<Vec<(TransactionType, TxId)>> {
+        let mut processed: Vec<_> = self.processed.iter().copied().collect();
+        processed.sort_unstable();
+        Ok(processed)
+    }
+}
@@ -0,0 +1,153 @@
+//! Keeps the state of several tenants, e.g. partners, apart in a single run, by the `tenant`
+//! column of the CSV input.
+//!
+//! Each tenant has an engine of its own, so the same client and transaction ids can be used by
+//! different tenants, and a dispute of one of them can't refer to a transaction of another.
+//! The records without a `tenant` column, or whose tenant can't be read, are the ones of the
+//! tenant with an empty name.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Read;
+
+use csv::StringRecord;
+
+use crate::accounts::{AccountBalance, AmountFormat, ReportColumn};
+use crate::csv_input::CsvOptions;
+use crate::engine::{signature_column, PaymentsEngine};
+
+/// The name of the column of the CSV input with the tenant of each record.
+pub const TENANT_COLUMN: &str = "tenant";
+
+/// The engines of the tenants seen so far, by name.
+pub struct TenantEngines<'a> {
+    engines: BTreeMap<String, PaymentsEngine>,
+    // Creates the engine of a tenant the first time it is seen
+    new_engine: Box<dyn FnMut() -> Result<PaymentsEngine, Box<dyn Error>> + 'a>,
+}
+
+impl<'a> TenantEngines<'a> {
+    /// Tenants with engines created by `new_engine`, which should give each of them stores of its own.
+    pub fn new(new_engine: impl FnMut() -> Result<PaymentsEngine, Box<dyn Error>> + 'a) -> Self {
+        TenantEngines {
+            engines: BTreeMap::new(),
+            new_engine: Box::new(new_engine),
+        }
+    }
+
+    /// Applies all the transactions read as CSV, each to the engine of its tenant.
+    pub fn process_csv_reader<R: Read>(&mut self, reader: R, options: &CsvOptions) -> Result<(), Box<dyn Error>> {
+        let (mut rdr, headers) = options.reader(reader)?;
+        let tenant_column = headers.iter().position(|header| header == TENANT_COLUMN);
+        let signature_column = signature_column(&headers);
+        let mut record = StringRecord::new();
+
+        loop {
+            let transaction = match rdr.read_record(&mut record) {
+                Ok(false) => break,
+                Ok(true) => options.deserialize(&record, &headers),
+                Err(error) => Err(error),
+            };
+            let tenant = match &transaction {
+                Ok(_) => tenant_column.and_then(|index| record.get(index)).unwrap_or_default(),
+                Err(_) => "",
+            };
+            let line = match &transaction {
+                Err(error) => error.position(),
+                Ok(_) => None,
+            };
+            let line = line.or(record.position()).map_or(0, |position| position.line());
+            let engine = self.engine(tenant)?;
+            if engine.check_signature(line, record.as_byte_record().iter(), signature_column, &transaction)? {
+                engine.apply_record(line, transaction, options.lenient)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The engine of a tenant, created if it wasn't seen before.
+    fn engine(&mut self, tenant: &str) -> Result<&mut PaymentsEngine, Box<dyn Error>> {
+        if !self.engines.contains_key(tenant) {
+            self.engines.insert(tenant.to_string(), (self.new_engine)()?);
+        }
+        Ok(self.engines.get_mut(tenant).expect("the engine was just inserted"))
+    }
+
+    /// The engine of a tenant, if it had any transactions.
+    pub fn get(&self, tenant: &str) -> Option<&PaymentsEngine> {
+        self.engines.get(tenant)
+    }
+
+    /// The tenants and their engines, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &PaymentsEngine)> {
+        self.engines.iter().map(|(tenant, engine)| (tenant.as_str(), engine))
+    }
+}
+
+/// Formats the account balances report of several tenants with only these columns, in this order,
+/// after a `tenant` one, the balances of each tenant together.
+pub fn format_report(
+    tenants: &[(&str, Vec<AccountBalance>)],
+    columns: &[ReportColumn],
+    amounts: &AmountFormat,
+) -> String {
+    let mut header = vec![TENANT_COLUMN];
+    header.extend(columns.iter().map(ReportColumn::name));
+    let mut output = vec![header.join(", ")];
+    for (tenant, balances) in tenants {
+        for account_balance in balances {
+            let mut row = vec![tenant.to_string()];
+            row.extend(columns.iter().map(|column| column.value(account_balance, amounts)));
+            output.push(row.join(", "));
+        }
+    }
+    output.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_report, TenantEngines};
+    use crate::accounts::{AmountFormat, REPORT_COLUMNS};
+    use crate::amount::amount;
+    use crate::csv_input::CsvOptions;
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_tenants() {
+        let input = "type, client, tx, amount, tenant\n\
+                     deposit, 1, 1, 10.0, acme\n\
+                     deposit, 1, 1, 5.0, globex\n\
+                     dispute, 1, 1, , globex\n\
+                     withdrawal, 1, 2, 3.0, acme\n\
+                     chargeback, 1, 1, , globex\n\
+                     deposit, 2, 3, 1.0,\n";
+        let mut tenants = TenantEngines::new(|| Ok(PaymentsEngine::new()));
+        tenants
+            .process_csv_reader(input.as_bytes(), &CsvOptions::default())
+            .unwrap();
+
+        // The same client and transaction ids, with their own state
+        let acme = tenants.get("acme").unwrap().account(1).unwrap().unwrap();
+        assert_eq!((acme.available, acme.is_locked()), (amount(7.0), false));
+        let globex = tenants.get("globex").unwrap().account(1).unwrap().unwrap();
+        assert_eq!((globex.total(), globex.is_locked()), (amount(0.0), true));
+        let processed: Vec<_> = tenants
+            .iter()
+            .map(|(tenant, engine)| (tenant, engine.stats().processed))
+            .collect();
+        assert_eq!(processed, [("", 1), ("acme", 2), ("globex", 3)]);
+
+        let reports: Vec<_> = tenants
+            .iter()
+            .map(|(tenant, engine)| (tenant, engine.balances().unwrap()))
+            .collect();
+        assert_eq!(
+            format_report(&reports, &REPORT_COLUMNS, &AmountFormat::default()),
+            "tenant, client, available, held, pending, total, locked\n\
+             , 2, 1.0000, 0.0000, 0.0000, 1.0000, false\n\
+             acme, 1, 7.0000, 0.0000, 0.0000, 7.0000, false\n\
+             globex, 1, 0.0000, 0.0000, 0.0000, 0.0000, true"
+        );
+    }
+}
@@ -0,0 +1,320 @@
+//! Helpers to property-test integrations against the engine, with the `testing` feature:
+//! arbitrary transactions and configs from a seeded generator, and a reference [`Model`] of
+//! the core transactions to check the engine, or anything built on it, against.
+//!
+//! ```text
+//! let mut gen = Gen::new(seed);
+//! let mut model = Model::default();
+//! for transaction in gen.core_transactions(200) {
+//!     let applied = engine.apply(&transaction)?.is_ok();
+//!     assert_eq!(applied, model.apply(&transaction));
+//! }
+//! ```
+//!
+//! The same seed always gives the same values, so a failing case can be replayed from its seed.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::accounts::{AccountBalance, AccountStatus};
+use crate::amount::AmountValue;
+use crate::bench::SplitMix64;
+use crate::config::{AmountRange, EngineConfig, OverAvailable};
+use crate::transactions::{Amount, ClientId, Transaction, TransactionType, TxId};
+
+/// A seeded source of arbitrary values, which keeps the deposits it generated so that the
+/// disputes, resolves and chargebacks it generates mostly refer to them.
+#[derive(Debug)]
+pub struct Gen {
+    rng: SplitMix64,
+    clients: ClientId,
+    next_tx: TxId,
+    deposits: Vec<(ClientId, TxId)>,
+}
+
+impl Gen {
+    /// A generator of transactions of up to 10 clients.
+    pub fn new(seed: u64) -> Self {
+        Gen {
+            rng: SplitMix64(seed),
+            clients: 10,
+            next_tx: 1,
+            deposits: vec![],
+        }
+    }
+
+    /// Generates the transactions of up to `clients` clients instead.
+    pub fn with_clients(mut self, clients: ClientId) -> Self {
+        self.clients = clients.max(1);
+        self
+    }
+
+    /// A number in `0..bound`.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.rng.below(bound.max(1))
+    }
+
+    /// Whether something that happens `percent` of the time happens.
+    pub fn chance(&mut self, percent: u64) -> bool {
+        self.below(100) < percent
+    }
+
+    /// A positive amount with up to four decimal places, under 10,000.
+    pub fn amount(&mut self) -> Amount {
+        Amount::from_f64((1 + self.below(100_000_000)) as f64 / 10_000.0)
+    }
+
+    /// One of the clients.
+    pub fn client(&mut self) -> ClientId {
+        1 + self.below(self.clients as u64) as ClientId
+    }
+
+    /// An arbitrary value of `T`.
+    pub fn arbitrary<T: Arbitrary>(&mut self) -> T {
+        T::arbitrary(self)
+    }
+
+    /// `count` transactions of the types the [`Model`] knows: deposits, withdrawals, disputes,
+    /// resolves and chargebacks.
+    pub fn core_transactions(&mut self, count: usize) -> Vec<Transaction> {
+        const CORE: [TransactionType; 5] = [
+            TransactionType::Deposit,
+            TransactionType::Withdrawal,
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+        ];
+        (0..count)
+            .map(|_| {
+                let tx_type = CORE[self.below(CORE.len() as u64) as usize];
+                self.transaction(tx_type)
+            })
+            .collect()
+    }
+
+    /// A transaction of the given type, with the fields it needs.
+    #[cfg_attr(feature = "wide-ids", allow(clippy::useless_conversion))]
+    pub fn transaction(&mut self, tx_type: TransactionType) -> Transaction {
+        let refers_to_transaction = matches!(
+            tx_type,
+            TransactionType::Dispute
+                | TransactionType::Resolve
+                | TransactionType::Chargeback
+                | TransactionType::ChargebackReversal
+                | TransactionType::Reversal
+                | TransactionType::Settle
+        );
+        let (client_id, tx_id) = match self.deposits.len() {
+            // Mostly of an earlier deposit, of its client
+            len if refers_to_transaction && len > 0 && self.chance(90) => {
+                let index = self.below(len as u64) as usize;
+                self.deposits[index]
+            }
+            _ if refers_to_transaction => {
+                let tx_id = TxId::try_from(1 + self.below(u64::from(self.next_tx))).expect("below the next id");
+                (self.client(), tx_id)
+            }
+            _ => {
+                let tx_id = self.next_tx;
+                self.next_tx += 1;
+                (self.client(), tx_id)
+            }
+        };
+        if tx_type == TransactionType::Deposit {
+            self.deposits.push((client_id, tx_id));
+        }
+        let amount = match tx_type {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Fee => Some(self.amount()),
+            TransactionType::Adjustment if self.chance(50) => Some(-self.amount()),
+            TransactionType::Adjustment => Some(self.amount()),
+            // Sometimes only part of the deposit
+            TransactionType::Dispute if self.chance(20) => Some(self.amount()),
+            _ => None,
+        };
+        Transaction {
+            tx_type,
+            client_id,
+            tx_id,
+            amount,
+            reason: (tx_type == TransactionType::Adjustment).then(|| String::from("correction")),
+            timestamp: None,
+            settles_at: None,
+        }
+    }
+}
+
+/// A type with arbitrary values, for property tests.
+pub trait Arbitrary {
+    fn arbitrary(gen: &mut Gen) -> Self;
+}
+
+impl Arbitrary for TransactionType {
+    /// Any of the types that can be submitted, i.e. not `interest`.
+    fn arbitrary(gen: &mut Gen) -> Self {
+        let types: Vec<TransactionType> = TransactionType::ALL
+            .into_iter()
+            .filter(|tx_type| *tx_type != TransactionType::Interest)
+            .collect();
+        types[gen.below(types.len() as u64) as usize]
+    }
+}
+
+impl Arbitrary for Transaction {
+    /// A transaction of any type that can be submitted, see [`Gen::transaction`].
+    fn arbitrary(gen: &mut Gen) -> Self {
+        let tx_type = gen.arbitrary();
+        gen.transaction(tx_type)
+    }
+}
+
+impl Arbitrary for OverAvailable {
+    fn arbitrary(gen: &mut Gen) -> Self {
+        [OverAvailable::Allow, OverAvailable::Cap, OverAvailable::Reject][gen.below(3) as usize]
+    }
+}
+
+impl Arbitrary for EngineConfig {
+    /// The default settings with arbitrary admin operations, dispute rules and amount limits.
+    fn arbitrary(gen: &mut Gen) -> Self {
+        let mut config = EngineConfig {
+            admin_ops: gen.chance(50),
+            ..EngineConfig::default()
+        };
+        config.disputes.unlock_on_chargeback_reversal = gen.chance(50);
+        config.disputes.over_available = gen.arbitrary();
+        for range in [&mut config.limits.deposit, &mut config.limits.withdrawal] {
+            if gen.chance(25) {
+                let (min, max) = (Amount::from_f64(gen.amount().to_f64() / 100.0), gen.amount());
+                *range = AmountRange {
+                    min: Some(min.min(max)),
+                    max: Some(min.max(max)),
+                };
+            }
+        }
+        config
+    }
+}
+
+/// A plain reference implementation of the core transactions, with the default settings:
+/// deposits, withdrawals, disputes, resolves and chargebacks.
+#[derive(Debug, Default)]
+pub struct Model {
+    accounts: BTreeMap<ClientId, AccountBalance>,
+    // The amount of each deposit
+    deposits: HashMap<TxId, Amount>,
+    // The client of each deposit and withdrawal applied
+    owners: HashMap<TxId, ClientId>,
+    // The amount held by each open dispute
+    disputes: HashMap<TxId, Amount>,
+}
+
+impl Model {
+    /// Applies a transaction, returning whether it was applied. The transactions of other types
+    /// aren't applied, only their client is seen.
+    pub fn apply(&mut self, transaction: &Transaction) -> bool {
+        let client = transaction.client_id;
+        let account = self
+            .accounts
+            .entry(client)
+            .or_insert_with(|| AccountBalance::new(client));
+        if account.status == AccountStatus::Locked {
+            return false;
+        }
+        let tx = transaction.tx_id;
+        if transaction.tx_type != TransactionType::Deposit
+            && transaction.tx_type != TransactionType::Withdrawal
+            && self.owners.get(&tx).is_some_and(|owner| *owner != client)
+        {
+            return false;
+        }
+        match (transaction.tx_type, transaction.amount) {
+            // An account driven negative by a dispute can't stay negative after a deposit
+            (TransactionType::Deposit, Some(amount)) if account.available + amount >= Amount::ZERO => {
+                account.available += amount;
+                self.deposits.insert(tx, amount);
+                self.owners.insert(tx, client);
+            }
+            (TransactionType::Withdrawal, Some(amount)) if account.available - amount >= Amount::ZERO => {
+                account.available -= amount;
+                self.owners.insert(tx, client);
+            }
+            (TransactionType::Dispute, amount) => {
+                let Some(deposited) = self.deposits.get(&tx) else {
+                    return false;
+                };
+                let amount = amount.unwrap_or(*deposited);
+                if amount > *deposited || self.disputes.contains_key(&tx) {
+                    return false;
+                }
+                account.available -= amount;
+                account.held += amount;
+                self.disputes.insert(tx, amount);
+            }
+            (TransactionType::Resolve, _) => {
+                let Some(amount) = self.disputes.remove(&tx) else {
+                    return false;
+                };
+                account.held -= amount;
+                account.available += amount;
+            }
+            (TransactionType::Chargeback, _) => {
+                // The dispute stays open, the account is locked anyway
+                let Some(&amount) = self.disputes.get(&tx) else {
+                    return false;
+                };
+                account.held -= amount;
+                account.status = AccountStatus::Locked;
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// The balances of all the clients seen, sorted by client id.
+    pub fn balances(&self) -> Vec<AccountBalance> {
+        self.accounts.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Gen, Model};
+    use crate::config::EngineConfig;
+    use crate::engine::PaymentsEngine;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_model() {
+        for seed in 0..50 {
+            let mut gen = Gen::new(seed).with_clients(5);
+            let mut engine = PaymentsEngine::new();
+            let mut model = Model::default();
+            for transaction in gen.core_transactions(300) {
+                let applied = engine.apply(&transaction).unwrap().is_ok();
+                assert_eq!(applied, model.apply(&transaction), "seed {}: {:?}", seed, transaction);
+            }
+            let balances = |balances: Vec<crate::AccountBalance>| -> Vec<_> {
+                balances
+                    .iter()
+                    .map(|balance| (balance.client, balance.available, balance.held, balance.status))
+                    .collect()
+            };
+            assert_eq!(
+                balances(engine.balances().unwrap()),
+                balances(model.balances()),
+                "seed {}",
+                seed
+            );
+        }
+
+        // Any transaction and config is handled, if not always applied
+        let mut gen = Gen::new(7);
+        for _ in 0..20 {
+            let config: EngineConfig = gen.arbitrary();
+            let mut engine = PaymentsEngine::new().with_config(config);
+            for _ in 0..50 {
+                let transaction: Transaction = gen.arbitrary();
+                let _ = engine.apply(&transaction);
+            }
+        }
+    }
+}
@@ -1,19 +1,141 @@
+use alloc::string::String;
+use core::convert::TryFrom;
 use core::fmt;
-use std::convert::TryFrom;
+use core::str::FromStr;
 use serde::Deserialize;
+#[cfg(feature = "std")]
+use std::error::Error;
 
-#[derive(Debug, Deserialize)]
+use crate::amount::AmountValue;
+use crate::reasons::{TransactionErrorType, TransactionRecordError};
+
+/// The id of a client, a `u16`, or a `u32` with the `wide-ids` feature.
+#[cfg(not(feature = "wide-ids"))]
+pub type ClientId = u16;
+/// The id of a client, a `u16`, or a `u32` with the `wide-ids` feature.
+#[cfg(feature = "wide-ids")]
+pub type ClientId = u32;
+
+/// The id of a transaction, a `u32`, or a `u64` with the `wide-ids` feature.
+#[cfg(not(feature = "wide-ids"))]
+pub type TxId = u32;
+/// The id of a transaction, a `u32`, or a `u64` with the `wide-ids` feature.
+#[cfg(feature = "wide-ids")]
+pub type TxId = u64;
+
+/// An amount of funds, an `f32`, an `f64` with the `wide-amounts` feature, or exact minor units
+/// with the `fixed-amounts` one, see [`amount`](crate::amount).
+#[cfg(not(any(feature = "wide-amounts", feature = "fixed-amounts")))]
+pub type Amount = f32;
+/// An amount of funds, an `f32`, an `f64` with the `wide-amounts` feature, or exact minor units
+/// with the `fixed-amounts` one, see [`amount`](crate::amount).
+#[cfg(all(feature = "wide-amounts", not(feature = "fixed-amounts")))]
+pub type Amount = f64;
+/// An amount of funds, an `f32`, an `f64` with the `wide-amounts` feature, or exact minor units
+/// with the `fixed-amounts` one, see [`amount`](crate::amount).
+#[cfg(feature = "fixed-amounts")]
+pub type Amount = crate::amount::MinorUnits;
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Transaction {
     #[serde(rename = "type")]
     pub tx_type: TransactionType,
     #[serde(rename = "client")]
-    pub client_id: u16,
+    pub client_id: ClientId,
     #[serde(rename = "tx")]
-    pub tx_id: u32,
-    pub amount: Option<f32>,
+    pub tx_id: TxId,
+    #[cfg_attr(
+        feature = "fixed-amounts",
+        serde(default, deserialize_with = "crate::amount::deserialize_text")
+    )]
+    pub amount: Option<Amount>,
+    /// Why an adjustment was made, e.g. a reason code.
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// When the transaction happened, in seconds since the Unix epoch.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// When the funds of a deposit settle, in seconds since the Unix epoch.
+    /// Until then they are pending instead of available.
+    #[serde(default)]
+    pub settles_at: Option<u64>,
 }
 
-#[derive(Debug, PartialEq, Deserialize)]
+/// The CSV columns of a transaction, in order. The `reason`, `timestamp` and `settles_at` are optional.
+pub const CSV_COLUMNS: [&str; 7] = ["type", "client", "tx", "amount", "reason", "timestamp", "settles_at"];
+
+impl Transaction {
+    /// Parses a single CSV record, without a header, with the columns in the usual order.
+    #[cfg(feature = "std")]
+    pub fn parse_csv_record(line: &[u8]) -> Result<Transaction, csv::Error> {
+        let headers = csv::StringRecord::from(CSV_COLUMNS.to_vec());
+        let mut rdr = crate::engine::csv_reader_builder().has_headers(false).from_reader(line);
+        let mut record = csv::StringRecord::new();
+        rdr.read_record(&mut record)?;
+        record.deserialize(Some(&headers))
+    }
+
+    /// Parses a single CSV record, like [`parse_csv_record`](Transaction::parse_csv_record),
+    /// and checks that it is a valid transaction, see [`validate`](Transaction::validate).
+    /// Anything else than one record, e.g. an empty line or two records, is an error.
+    #[cfg(feature = "std")]
+    pub fn parse_bytes(line: &[u8]) -> Result<Transaction, Box<dyn Error>> {
+        let headers = csv::StringRecord::from(CSV_COLUMNS.to_vec());
+        let mut rdr = crate::engine::csv_reader_builder().has_headers(false).from_reader(line);
+        let mut record = csv::StringRecord::new();
+        let mut other = csv::StringRecord::new();
+        if !rdr.read_record(&mut record)? || rdr.read_record(&mut other)? {
+            return Err(Box::new(TransactionRecordError {
+                error_type: TransactionErrorType::NotSingleRecord,
+            }));
+        }
+        let transaction: Transaction = record.deserialize(Some(&headers))?;
+        transaction.validate()?;
+        Ok(transaction)
+    }
+
+    /// Parses a single CSV record from a string, see [`parse_bytes`](Transaction::parse_bytes).
+    #[cfg(feature = "std")]
+    pub fn parse_csv_line(line: &str) -> Result<Transaction, Box<dyn Error>> {
+        Transaction::parse_bytes(line.as_bytes())
+    }
+
+    /// Checks what the engine checks of a transaction on its own, without the state of the
+    /// accounts: that the types that need an amount, or a reason, have one, and that the
+    /// amount is a positive number, or zero for a fee, or any number for an adjustment.
+    pub fn validate(&self) -> Result<(), TransactionRecordError> {
+        let error = |error_type| Err(TransactionRecordError { error_type });
+        let missing = match self.tx_type {
+            TransactionType::Deposit => Some(TransactionErrorType::NoDepositAmount),
+            TransactionType::Withdrawal => Some(TransactionErrorType::NoWithdrawalAmount),
+            TransactionType::Adjustment => Some(TransactionErrorType::NoAdjustmentAmount),
+            TransactionType::Fee => Some(TransactionErrorType::NoFeeAmount),
+            _ => None,
+        };
+        let Some(amount) = self.amount else {
+            return match missing {
+                Some(error_type) => error(error_type),
+                None => Ok(()),
+            };
+        };
+        if !amount.is_finite() {
+            return error(TransactionErrorType::InvalidAmount);
+        }
+        match self.tx_type {
+            TransactionType::Dispute if amount <= Amount::ZERO => error(TransactionErrorType::InvalidDisputeAmount),
+            TransactionType::Adjustment if self.reason.as_ref().is_none_or(|reason| reason.is_empty()) => {
+                error(TransactionErrorType::NoAdjustmentReason)
+            }
+            TransactionType::Adjustment => Ok(()),
+            TransactionType::Fee if amount < Amount::ZERO => error(TransactionErrorType::InvalidAmount),
+            TransactionType::Fee => Ok(()),
+            _ if amount <= Amount::ZERO => error(TransactionErrorType::InvalidAmount),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
 #[serde(try_from = "String")]
 pub enum TransactionType {
     Deposit,
@@ -21,26 +143,142 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    /// Undoes an earlier deposit or withdrawal, without locking the account.
+    Reversal,
+    /// A signed correction of the available funds, only allowed with admin operations.
+    Adjustment,
+    /// Charges a fee from the available funds.
+    Fee,
+    /// Closes the account, paying out its available funds.
+    Close,
+    /// Stops withdrawals from the account, only allowed with admin operations.
+    Freeze,
+    /// Undoes a freeze, only allowed with admin operations.
+    Unfreeze,
+    /// Accrued interest, posted by the engine itself. It can't be submitted.
+    Interest,
+    /// Undoes the chargeback of a deposit, after it was won on appeal, even though the account is locked.
+    ChargebackReversal,
+    /// Makes the pending funds of the deposit it refers to available before it settles.
+    Settle,
+}
+
+impl TransactionType {
+    /// All the types, in the order they are declared.
+    pub const ALL: [TransactionType; 14] = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::Reversal,
+        TransactionType::Adjustment,
+        TransactionType::Fee,
+        TransactionType::Close,
+        TransactionType::Freeze,
+        TransactionType::Unfreeze,
+        TransactionType::Interest,
+        TransactionType::ChargebackReversal,
+        TransactionType::Settle,
+    ];
+
+    /// The name of the type in the input, or `interest` for the interest posted by the engine.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Reversal => "reversal",
+            TransactionType::Adjustment => "adjustment",
+            TransactionType::Fee => "fee",
+            TransactionType::Close => "close",
+            TransactionType::Freeze => "freeze",
+            TransactionType::Unfreeze => "unfreeze",
+            TransactionType::Interest => "interest",
+            TransactionType::ChargebackReversal => "chargeback_reversal",
+            TransactionType::Settle => "settle",
+        }
+    }
 }
 
 impl TryFrom<String> for TransactionType {
     type Error = TransactionTypeFromStrError;
     fn try_from(s: String) -> Result<Self, Self::Error> {
-        match s.as_str() {
+        s.parse()
+    }
+}
+
+impl FromStr for TransactionType {
+    type Err = TransactionTypeFromStrError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
             "deposit" => Ok(TransactionType::Deposit),
             "withdrawal" => Ok(TransactionType::Withdrawal),
             "dispute" => Ok(TransactionType::Dispute),
             "resolve" => Ok(TransactionType::Resolve),
             "chargeback" => Ok(TransactionType::Chargeback),
+            "reversal" | "refund" => Ok(TransactionType::Reversal),
+            "adjustment" => Ok(TransactionType::Adjustment),
+            "fee" => Ok(TransactionType::Fee),
+            "close" => Ok(TransactionType::Close),
+            "freeze" => Ok(TransactionType::Freeze),
+            "unfreeze" => Ok(TransactionType::Unfreeze),
+            "chargeback_reversal" => Ok(TransactionType::ChargebackReversal),
+            "settle" => Ok(TransactionType::Settle),
             _ => Err(TransactionTypeFromStrError),
         }
     }
 }
 
+#[derive(Debug)]
 pub struct TransactionTypeFromStrError;
 
+impl core::error::Error for TransactionTypeFromStrError {}
+
 impl fmt::Display for TransactionTypeFromStrError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write_str("Could not decode CSV type into the transaction type enum")
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::{Transaction, TransactionType};
+    use crate::amount::amount;
+
+    #[test]
+    fn test_parse_csv_line() {
+        let transaction = Transaction::parse_csv_line("withdrawal, 2, 7, 1.5").unwrap();
+        assert_eq!(
+            (transaction.tx_type, transaction.client_id, transaction.tx_id),
+            (TransactionType::Withdrawal, 2, 7)
+        );
+        assert_eq!(transaction.amount, Some(amount(1.5)));
+        assert!(Transaction::parse_bytes(b"dispute, 1, 1").is_ok());
+        assert!(Transaction::parse_bytes(b"adjustment, 1, 2, -3.0, chargeback fee").is_ok());
+
+        let error = |line: &str| Transaction::parse_csv_line(line).unwrap_err().to_string();
+        assert_eq!(error("deposit, 1, 1"), "A deposit must have an amount");
+        assert_eq!(
+            error("deposit, 1, 1, -2.0"),
+            "The amount must be a positive number, or any number in an adjustment"
+        );
+        #[cfg(not(feature = "fixed-amounts"))]
+        assert_eq!(
+            error("withdrawal, 1, 1, NaN"),
+            "The amount must be a positive number, or any number in an adjustment"
+        );
+        #[cfg(feature = "fixed-amounts")]
+        assert!(error("withdrawal, 1, 1, NaN").contains("Invalid amount `NaN`"));
+        assert_eq!(error("dispute, 1, 1, 0.0"), "The amount of a dispute must be positive");
+        assert_eq!(error("adjustment, 1, 1, 2.0"), "An adjustment must have a reason");
+        assert_eq!(error(""), "The line must have a single CSV record");
+        assert_eq!(
+            error("deposit, 1, 1, 1.0\ndeposit, 1, 2, 1.0"),
+            "The line must have a single CSV record"
+        );
+        assert!(Transaction::parse_bytes(b"deposit, 1, \xff, 1.0").is_err());
+        assert!(Transaction::parse_csv_line("deposit, 5000000000, 1, 1.0").is_err());
+    }
+}
@@ -0,0 +1,195 @@
+//! The pre-images of the last transactions the engine processed, so that they can be undone,
+//! see [`PaymentsEngine::with_undo`](crate::engine::PaymentsEngine::with_undo), or rolled back
+//! to a savepoint, see [`PaymentsEngine::savepoint`](crate::engine::PaymentsEngine::savepoint).
+//!
+//! Before a transaction is applied, the balance of its client, the deposit or withdrawal it
+//! records or refers to, its open dispute, the losses of the client to chargebacks and what the
+//! risk rules remember of it are kept as they were, and so is the interest accrued if it starts
+//! a new day. Undoing it puts them back, forgets it in the dedup store, and the reverse of its
+//! ledger entry and of the interest it posted is posted to the journal and the event log, so
+//! that they still add up to the balances. The stats stay as they are, and so do the
+//! settlements and expired disputes of the other transactions because of its timestamp.
+
+use std::collections::VecDeque;
+
+use crate::accounts::AccountBalance;
+use crate::custom_errors::{SavepointError, SavepointErrorType};
+use crate::interest::InterestAccrual;
+use crate::ledger::{JournalEntry, Posting};
+use crate::losses::LossTotals;
+use crate::risk::ClientRisk;
+use crate::store::{PendingSettlement, RecordedTx, StoreResult, TxStore};
+use crate::transactions::{ClientId, Transaction, TxId};
+
+/// What a transaction changed, as it was before it was processed.
+#[derive(Debug, Clone)]
+pub(crate) struct PreImage {
+    pub(crate) transaction: Transaction,
+    /// The balance of the client, if it had one.
+    pub(crate) balance: Option<AccountBalance>,
+    /// The deposit or withdrawal with the id of the transaction, if there was one.
+    pub(crate) recorded: Option<RecordedTx>,
+    /// The settlement of the deposit, if it was pending.
+    pub(crate) settlement: Option<PendingSettlement>,
+    /// The entry posted, if the transaction was applied.
+    pub(crate) entry: Option<JournalEntry>,
+    /// What the risk rules remembered of the client.
+    pub(crate) risk: ClientRisk,
+    /// The losses of the client, if it had any chargebacks.
+    pub(crate) losses: Option<LossTotals>,
+    /// When the dispute of the transaction was opened and its client, if it was open.
+    pub(crate) dispute: Option<(u64, ClientId)>,
+    /// Whether it was recorded in the dedup store.
+    pub(crate) deduped: bool,
+    /// The interest accrued, and the entries it posted, if the transaction started a new day.
+    pub(crate) interest: Option<(InterestAccrual, Vec<JournalEntry>)>,
+}
+
+/// The pre-images of the last `depth` transactions, the latest last, and the savepoints
+/// among them.
+#[derive(Debug)]
+pub(crate) struct UndoLog {
+    depth: usize,
+    // Whether it only keeps the pre-images since the oldest savepoint, see `for_savepoints`
+    for_savepoints: bool,
+    pre_images: VecDeque<PreImage>,
+    // How many transactions were processed and not undone
+    position: u64,
+    // The savepoints by name, with the position they were set at, the oldest first
+    savepoints: Vec<(String, u64)>,
+}
+
+impl UndoLog {
+    pub(crate) fn new(depth: usize) -> Self {
+        UndoLog {
+            depth,
+            for_savepoints: false,
+            pre_images: VecDeque::new(),
+            position: 0,
+            savepoints: vec![],
+        }
+    }
+
+    /// A log of the pre-images of the transactions since the oldest of its savepoints only,
+    /// however many there are, which isn't needed any more once they are all released.
+    pub(crate) fn for_savepoints() -> Self {
+        UndoLog {
+            for_savepoints: true,
+            ..UndoLog::new(usize::MAX)
+        }
+    }
+
+    pub(crate) fn push(&mut self, pre_image: PreImage) {
+        self.position += 1;
+        if self.pre_images.len() == self.depth {
+            self.pre_images.pop_front();
+        }
+        if self.depth > 0 {
+            self.pre_images.push_back(pre_image);
+        }
+        self.trim();
+    }
+
+    /// Drops the pre-images from before the oldest savepoint, if it only keeps the ones since.
+    fn trim(&mut self) {
+        if !self.for_savepoints {
+            return;
+        }
+        let oldest = self.savepoints.first().map_or(self.position, |(_, at)| *at);
+        let since = (self.position - oldest) as usize;
+        while self.pre_images.len() > since {
+            self.pre_images.pop_front();
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<PreImage> {
+        let pre_image = self.pre_images.pop_back()?;
+        self.position -= 1;
+        // The savepoints set after it are gone with it
+        let position = self.position;
+        self.savepoints.retain(|(_, at)| *at <= position);
+        Some(pre_image)
+    }
+
+    /// Sets the savepoint `name` at the current position, instead of anywhere earlier.
+    pub(crate) fn savepoint(&mut self, name: &str) {
+        self.savepoints.retain(|(savepoint, _)| savepoint != name);
+        self.savepoints.push((name.to_string(), self.position));
+    }
+
+    /// Removes the savepoint `name` and the ones set after it.
+    pub(crate) fn release(&mut self, name: &str) -> Result<(), SavepointError> {
+        let Some(index) = self.savepoints.iter().position(|(savepoint, _)| savepoint == name) else {
+            return Err(SavepointError {
+                name: name.to_string(),
+                error_type: SavepointErrorType::Unknown,
+            });
+        };
+        self.savepoints.truncate(index);
+        self.trim();
+        Ok(())
+    }
+
+    /// Whether it is only kept for savepoints, and there are none left.
+    pub(crate) fn unused(&self) -> bool {
+        self.for_savepoints && self.savepoints.is_empty()
+    }
+
+    /// How many transactions to undo to get back to the savepoint `name`.
+    pub(crate) fn since(&self, name: &str) -> Result<usize, SavepointError> {
+        let error = |error_type| SavepointError {
+            name: name.to_string(),
+            error_type,
+        };
+        let Some((_, at)) = self.savepoints.iter().find(|(savepoint, _)| savepoint == name) else {
+            return Err(error(SavepointErrorType::Unknown));
+        };
+        let since = (self.position - at) as usize;
+        if since > self.pre_images.len() {
+            return Err(error(SavepointErrorType::TooOld));
+        }
+        Ok(since)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.pre_images.clear();
+        self.savepoints.clear();
+    }
+}
+
+/// The entry that moves the funds of `entry` back.
+pub(crate) fn reverse(entry: &JournalEntry) -> JournalEntry {
+    JournalEntry {
+        postings: entry
+            .postings
+            .iter()
+            .rev()
+            .map(|posting| Posting {
+                from: posting.to,
+                to: posting.from,
+                amount: posting.amount,
+            })
+            .collect(),
+        reason: Some(String::from("undo")),
+        ..entry.clone()
+    }
+}
+
+/// Puts the deposit or withdrawal with this id back in the store as it was, with its
+/// `settlement` if it was pending, or forgets it if there wasn't any.
+pub(crate) fn restore(
+    transactions: &mut dyn TxStore,
+    tx_id: TxId,
+    recorded: Option<&RecordedTx>,
+    settlement: Option<&PendingSettlement>,
+) -> StoreResult<()> {
+    transactions.forget(tx_id)?;
+    let Some(recorded) = recorded else {
+        return Ok(());
+    };
+    transactions.record(recorded)?;
+    if let Some(settlement) = settlement {
+        transactions.record_settlement(settlement)?;
+    }
+    Ok(())
+}
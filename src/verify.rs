@@ -0,0 +1,150 @@
+//! Checks the balances of a run against a golden report, e.g. of a regression corpus, with a
+//! readable difference of each client that doesn't match, see `payments-engine verify`.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io::Write;
+
+use crate::accounts::{AccountBalance, AmountFormat, ReportColumn, REPORT_COLUMNS};
+use crate::transactions::ClientId;
+
+/// How a client of the run differs from the expected report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// The client is in the expected report, but had no transactions.
+    Missing {
+        client: ClientId,
+        expected: Vec<(&'static str, String)>,
+    },
+    /// The client had transactions, but isn't in the expected report.
+    Unexpected {
+        client: ClientId,
+        actual: Vec<(&'static str, String)>,
+    },
+    /// The columns of the report that differ, with the expected and the actual values.
+    Changed {
+        client: ClientId,
+        columns: Vec<(&'static str, String, String)>,
+    },
+}
+
+/// The values of the columns of the report of a balance, but its client.
+fn values(account_balance: &AccountBalance, amounts: &AmountFormat) -> Vec<(&'static str, String)> {
+    REPORT_COLUMNS
+        .iter()
+        .filter(|column| **column != ReportColumn::Client)
+        .map(|column| (column.name(), column.value(account_balance, amounts)))
+        .collect()
+}
+
+/// The clients whose balances differ from the expected ones, as the report writes them with
+/// `amounts`, sorted by client id.
+pub fn compare(expected: &[AccountBalance], actual: &[AccountBalance], amounts: &AmountFormat) -> Vec<Difference> {
+    let by_client = |balances: &[AccountBalance]| -> BTreeMap<ClientId, Vec<(&'static str, String)>> {
+        balances
+            .iter()
+            .map(|account_balance| (account_balance.client, values(account_balance, amounts)))
+            .collect()
+    };
+    let (expected, actual) = (by_client(expected), by_client(actual));
+    let clients: BTreeSet<ClientId> = expected.keys().chain(actual.keys()).copied().collect();
+    clients
+        .into_iter()
+        .filter_map(|client| match (expected.get(&client), actual.get(&client)) {
+            (Some(expected), None) => Some(Difference::Missing {
+                client,
+                expected: expected.clone(),
+            }),
+            (None, Some(actual)) => Some(Difference::Unexpected {
+                client,
+                actual: actual.clone(),
+            }),
+            (Some(expected), Some(actual)) => {
+                let columns: Vec<_> = expected
+                    .iter()
+                    .zip(actual)
+                    .filter(|((_, expected), (_, actual))| expected != actual)
+                    .map(|((column, expected), (_, actual))| (*column, expected.clone(), actual.clone()))
+                    .collect();
+                (!columns.is_empty()).then_some(Difference::Changed { client, columns })
+            }
+            (None, None) => None,
+        })
+        .collect()
+}
+
+/// Writes the differences, one client per line, e.g.
+/// `client 1: held expected 1.0000, got 0.0000; locked expected false, got true`.
+pub fn write_differences<W: Write>(differences: &[Difference], mut writer: W) -> Result<(), Box<dyn Error>> {
+    let list = |values: &[(&str, String)]| {
+        values
+            .iter()
+            .map(|(column, value)| format!("{} {}", column, value))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    for difference in differences {
+        match difference {
+            Difference::Missing { client, expected } => {
+                writeln!(writer, "client {}: missing, expected {}", client, list(expected))?
+            }
+            Difference::Unexpected { client, actual } => {
+                writeln!(writer, "client {}: not expected, got {}", client, list(actual))?
+            }
+            Difference::Changed { client, columns } => {
+                let columns: Vec<String> = columns
+                    .iter()
+                    .map(|(column, expected, actual)| format!("{} expected {}, got {}", column, expected, actual))
+                    .collect();
+                writeln!(writer, "client {}: {}", client, columns.join("; "))?
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{compare, write_differences};
+    use crate::accounts::AmountFormat;
+    use crate::engine::PaymentsEngine;
+    use crate::reconcile::read_expected;
+
+    #[test]
+    fn test_verify() {
+        let mut engine = PaymentsEngine::new();
+        engine
+            .process_csv(Path::new("sample_files/multiple_clients.csv"))
+            .unwrap();
+        let amounts = AmountFormat::default();
+        let golden = engine.report().unwrap();
+        assert!(compare(
+            &read_expected(golden.as_bytes()).unwrap(),
+            &engine.balances().unwrap(),
+            &amounts
+        )
+        .is_empty());
+
+        let expected = "client, available, held, pending, total, locked
+1, 2.0000, 1.0000, 0.0000, 3.0000, false
+2, 0.5000, 0.0000, 1.0000, 1.5000, false
+4, 1.0000, 0.0000, 0.0000, 1.0000, false";
+        let differences = compare(
+            &read_expected(expected.as_bytes()).unwrap(),
+            &engine.balances().unwrap(),
+            &amounts,
+        );
+        let mut output = vec![];
+        write_differences(&differences, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client 1: held expected 1.0000, got 0.0000; total expected 3.0000, got 2.0000; locked expected false, got true\n\
+             client 2: pending expected 1.0000, got 0.0000; total expected 1.5000, got 0.5000\n\
+             client 3: not expected, got available 0.0000, held 5.5000, pending 0.0000, total 5.5000, locked false\n\
+             client 4: missing, expected available 1.0000, held 0.0000, pending 0.0000, total 1.0000, locked false\n"
+        );
+    }
+}
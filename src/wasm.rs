@@ -0,0 +1,91 @@
+//! JavaScript bindings for the WebAssembly build, made with wasm-bindgen.
+//!
+//! Only the reader based APIs are exposed, since there is no file system in the browser:
+//!
+//! ```js
+//! import init, { PaymentsEngine } from "./pkg/payments_engine.js";
+//!
+//! await init();
+//! const engine = new PaymentsEngine();
+//! engine.processCsv(await file.text());
+//! console.log(engine.report());
+//! ```
+
+use wasm_bindgen::prelude::*;
+
+use crate::accounts::format_report_json;
+use crate::engine::PaymentsEngine;
+use crate::transactions::Transaction;
+
+fn to_js_error(err: impl std::fmt::Display) -> JsError {
+    JsError::new(&err.to_string())
+}
+
+/// Processes a whole CSV document with a new engine, and returns the report the CLI
+/// would print for it. Throws on the first malformed record.
+#[wasm_bindgen(js_name = previewCsv)]
+pub fn preview_csv(csv: &str) -> Result<String, JsError> {
+    let mut engine = PaymentsEngine::new();
+    engine.process_csv_reader(csv.as_bytes()).map_err(to_js_error)?;
+    engine.report().map_err(to_js_error)
+}
+
+/// The payments engine, keeping all of its state in memory.
+#[wasm_bindgen(js_name = PaymentsEngine)]
+pub struct WasmPaymentsEngine {
+    engine: PaymentsEngine,
+}
+
+impl Default for WasmPaymentsEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen(js_class = PaymentsEngine)]
+impl WasmPaymentsEngine {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmPaymentsEngine {
+        WasmPaymentsEngine {
+            engine: PaymentsEngine::new(),
+        }
+    }
+
+    /// Applies all the transactions of a CSV document, with its header.
+    /// Throws on the first malformed record, with its line in the message.
+    #[wasm_bindgen(js_name = processCsv)]
+    pub fn process_csv(&mut self, csv: &str) -> Result<(), JsError> {
+        self.engine.process_csv_reader(csv.as_bytes()).map_err(to_js_error)
+    }
+
+    /// Applies a single CSV record, without a header, e.g. `deposit, 1, 1, 1.0`.
+    #[wasm_bindgen(js_name = applyCsvLine)]
+    pub fn apply_csv_line(&mut self, line: &str) -> Result<(), JsError> {
+        let transaction = Transaction::parse_csv_record(line.as_bytes()).map_err(to_js_error)?;
+        self.engine.apply(&transaction).map(|_| ()).map_err(to_js_error)
+    }
+
+    /// The account balances report, as printed by the CLI.
+    pub fn report(&self) -> Result<String, JsError> {
+        self.engine.report().map_err(to_js_error)
+    }
+
+    /// The account balances as a JSON array, sorted by client id.
+    #[wasm_bindgen(js_name = balancesJson)]
+    pub fn balances_json(&self) -> Result<String, JsError> {
+        let balances = self.engine.balances().map_err(to_js_error)?;
+        Ok(format_report_json(&balances))
+    }
+
+    /// How many transactions were processed so far.
+    #[wasm_bindgen(getter)]
+    pub fn processed(&self) -> f64 {
+        self.engine.stats().processed as f64
+    }
+
+    /// How many transactions were ignored so far because they were invalid.
+    #[wasm_bindgen(getter)]
+    pub fn rejected(&self) -> f64 {
+        self.engine.stats().rejected as f64
+    }
+}
@@ -0,0 +1,130 @@
+//! The accounts of time-ordered input that can be reported before the end of it, see
+//! [`PaymentsEngine::with_close_after`](crate::engine::PaymentsEngine::with_close_after).
+//!
+//! The watermark is the latest `timestamp` seen so far. A client whose last transaction is
+//! `close_after` seconds or more behind the watermark is taken to have no more transactions, so
+//! its account is final: it is handed to the caller and dropped from the account store, keeping
+//! only its id to reject anything that would still come for it.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
+
+use crate::accounts::AccountBalance;
+use crate::transactions::ClientId;
+
+/// What is done with the account of a client once it is final, e.g. writing its row of the report.
+pub type FinalizedSink = Box<dyn FnMut(&AccountBalance) -> Result<(), Box<dyn Error>> + Send>;
+
+pub(crate) struct Watermark {
+    close_after: u64,
+    // The latest timestamp seen, if any
+    now: Option<u64>,
+    // The clients that aren't final yet, by when their last transaction was seen
+    by_time: BTreeSet<(u64, ClientId)>,
+    last_seen: HashMap<ClientId, u64>,
+    finalized: HashSet<ClientId>,
+    pub(crate) sink: FinalizedSink,
+}
+
+impl Watermark {
+    pub(crate) fn new(close_after: u64, sink: FinalizedSink) -> Self {
+        Watermark {
+            close_after,
+            now: None,
+            by_time: BTreeSet::new(),
+            last_seen: HashMap::new(),
+            finalized: HashSet::new(),
+            sink,
+        }
+    }
+
+    /// Moves the watermark up to `timestamp`, returning the clients idle for `close_after` since,
+    /// by when they were last seen. They aren't tracked anymore until they are seen again.
+    pub(crate) fn advance(&mut self, timestamp: u64) -> Vec<ClientId> {
+        let now = self.now.map_or(timestamp, |now| now.max(timestamp));
+        self.now = Some(now);
+        let Some(until) = now.checked_sub(self.close_after) else {
+            return vec![];
+        };
+        let later = match until.checked_add(1) {
+            Some(after) => self.by_time.split_off(&(after, 0)),
+            None => BTreeSet::new(),
+        };
+        let idle = std::mem::replace(&mut self.by_time, later);
+        idle.into_iter()
+            .map(|(_, client_id)| {
+                self.last_seen.remove(&client_id);
+                client_id
+            })
+            .collect()
+    }
+
+    /// Records a transaction of the client at the watermark. Nothing is recorded before the
+    /// first timestamp, and the clients seen by then are only tracked from their next transaction.
+    pub(crate) fn seen(&mut self, client_id: ClientId) {
+        let Some(now) = self.now else {
+            return;
+        };
+        if let Some(last_seen) = self.last_seen.insert(client_id, now) {
+            self.by_time.remove(&(last_seen, client_id));
+        }
+        self.by_time.insert((now, client_id));
+    }
+
+    /// Whether the account of the client was final already.
+    pub(crate) fn is_finalized(&self, client_id: ClientId) -> bool {
+        self.finalized.contains(&client_id)
+    }
+
+    pub(crate) fn finalize(&mut self, client_id: ClientId) {
+        self.finalized.insert(client_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::amount::amount;
+    use crate::engine::PaymentsEngine;
+    use crate::rejections::RejectionReason;
+
+    #[test]
+    fn test_close_after() {
+        let input = "type, client, tx, amount, timestamp, settles_at\n\
+                     deposit, 1, 1, 10.0, 100,\n\
+                     deposit, 2, 2, 5.0, 150,\n\
+                     deposit, 3, 3, 1.0, 160, 1000\n\
+                     withdrawal, 2, 4, 1.0, 200,\n\
+                     deposit, 4, 5, 2.0, 300,\n\
+                     deposit, 1, 6, 1.0, 301,\n\
+                     deposit, 4, 7, 2.0, 390,\n\
+                     deposit, 5, 8, 1.0, 1200,\n";
+        let finalized = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&finalized);
+        let mut engine = PaymentsEngine::new().with_close_after(100, move |account_balance| {
+            sink.lock()
+                .unwrap()
+                .push((account_balance.client, account_balance.available));
+            Ok(())
+        });
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+
+        // Client 3 only once its deposit settled, and client 1 was already final
+        assert_eq!(
+            *finalized.lock().unwrap(),
+            [(1, amount(10.0)), (2, amount(4.0)), (3, amount(1.0)), (4, amount(4.0))]
+        );
+        let clients: Vec<_> = engine
+            .balances()
+            .unwrap()
+            .iter()
+            .map(|account_balance| account_balance.client)
+            .collect();
+        assert_eq!(clients, [5]);
+        assert_eq!(
+            engine.stats().rejections.get(&RejectionReason::AccountFinalized),
+            Some(&1)
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! Notifications of the events that need attention right away, POSTed as JSON to webhook URLs
+//! as they happen, in follow and serve modes, see [`Webhooks`].
+//!
+//! Only an account getting locked, a chargeback and a balance going negative are notified.
+//! They are sent by a thread of their own, so that a slow or unreachable endpoint doesn't hold
+//! up the engine, and each one is retried with an exponential backoff before giving up on it.
+//! At most `capacity` of them wait to be sent, so an endpoint that is down can't make the
+//! memory grow without limit: the ones beyond it are dropped, and counted.
+
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::accounts::{AccountBalance, AccountStatus};
+use crate::amount::AmountValue;
+use crate::dates::rfc3339;
+use crate::engine::{Applied, Observer};
+use crate::transactions::{Amount, ClientId, TransactionType, TxId};
+
+/// The settings of the webhooks.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// The URLs every notification is POSTed to. None by default.
+    pub urls: Vec<String>,
+    /// The events notified.
+    pub events: Vec<WebhookEventType>,
+    /// How many more times a notification is sent when it fails, before giving up on it.
+    pub retries: u32,
+    /// How long to wait before the first retry, in milliseconds, doubling for each one after it.
+    pub backoff_ms: u64,
+    /// How many notifications can wait to be sent, before the next ones are dropped.
+    pub capacity: usize,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            urls: vec![],
+            events: WebhookEventType::ALL.to_vec(),
+            retries: 3,
+            backoff_ms: 500,
+            capacity: 1024,
+        }
+    }
+}
+
+/// What happened to the account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventType {
+    /// It was locked, e.g. by a chargeback or an auto-lock rule.
+    AccountLocked,
+    /// A deposit of the client was charged back.
+    Chargeback,
+    /// Its available or total funds went below zero.
+    NegativeBalance,
+}
+
+impl WebhookEventType {
+    pub const ALL: [WebhookEventType; 3] = [
+        WebhookEventType::AccountLocked,
+        WebhookEventType::Chargeback,
+        WebhookEventType::NegativeBalance,
+    ];
+
+    /// The events of an applied transaction, given the balance of the client before it.
+    pub fn of(before: &AccountBalance, applied: &Applied) -> Vec<WebhookEventType> {
+        let negative = |account_balance: &AccountBalance| {
+            account_balance.available < Amount::ZERO || account_balance.total() < Amount::ZERO
+        };
+        let after = &applied.balance;
+        let mut events = vec![];
+        if after.status == AccountStatus::Locked && before.status != AccountStatus::Locked {
+            events.push(WebhookEventType::AccountLocked);
+        }
+        if applied.entry.tx_type == TransactionType::Chargeback {
+            events.push(WebhookEventType::Chargeback);
+        }
+        if negative(after) && !negative(before) {
+            events.push(WebhookEventType::NegativeBalance);
+        }
+        events
+    }
+}
+
+/// The body of a notification.
+#[derive(Debug, Clone, Serialize)]
+pub struct WebhookEvent {
+    pub event: WebhookEventType,
+    /// When it happened, as an RFC 3339 UTC time.
+    pub time: String,
+    /// The type of the transaction that caused it, as in the input.
+    #[serde(rename = "type")]
+    pub tx_type: String,
+    pub client: ClientId,
+    pub tx: TxId,
+    /// The balance of the client after the transaction.
+    pub balance: AccountBalance,
+}
+
+/// Sends the body of a notification to a URL, failing if it wasn't accepted.
+pub type Post = Box<dyn FnMut(&str, &str) -> Result<(), Box<dyn Error>> + Send>;
+
+/// An [`Observer`] that notifies the webhooks of the events of the transactions applied.
+///
+/// Dropping it waits for the notifications still being sent, retries included.
+pub struct Webhooks {
+    events: Vec<WebhookEventType>,
+    sender: Option<SyncSender<WebhookEvent>>,
+    deliverer: Option<JoinHandle<()>>,
+    failures: WebhookFailures,
+}
+
+/// How many notifications were given up on, once all their retries failed, and how many were
+/// dropped since too many were waiting to be sent. Cheap to clone.
+#[derive(Debug, Clone, Default)]
+pub struct WebhookFailures {
+    given_up: Arc<AtomicU64>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl WebhookFailures {
+    /// The notifications given up on, to a URL each.
+    pub fn count(&self) -> u64 {
+        self.given_up.load(Ordering::Relaxed)
+    }
+
+    /// The notifications dropped without sending them to any URL.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Webhooks {
+    /// Webhooks that POST the notifications over HTTP.
+    #[cfg(feature = "http")]
+    pub fn new(config: &WebhookConfig) -> Self {
+        Self::with_post(
+            config,
+            Box::new(|url, body| {
+                ureq::post(url).header("Content-Type", "application/json").send(body)?;
+                Ok(())
+            }),
+        )
+    }
+
+    /// Webhooks that send the notifications with `post`, e.g. to deliver them otherwise.
+    pub fn with_post(config: &WebhookConfig, post: Post) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(config.capacity);
+        let failures = WebhookFailures::default();
+        let deliverer = {
+            let (config, failures) = (config.clone(), failures.clone());
+            thread::spawn(move || deliver(&config, receiver, post, &failures))
+        };
+        Webhooks {
+            events: config.events.clone(),
+            sender: Some(sender),
+            deliverer: Some(deliverer),
+            failures,
+        }
+    }
+
+    /// A handle to the count of the notifications given up on.
+    pub fn failures(&self) -> WebhookFailures {
+        self.failures.clone()
+    }
+}
+
+impl Observer for Webhooks {
+    fn on_applied(&mut self, before: &AccountBalance, applied: &Applied) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        for event in WebhookEventType::of(before, applied) {
+            if !self.events.contains(&event) {
+                continue;
+            }
+            let sent = sender.try_send(WebhookEvent {
+                event,
+                time: rfc3339(SystemTime::now()),
+                tx_type: applied.entry.tx_type.as_str().to_string(),
+                client: applied.entry.client_id,
+                tx: applied.entry.tx_id,
+                balance: applied.balance.clone(),
+            });
+            if let Err(TrySendError::Full(_)) = sent {
+                self.failures.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Drop for Webhooks {
+    fn drop(&mut self) {
+        // The deliverer stops once it has sent everything queued
+        self.sender.take();
+        if let Some(deliverer) = self.deliverer.take() {
+            let _ = deliverer.join();
+        }
+    }
+}
+
+/// Sends each notification to each URL, retrying it until it is accepted or out of retries.
+fn deliver(config: &WebhookConfig, receiver: Receiver<WebhookEvent>, mut post: Post, failures: &WebhookFailures) {
+    for event in receiver {
+        let Ok(body) = serde_json::to_string(&event) else {
+            continue;
+        };
+        for url in &config.urls {
+            let mut backoff = Duration::from_millis(config.backoff_ms);
+            let mut attempts = 0;
+            while post(url, &body).is_err() {
+                if attempts == config.retries {
+                    failures.given_up.fetch_add(1, Ordering::Relaxed);
+                    break;
+                }
+                attempts += 1;
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{mpsc, Arc, Mutex};
+
+    use super::{WebhookConfig, Webhooks};
+    use crate::engine::PaymentsEngine;
+
+    #[test]
+    fn test_webhooks() {
+        let config = WebhookConfig {
+            urls: vec![String::from("http://risk"), String::from("http://down")],
+            retries: 2,
+            backoff_ms: 0,
+            ..WebhookConfig::default()
+        };
+        let sent = Arc::new(Mutex::new(vec![]));
+        let attempts = Arc::new(Mutex::new(0));
+        let webhooks = Webhooks::with_post(&config, {
+            let (sent, attempts) = (Arc::clone(&sent), Arc::clone(&attempts));
+            Box::new(move |url, body| {
+                *attempts.lock().unwrap() += 1;
+                if url == "http://down" {
+                    return Err("unreachable".into());
+                }
+                let body: serde_json::Value = serde_json::from_str(body)?;
+                sent.lock()
+                    .unwrap()
+                    .push((body["event"].to_string(), body["tx"].to_string()));
+                Ok(())
+            })
+        });
+        let failures = webhooks.failures();
+        let mut engine = PaymentsEngine::new().with_observer(Box::new(webhooks));
+
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 10.0\n\
+                     withdrawal, 1, 2, 6.0\n\
+                     dispute, 1, 1,\n\
+                     chargeback, 1, 1,\n\
+                     deposit, 2, 3, 1.0\n";
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        // Waits for the notifications
+        drop(engine);
+
+        let sent = sent.lock().unwrap();
+        let sent: Vec<_> = sent.iter().map(|(event, tx)| (event.as_str(), tx.as_str())).collect();
+        assert_eq!(
+            sent,
+            [
+                ("\"negative_balance\"", "1"),
+                ("\"account_locked\"", "1"),
+                ("\"chargeback\"", "1")
+            ]
+        );
+        // Each notification was tried 3 times on the endpoint that is down, and once on the other
+        assert_eq!((failures.count(), *attempts.lock().unwrap()), (3, 12));
+        assert_eq!(failures.dropped(), 0);
+    }
+
+    #[test]
+    fn test_webhooks_capacity() {
+        let config = WebhookConfig {
+            urls: vec![String::from("http://slow")],
+            capacity: 1,
+            ..WebhookConfig::default()
+        };
+        // The endpoint doesn't answer until the transactions are applied
+        let (release, blocked) = mpsc::channel::<()>();
+        let blocked = Mutex::new(blocked);
+        let webhooks = Webhooks::with_post(
+            &config,
+            Box::new(move |_, _| {
+                let _ = blocked.lock().unwrap().recv();
+                Ok(())
+            }),
+        );
+        let failures = webhooks.failures();
+        let mut engine = PaymentsEngine::new().with_observer(Box::new(webhooks));
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 1.0\n\
+                     dispute, 1, 1,\n\
+                     chargeback, 1, 1,\n\
+                     deposit, 2, 2, 1.0\n\
+                     dispute, 2, 2,\n\
+                     chargeback, 2, 2,\n";
+        engine.process_csv_reader(input.as_bytes()).unwrap();
+        drop(release);
+        drop(engine);
+        // 4 notifications: one being sent, one waiting and the others dropped
+        assert!(failures.dropped() >= 2);
+        assert_eq!(failures.count(), 0);
+    }
+}
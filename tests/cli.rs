@@ -0,0 +1,26 @@
+//! The command line, run as the binary itself, for what only `main` does.
+#![cfg(feature = "std")]
+
+use std::process::Command;
+
+#[test]
+fn test_bad_arguments() {
+    let failure = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_payments-engine"))
+            .args(args)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2));
+        let failure: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+        assert_eq!(failure["code"], "bad_arguments");
+        failure["message"].as_str().unwrap().to_string()
+    };
+    // The arguments missing are named, and the usage isn't part of the message
+    assert_eq!(
+        failure(&[]),
+        "the following required arguments were not provided: <INPUT>"
+    );
+    let message = failure(&["transactions.csv", "--disputes-over-available", "sometimes"]);
+    assert!(message.starts_with("invalid value 'sometimes' for '--disputes-over-available <POLICY>'"));
+    assert!(!message.contains("Usage:"));
+}